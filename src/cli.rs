@@ -0,0 +1,619 @@
+//! Command line interface, to manage the library without the GUI.
+//! `crab-reader <command> [arguments]` runs the command and exits, without a window;
+//! the books are found by path, by file name or by a part of their title.
+
+use std::{
+    error::Error,
+    ffi::OsStr,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    models::{book::Book, documents::BookMetadata},
+    traits::gui::GUIBook,
+    utils::{
+        calibre, clippings,
+        dir_manager::{get_book_dir, get_epub_dir, get_fulltext_index_dir, get_saved_covers_dir},
+        duplicates,
+        epub_utils::{self, get_metadata_of_book},
+        export::{self, ExportFormat, ExportScope},
+        identity, kosync, opds,
+        saveload::{self, copy_book_in_folder},
+        stats::{format_day, format_duration},
+        storage::{self, Document},
+    },
+};
+
+const USAGE: &str = "Uso: crab-reader <comando> [argomenti]
+
+Comandi:
+  import <file.epub>...           aggiunge i libri alla libreria
+  list                            elenca i libri con il loro avanzamento
+  info <libro>                    mostra i metadati del libro
+  favorite <libro> [on|off]       aggiunge o toglie il libro dai preferiti
+  export-notes <libro> [file]     esporta note ed evidenziazioni del libro
+  export-notes --all [file]       esporta note ed evidenziazioni di tutti i libri
+  import-annotations <file>...    importa le evidenziazioni di Kindle (My Clippings.txt)
+                                  e KOReader (metadata.epub.lua)
+  import-calibre <cartella>       importa gli EPUB di una libreria Calibre con i suoi
+                                  metadati: titolo, autori, serie, tag, voto e copertina
+  opds <catalogo> [parole]        mostra un feed OPDS, o cerca le parole nel catalogo;
+                                  il catalogo è un indirizzo o il nome di uno configurato
+  opds-download <url> [titolo]    scarica l'EPUB di un catalogo e lo aggiunge alla libreria
+  sync-login <server> <utente>    attiva la sincronizzazione della posizione con un
+                                  server KOReader, creando l'account se non esiste;
+                                  la password è chiesta o letta da CRAB_READER_PASSWORD
+  sync-logout                     disattiva la sincronizzazione
+  sync <libro>                    riprende la posizione letta su un altro dispositivo
+                                  se è più avanti, altrimenti invia quella locale
+  clear-cache                     svuota la cache di copertine, indici e paginazione
+  help                            mostra questo messaggio
+
+Il libro è indicato dal percorso, dal nome del file o da una parte del titolo.
+Le note sono esportate in Markdown, HTML o JSON secondo l'estensione del file,
+senza file sono scritte in Markdown.
+Senza comandi, o con solo un file EPUB che viene aggiunto alla libreria,
+si apre l'interfaccia grafica.";
+
+/// environment variable with the password of `sync-login`, instead of the prompt
+const PASSWORD_VAR: &str = "CRAB_READER_PASSWORD";
+
+#[derive(Debug, PartialEq)]
+enum CliCommand {
+    Import(Vec<String>),
+    List,
+    Info(String),
+    Favorite(String, bool),
+    /// the book, None for all of them, and the file
+    ExportNotes(Option<String>, Option<String>),
+    ImportAnnotations(Vec<String>),
+    /// the folder of the Calibre library
+    ImportCalibre(String),
+    /// the catalog and the words searched
+    Opds(String, Vec<String>),
+    /// the url of the epub and its title
+    OpdsDownload(String, Option<String>),
+    /// server and username, the password is asked
+    SyncLogin(String, String),
+    SyncLogout,
+    Sync(String),
+    ClearCache,
+    Help,
+}
+
+/// Runs the command given in the arguments (without the program name),
+/// returns the exit code of the process
+pub fn run(args: &[String]) -> i32 {
+    let command = match parse(args) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            return 2;
+        }
+    };
+
+    let result = match command {
+        CliCommand::Import(files) => import(&files),
+        CliCommand::List => list(),
+        CliCommand::Info(book) => info(&book),
+        CliCommand::Favorite(book, favorite) => set_favorite(&book, favorite),
+        CliCommand::ExportNotes(book, output) => export_notes(book.as_deref(), output.as_deref()),
+        CliCommand::ImportAnnotations(files) => import_annotations(&files),
+        CliCommand::ImportCalibre(dir) => import_calibre(&dir),
+        CliCommand::Opds(catalog, words) => opds_feed(&catalog, &words),
+        CliCommand::OpdsDownload(url, title) => opds_download(&url, title.as_deref()),
+        CliCommand::SyncLogin(server, username) => sync_login(&server, &username),
+        CliCommand::SyncLogout => kosync::logout(),
+        CliCommand::Sync(book) => sync(&book),
+        CliCommand::ClearCache => clear_cache(),
+        CliCommand::Help => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Errore: {}", e);
+            1
+        }
+    }
+}
+
+fn parse(args: &[String]) -> Result<CliCommand, String> {
+    let Some((command, rest)) = args.split_first() else {
+        return Ok(CliCommand::Help);
+    };
+    let rest: Vec<&str> = rest.iter().map(|arg| arg.as_str()).collect();
+
+    match (command.as_str(), rest.as_slice()) {
+        ("import", files) if !files.is_empty() => Ok(CliCommand::Import(
+            files.iter().map(|file| file.to_string()).collect(),
+        )),
+        ("list", []) => Ok(CliCommand::List),
+        ("info", [book]) => Ok(CliCommand::Info(book.to_string())),
+        ("favorite", [book]) => Ok(CliCommand::Favorite(book.to_string(), true)),
+        ("favorite", [book, "on"]) => Ok(CliCommand::Favorite(book.to_string(), true)),
+        ("favorite", [book, "off"]) => Ok(CliCommand::Favorite(book.to_string(), false)),
+        ("export-notes", ["--all"]) => Ok(CliCommand::ExportNotes(None, None)),
+        ("export-notes", ["--all", output]) => {
+            Ok(CliCommand::ExportNotes(None, Some(output.to_string())))
+        }
+        ("export-notes", [book]) => Ok(CliCommand::ExportNotes(Some(book.to_string()), None)),
+        ("export-notes", [book, output]) => Ok(CliCommand::ExportNotes(
+            Some(book.to_string()),
+            Some(output.to_string()),
+        )),
+        ("import-annotations", files) if !files.is_empty() => Ok(CliCommand::ImportAnnotations(
+            files.iter().map(|file| file.to_string()).collect(),
+        )),
+        ("import-calibre", [dir]) => Ok(CliCommand::ImportCalibre(dir.to_string())),
+        ("opds", [catalog, words @ ..]) => Ok(CliCommand::Opds(
+            catalog.to_string(),
+            words.iter().map(|word| word.to_string()).collect(),
+        )),
+        ("opds-download", [url]) => Ok(CliCommand::OpdsDownload(url.to_string(), None)),
+        ("opds-download", [url, title]) => Ok(CliCommand::OpdsDownload(
+            url.to_string(),
+            Some(title.to_string()),
+        )),
+        ("sync-login", [server, username]) => Ok(CliCommand::SyncLogin(
+            server.to_string(),
+            username.to_string(),
+        )),
+        ("sync-logout", []) => Ok(CliCommand::SyncLogout),
+        ("sync", [book]) => Ok(CliCommand::Sync(book.to_string())),
+        ("clear-cache", []) => Ok(CliCommand::ClearCache),
+        ("help" | "--help" | "-h", _) => Ok(CliCommand::Help),
+        (
+            "import" | "list" | "info" | "favorite" | "export-notes" | "import-annotations"
+            | "import-calibre" | "opds" | "opds-download" | "sync-login" | "sync-logout" | "sync"
+            | "clear-cache",
+            _,
+        ) => Err(format!("Argomenti non validi per {}", command)),
+        _ => Err(format!("Comando sconosciuto: {}", command)),
+    }
+}
+
+/// Returns the book when it is the only argument, as "Open with" of the file managers gives it:
+/// it is added to the library and the GUI is opened
+pub fn book_argument(args: &[String]) -> Option<&String> {
+    let [file] = args else {
+        return None;
+    };
+    let path = Path::new(file);
+    (path.is_file() && path.extension() == Some(OsStr::new("epub"))).then_some(file)
+}
+
+/// Adds the book to the library, unless it is already there
+pub fn import_book(file: &str) -> Result<(), Box<dyn Error>> {
+    import(&[file.to_string()])
+}
+
+/// Returns the paths of the books in the library, sorted by name
+fn library_paths() -> Result<Vec<String>, Box<dyn Error>> {
+    let mut paths: Vec<String> = std::fs::read_dir(get_epub_dir())?
+        .filter_map(|file| file.ok().map(|file| file.path()))
+        .filter(|path| path.extension().unwrap_or_default() == "epub")
+        .filter_map(|path| path.to_str().map(|path| path.to_string()))
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+/// Returns the path of the only book of the library matching the query
+fn find_book(query: &str) -> Result<String, Box<dyn Error>> {
+    let paths = library_paths()?;
+    let query_path = PathBuf::from(query);
+    if let Some(path) = paths.iter().find(|path| {
+        Path::new(path) == query_path || Path::new(path).file_name() == Some(OsStr::new(query))
+    }) {
+        return Ok(path.clone());
+    }
+
+    let query = query.to_lowercase();
+    let matching: Vec<(String, BookMetadata)> = paths
+        .into_iter()
+        .map(|path| {
+            let metadata = get_metadata_of_book(&path);
+            (path, metadata)
+        })
+        .filter(|(_, metadata)| metadata.title.to_lowercase().contains(&query))
+        .collect();
+
+    match matching.as_slice() {
+        [] => Err(format!("nessun libro corrisponde a \"{}\"", query).into()),
+        [(path, _)] => Ok(path.clone()),
+        _ => {
+            let titles: Vec<String> = matching
+                .iter()
+                .map(|(path, metadata)| format!("  {} ({})", metadata.title, path))
+                .collect();
+            Err(format!(
+                "più libri corrispondono a \"{}\":\n{}",
+                query,
+                titles.join("\n")
+            )
+            .into())
+        }
+    }
+}
+
+/// Loads the book as the library does, extracting it the first time
+fn load_book(path: &str) -> Result<Book, Box<dyn Error>> {
+    if !get_book_dir(path).exists() {
+        epub_utils::extract_all(path)?;
+    }
+    Ok(Book::new(path))
+}
+
+fn import(files: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut library = library_paths()?;
+    let mut failed = 0;
+    for file in files {
+        let Some(id) = identity::compute_id(file) else {
+            eprintln!("{}: non è un EPUB leggibile", file);
+            failed += 1;
+            continue;
+        };
+        // the same book is not imported twice, `merge` in the GUI joins the copies
+        if let Some(existing) = duplicates::find_copy(&id, &library) {
+            println!("{}: già presente come {}", file, existing);
+            continue;
+        }
+        let imported = copy_book_in_folder(file).and_then(|path| {
+            let book = load_book(&path)?;
+            library.push(path);
+            Ok(book)
+        });
+        match imported {
+            Ok(book) => println!("{}: aggiunto \"{}\"", file, book.get_title()),
+            Err(e) => {
+                eprintln!("{}: {}", file, e);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(format!("{} libri non aggiunti", failed).into());
+    }
+    Ok(())
+}
+
+fn list() -> Result<(), Box<dyn Error>> {
+    for path in library_paths()? {
+        let book = match load_book(&path) {
+            Ok(book) => book,
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                continue;
+            }
+        };
+        let favorite = if book.is_favorite() { "*" } else { " " };
+        println!(
+            "{} {:>3.0}%  {} - {}  ({})",
+            favorite,
+            book.get_perc_read(),
+            book.get_title(),
+            book.get_author(),
+            path
+        );
+    }
+    Ok(())
+}
+
+fn info(query: &str) -> Result<(), Box<dyn Error>> {
+    let path = find_book(query)?;
+    let book = load_book(&path)?;
+    let metadata = get_metadata_of_book(&path);
+
+    let mut fields = vec![
+        ("Titolo", metadata.title.clone()),
+        ("Autore", metadata.author.clone()),
+        ("Lingua", metadata.lang.clone()),
+        ("Editore", metadata.publisher.clone()),
+        ("Serie", book.get_series_label()),
+        ("Identificativi", metadata.identifiers.join(", ")),
+        ("Tag", metadata.tags.clone().unwrap_or_default().join(", ")),
+        ("Scaffale", metadata.shelf.clone()),
+        ("Capitoli", metadata.chapters.to_string()),
+        ("Pagine", book.get_number_of_pages().to_string()),
+        ("Avanzamento", format!("{:.0}%", book.get_perc_read())),
+        (
+            "Tempo rimanente",
+            format_duration(book.get_time_left_in_book()),
+        ),
+        (
+            "Preferito",
+            if metadata.favorite { "sì" } else { "no" }.to_string(),
+        ),
+        ("Aggiunto", format_date(metadata.added)),
+        ("Aperto", format_date(metadata.last_opened)),
+        ("File", path.clone()),
+    ];
+    fields.retain(|(_, value)| !value.is_empty());
+    for (name, value) in fields {
+        println!("{}: {}", name, value);
+    }
+    if let Some(desc) = metadata.desc {
+        println!("\n{}", desc);
+    }
+    Ok(())
+}
+
+fn format_date(time: Option<u64>) -> String {
+    time.filter(|time| *time > 0)
+        .map_or(String::new(), |time| format_day(time / (24 * 60 * 60)))
+}
+
+fn set_favorite(query: &str, favorite: bool) -> Result<(), Box<dyn Error>> {
+    let path = find_book(query)?;
+    saveload::save_favorite(path.as_str(), favorite)?;
+    let title = get_metadata_of_book(&path).title;
+    if favorite {
+        println!("\"{}\" aggiunto ai preferiti", title);
+    } else {
+        println!("\"{}\" tolto dai preferiti", title);
+    }
+    Ok(())
+}
+
+fn export_notes(query: Option<&str>, output: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let (paths, scope) = match query {
+        Some(query) => (vec![find_book(query)?], ExportScope::Book),
+        None => (library_paths()?, ExportScope::Library),
+    };
+
+    match output {
+        Some(output) => {
+            let count = export::export_to_file(&paths, scope, Path::new(output))?;
+            println!("{} note esportate in {}", count, output);
+        }
+        None => {
+            let books = export::collect_all(&paths, scope)?;
+            print!("{}", export::render(&books, ExportFormat::Markdown, scope)?);
+        }
+    }
+    Ok(())
+}
+
+fn import_annotations(files: &[String]) -> Result<(), Box<dyn Error>> {
+    let library = library_paths()?;
+    for file in files {
+        let report = clippings::import_file(Path::new(file), &library)?;
+        println!("{}:\n{}", file, report.describe());
+    }
+    Ok(())
+}
+
+fn import_calibre(dir: &str) -> Result<(), Box<dyn Error>> {
+    let report = calibre::import_library(Path::new(dir), &library_paths()?)?;
+    println!("{}", report.describe());
+    Ok(())
+}
+
+/// Prints the entries of a feed, with the urls to open or download them
+fn opds_feed(catalog: &str, words: &[String]) -> Result<(), Box<dyn Error>> {
+    let url = opds::catalogs()
+        .into_iter()
+        .find(|saved| saved.name == catalog)
+        .map_or_else(|| opds::catalog_url(catalog), |saved| saved.url);
+    let mut feed = opds::load_feed(&url)?;
+    if !words.is_empty() {
+        let template = feed.search.ok_or("il catalogo non permette di cercare")?;
+        feed = opds::load_feed(&opds::search_url(&template, &words.join(" ")))?;
+    }
+
+    println!("{}", feed.title);
+    for entry in feed.entries.iter() {
+        match (&entry.acquisition, &entry.navigation) {
+            (Some(epub), _) if entry.author.is_empty() => {
+                println!("\n{}\n  EPUB: {}", entry.title, epub)
+            }
+            (Some(epub), _) => println!("\n{} - {}\n  EPUB: {}", entry.title, entry.author, epub),
+            (None, Some(navigation)) => println!("\n{}\n  feed: {}", entry.title, navigation),
+            (None, None) => println!("\n{}", entry.title),
+        }
+    }
+    if let Some(next) = feed.next {
+        println!("\nPagina successiva: {}", next);
+    }
+    Ok(())
+}
+
+/// Downloads an epub from a catalog and imports it as the files on disk
+fn opds_download(url: &str, title: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let title = title.map_or_else(
+        || {
+            Path::new(url)
+                .file_stem()
+                .map_or("libro".to_string(), |stem| {
+                    stem.to_string_lossy().to_string()
+                })
+        },
+        |title| title.to_string(),
+    );
+    let path = opds::download(url, &title)?;
+    let result = import(&[path.clone()]);
+    std::fs::remove_file(path)?;
+    result
+}
+
+fn sync_login(server: &str, username: &str) -> Result<(), Box<dyn Error>> {
+    let password = match std::env::var(PASSWORD_VAR) {
+        Ok(password) => password,
+        Err(_) => read_password()?,
+    };
+    kosync::login(server, username, &password)?;
+    println!("Sincronizzazione attiva con {} come {}", server, username);
+    Ok(())
+}
+
+/// Asks the password on the terminal, without showing it while it is typed
+fn read_password() -> Result<String, Box<dyn Error>> {
+    eprint!("Password: ");
+    std::io::stderr().flush()?;
+    // without a terminal (e.g. with the password piped, or on Windows)
+    // the echo can't be turned off and the password is read as it is
+    let echo_off = set_echo(false);
+    let mut password = String::new();
+    let result = std::io::stdin().read_line(&mut password);
+    if echo_off {
+        set_echo(true);
+        eprintln!();
+    }
+    result?;
+    Ok(password.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Turns the echo of the terminal on or off, returns false if it failed
+#[cfg(unix)]
+fn set_echo(on: bool) -> bool {
+    use std::io::IsTerminal;
+
+    if !std::io::stdin().is_terminal() {
+        return false;
+    }
+    std::process::Command::new("stty")
+        .arg(if on { "echo" } else { "-echo" })
+        .stdin(std::process::Stdio::inherit())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .map_or(false, |status| status.success())
+}
+
+#[cfg(not(unix))]
+fn set_echo(_on: bool) -> bool {
+    false
+}
+
+/// Resumes the position read on another device if it is further on, pushes the local one otherwise
+fn sync(query: &str) -> Result<(), Box<dyn Error>> {
+    if !kosync::is_enabled() {
+        return Err("la sincronizzazione non è attiva, usa sync-login".into());
+    }
+    let path = find_book(query)?;
+    if !get_book_dir(&path).exists() {
+        epub_utils::extract_all(&path)?;
+    }
+    let local = saveload::load_data(&path).unwrap_or_default();
+    match kosync::pull_position(&path, local) {
+        Some(remote) => {
+            saveload::save_data(&path, remote, false)?;
+            println!(
+                "Posizione ripresa da un altro dispositivo: capitolo {}",
+                remote.get_chapter() + 1
+            );
+        }
+        None => {
+            kosync::push_now(&path, local)?;
+            println!("Posizione inviata: capitolo {}", local.get_chapter() + 1);
+        }
+    }
+    Ok(())
+}
+
+/// Removes what the library can build again: the covers, the full-text index
+/// and the pages of the books, calculated again the next time they are opened
+fn clear_cache() -> Result<(), Box<dyn Error>> {
+    for dir in [get_saved_covers_dir(), get_fulltext_index_dir()] {
+        std::fs::remove_dir_all(&dir)?;
+        println!("Rimossa {}", dir.display());
+    }
+
+    for path in library_paths()? {
+        let document = Document::Metadata(path);
+        if !document.exists() {
+            continue;
+        }
+        storage::update(&document, |json| {
+            if let Some(metadata) = json.as_object_mut() {
+                metadata.remove("total_pages");
+                metadata.remove("pages_per_chapter");
+            }
+        })?;
+    }
+    println!("Paginazione dei libri rimossa");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn arguments_are_parsed() {
+        assert_eq!(parse(&args(&[])), Ok(CliCommand::Help));
+        assert_eq!(
+            parse(&args(&["import", "a.epub", "b.epub"])),
+            Ok(CliCommand::Import(args(&["a.epub", "b.epub"])))
+        );
+        assert_eq!(
+            parse(&args(&["favorite", "zeno", "off"])),
+            Ok(CliCommand::Favorite("zeno".to_string(), false))
+        );
+        assert_eq!(
+            parse(&args(&["export-notes", "zeno", "note.md"])),
+            Ok(CliCommand::ExportNotes(
+                Some("zeno".to_string()),
+                Some("note.md".to_string())
+            ))
+        );
+        assert_eq!(
+            parse(&args(&["export-notes", "--all"])),
+            Ok(CliCommand::ExportNotes(None, None))
+        );
+        assert_eq!(
+            parse(&args(&["sync", "zeno"])),
+            Ok(CliCommand::Sync("zeno".to_string()))
+        );
+        assert_eq!(
+            parse(&args(&["opds", "gutenberg", "la", "coscienza"])),
+            Ok(CliCommand::Opds(
+                "gutenberg".to_string(),
+                args(&["la", "coscienza"])
+            ))
+        );
+        assert_eq!(
+            parse(&args(&["import-calibre", "Calibre Library"])),
+            Ok(CliCommand::ImportCalibre("Calibre Library".to_string()))
+        );
+        assert_eq!(
+            parse(&args(&["sync-login", "https://sync.example", "mario"])),
+            Ok(CliCommand::SyncLogin(
+                "https://sync.example".to_string(),
+                "mario".to_string()
+            ))
+        );
+        // the password isn't given in the arguments
+        assert!(parse(&args(&["sync-login", "server", "mario", "segreta"])).is_err());
+        assert!(parse(&args(&["import-calibre"])).is_err());
+        assert!(parse(&args(&["import"])).is_err());
+        assert!(parse(&args(&["favorite", "zeno", "maybe"])).is_err());
+        assert!(parse(&args(&["unknown"])).is_err());
+    }
+
+    #[test]
+    fn a_lone_epub_opens_the_gui() {
+        let dir = std::env::temp_dir().join("crab-reader-cli-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let epub = dir.join("book.epub");
+        std::fs::write(&epub, b"epub").unwrap();
+        let epub = epub.to_str().unwrap().to_string();
+
+        assert_eq!(book_argument(&[epub.clone()]), Some(&epub));
+        assert_eq!(book_argument(&args(&["import", epub.as_str()])), None);
+        // a command, or a book that doesn't exist
+        assert_eq!(book_argument(&args(&["list"])), None);
+        assert_eq!(book_argument(&args(&["missing.epub"])), None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
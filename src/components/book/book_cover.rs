@@ -22,6 +22,7 @@ pub struct BookCover<B: GUIBook> {
     is_hot: bool,
     star: WidgetPod<B, Label<B>>,
     label: WidgetPod<B, Label<B>>,
+    series: WidgetPod<B, Label<B>>,
 }
 
 impl<B: GUIBook> BookCover<B> {
@@ -40,10 +41,16 @@ impl<B: GUIBook> BookCover<B> {
             .with_font(fonts::medium)
             .with_text_color(colors::ON_PRIMARY);
 
+        let series = Label::dynamic(|data: &B, _| data.get_series_label())
+            .with_line_break_mode(LineBreaking::WordWrap)
+            .with_font(fonts::small)
+            .with_text_color(colors::ON_PRIMARY);
+
         Self {
             is_hot: false,
             star: WidgetPod::new(star),
             label: WidgetPod::new(label),
+            series: WidgetPod::new(series),
         }
     }
 
@@ -98,6 +105,16 @@ impl<B: GUIBook> BookCover<B> {
         self.label.paint(ctx, data, env);
     }
 
+    /// The series is shown at the top of the cover, on a band of the color of the book
+    fn paint_series(&mut self, ctx: &mut PaintCtx, data: &B, env: &Env) {
+        if data.get_series().is_empty() {
+            return;
+        }
+        let band = self.series.layout_rect().inflate(5.0, 5.0).to_rounded_rect(5.0);
+        ctx.fill(band, &self.get_bg_color(data, env));
+        self.series.paint(ctx, data, env);
+    }
+
     fn get_bg_color(&self, data: &impl GUIBook, env: &Env) -> Color {
         if data.is_selected() {
             env.get(colors::PRIMARY_VARIANT)
@@ -113,6 +130,7 @@ impl<B: GUIBook + Data> Widget<B> for BookCover<B> {
     fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut B, env: &Env) {
         self.star.event(ctx, event, data, env);
         self.label.event(ctx, event, data, env);
+        self.series.event(ctx, event, data, env);
 
         if ctx.is_hot() {
             ctx.set_cursor(&Pointer);
@@ -138,12 +156,14 @@ impl<B: GUIBook + Data> Widget<B> for BookCover<B> {
     fn lifecycle(&mut self, ctx: &mut LifeCycleCtx, event: &LifeCycle, data: &B, env: &Env) {
         self.star.lifecycle(ctx, event, data, env);
         self.label.lifecycle(ctx, event, data, env);
+        self.series.lifecycle(ctx, event, data, env);
     }
 
     fn update(&mut self, ctx: &mut UpdateCtx, old_data: &B, data: &B, env: &Env) {
         if !data.same(old_data) || ctx.env_changed() {
             self.star.update(ctx, data, env);
             self.label.update(ctx, data, env);
+            self.series.update(ctx, data, env);
         }
     }
 
@@ -162,6 +182,9 @@ impl<B: GUIBook + Data> Widget<B> for BookCover<B> {
         let origin_y = (BOOK_WIDGET_SIZE.height - ls.height) / 2.0;
         let origin = (origin_x, origin_y).into();
         self.label.set_origin(ctx, data, env, origin);
+        let sbc = BoxConstraints::new(Size::ZERO, (BOOK_WIDGET_SIZE.width - 20.0, 60.0).into());
+        self.series.layout(ctx, &sbc, data, env);
+        self.series.set_origin(ctx, data, env, (10.0, 10.0).into());
         BOOK_WIDGET_SIZE
     }
 
@@ -170,6 +193,7 @@ impl<B: GUIBook + Data> Widget<B> for BookCover<B> {
             self.paint_shadow(ctx);
         }
         self.paint_cover(ctx, data, env);
+        self.paint_series(ctx, data, env);
         self.star.paint(ctx, data, env);
     }
 }
@@ -1,17 +1,17 @@
 use druid::{
-    widget::{Flex, Label, LineBreaking},
+    widget::{Either, Flex, Label, LineBreaking, SizedBox, TextBox},
     BoxConstraints, Command, Data, Env, Event, EventCtx, LayoutCtx, LifeCycle, LifeCycleCtx,
     PaintCtx, Size, Target, UpdateCtx, Widget, WidgetExt, WidgetPod,
 };
 
 use crate::{
-    components::buttons::rbtn::RoundedButton,
+    components::{book::metadata_editor::metadata_editor, buttons::rbtn::RoundedButton},
     models::book::Book,
     traits::{
         gui::{GUIBook, GUILibrary},
-        reader::BookManagement,
+        reader::{BookManagement, BookReading},
     },
-    utils::{colors, fonts, saveload::delete_book},
+    utils::{colors, fonts, kosync, saveload::delete_book, stats::format_duration},
     Library, ENTERING_READING_MODE,
 };
 
@@ -73,14 +73,111 @@ impl BookDetails {
         .align_left()
         .padding(5.0);
 
+        let time_left_label = Label::dynamic(|data: &Library<Book>, _| {
+            data.get_selected_book()
+                .map_or("".into(), |book: &Book| {
+                    if book.is_finished() {
+                        return "Libro finito".into();
+                    }
+                    format!(
+                        "Tempo di lettura rimanente: {}",
+                        format_duration(book.get_time_left_in_book())
+                    )
+                })
+        })
+        .with_font(fonts::small)
+        .with_text_color(colors::ON_BACKGROUND)
+        .align_left()
+        .padding(5.0);
+
+        let tags_label = Label::dynamic(|data: &Library<Book>, _| {
+            data.get_selected_book()
+                .map_or("Nessun libro selezionato".into(), |book: &Book| {
+                    let tags = book.get_tags();
+                    if tags.is_empty() {
+                        "Tag: nessuno".into()
+                    } else {
+                        format!("Tag: {}", tags.iter().cloned().collect::<Vec<_>>().join(", "))
+                    }
+                })
+        })
+        .with_font(fonts::small)
+        .with_text_color(colors::ON_BACKGROUND)
+        .with_line_break_mode(LineBreaking::WordWrap)
+        .align_left()
+        .padding(5.0);
+
+        let shelf_label = Label::dynamic(|data: &Library<Book>, _| {
+            data.get_selected_book()
+                .map_or("Nessun libro selezionato".into(), |book: &Book| {
+                    let shelf = book.get_shelf();
+                    if shelf.is_empty() {
+                        "Scaffale: nessuno".into()
+                    } else {
+                        format!("Scaffale: {}", shelf)
+                    }
+                })
+        })
+        .with_font(fonts::small)
+        .with_text_color(colors::ON_BACKGROUND)
+        .align_left()
+        .padding(5.0);
+
+        let tag_input = TextBox::new()
+            .with_placeholder("Tag o scaffale")
+            .with_font(fonts::medium)
+            .with_text_color(colors::ON_BACKGROUND)
+            .expand_width()
+            .lens(Library::tag_input);
+
+        let add_tag_btn = RoundedButton::from_text("Aggiungi tag")
+            .with_on_click(|_, library: &mut Library<Book>, _: &Env| {
+                library.add_tag_to_selected();
+            })
+            .with_font(fonts::small);
+
+        let remove_tag_btn = RoundedButton::from_text("Rimuovi tag")
+            .with_on_click(|_, library: &mut Library<Book>, _: &Env| {
+                library.remove_tag_from_selected();
+            })
+            .with_font(fonts::small);
+
+        let shelf_btn = RoundedButton::from_text("Sposta nello scaffale")
+            .with_on_click(|_, library: &mut Library<Book>, _: &Env| {
+                library.move_selected_to_shelf();
+            })
+            .with_font(fonts::small);
+
+        let tag_ctls = Flex::column()
+            .with_child(tag_input)
+            .with_spacer(5.0)
+            .with_child(
+                Flex::row()
+                    .with_flex_child(add_tag_btn, 1.0)
+                    .with_spacer(5.0)
+                    .with_flex_child(remove_tag_btn, 1.0)
+                    .with_spacer(5.0)
+                    .with_flex_child(shelf_btn, 1.0),
+            )
+            .expand_width()
+            .padding(5.0);
+
         let keep_reading_btn = RoundedButton::from_text("Continua a Leggere")
             .with_on_click(|ctx, library: &mut Library<Book>, _: &Env| {
                 let current_book = library.get_selected_book_mut().unwrap();
 
                 // @cocco: thread?
                 current_book.load_chapter();
+                kosync::pull_in_background(
+                    ctx.get_external_handle(),
+                    current_book.get_path(),
+                    current_book.get_position(),
+                );
                 // @cocco: thread?
                 current_book.load_notes();
+                current_book.load_bookmarks();
+                current_book.load_highlights();
+                current_book.load_toc();
                 let cmd: Command = Command::new(ENTERING_READING_MODE, (), Target::Auto);
                 ctx.submit_command(cmd.clone());
             })
@@ -114,6 +211,65 @@ impl BookDetails {
 
         let btn_ctls = btn_ctls.expand_width().padding(5.0);
 
+        let next_in_series_btn = RoundedButton::dynamic(|library: &Library<Book>, _| {
+            library
+                .next_to_read_idx()
+                .and_then(|idx| library.get_book(idx))
+                .map_or(String::new(), |book| {
+                    format!("Prossimo della serie: {}", book.get_title())
+                })
+        })
+        .with_on_click(|_, library: &mut Library<Book>, _: &Env| {
+            if let Some(idx) = library.next_to_read_idx() {
+                library.set_selected_book_idx(idx);
+            }
+        })
+        .with_font(fonts::medium)
+        .padding(5.0);
+
+        let next_in_series = Either::new(
+            |library: &Library<Book>, _| library.next_to_read_idx().is_some(),
+            next_in_series_btn,
+            SizedBox::empty(),
+        );
+
+        let series_label = Label::dynamic(|data: &Library<Book>, _| {
+            data.get_selected_book()
+                .map_or("Nessun libro selezionato".into(), |book: &Book| {
+                    let series = book.get_series_label();
+                    if series.is_empty() {
+                        "Serie: nessuna".into()
+                    } else {
+                        format!("Serie: {}", series)
+                    }
+                })
+        })
+        .with_font(fonts::medium)
+        .with_text_color(colors::ON_BACKGROUND)
+        .with_line_break_mode(LineBreaking::WordWrap)
+        .align_left()
+        .padding(5.0);
+
+        let rating_label = Label::dynamic(|data: &Library<Book>, _| {
+            data.get_selected_book()
+                .and_then(|book: &Book| book.get_rating())
+                .map_or(String::new(), |rating| {
+                    let stars = rating.min(5) as usize;
+                    format!("Valutazione: {}{}", "★".repeat(stars), "☆".repeat(5 - stars))
+                })
+        })
+        .with_font(fonts::medium)
+        .with_text_color(colors::ON_BACKGROUND)
+        .align_left()
+        .padding(5.0);
+
+        let edit_btn = RoundedButton::from_text("Modifica metadati")
+            .with_on_click(|_, library: &mut Library<Book>, _: &Env| {
+                library.start_metadata_edit();
+            })
+            .with_font(fonts::medium)
+            .padding(5.0);
+
         let del_btn = RoundedButton::from_text("Elimina")
             .with_on_click(|ctx, library: &mut Library<Book>, _: &Env| {
                 if let Some(book) = library.get_selected_book() {
@@ -137,17 +293,31 @@ impl BookDetails {
         // the book's functions lo load chapters and page
         // Book::load_chapter(), Book::load_page()
 
-        let widget = Flex::column()
+        let details = Flex::column()
             .with_child(header_label)
             .with_child(title_label)
             .with_child(author_label)
             .with_child(lang_label)
+            .with_child(series_label)
+            .with_child(rating_label)
             .with_child(completion_label)
+            .with_child(time_left_label)
+            .with_child(tags_label)
+            .with_child(shelf_label)
+            .with_child(tag_ctls)
             .with_child(btn_ctls)
-            .with_child(del_btn)
-            .padding(10.0)
-            .expand()
-            .boxed();
+            .with_child(next_in_series)
+            .with_child(edit_btn)
+            .with_child(del_btn);
+
+        let widget = Either::new(
+            |library: &Library<Book>, _| library.editing_metadata,
+            metadata_editor(),
+            details,
+        )
+        .padding(10.0)
+        .expand()
+        .boxed();
         let inner = WidgetPod::new(widget);
 
         Self { inner }
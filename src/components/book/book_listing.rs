@@ -19,7 +19,14 @@ pub struct BookListing<T> {
 
 impl<T: GUIBook> BookListing<T> {
     pub fn new() -> Self {
-        let title_label = Label::dynamic(|data: &T, _| format!("{}", data.get_title().to_string()))
+        let title_label = Label::dynamic(|data: &T, _| {
+            let series = data.get_series_label();
+            if series.is_empty() {
+                data.get_title()
+            } else {
+                format!("{}\n{}", data.get_title(), series)
+            }
+        })
             .with_font(fonts::medium)
             .with_line_break_mode(LineBreaking::WordWrap)
             .with_text_color(colors::ON_PRIMARY);
@@ -0,0 +1,108 @@
+use druid::{
+    commands::SHOW_SAVE_PANEL,
+    widget::{CrossAxisAlignment, Flex, Label, TextBox},
+    Env, FileDialogOptions, FileSpec, Lens, Target, Widget, WidgetExt,
+};
+
+use crate::{
+    components::buttons::rbtn::RoundedButton,
+    models::{book::Book, library::Library, metadata::MetadataEdit},
+    traits::gui::{GUIBook, GUILibrary},
+    utils::{colors, fonts},
+};
+
+/// Editor of the metadata of the selected book, shown in place of its details
+pub fn metadata_editor() -> impl Widget<Library<Book>> {
+    let header_label = Label::new("Modifica metadati")
+        .with_text_color(colors::ON_BACKGROUND)
+        .with_font(fonts::bold::xlarge);
+
+    let fields = Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(field("Titolo", "", MetadataEdit::title))
+        .with_child(field("Autori", "Separati da virgole", MetadataEdit::authors))
+        .with_child(field("Serie", "", MetadataEdit::series))
+        .with_child(field("Numero nella serie", "1, 2.5...", MetadataEdit::series_index))
+        .with_child(field("Lingua", "it, en...", MetadataEdit::lang))
+        .with_child(field("Editore", "", MetadataEdit::publisher))
+        .with_child(field("Descrizione", "", MetadataEdit::desc))
+        .with_child(field(
+            "Identificativi",
+            "urn:isbn:..., separati da virgole",
+            MetadataEdit::identifiers,
+        ))
+        .lens(Library::metadata_edit);
+
+    let save_btn = RoundedButton::from_text("Salva")
+        .with_on_click(|_, library: &mut Library<Book>, _: &Env| {
+            // the editor stays open if they can't be saved
+            if let Err(e) = library.save_metadata_edit() {
+                println!("ERROR: failed to save the metadata: {}", e);
+            }
+        })
+        .with_font(fonts::medium);
+
+    let cancel_btn = RoundedButton::from_text("Annulla")
+        .with_on_click(|_, library: &mut Library<Book>, _: &Env| {
+            library.editing_metadata = false;
+        })
+        .secondary()
+        .with_font(fonts::medium);
+
+    // the edits are saved before, the copy is written with the saved metadata
+    let export_btn = RoundedButton::from_text("Salva una copia dell'EPUB")
+        .with_on_click(|ctx, library: &mut Library<Book>, _: &Env| {
+            let title = library.metadata_edit.title.trim().to_string();
+            if let Err(e) = library.save_metadata_edit() {
+                println!("ERROR: failed to save the metadata: {}", e);
+                return;
+            }
+            let Some(book) = library.get_selected_book() else {
+                return;
+            };
+            let name = if title.is_empty() { book.get_title() } else { title };
+            let options = FileDialogOptions::new()
+                .allowed_types(vec![FileSpec::new("Epub", &["epub"])])
+                .default_name(format!("{}.epub", name))
+                .title("Salva una copia con i metadati modificati");
+            ctx.submit_command(SHOW_SAVE_PANEL.with(options).to(Target::Auto));
+        })
+        .with_font(fonts::medium);
+
+    let btn_ctls = Flex::row()
+        .with_flex_child(save_btn, 1.0)
+        .with_spacer(5.0)
+        .with_flex_child(cancel_btn, 1.0)
+        .expand_width()
+        .padding(5.0);
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(header_label)
+        .with_child(fields)
+        .with_child(btn_ctls)
+        .with_child(export_btn.expand_width().padding(5.0))
+}
+
+fn field(
+    name: &str,
+    placeholder: &str,
+    lens: impl Lens<MetadataEdit, String> + 'static,
+) -> impl Widget<MetadataEdit> {
+    let label = Label::new(name)
+        .with_text_color(colors::ON_BACKGROUND)
+        .with_font(fonts::small);
+
+    let text_box = TextBox::new()
+        .with_placeholder(placeholder)
+        .with_font(fonts::medium)
+        .with_text_color(colors::ON_BACKGROUND)
+        .expand_width()
+        .lens(lens);
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(label)
+        .with_child(text_box)
+        .padding(5.0)
+}
@@ -1,3 +1,4 @@
 pub mod book_cover;
 pub mod book_details;
-pub mod book_listing;
\ No newline at end of file
+pub mod book_listing;
+pub mod metadata_editor;
@@ -0,0 +1,79 @@
+use druid::{
+    widget::{Flex, List},
+    Lens, LensExt, Selector, Widget, WidgetExt,
+};
+
+use crate::{
+    models::{
+        bookmark::{BookBookmarks, Bookmark},
+        library::LibrarySelectedBookLens,
+        position::Position,
+    },
+    traits::reader::BookManagement,
+    utils::fonts,
+    CrabReaderState,
+};
+
+use super::buttons::rbtn::RoundedButton;
+
+/// move the reading position to the bookmark
+pub const GO_TO_BOOKMARK: Selector<Position> = Selector::new("crabreader.go_to_bookmark");
+/// rename the bookmark with the text written in the bookmarks text box
+pub const RENAME_BOOKMARK: Selector<Position> = Selector::new("crabreader.rename_bookmark");
+pub const DELETE_BOOKMARK: Selector<Position> = Selector::new("crabreader.delete_bookmark");
+
+pub struct SelectedBookBookmarksLens;
+
+impl<B: BookManagement> Lens<B, BookBookmarks> for SelectedBookBookmarksLens {
+    fn with<V, F: FnOnce(&BookBookmarks) -> V>(&self, data: &B, f: F) -> V {
+        f(data.get_bookmarks())
+    }
+
+    fn with_mut<V, F: FnOnce(&mut BookBookmarks) -> V>(&self, data: &mut B, f: F) -> V {
+        f(data.get_bookmarks_mut())
+    }
+}
+
+pub fn get_bookmarks_list() -> impl Widget<CrabReaderState> {
+    let bookmarks = List::new(|| {
+        let jump = RoundedButton::dynamic(|bookmark: &Bookmark, _env: &_| {
+            bookmark.get_name().to_string()
+        })
+        .with_on_click(|ctx, bookmark: &mut Bookmark, _| {
+            ctx.submit_command(GO_TO_BOOKMARK.with(bookmark.get_position()));
+        })
+        .with_font(fonts::small);
+
+        let rename = RoundedButton::from_text("Rinomina")
+            .secondary()
+            .with_on_click(|ctx, bookmark: &mut Bookmark, _| {
+                ctx.submit_command(RENAME_BOOKMARK.with(bookmark.get_position()));
+            })
+            .with_font(fonts::xsmall);
+
+        let delete = RoundedButton::from_text("Elimina")
+            .secondary()
+            .with_on_click(|ctx, bookmark: &mut Bookmark, _| {
+                ctx.submit_command(DELETE_BOOKMARK.with(bookmark.get_position()));
+            })
+            .with_font(fonts::xsmall);
+
+        Flex::column()
+            .with_child(jump.expand_width())
+            .with_spacer(2.0)
+            .with_child(
+                Flex::row()
+                    .with_flex_child(rename.expand_width(), 1.0)
+                    .with_spacer(2.0)
+                    .with_flex_child(delete.expand_width(), 1.0),
+            )
+            .padding((0.0, 0.0, 0.0, 8.0))
+    })
+    .lens(
+        CrabReaderState::library
+            .then(LibrarySelectedBookLens)
+            .then(SelectedBookBookmarksLens),
+    );
+
+    bookmarks
+}
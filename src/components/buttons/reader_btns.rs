@@ -2,13 +2,13 @@ use crate::{
     models::{book::Book, command::Trigger},
     traits::{
         gui::{GUIBook, GUILibrary},
-        reader::BookReading,
+        reader::{BookManagement, BookReading},
     },
     utils::{
         button_functions::{
             edit_btn_fn, go_next, go_prev, page_number_switch_button, save_btn_fn, undo_btn_fn,
         },
-        fonts,
+        fonts, kosync, stats,
     },
     CrabReaderState,
 };
@@ -112,6 +112,10 @@ fn leave_btn() -> RoundedButton<CrabReaderState> {
     RoundedButton::from_text("Vai indietro")
         .with_on_click(|_, data: &mut CrabReaderState, _| {
             data.reading = false;
+            stats::end_session();
+            if let Some(book) = data.library.get_selected_book() {
+                kosync::push_position(&book.get_path(), book.get_position(), true);
+            }
         })
         .with_font(fonts::xlarge)
 }
@@ -1,45 +1,87 @@
-use druid::{widget::Label, Affine, Data, RenderContext, Size, Widget, WidgetPod};
+use druid::{
+    im::Vector,
+    widget::{Label, LineBreaking},
+    Data, Rect, RenderContext, Size, Widget, WidgetExt, WidgetPod,
+};
 
 use crate::{
-    models::book::Book,
-    traits::{gui::GUILibrary, reader::BookReading},
-    utils::{button_functions::change_chapter, colors},
+    models::{book::Book, toc::TocEntry},
+    traits::{
+        gui::GUILibrary,
+        reader::{BookManagement, BookReading},
+    },
+    utils::{button_functions::go_to_toc_entry, colors, fonts},
     Library,
 };
 
+const INDENT_PER_LEVEL: f64 = 15.0;
+
+/// Sidebar widget that lists the entries of the table of contents
+/// of the selected book and lets the user jump to them
 pub struct ChapterSelector {
     children: Vec<ChapterSelectorItem>,
+    entries: Vector<TocEntry>,
+    current: Option<usize>,
 }
 
 struct ChapterSelectorItem {
     idx: usize,
+    entry: TocEntry,
     inner: WidgetPod<Library<Book>, Box<dyn Widget<Library<Book>>>>,
-    pod_size: Size,
+    pod_rect: Rect,
     hot: bool,
 }
 
 impl ChapterSelector {
     pub fn new() -> Self {
-        Self { children: vec![] }
+        Self {
+            children: vec![],
+            entries: Vector::new(),
+            current: None,
+        }
     }
 
-    fn add_child(&mut self, idx: usize) {
-        let pod = ChapterSelectorItem::new(idx);
-        self.children.push(pod);
+    /// rebuilds the children if the toc of the selected book changed,
+    /// returns true if the children have been rebuilt
+    fn update_entries(&mut self, data: &Library<Book>) -> bool {
+        let Some(book) = data.get_selected_book() else {
+            return false;
+        };
+        let entries = book.get_toc_entries();
+        if entries.same(&self.entries) && self.children.len() == entries.len() {
+            return false;
+        }
+
+        self.entries = entries.clone();
+        self.children = entries
+            .iter()
+            .enumerate()
+            .map(|(idx, entry)| ChapterSelectorItem::new(idx, entry.clone()))
+            .collect();
+        true
     }
 }
 
 impl ChapterSelectorItem {
-    pub fn new(idx: usize) -> Self {
-        let label =
-            Label::dynamic(move |_: &Library<Book>, _env: &_| format!("Capitolo {}", idx + 1))
-                .with_text_color(colors::ON_PRIMARY);
+    pub fn new(idx: usize, entry: TocEntry) -> Self {
+        let left_padding = 5.0 + entry.get_depth() as f64 * INDENT_PER_LEVEL;
+        let font = if entry.get_depth() == 0 {
+            fonts::medium
+        } else {
+            fonts::small
+        };
+        let label = Label::new(entry.get_label().to_string())
+            .with_text_color(colors::ON_PRIMARY)
+            .with_font(font)
+            .with_line_break_mode(LineBreaking::WordWrap)
+            .padding((left_padding, 4.0, 5.0, 4.0));
         let boxed = Box::new(label);
 
         Self {
             idx,
+            entry,
             inner: WidgetPod::new(boxed),
-            pod_size: Size::ZERO,
+            pod_rect: Rect::ZERO,
             hot: false,
         }
     }
@@ -66,10 +108,8 @@ impl Widget<Library<Book>> for ChapterSelector {
         data: &Library<Book>,
         env: &druid::Env,
     ) {
-        let book = data.get_selected_book().unwrap();
-        while self.children.len() < book.get_number_of_chapters() {
-            let idx = self.children.len();
-            self.add_child(idx);
+        if let druid::LifeCycle::WidgetAdded = event {
+            self.update_entries(data);
         }
 
         for child in self.children.iter_mut() {
@@ -84,6 +124,11 @@ impl Widget<Library<Book>> for ChapterSelector {
         data: &Library<Book>,
         env: &druid::Env,
     ) {
+        if self.update_entries(data) {
+            ctx.children_changed();
+            return;
+        }
+
         if !data.same(old_data) || ctx.env_changed() {
             for child in self.children.iter_mut() {
                 child.update(ctx, old_data, data, env);
@@ -98,36 +143,58 @@ impl Widget<Library<Book>> for ChapterSelector {
         data: &Library<Book>,
         env: &druid::Env,
     ) -> druid::Size {
+        let w = if bc.is_width_bounded() {
+            bc.max().width
+        } else {
+            400.0
+        };
+        let child_bc = druid::BoxConstraints::new(Size::ZERO, Size::new(w, f64::INFINITY));
+
         let mut h = 0.0;
         for child in self.children.iter_mut() {
             let pos = druid::Point::new(0.0, h);
-            let pod_h = child.layout(ctx, bc, data, env).height;
-            let pod_w = if bc.is_width_bounded() {
-                bc.max().width
-            } else {
-                400.0
-            };
-            h += pod_h;
+            let pod_h = child.layout(ctx, &child_bc, data, env).height;
             child.inner.set_origin(ctx, data, env, pos);
-            child.pod_size = (pod_w, pod_h).into();
+            child.pod_rect = Rect::from_origin_size(pos, (w, pod_h));
+            h += pod_h;
         }
 
-        let w = if bc.is_width_bounded() {
-            bc.max().width
-        } else {
-            bc.min().width
-        };
-
         (w, h).into()
     }
 
     fn paint(&mut self, ctx: &mut druid::PaintCtx, data: &Library<Book>, env: &druid::Env) {
+        self.current = data
+            .get_selected_book()
+            .and_then(|book| book.get_current_toc_index());
+
         for child in self.children.iter_mut() {
-            child.paint(ctx, data, env);
+            let is_current = self.current == Some(child.idx);
+            child.paint_item(ctx, data, env, is_current);
         }
     }
 }
 
+impl ChapterSelectorItem {
+    fn paint_item(
+        &mut self,
+        ctx: &mut druid::PaintCtx,
+        data: &Library<Book>,
+        env: &druid::Env,
+        is_current: bool,
+    ) {
+        let color = if is_current {
+            env.get(colors::PRIMARY_VARIANT)
+        } else if self.hot {
+            env.get(colors::PRIMARY_ACCENT)
+        } else {
+            env.get(colors::PRIMARY)
+        };
+
+        ctx.fill(self.pod_rect, &color);
+        self.inner.paint(ctx, data, env);
+    }
+}
+
 impl Widget<Library<Book>> for ChapterSelectorItem {
     fn event(
         &mut self,
@@ -139,16 +206,11 @@ impl Widget<Library<Book>> for ChapterSelectorItem {
         self.inner.event(ctx, event, data, env);
         match event {
             druid::Event::MouseMove(mouse) => {
-                let pos = mouse.pos;
-                let y = pos.y;
-                let h0 = self.pod_size.height * self.idx as f64;
-                let h1 = h0 + self.pod_size.height;
-                self.hot = y >= h0 && y <= h1;
+                self.hot = self.pod_rect.contains(mouse.pos);
             }
-            druid::Event::MouseDown(_) => {
-                if self.hot {
-                    println!("Current index: {}", self.idx);
-                    change_chapter(data.get_selected_book_mut().unwrap(), self.idx);
+            druid::Event::MouseDown(mouse) => {
+                if self.pod_rect.contains(mouse.pos) {
+                    go_to_toc_entry(data.get_selected_book_mut().unwrap(), &self.entry);
                     ctx.request_paint();
                 }
             }
@@ -193,24 +255,6 @@ impl Widget<Library<Book>> for ChapterSelectorItem {
     }
 
     fn paint(&mut self, ctx: &mut druid::PaintCtx, data: &Library<Book>, env: &druid::Env) {
-        // todo: chiedre a Sam un metodo per avere il chapter attualmete in lettura
-        // ( è possibile solo tramite library )
-        let rect = self.pod_size.to_rect();
-        let h = rect.height();
-        let dh = self.idx as f64 * h;
-
-        let color = if self.idx == data.get_selected_book().unwrap().get_chapter_number() {
-            env.get(colors::PRIMARY_VARIANT)
-        } else if self.hot {
-            env.get(colors::PRIMARY_ACCENT)
-        } else {
-            env.get(colors::PRIMARY)
-        };
-
-        ctx.with_save(|ctx| {
-            ctx.transform(Affine::translate((0.0, dh)));
-            ctx.fill(rect, &color);
-        });
-        self.inner.paint(ctx, data, env);
+        self.paint_item(ctx, data, env, false);
     }
 }
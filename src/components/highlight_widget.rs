@@ -0,0 +1,89 @@
+use druid::{
+    im::Vector,
+    widget::{Container, Flex, Label, LineBreaking, List, Painter},
+    Lens, LensExt, RenderContext, Selector, Widget, WidgetExt,
+};
+
+use crate::{
+    models::{
+        highlight::{Highlight, TextRange},
+        library::LibrarySelectedBookLens,
+    },
+    traits::reader::{BookManagement, BookReading},
+    utils::{colors, fonts},
+    CrabReaderState, ROUND_FACTR,
+};
+
+use super::buttons::rbtn::RoundedButton;
+
+pub const DELETE_HIGHLIGHT: Selector<TextRange> = Selector::new("crabreader.delete_highlight");
+
+/// Lens to the highlights of the current chapter of the book, read only
+pub struct ChapterHighlightsLens;
+
+impl<B: BookReading + BookManagement> Lens<B, Vector<Highlight>> for ChapterHighlightsLens {
+    fn with<V, F: FnOnce(&Vector<Highlight>) -> V>(&self, data: &B, f: F) -> V {
+        f(&data.get_highlights().get_in_chapter(data.get_chapter_number()))
+    }
+
+    fn with_mut<V, F: FnOnce(&mut Vector<Highlight>) -> V>(&self, data: &mut B, f: F) -> V {
+        f(&mut data.get_highlights().get_in_chapter(data.get_chapter_number()))
+    }
+}
+
+pub fn get_highlights_list() -> impl Widget<CrabReaderState> {
+    List::new(|| {
+        // a stripe of the colour of the highlight
+        let stripe = Painter::new(|ctx, highlight: &Highlight, _env| {
+            let rect = ctx.size().to_rect();
+            ctx.fill(rect, &highlight.get_color().to_color().with_alpha(1.0));
+        })
+        .fix_width(4.0);
+
+        let text = Label::new(|highlight: &Highlight, _env: &_| {
+            let text = highlight.get_text();
+            match text.char_indices().nth(80) {
+                Some((end, _)) => format!("«{}...»", &text[..end]),
+                None => format!("«{}»", text),
+            }
+        })
+        .with_font(fonts::italic::small)
+        .with_text_color(colors::ON_SECONDARY)
+        .with_line_break_mode(LineBreaking::WordWrap)
+        .padding(2.0);
+
+        let note = Label::new(|highlight: &Highlight, _env: &_| highlight.get_note().to_string())
+            .with_text_color(colors::ON_SECONDARY)
+            .with_line_break_mode(LineBreaking::WordWrap)
+            .padding(2.0);
+
+        let delete = RoundedButton::from_text("Elimina")
+            .with_on_click(|ctx, highlight: &mut Highlight, _| {
+                ctx.submit_command(DELETE_HIGHLIGHT.with(highlight.get_range()));
+            })
+            .with_font(fonts::xsmall);
+
+        let content = Flex::column()
+            .cross_axis_alignment(druid::widget::CrossAxisAlignment::Start)
+            .with_child(text)
+            .with_child(note)
+            .with_default_spacer()
+            .with_child(delete);
+
+        Container::new(
+            Flex::row()
+                .cross_axis_alignment(druid::widget::CrossAxisAlignment::Fill)
+                .with_child(stripe)
+                .with_flex_child(content, 1.0),
+        )
+        .expand_width()
+        .background(colors::SECONDARY)
+        .rounded(ROUND_FACTR)
+        .padding((0.0, 0.0, 0.0, 4.0))
+    })
+    .lens(
+        CrabReaderState::library
+            .then(LibrarySelectedBookLens)
+            .then(ChapterHighlightsLens),
+    )
+}
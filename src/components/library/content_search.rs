@@ -0,0 +1,117 @@
+use druid::{
+    widget::{Container, Either, Flex, Label, LineBreaking, List, TextBox},
+    Env, LensExt, Selector, Widget, WidgetExt,
+};
+
+use crate::{
+    components::buttons::rbtn::RoundedButton,
+    models::{
+        book::Book,
+        library::{Library, LibraryContentSearchLens},
+        search::{LibraryHit, LibrarySearch},
+    },
+    utils::{colors, fonts},
+    ROUND_FACTR,
+};
+
+/// open the book of the result of the library search with the given index, at the hit
+pub const OPEN_LIBRARY_HIT: Selector<usize> = Selector::new("crabreader.open_library_hit");
+
+fn get_library_hits_list() -> impl Widget<LibrarySearch> {
+    List::new(|| {
+        let header = Label::new(|hit: &LibraryHit, _env: &_| {
+            format!("{}, capitolo {}", hit.get_title(), hit.get_position().get_chapter() + 1)
+        })
+        .with_font(fonts::bold::small)
+        .with_text_color(colors::ON_SECONDARY)
+        .with_line_break_mode(LineBreaking::WordWrap)
+        .padding(2.0);
+
+        let context = Label::new(|hit: &LibraryHit, _env: &_| hit.get_context().to_string())
+            .with_font(fonts::small)
+            .with_text_color(colors::ON_SECONDARY)
+            .with_line_break_mode(LineBreaking::WordWrap)
+            .padding(2.0);
+
+        let open = RoundedButton::from_text("Apri")
+            .with_on_click(|ctx, hit: &mut LibraryHit, _| {
+                ctx.submit_command(OPEN_LIBRARY_HIT.with(hit.get_index()));
+            })
+            .with_font(fonts::xsmall);
+
+        Container::new(
+            Flex::column()
+                .cross_axis_alignment(druid::widget::CrossAxisAlignment::Start)
+                .with_child(header)
+                .with_child(context)
+                .with_child(open),
+        )
+        .expand_width()
+        .background(colors::SECONDARY)
+        .rounded(ROUND_FACTR)
+        .padding((0.0, 0.0, 0.0, 4.0))
+    })
+}
+
+/// Search box for the text of all the books, with the ranked results
+pub fn content_search_panel() -> impl Widget<Library<Book>> {
+    let text_edit = TextBox::new()
+        .with_font(fonts::large)
+        .with_placeholder("Parole nel testo dei libri...")
+        .with_text_color(colors::ON_BACKGROUND)
+        .lens(LibraryContentSearchLens.then(LibrarySearch::query));
+
+    let label = Label::new("Cerca nei libri")
+        .with_text_color(colors::ON_BACKGROUND)
+        .with_font(fonts::medium)
+        .center()
+        .expand_width();
+
+    let search_btn = RoundedButton::from_text("Cerca")
+        .with_on_click(|ctx, data: &mut Library<Book>, _: &Env| {
+            data.search_contents();
+            ctx.request_layout();
+        })
+        .with_font(fonts::medium)
+        .padding(5.0);
+
+    let clear_btn = RoundedButton::from_text("X")
+        .with_on_click(|ctx, data: &mut Library<Book>, _: &Env| {
+            data.clear_content_search();
+            ctx.request_layout();
+        })
+        .secondary()
+        .with_font(fonts::small)
+        .padding(5.0);
+
+    let search_row = Flex::row()
+        .with_flex_child(label, 1.0)
+        .with_flex_child(text_edit.expand_width(), 3.0)
+        .with_flex_child(search_btn, 0.5)
+        .with_flex_child(clear_btn, 0.5);
+
+    let no_results = Label::dynamic(|search: &LibrarySearch, _: &Env| {
+        if search.get_searched().is_empty() {
+            String::new()
+        } else {
+            format!("Nessun risultato per \"{}\"", search.get_searched())
+        }
+    })
+    .with_text_color(colors::ON_BACKGROUND)
+    .with_font(fonts::small);
+
+    let results = Either::new(
+        |search: &LibrarySearch, _: &Env| search.get_hits().is_empty(),
+        no_results,
+        get_library_hits_list(),
+    )
+    .lens(LibraryContentSearchLens);
+
+    Flex::column()
+        .with_child(search_row)
+        .with_child(results)
+        .padding(druid::Insets::uniform_xy(15.0, 10.0))
+        .background(colors::BACKGROUND_VARIANT)
+        .rounded(ROUND_FACTR)
+        .expand_width()
+}
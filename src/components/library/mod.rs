@@ -1,2 +1,3 @@
+pub mod content_search;
 pub mod cover_library;
 pub mod listing_library;
\ No newline at end of file
@@ -1,6 +1,12 @@
 pub mod book;
+pub mod bookmark_widget;
 pub mod buttons;
 pub mod chapter_selector;
+pub mod highlight_widget;
 pub mod library;
 pub mod note_widget;
+pub mod opds_browser;
+pub mod page_label;
+pub mod reading_stats;
+pub mod search_widget;
 pub mod views;
@@ -0,0 +1,141 @@
+use std::ops::Range;
+
+use druid::{
+    piet::TextStorage as PietTextStorage, text::TextLayout, theme, widget::prelude::*, Color,
+    Cursor, FontDescriptor, KeyOrValue, Point, Selector, TextAlignment, Vec2,
+};
+
+use crate::{models::rich::rich_text::RichText, utils::paginator::LABEL_X_PADDING};
+
+/// Sent when the user selects some text of a page: the page (0 in single view,
+/// 0 or 1 in dual view) and the selected range of the source text of the page.
+/// An empty range means that the selection has been removed
+pub const TEXT_SELECTED: Selector<(usize, Range<usize>)> = Selector::new("crabreader.text_selected");
+
+/// A label that shows a page of a book, paints its highlights
+/// and lets the user select the text with the mouse
+pub struct PageLabel {
+    layout: TextLayout<RichText>,
+    /// which page of the view the label shows
+    side: usize,
+    /// anchor and active end of the selection, in the rendered text
+    selection: Option<(usize, usize)>,
+}
+
+impl PageLabel {
+    pub fn new(side: usize) -> Self {
+        Self {
+            layout: TextLayout::new(),
+            side,
+            selection: None,
+        }
+    }
+
+    pub fn with_font(mut self, font: impl Into<KeyOrValue<FontDescriptor>>) -> Self {
+        self.layout.set_font(font);
+        self
+    }
+
+    pub fn with_text_color(mut self, color: impl Into<KeyOrValue<Color>>) -> Self {
+        self.layout.set_text_color(color);
+        self
+    }
+
+    pub fn with_text_alignment(mut self, alignment: TextAlignment) -> Self {
+        self.layout.set_text_alignment(alignment);
+        self
+    }
+
+    fn selected_range(&self) -> Option<Range<usize>> {
+        let (anchor, active) = self.selection?;
+        Some(anchor.min(active)..anchor.max(active))
+    }
+
+    fn text_position(&self, point: Point) -> usize {
+        self.layout
+            .text_position_for_point(point - Vec2::new(LABEL_X_PADDING, 0.0))
+    }
+}
+
+impl Widget<RichText> for PageLabel {
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut RichText, _env: &Env) {
+        match event {
+            Event::MouseDown(mouse) if mouse.button.is_left() => {
+                let pos = self.text_position(mouse.pos);
+                self.selection = Some((pos, pos));
+                ctx.set_active(true);
+                ctx.request_paint();
+            }
+            Event::MouseMove(mouse) => {
+                ctx.set_cursor(&Cursor::IBeam);
+                if ctx.is_active() {
+                    if let Some((anchor, _)) = self.selection {
+                        self.selection = Some((anchor, self.text_position(mouse.pos)));
+                        ctx.request_paint();
+                    }
+                }
+            }
+            Event::MouseUp(mouse) if mouse.button.is_left() && ctx.is_active() => {
+                ctx.set_active(false);
+                let range = self.selected_range().unwrap_or_default();
+                if range.is_empty() {
+                    self.selection = None;
+                }
+                // the book knows only the source text of the page
+                let source = data.text_to_source(range.start)..data.text_to_source(range.end);
+                ctx.submit_command(TEXT_SELECTED.with((self.side, source)));
+                ctx.request_paint();
+            }
+            _ => {}
+        }
+    }
+
+    fn lifecycle(&mut self, _ctx: &mut LifeCycleCtx, event: &LifeCycle, data: &RichText, _env: &Env) {
+        if let LifeCycle::WidgetAdded = event {
+            self.layout.set_text(data.clone());
+        }
+    }
+
+    fn update(&mut self, ctx: &mut UpdateCtx, old_data: &RichText, data: &RichText, _env: &Env) {
+        if !old_data.same(data) {
+            // the page is rendered again at every change of the book,
+            // the selection is kept while the page shows the same text
+            if old_data.as_str() != data.as_str() {
+                self.selection = None;
+            }
+            self.layout.set_text(data.clone());
+            ctx.request_layout();
+        }
+        if self.layout.needs_rebuild_after_update(ctx) {
+            ctx.request_layout();
+        }
+    }
+
+    fn layout(&mut self, ctx: &mut LayoutCtx, bc: &BoxConstraints, _data: &RichText, env: &Env) -> Size {
+        let width = bc.max().width - LABEL_X_PADDING * 2.0;
+        self.layout.set_wrap_width(width.max(0.0));
+        self.layout.rebuild_if_needed(ctx.text(), env);
+
+        let text_size = self.layout.size();
+        bc.constrain(Size::new(text_size.width + LABEL_X_PADDING * 2.0, text_size.height))
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &RichText, env: &Env) {
+        let origin = Point::new(LABEL_X_PADDING, 0.0);
+
+        for (range, color) in data.highlights(env) {
+            for rect in self.layout.rects_for_range(range) {
+                ctx.fill(rect + origin.to_vec2(), &color);
+            }
+        }
+
+        if let Some(range) = self.selected_range() {
+            let color = env.get(theme::SELECTED_TEXT_BACKGROUND_COLOR);
+            for rect in self.layout.rects_for_range(range) {
+                ctx.fill(rect + origin.to_vec2(), &color);
+            }
+        }
+
+        self.layout.draw(ctx, origin);
+    }
+}
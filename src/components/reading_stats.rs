@@ -0,0 +1,66 @@
+use druid::{
+    widget::{CrossAxisAlignment, Flex, Label, LineBreaking, Scroll},
+    Data, Widget, WidgetExt,
+};
+
+use crate::utils::{
+    colors, fonts,
+    stats::{format_day, format_duration, ReadingSummary},
+};
+
+/// days shown in the pages read per day
+const DAYS_SHOWN: usize = 14;
+
+/// View of the statistics of the reading sessions
+pub fn reading_stats<T: Data>(summary: &ReadingSummary) -> impl Widget<T> {
+    let mut column = Flex::column().cross_axis_alignment(CrossAxisAlignment::Start);
+
+    if summary.total_pages == 0 {
+        column.add_child(text("Nessuna sessione di lettura registrata", fonts::medium));
+        return Scroll::new(column.padding(10.0)).vertical();
+    }
+
+    let speed = summary
+        .pages_per_hour()
+        .map_or("-".to_string(), |speed| format!("{:.0} pagine all'ora", speed));
+    column.add_child(text("Riepilogo", fonts::bold::large));
+    column.add_child(text(
+        format!(
+            "Tempo di lettura: {}\nPagine lette: {}\nCapitoli finiti: {}\nVelocità media: {}",
+            format_duration(summary.total_time),
+            summary.total_pages,
+            summary.total_chapters,
+            speed
+        ),
+        fonts::medium,
+    ));
+    column.add_child(text(
+        format!(
+            "Giorni di fila: {} (record: {})",
+            summary.current_streak, summary.longest_streak
+        ),
+        fonts::medium,
+    ));
+
+    column.add_default_spacer();
+    column.add_child(text("Tempo per libro", fonts::bold::large));
+    for (title, time) in summary.time_per_book.iter() {
+        column.add_child(text(format!("{}: {}", title, format_duration(*time)), fonts::small));
+    }
+
+    column.add_default_spacer();
+    column.add_child(text("Pagine al giorno", fonts::bold::large));
+    for (day, pages) in summary.pages_per_day.iter().take(DAYS_SHOWN) {
+        column.add_child(text(format!("{}: {}", format_day(*day), pages), fonts::small));
+    }
+
+    Scroll::new(column.padding(10.0)).vertical()
+}
+
+fn text<T: Data>(text: impl Into<String>, font: druid::FontDescriptor) -> impl Widget<T> {
+    Label::new(text.into())
+        .with_font(font)
+        .with_text_color(colors::ON_BACKGROUND)
+        .with_line_break_mode(LineBreaking::WordWrap)
+        .padding(2.0)
+}
@@ -0,0 +1,70 @@
+use druid::{
+    im::Vector,
+    widget::{Container, Flex, Label, LineBreaking, List},
+    Lens, LensExt, Selector, Widget, WidgetExt,
+};
+
+use crate::{
+    models::{library::LibrarySelectedBookLens, search::SearchHit},
+    traits::reader::BookManagement,
+    utils::{colors, fonts},
+    CrabReaderState, ROUND_FACTR,
+};
+
+use super::buttons::rbtn::RoundedButton;
+
+/// move the reading position to the hit of the search with the given index
+pub const GO_TO_SEARCH_HIT: Selector<usize> = Selector::new("crabreader.go_to_search_hit");
+
+/// Lens to the hits of the last search in the book, read only
+pub struct SearchHitsLens;
+
+impl<B: BookManagement> Lens<B, Vector<SearchHit>> for SearchHitsLens {
+    fn with<V, F: FnOnce(&Vector<SearchHit>) -> V>(&self, data: &B, f: F) -> V {
+        f(data.get_search().get_hits())
+    }
+
+    fn with_mut<V, F: FnOnce(&mut Vector<SearchHit>) -> V>(&self, data: &mut B, f: F) -> V {
+        f(&mut data.get_search().get_hits().clone())
+    }
+}
+
+pub fn get_search_hits_list() -> impl Widget<CrabReaderState> {
+    List::new(|| {
+        let header = Label::new(|hit: &SearchHit, _env: &_| {
+            format!("Capitolo {}", hit.get_chapter() + 1)
+        })
+        .with_font(fonts::bold::xsmall)
+        .with_text_color(colors::ON_SECONDARY)
+        .padding(2.0);
+
+        let context = Label::new(|hit: &SearchHit, _env: &_| hit.get_context().to_string())
+            .with_font(fonts::small)
+            .with_text_color(colors::ON_SECONDARY)
+            .with_line_break_mode(LineBreaking::WordWrap)
+            .padding(2.0);
+
+        let go = RoundedButton::from_text("Vai")
+            .with_on_click(|ctx, hit: &mut SearchHit, _| {
+                ctx.submit_command(GO_TO_SEARCH_HIT.with(hit.get_index()));
+            })
+            .with_font(fonts::xsmall);
+
+        Container::new(
+            Flex::column()
+                .cross_axis_alignment(druid::widget::CrossAxisAlignment::Start)
+                .with_child(header)
+                .with_child(context)
+                .with_child(go),
+        )
+        .expand_width()
+        .background(colors::SECONDARY)
+        .rounded(ROUND_FACTR)
+        .padding((0.0, 0.0, 0.0, 4.0))
+    })
+    .lens(
+        CrabReaderState::library
+            .then(LibrarySelectedBookLens)
+            .then(SearchHitsLens),
+    )
+}
@@ -1,13 +1,17 @@
+use std::time::Duration;
+
 use druid::{
-    widget::{Container, Flex, Label, LineBreaking, RawLabel, Scroll, TextBox, ViewSwitcher},
-    Data, Env, FontDescriptor, LensExt, TextAlignment, Widget, WidgetExt, Key, KeyOrValue,
+    widget::{Container, Controller, Flex, Label, Scroll, TextBox, ViewSwitcher},
+    Data, Env, Event, EventCtx, FontDescriptor, LensExt, LifeCycle, LifeCycleCtx, Size,
+    TextAlignment, TimerToken, UpdateCtx, Widget, WidgetExt, Key, KeyOrValue,
 };
 
 use crate::{
+    components::page_label::PageLabel,
     models::library::LibrarySelectedBookLens,
     models::rich::custom_lens::{DualPage0Lens, DualPage1Lens, SelectedPageLens},
-    traits::{gui::GUILibrary, reader::BookReading},
-    utils::{colors, fonts::{self, FONT}},
+    traits::{gui::GUILibrary, reader::{BookManagement, BookReading}},
+    utils::{colors, fonts::{self, FONT}, paginator, stats::format_duration},
     CrabReaderState, ReadingState, MYENV,
 };
 
@@ -48,16 +52,98 @@ impl ReaderView {
             .background(colors::BACKGROUND)
             .center()
             .expand()
+            .controller(PaginationController::new())
+    }
+}
+
+/// time to wait after the last resize before paginating again
+const REPAGINATE_DELAY: Duration = Duration::from_millis(300);
+
+/// Controller that keeps the pages of the selected book in sync with
+/// the size of the reader view, the font and the view mode (single / dual)
+struct PaginationController {
+    timer: TimerToken,
+    size: Size,
+    /// the font or the view mode changed, the pages change even if their size doesn't
+    layout_changed: bool,
+}
+
+impl PaginationController {
+    fn new() -> Self {
+        Self {
+            timer: TimerToken::INVALID,
+            size: Size::ZERO,
+            layout_changed: false,
+        }
+    }
+}
+
+impl<W: Widget<CrabReaderState>> Controller<CrabReaderState, W> for PaginationController {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut CrabReaderState,
+        env: &Env,
+    ) {
+        match event {
+            Event::Timer(token) if *token == self.timer => {
+                self.timer = TimerToken::INVALID;
+                let resized = paginator::set_view_size(self.size, data.reading_state.single_view);
+                if resized || self.layout_changed {
+                    self.layout_changed = false;
+                    if let Some(book) = data.library.get_selected_book_mut() {
+                        book.repaginate(ctx.get_external_handle());
+                    }
+                }
+                ctx.request_layout();
+                ctx.set_handled();
+            }
+            _ => child.event(ctx, event, data, env),
+        }
+    }
+
+    fn lifecycle(
+        &mut self,
+        child: &mut W,
+        ctx: &mut LifeCycleCtx,
+        event: &LifeCycle,
+        data: &CrabReaderState,
+        env: &Env,
+    ) {
+        if let LifeCycle::Size(size) = event {
+            self.size = *size;
+            // wait for the resize to end, paginating is expensive
+            self.timer = ctx.request_timer(REPAGINATE_DELAY);
+        }
+        child.lifecycle(ctx, event, data, env)
+    }
+
+    fn update(
+        &mut self,
+        child: &mut W,
+        ctx: &mut UpdateCtx,
+        old_data: &CrabReaderState,
+        data: &CrabReaderState,
+        env: &Env,
+    ) {
+        if !old_data.font.same(&data.font)
+            || old_data.reading_state.single_view != data.reading_state.single_view
+        {
+            self.layout_changed = true;
+            self.timer = ctx.request_timer(REPAGINATE_DELAY);
+        }
+        child.update(ctx, old_data, data, env)
     }
 }
 
 // single page view for text reader
 fn single_view_widget(font: KeyOrValue<FontDescriptor>) -> Container<CrabReaderState> {
-    let raw_label = RawLabel::new()
+    let raw_label = PageLabel::new(0)
         .with_text_color(colors::ON_BACKGROUND)
         .with_font(font)
         .with_text_alignment(TextAlignment::Justified)
-        .with_line_break_mode(LineBreaking::WordWrap)
         .lens(
             CrabReaderState::library
                 .then(LibrarySelectedBookLens)
@@ -84,11 +170,10 @@ fn single_view_edit_widget(font: KeyOrValue<FontDescriptor>) -> Container<CrabRe
 
 // dual page view for text reader
 fn dual_view_widget(font: KeyOrValue<FontDescriptor>) -> Container<CrabReaderState> {
-    let page_0 = RawLabel::new()
+    let page_0 = PageLabel::new(0)
         .with_text_color(colors::ON_BACKGROUND)
         .with_font(font.clone())
         .with_text_alignment(TextAlignment::Justified)
-        .with_line_break_mode(LineBreaking::WordWrap)
         .lens(
             CrabReaderState::library
                 .then(LibrarySelectedBookLens)
@@ -96,11 +181,10 @@ fn dual_view_widget(font: KeyOrValue<FontDescriptor>) -> Container<CrabReaderSta
         )
         .expand_width();
 
-    let page_1 = RawLabel::new()
+    let page_1 = PageLabel::new(1)
         .with_text_color(colors::ON_BACKGROUND)
         .with_font(font)
         .with_text_alignment(TextAlignment::Justified)
-        .with_line_break_mode(LineBreaking::WordWrap)
         .lens(
             CrabReaderState::library
                 .then(LibrarySelectedBookLens)
@@ -139,15 +223,32 @@ fn dual_view_edit_widget(font: KeyOrValue<FontDescriptor>) -> Container<CrabRead
 
 pub fn current_chapter_widget() -> Label<CrabReaderState> {
     Label::dynamic(|data: &CrabReaderState, _env: &_| {
-        // + 1
-        let display_number = data
-            .library
-            .get_selected_book()
-            .unwrap()
-            .get_chapter_number()
-            + 1;
+        let book = data.library.get_selected_book().unwrap();
 
+        // title of the current toc entry, chapter number as fallback
+        if let Some(idx) = book.get_current_toc_index() {
+            if let Some(entry) = book.get_toc_entries().get(idx) {
+                return entry.get_label().to_string();
+            }
+        }
+
+        // + 1
+        let display_number = book.get_chapter_number() + 1;
         format!("Chapter {}", display_number)
     })
     .with_text_color(colors::ON_BACKGROUND)
 }
+
+pub fn time_left_widget() -> Label<CrabReaderState> {
+    Label::dynamic(|data: &CrabReaderState, _env: &_| {
+        let Some(book) = data.library.get_selected_book() else {
+            return String::new();
+        };
+        format!(
+            "{} alla fine del capitolo, {} alla fine del libro",
+            format_duration(book.get_time_left_in_chapter()),
+            format_duration(book.get_time_left_in_book())
+        )
+    })
+    .with_text_color(colors::ON_BACKGROUND)
+}
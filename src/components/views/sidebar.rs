@@ -1,20 +1,28 @@
 use druid::{
-    widget::{Either, Flex, Scroll, TextBox},
+    widget::{Either, Flex, Label, Scroll, TextBox},
     LensExt, UnitPoint, Widget, WidgetExt,
 };
 
 use crate::{
     components::{
+        bookmark_widget::get_bookmarks_list,
         buttons::{rbtn::RoundedButton, reader_btns::ReaderBtn},
         chapter_selector::ChapterSelector,
+        highlight_widget::get_highlights_list,
+        search_widget::get_search_hits_list,
         note_widget::get_notes_list,
     },
     traits::{
+        bookmark::BookmarkManagement,
         gui::GUILibrary,
         note::NoteManagement,
         reader::{BookManagement, BookReading},
     },
-    utils::{colors, fonts},
+    models::highlight::HighlightColor,
+    utils::{
+        button_functions::{go_to_next_search_hit, highlight_selection, search_in_book},
+        colors, fonts,
+    },
     CrabReaderState, ReadingState,
 };
 
@@ -66,6 +74,118 @@ fn left_sidebar_widget() -> Flex<CrabReaderState> {
         .with_child(btn)
         .with_default_spacer()
         .with_flex_child(sidebar, 1.0)
+        .with_default_spacer()
+        .with_flex_child(search_widget(), 1.0)
+        .with_default_spacer()
+        .with_flex_child(bookmarks_widget(), 1.0)
+}
+
+fn search_widget() -> Flex<CrabReaderState> {
+    let tb = TextBox::new()
+        .with_placeholder("Cerca nel libro...")
+        .with_text_color(colors::ON_BACKGROUND)
+        .lens(CrabReaderState::reading_state.then(ReadingState::search_query))
+        .expand_width();
+
+    let search_btn = RoundedButton::from_text("Cerca")
+        .disabled_if(|data: &CrabReaderState, _env: &_| {
+            data.reading_state.search_query.trim().is_empty()
+        })
+        .with_on_click(|_, data: &mut CrabReaderState, _| {
+            search_in_book(data);
+        });
+
+    let no_hits = |data: &CrabReaderState, _env: &_| {
+        data.library.get_selected_book().unwrap().get_search().is_empty()
+    };
+
+    let prev_btn = RoundedButton::from_text("<")
+        .disabled_if(no_hits)
+        .with_on_click(|_, data: &mut CrabReaderState, _| {
+            go_to_next_search_hit(data.library.get_selected_book_mut().unwrap(), false);
+        });
+
+    let next_btn = RoundedButton::from_text(">")
+        .disabled_if(no_hits)
+        .with_on_click(|_, data: &mut CrabReaderState, _| {
+            go_to_next_search_hit(data.library.get_selected_book_mut().unwrap(), true);
+        });
+
+    // "hit x of n", or the result of the last search
+    let counter = Label::new(|data: &CrabReaderState, _env: &_| {
+        let search = data.library.get_selected_book().unwrap().get_search();
+        match search.get_current_index() {
+            _ if search.get_query().is_empty() => String::new(),
+            _ if search.is_empty() => "Nessun risultato".to_string(),
+            Some(index) => format!("{} di {}", index + 1, search.len()),
+            None => format!("{} risultati", search.len()),
+        }
+    })
+    .with_font(fonts::small)
+    .with_text_color(colors::ON_BACKGROUND);
+
+    let navigation = Flex::row()
+        .with_child(prev_btn)
+        .with_default_spacer()
+        .with_flex_child(counter.center(), 1.0)
+        .with_default_spacer()
+        .with_child(next_btn);
+
+    // hits of the last search
+    let hits = Scroll::new(get_search_hits_list()).vertical().expand();
+
+    Flex::column()
+        .with_child(tb)
+        .with_default_spacer()
+        .with_child(search_btn)
+        .with_default_spacer()
+        .with_child(navigation)
+        .with_default_spacer()
+        .with_flex_child(hits, 1.0)
+}
+
+fn bookmarks_widget() -> Flex<CrabReaderState> {
+    let title = Label::new("Segnalibri")
+        .with_font(fonts::medium)
+        .with_text_color(colors::ON_BACKGROUND)
+        .align_left();
+
+    // list of bookmarks
+    let bookmarks = Scroll::new(get_bookmarks_list()).vertical().expand();
+
+    // name of the bookmark to add or rename
+    let tb = TextBox::new()
+        .with_placeholder("Nome del segnalibro")
+        .with_text_color(colors::ON_BACKGROUND)
+        .lens(CrabReaderState::reading_state.then(ReadingState::bookmark_name))
+        .expand_width();
+
+    let add_bookmark = RoundedButton::from_text("Aggiungi segnalibro")
+        .disabled_if(|data: &CrabReaderState, _env: &_| {
+            let book = data.library.get_selected_book().unwrap();
+            book.get_bookmarks()
+                .get_bookmark(&book.get_position())
+                .is_some()
+        })
+        .with_on_click(|_, data: &mut CrabReaderState, _| {
+            let book = data.library.get_selected_book().unwrap().clone();
+            let name = data.reading_state.bookmark_name.clone();
+            data.reading_state.bookmark_name = "".into();
+            data.library
+                .get_selected_book_mut()
+                .unwrap()
+                .get_bookmarks_mut()
+                .add_bookmark(&book, name);
+        });
+
+    Flex::column()
+        .with_child(title)
+        .with_default_spacer()
+        .with_child(tb)
+        .with_default_spacer()
+        .with_child(add_bookmark)
+        .with_default_spacer()
+        .with_flex_child(bookmarks, 1.0)
 }
 
 fn right_sidebar_widget() -> Flex<CrabReaderState> {
@@ -76,6 +196,8 @@ fn right_sidebar_widget() -> Flex<CrabReaderState> {
     // list of notes
     let notes = Scroll::new(get_notes_list()).vertical().expand();
 
+    let highlights = highlights_widget();
+
     let tb = TextBox::multiline()
         .with_placeholder("Scrivi...")
         .with_text_color(colors::ON_BACKGROUND)
@@ -105,13 +227,12 @@ fn right_sidebar_widget() -> Flex<CrabReaderState> {
             let book = data.library.get_selected_book().unwrap();
             let book_path = book.get_path().clone();
             let chapter = book.get_chapter_number();
-            let page = book.get_current_page_number();
 
             data.library
                 .get_selected_book_mut()
                 .unwrap()
                 .get_notes_mut()
-                .delete_notes(book_path, chapter, page);
+                .delete_notes(book_path, chapter);
         });
 
     Flex::column()
@@ -120,6 +241,8 @@ fn right_sidebar_widget() -> Flex<CrabReaderState> {
         .with_default_spacer()
         .with_child(ocr_inverse_btn)
         .with_default_spacer()
+        .with_flex_child(highlights, 2.0)
+        .with_default_spacer()
         .with_flex_child(notes, 2.0)
         .with_flex_spacer(1.0)
         .with_child(tb)
@@ -128,3 +251,40 @@ fn right_sidebar_widget() -> Flex<CrabReaderState> {
         .with_default_spacer()
         .with_child(del_notes)
 }
+
+fn highlights_widget() -> Flex<CrabReaderState> {
+    let title = Label::new(|data: &ReadingState, _env: &_| {
+        if data.selection.is_some() {
+            "Evidenzia la selezione".to_string()
+        } else {
+            "Seleziona il testo da evidenziare".to_string()
+        }
+    })
+    .with_font(fonts::small)
+    .with_text_color(colors::ON_BACKGROUND)
+    .align_left()
+    .lens(CrabReaderState::reading_state);
+
+    // one button for every colour
+    let mut colors_row = Flex::row();
+    for color in HighlightColor::ALL {
+        let btn = RoundedButton::from_text(color.get_name())
+            .disabled_if(|data: &CrabReaderState, _env: &_| data.reading_state.selection.is_none())
+            .with_on_click(move |_, data: &mut CrabReaderState, _| {
+                highlight_selection(data, color);
+            })
+            .with_font(fonts::xsmall);
+        colors_row.add_flex_child(btn.expand_width(), 1.0);
+        colors_row.add_spacer(2.0);
+    }
+
+    // highlights of the current chapter
+    let highlights = Scroll::new(get_highlights_list()).vertical().expand();
+
+    Flex::column()
+        .with_child(title)
+        .with_default_spacer()
+        .with_child(colors_row)
+        .with_default_spacer()
+        .with_flex_child(highlights, 1.0)
+}
@@ -2,17 +2,20 @@ use crate::models::book::Book;
 use crate::utils::colors;
 use components::book::book_details::BookDetails;
 use components::buttons::{rbtn::RoundedButton, reader_btns::ReaderBtn};
+use components::library::content_search::content_search_panel;
 use components::library::cover_library::CoverLibrary;
 use components::library::listing_library::ListLibrary;
 use druid::commands::SHOW_OPEN_PANEL;
 use models::command::Trigger;
-use models::library::{Library, LibraryFilterLens, SortBy};
+use models::highlight::TextRange;
+use models::library::{Library, LibraryFilterLens, LibraryTagFilterLens, SortBy};
+use models::opds::OpdsState;
 
-use components::views::reader_view::{current_chapter_widget, ReaderView};
+use components::views::reader_view::{current_chapter_widget, time_left_widget, ReaderView};
 use components::views::sidebar::Sidebar;
 use druid::widget::{Either, Flex, Label, Scroll, SizedBox, ViewSwitcher};
 use druid::{
-    AppLauncher, Command, Data, Env, FileDialogOptions, FileSpec, FontDescriptor, Lens,
+    im::Vector, AppLauncher, Command, Data, Env, FileDialogOptions, FileSpec, FontDescriptor, Lens,
     PlatformError, Selector, Target, UnitPoint, Widget, WidgetExt, WindowDesc,
 };
 
@@ -23,8 +26,9 @@ use traits::gui::{GUIBook, GUILibrary};
 use utils::colors::{update_theme, CrabTheme};
 use utils::envmanager::MyEnv;
 use utils::fonts::{update_font_family, FONT};
-use utils::{ctx_menu, delegates, fonts};
+use utils::{ctx_menu, delegates, fonts, stats, storage, watcher};
 
+mod cli;
 mod components;
 mod models;
 mod traits;
@@ -50,6 +54,11 @@ pub struct ReadingState {
     text_1: String,
     notes: String,
     is_editing_notes: bool,
+    bookmark_name: String,
+    /// text selected in the reader, to highlight
+    selection: Option<TextRange>,
+    selected_text: String,
+    search_query: String,
 }
 
 impl ReadingState {
@@ -69,6 +78,14 @@ impl ReadingState {
         self.text_0 = String::default();
         self.text_1 = String::default();
         self.notes = String::default();
+        self.bookmark_name = String::default();
+        self.clear_selection();
+        self.search_query = String::default();
+    }
+
+    fn clear_selection(&mut self) {
+        self.selection = None;
+        self.selected_text = String::default();
     }
 }
 
@@ -83,6 +100,10 @@ impl Default for ReadingState {
             text_0: String::default(),
             text_1: String::default(),
             notes: String::default(),
+            bookmark_name: String::default(),
+            selection: None,
+            selected_text: String::default(),
+            search_query: String::default(),
         }
     }
 }
@@ -104,6 +125,8 @@ pub struct CrabReaderState {
     pub theme: CrabTheme,
     pub paint_shadows: bool,
     pub font: FontDescriptor,
+    pub watched_folders: Vector<String>,
+    opds: OpdsState,
 }
 
 impl Default for CrabReaderState {
@@ -122,6 +145,8 @@ impl Default for CrabReaderState {
             theme: CrabTheme::from(theme),
             paint_shadows: shadows,
             font: font,
+            watched_folders: watcher::folders().into(),
+            opds: OpdsState::new(),
         }
     }
 }
@@ -185,6 +210,48 @@ fn author_sorter_btn() -> impl Widget<Library<Book>> {
     .padding(5.0)
 }
 
+/// Button that sorts the books by `by`, or by `rev` if they already are
+fn sorter_btn(name: &'static str, by: SortBy, rev: SortBy) -> impl Widget<Library<Book>> {
+    let (by_toggle, rev_toggle) = (by.clone(), rev.clone());
+    let (by_label, rev_label) = (by.clone(), rev.clone());
+    RoundedButton::dynamic(move |data: &Library<Book>, _env: &Env| {
+        let sort = data.get_sort_order();
+        let arrow = if sort == by_label {
+            DOWN_ARROW
+        } else if sort == rev_label {
+            UP_ARROW
+        } else {
+            ""
+        };
+        format!("{}{}", name, arrow)
+    })
+    .with_font(fonts::medium)
+    .with_on_click(move |ctx, data: &mut Library<Book>, _| {
+        if data.get_sort_order() == by {
+            data.sort_by(rev.clone());
+        } else {
+            data.sort_by(by.clone());
+        }
+        ctx.request_update();
+    })
+    .with_toggle(move |data: &Library<Book>, _env: &Env| {
+        let sort = data.get_sort_order();
+        sort == by_toggle || sort == rev_toggle
+    })
+    .padding(5.0)
+}
+
+fn series_sorter_btn() -> impl Widget<Library<Book>> {
+    RoundedButton::from_text("Serie")
+        .with_font(fonts::large)
+        .with_on_click(|ctx, data: &mut Library<Book>, _| {
+            data.sort_by(SortBy::Series);
+            ctx.request_update();
+        })
+        .with_toggle(|data: &Library<Book>, _env: &Env| data.get_sort_order() == SortBy::Series)
+        .padding(5.0)
+}
+
 fn filter_fav_btn() -> impl Widget<Library<Book>> {
     RoundedButton::from_text(fonts::HEART_EMOJI)
         .with_font(fonts::small)
@@ -229,11 +296,29 @@ fn picker_sort_by() -> impl Widget<Library<Book>> {
         .center()
         .expand_width();
 
-    Flex::row()
+    let main_sorts = Flex::row()
         .with_flex_child(label, 1.0)
         .with_flex_child(completion_sorter_btn(), 1.0)
         .with_flex_child(author_sorter_btn(), 1.0)
         .with_flex_child(title_sorter_btn(), 1.0)
+        .with_flex_child(series_sorter_btn(), 1.0);
+
+    let other_sorts = Flex::row()
+        .with_flex_child(
+            sorter_btn("Aperti di recente", SortBy::LastOpened, SortBy::LastOpenedRev),
+            1.0,
+        )
+        .with_flex_child(
+            sorter_btn("Aggiunti di recente", SortBy::DateAdded, SortBy::DateAddedRev),
+            1.0,
+        )
+        .with_flex_child(sorter_btn("Lingua", SortBy::Language, SortBy::LanguageRev), 1.0)
+        .with_flex_child(sorter_btn("Lunghezza", SortBy::Length, SortBy::LengthRev), 1.0)
+        .with_flex_child(sorter_btn("Dimensione", SortBy::FileSize, SortBy::FileSizeRev), 1.0);
+
+    Flex::column()
+        .with_child(main_sorts)
+        .with_child(other_sorts)
         .padding(druid::Insets::uniform_xy(15.0, 5.0))
         .background(colors::BACKGROUND_VARIANT)
         .rounded(ROUND_FACTR)
@@ -247,16 +332,37 @@ fn picker_filter_by() -> impl Widget<Library<Book>> {
         .with_text_color(colors::ON_BACKGROUND)
         .lens(LibraryFilterLens);
 
+    let tags_edit = druid::widget::TextBox::new()
+        .with_font(fonts::medium)
+        .with_placeholder("Tag o scaffale, separati da virgole")
+        .with_text_color(colors::ON_BACKGROUND)
+        .lens(LibraryTagFilterLens);
+
     let label = Label::new("Cerca libro")
         .with_text_color(colors::ON_BACKGROUND)
         .with_font(fonts::medium)
         .center()
         .expand_width();
 
-    Flex::row()
+    let search_row = Flex::row()
         .with_flex_child(label, 1.0)
         .with_flex_child(text_edit.expand_width(), 3.0)
-        .with_flex_child(filter_fav_btn(), 0.5)
+        .with_flex_child(filter_fav_btn(), 0.5);
+
+    let tags_label = Label::new("Filtra per tag")
+        .with_text_color(colors::ON_BACKGROUND)
+        .with_font(fonts::medium)
+        .center()
+        .expand_width();
+
+    let tags_row = Flex::row()
+        .with_flex_child(tags_label, 1.0)
+        .with_flex_child(tags_edit.expand_width(), 3.5);
+
+    Flex::column()
+        .with_child(search_row)
+        .with_spacer(5.0)
+        .with_child(tags_row)
         .padding(druid::Insets::uniform_xy(15.0, 10.0))
         .background(colors::BACKGROUND_VARIANT)
         .rounded(ROUND_FACTR)
@@ -309,6 +415,8 @@ fn build_ui() -> impl Widget<CrabReaderState> {
     let left_panel = Flex::column()
         .with_child(ctls.lens(CrabReaderState::library))
         .with_default_spacer()
+        .with_child(content_search_panel().lens(CrabReaderState::library))
+        .with_default_spacer()
         .with_child(view_either)
         .padding(15.0);
     let scroll = Scroll::new(left_panel)
@@ -384,6 +492,8 @@ fn read_book_ui() -> impl Widget<CrabReaderState> {
 
     let current_chapter = current_chapter_widget().with_font(fonts::large).center();
 
+    let time_left = time_left_widget().with_font(fonts::small).center();
+
     let sidebar_lx = Sidebar::LEFT.get();
 
     let sidebar_rx = Sidebar::RIGHT.get();
@@ -438,6 +548,7 @@ fn read_book_ui() -> impl Widget<CrabReaderState> {
         .with_child(header)
         .with_child(title)
         .with_child(current_chapter)
+        .with_child(time_left)
         .with_spacer(20.0)
         .with_flex_child(text, 1.0)
         .with_child(footer)
@@ -447,21 +558,36 @@ fn read_book_ui() -> impl Widget<CrabReaderState> {
 }
 
 fn main() -> Result<(), PlatformError> {
+    if let Err(e) = storage::init() {
+        println!("ERROR: failed to prepare the storage: {}", e);
+    }
+    // with a command the library is managed from the command line, without a window
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(book) = cli::book_argument(&args) {
+        if let Err(e) = cli::import_book(book) {
+            println!("ERROR: failed to add {} to the library: {}", book, e);
+        }
+    } else if !args.is_empty() {
+        std::process::exit(cli::run(&args));
+    }
     let crab_state = CrabReaderState::default();
-    AppLauncher::with_window(
+    let launcher = AppLauncher::with_window(
         WindowDesc::new(get_viewswitcher().env_scope(|env, data| {
             update_theme(env, data);
             update_font_family(env, data);
         }))
         .title("CrabReader")
         .window_size((1280.0, 720.0))
-        .menu(|_, _, _| ctx_menu::main_window())
+        .menu(|_, data, _| ctx_menu::main_window(data))
         .with_min_size((800.0, 600.0)),
     )
     .configure_env(|env, _| {
         env.set(FONT, MYENV.lock().unwrap().font.clone());
     })
-    .delegate(delegates::ReadModeDelegate)
-    .launch(crab_state)?;
+    .delegate(delegates::ReadModeDelegate);
+    watcher::start(launcher.get_external_handle());
+    launcher.launch(crab_state)?;
+    // the session of a book still open when the window is closed
+    stats::save_on_exit();
     Ok(())
 }
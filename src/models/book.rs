@@ -2,16 +2,18 @@ use derivative::Derivative;
 use druid::{
     im::Vector,
     piet::{Error, ImageFormat, PietImage},
-    Data, Lens, PaintCtx, RenderContext,
+    Data, ExtEventSink, Lens, PaintCtx, RenderContext,
 };
 use epub::doc::EpubDoc;
 use image::io::Reader as ImageReader;
 use std::{
     cell::{Ref, RefCell},
     io::Cursor as ImageCursor,
+    ops::Range,
     rc::Rc,
     string::String,
     sync::Arc,
+    time::SystemTime,
 };
 
 use crate::{
@@ -20,20 +22,28 @@ use crate::{
         reader::{BookManagement, BookReading},
     },
     utils::{
-        envmanager::FontSize,
         epub_utils,
+        fulltext,
         epub_utils::{
-            calculate_number_of_pages, edit_chapter, get_cumulative_current_page_number,
+            calculate_number_of_pages, edit_chapter, get_start_end_pages_per_chapter,
             split_chapter_in_vec,
         },
-        saveload::{load_data, remove_edited_chapter, save_favorite},
+        paginator::PageLayout,
+        saveload::{load_data, save_favorite, save_last_opened, save_shelf, save_tags, unix_time},
+        stats,
     },
-    MYENV,
 };
 
-use super::note::BookNotes;
+use super::{
+    bookmark::BookBookmarks,
+    highlight::BookHighlights,
+    note::BookNotes,
+    position::Position,
+    search::BookSearch,
+    toc::{flatten_toc, TocEntry},
+};
 
-const NUMBER_OF_LINES: usize = 8;
+/// default size of a page, used until the reader view is laid out
 pub const PAGE_WIDTH: f32 = 1000.0;
 pub const PAGE_HEIGHT: f32 = 800.0;
 /// Struct that models EPUB file
@@ -46,6 +56,8 @@ pub struct Book {
     number_of_pages: usize,
     number_of_chapters: usize,
     cumulative_current_page: usize,
+    /// first and last page of every chapter with the current page layout
+    pages_per_chapter: Rc<Vec<(usize, usize)>>,
     idx: usize,
     selected: bool,
     title: Rc<String>,
@@ -53,6 +65,19 @@ pub struct Book {
     lang: Rc<String>,
     path: Rc<String>,
     is_favorite: bool,
+    tags: Vector<String>,
+    shelf: Rc<String>,
+    series: Rc<String>,
+    /// number of the book in its series
+    series_index: Option<f64>,
+    /// stars given to the book, from 1 to 5
+    rating: Option<u8>,
+    /// when the book was added to the library, in seconds from the epoch
+    added: u64,
+    /// when the book was opened the last time, 0 if never
+    last_opened: u64,
+    /// size of the epub in bytes
+    file_size: u64,
     chapter_text_split: Vector<String>,
     description: Rc<String>,
     cover_buffer: Arc<Vec<u8>>,
@@ -61,6 +86,11 @@ pub struct Book {
     cover_image: RefCell<Option<PietImage>>,
     filtered_out: bool,
     notes: BookNotes,
+    bookmarks: BookBookmarks,
+    highlights: BookHighlights,
+    search: BookSearch,
+    toc: Vector<TocEntry>,
+    toc_flat: Vector<TocEntry>,
 }
 
 impl Book {
@@ -72,6 +102,7 @@ impl Book {
             number_of_pages: 0,
             number_of_chapters: 0,
             cumulative_current_page: 0,
+            pages_per_chapter: Rc::new(vec![]),
             idx: 0,
             selected: false,
             title: Rc::new(String::new()),
@@ -79,12 +110,25 @@ impl Book {
             lang: e.clone(),
             path: e.clone(),
             is_favorite: false,
+            tags: Vector::new(),
+            shelf: e.clone(),
+            series: e.clone(),
+            series_index: None,
+            rating: None,
+            added: 0,
+            last_opened: 0,
+            file_size: 0,
             chapter_text_split: vec![].into(),
             description: e.clone(),
             cover_buffer: vec![].into(),
             cover_image: None.into(),
             filtered_out: true,
             notes: BookNotes::default(),
+            bookmarks: BookBookmarks::default(),
+            highlights: BookHighlights::default(),
+            search: BookSearch::default(),
+            toc: Vector::new(),
+            toc_flat: Vector::new(),
         }
     }
 
@@ -97,49 +141,40 @@ impl Book {
         // this function has to be called when the book is first added to the library
         // epub_utils::extract_pages(&path_str).expect("Couldn't extract pages in Book::new()");
 
-        let book_map = epub_utils::get_metadata_of_book(path_str);
-        let title = book_map
-            .get("title")
-            .unwrap_or(&"No title".to_string())
-            .to_string();
-        let author = book_map
-            .get("author")
-            .unwrap_or(&"No author".to_string())
-            .to_string();
-        let lang = book_map
-            .get("lang")
-            .unwrap_or(&"No language".to_string())
-            .to_string();
+        let mut book_map = epub_utils::get_metadata_of_book(path_str);
+        epub_utils::complete_metadata(path_str, &mut book_map);
+        let title = book_map.title.clone();
+        let author = book_map.author.clone();
+        let lang = book_map.lang.clone();
         let desc = book_map
-            .get("desc")
-            .unwrap_or(&"No description".to_string())
-            .to_string();
-        let is_fav = book_map
-            .get("favorite")
-            .unwrap_or(&"false".to_string())
-            .parse::<bool>()
-            .unwrap();
-        let number_of_chapters = book_map
-            .get("chapters")
-            .map_or(1, |x| x.parse::<usize>().unwrap_or_default());
-
-        let (chapter_number, current_page, _font_size) =
-            load_data(path_str, false).unwrap_or((1, 0, FontSize::SMALL.to_f64()));
-
-        let number_of_pages = match book_map.get("total_pages") {
-            Some(x) => x.parse::<usize>().unwrap_or_default(),
-            None => calculate_number_of_pages(path_str, 8, MYENV.lock().unwrap().font.size)
-                .map_or(0, |(x, _)| x as usize),
-        };
-
-        let cumulative_current_page = get_cumulative_current_page_number(
-            path_str,
-            chapter_number,
-            current_page,
-            Some(book_map),
-        );
-
-        let notes = BookNotes::with_loading(path_str.into(), chapter_number, current_page);
+            .desc
+            .clone()
+            .unwrap_or("No description".to_string());
+        let is_fav = book_map.favorite;
+        let shelf = book_map.shelf.clone();
+        let tags = book_map.tags.clone().unwrap_or_default();
+        let series = book_map.series.clone();
+        let series_index = book_map.series_index;
+        let rating = book_map.rating;
+        let added = book_map.added.unwrap_or_default();
+        let last_opened = book_map.last_opened.unwrap_or_default();
+        let file_size = std::fs::metadata(path_str).map_or(0, |file| file.len());
+        let number_of_chapters = book_map.chapters;
+
+        let position = load_data(path_str).unwrap_or_else(|_| Position::new(1, 0));
+        let chapter_number = position.get_chapter();
+
+        // the books are loaded in background, the pages can be counted here
+        let pages_per_chapter = get_start_end_pages_per_chapter(path_str, Some(book_map));
+        let number_of_pages = pages_per_chapter.last().map_or(0, |(_, end)| end + 1);
+
+        // the page of the saved position is known only once the chapter is paginated,
+        // until the book is opened the progress counts from the start of the chapter
+        let cumulative_current_page = pages_per_chapter
+            .get(chapter_number)
+            .map_or(0, |(start, _)| *start);
+
+        let notes = BookNotes::with_loading(path_str.into(), chapter_number, 0..0);
 
         Book {
             title: title.into(),
@@ -147,12 +182,21 @@ impl Book {
             lang: lang.into(),
             path: path.into(),
             chapter_number: chapter_number,
-            current_page: current_page,
+            current_page: 0,
             number_of_chapters: number_of_chapters,
             cumulative_current_page: cumulative_current_page,
+            pages_per_chapter: Rc::new(pages_per_chapter),
             number_of_pages: number_of_pages,
             idx: 0, // How to set early?
             is_favorite: is_fav,
+            tags: tags.into(),
+            shelf: shelf.into(),
+            series: series.into(),
+            series_index,
+            rating,
+            added,
+            last_opened,
+            file_size,
             selected: false,
             description: desc.into(),
             chapter_text_split: Vector::new(),
@@ -160,6 +204,11 @@ impl Book {
             cover_image: None.into(),
             filtered_out: false,
             notes: notes,
+            bookmarks: BookBookmarks::default(),
+            highlights: BookHighlights::default(),
+            search: BookSearch::default(),
+            toc: Vector::new(),
+            toc_flat: Vector::new(),
         }
     }
 
@@ -167,11 +216,91 @@ impl Book {
         self.lang.clone()
     }
 
+    pub fn set_lang(&mut self, lang: impl Into<String>) {
+        self.lang = Rc::new(lang.into());
+    }
+
+    pub fn get_added(&self) -> u64 {
+        self.added
+    }
+
+    pub fn get_last_opened(&self) -> u64 {
+        self.last_opened
+    }
+
+    pub fn get_file_size(&self) -> u64 {
+        self.file_size
+    }
+
+    #[cfg(test)]
+    pub fn with_library_data(mut self, added: u64, last_opened: u64, file_size: u64) -> Self {
+        self.added = added;
+        self.last_opened = last_opened;
+        self.file_size = file_size;
+        self
+    }
+
+    /// Records that the book has been opened now
+    pub fn mark_opened(&mut self) {
+        self.last_opened = unix_time(SystemTime::now());
+        if let Err(e) = save_last_opened(self.path.to_string(), self.last_opened) {
+            println!("ERROR: failed to save when the book has been opened: {}", e);
+        }
+    }
+
+    /// Returns true if the last page of the book has been reached
+    pub fn is_finished(&self) -> bool {
+        self.number_of_pages > 0 && self.cumulative_current_page + 1 >= self.number_of_pages
+    }
+
+    /// Estimated seconds to finish the current chapter, at the reading speed of the user
+    pub fn get_time_left_in_chapter(&self) -> u64 {
+        let pages_left = self
+            .chapter_text_split
+            .len()
+            .saturating_sub(self.current_page + 1);
+        stats::time_to_read(pages_left)
+    }
+
+    /// Estimated seconds to finish the book, at the reading speed of the user
+    pub fn get_time_left_in_book(&self) -> u64 {
+        let pages_left = self
+            .number_of_pages
+            .saturating_sub(self.cumulative_current_page + 1);
+        stats::time_to_read(pages_left)
+    }
+
     pub fn get_perc_read(&self) -> f64 {
         let total = self.get_number_of_pages() as f64;
         let read = self.get_number_of_read_pages() as f64;
         (read / total) * 100.0
     }
+
+    /// Method that returns the page of the current chapter
+    /// that contains the given offset (in characters) of the chapter text
+    fn page_of_offset(&self, offset: usize) -> usize {
+        let mut end = 0;
+        for (i, page) in self.chapter_text_split.iter().enumerate() {
+            end += page.chars().count();
+            if offset < end {
+                return i;
+            }
+        }
+        self.get_last_page_number()
+    }
+
+    /// Method that returns the range of characters
+    /// of the chapter text shown in the current page
+    fn current_page_range(&self) -> Range<usize> {
+        self.get_page_range(self.current_page)
+    }
+
+    /// Method that returns the number of the page in the whole book
+    fn cumulative_page(&self, chapter: usize, page: usize) -> usize {
+        self.pages_per_chapter
+            .get(chapter)
+            .map_or(page, |(start, _)| start + page)
+    }
 }
 
 impl BookReading for Book {
@@ -184,13 +313,9 @@ impl BookReading for Book {
 
         self.chapter_text_split = self.split_chapter_in_pages(true);
         self.current_page = if next { 0 } else { self.get_last_page_number() };
-        self.cumulative_current_page = epub_utils::get_cumulative_current_page_number(
-            self.path.as_str(),
-            chapter,
-            self.current_page,
-            None,
-        );
-        self.notes.update_current(chapter, self.current_page);
+        self.cumulative_current_page = self.cumulative_page(chapter, self.current_page);
+        self.notes
+            .update_current(chapter, self.current_page_range());
     }
 
     fn calculate_chars_until_current_page(&self, font_size: f64) -> usize {
@@ -206,10 +331,7 @@ impl BookReading for Book {
             "",
             chapter_text,
             self.chapter_number,
-            8,
-            font_size,
-            PAGE_WIDTH,
-            PAGE_HEIGHT,
+            &PageLayout::current().with_font_size(font_size),
         );
         for i in 0..self.current_page {
             chars += chapter_pages[i].len();
@@ -232,13 +354,9 @@ impl BookReading for Book {
 
     fn set_chapter_current_page_number(&mut self, page: usize) {
         self.current_page = page;
-        self.cumulative_current_page = epub_utils::get_cumulative_current_page_number(
-            self.path.as_str(),
-            self.chapter_number,
-            page,
-            None,
-        );
-        self.notes.update_current(self.chapter_number, page);
+        self.cumulative_current_page = self.cumulative_page(self.chapter_number, page);
+        self.notes
+            .update_current(self.chapter_number, self.current_page_range());
     }
 
     fn get_page_of_chapter(&self) -> String {
@@ -279,6 +397,56 @@ impl BookReading for Book {
     fn get_number_of_chapters(&self) -> usize {
         self.number_of_chapters
     }
+
+    fn get_page_range(&self, page: usize) -> Range<usize> {
+        let start = self
+            .chapter_text_split
+            .iter()
+            .take(page)
+            .map(|page| page.chars().count())
+            .sum();
+        let len = self
+            .chapter_text_split
+            .get(page)
+            .map_or(0, |page| page.chars().count());
+        start..start + len
+    }
+
+    fn get_position(&self) -> Position {
+        Position::new(self.chapter_number, self.current_page_range().start)
+    }
+
+    fn go_to_position(&mut self, position: Position) {
+        if position.get_chapter() != self.chapter_number {
+            self.set_chapter_number(position.get_chapter(), true);
+        }
+        let page = self.page_of_offset(position.get_offset());
+        self.set_chapter_current_page_number(page);
+    }
+
+    fn go_to_toc_entry(&mut self, entry: &TocEntry) {
+        self.set_chapter_number(entry.get_chapter(), true);
+        if let Some(offset) = entry.get_offset() {
+            let page = self.page_of_offset(offset);
+            self.set_chapter_current_page_number(page);
+        }
+    }
+
+    fn get_current_toc_index(&self) -> Option<usize> {
+        // end of the current page in the chapter text
+        let end_of_page = self.current_page_range().end;
+
+        self.toc_flat
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| {
+                entry.get_chapter() < self.chapter_number
+                    || (entry.get_chapter() == self.chapter_number
+                        && entry.get_offset().unwrap_or(0) < end_of_page)
+            })
+            .map(|(idx, _)| idx)
+            .last()
+    }
 }
 
 impl BookManagement for Book {
@@ -292,10 +460,7 @@ impl BookManagement for Book {
             self.path.as_str(),
             None,
             self.chapter_number,
-            NUMBER_OF_LINES,
-            MYENV.lock().unwrap().font.size,
-            PAGE_WIDTH,
-            PAGE_HEIGHT,
+            &PageLayout::current(),
         )
         .into_iter()
         .map(|s| s.to_string())
@@ -324,7 +489,9 @@ impl BookManagement for Book {
 
         let joined_text = split.into_iter().collect::<String>();
 
-        let _ = edit_chapter(self.path.as_str(), self.chapter_number, joined_text);
+        if edit_chapter(self.path.as_str(), self.chapter_number, joined_text.clone()).is_ok() {
+            let _ = fulltext::reindex_chapter(self.path.as_str(), self.chapter_number, &joined_text);
+        }
         let old_len = self.get_last_page_number() + 1;
         // check if the split's number of pages is the same as before
         self.load_chapter();
@@ -333,28 +500,15 @@ impl BookManagement for Book {
             self.path.as_str(),
             None,
             self.chapter_number,
-            NUMBER_OF_LINES,
-            MYENV.lock().unwrap().font.size,
-            PAGE_WIDTH,
-            PAGE_HEIGHT,
+            &PageLayout::current(),
         )
         .len();
         if new_len != old_len {
             println!("DEBUG: new_len: {}, old_len: {}", new_len, old_len);
             // recalculate pages
-            let (total_len, _) = calculate_number_of_pages(
-                self.path.as_str(),
-                NUMBER_OF_LINES,
-                MYENV.lock().unwrap().font.size,
-            )
-            .unwrap();
-            self.number_of_pages = total_len;
-            self.cumulative_current_page = epub_utils::get_cumulative_current_page_number(
-                self.path.as_str(),
-                self.chapter_number,
-                self.current_page,
-                None,
-            );
+            let (_, pages_per_chapter) =
+                calculate_number_of_pages(self.path.as_str(), &PageLayout::current()).unwrap();
+            self.set_pages_per_chapter(pages_per_chapter);
         }
     }
 
@@ -363,35 +517,88 @@ impl BookManagement for Book {
     }
 
     fn load_chapter(&mut self) {
+        // the pages change, the position in the chapter doesn't.
+        // The first time the book is opened the saved position is used,
+        // the one read on another device is pulled later, see `kosync::pull_in_background`
+        let position = if self.chapter_text_split.is_empty() {
+            load_data(self.get_path()).unwrap_or_else(|_| self.get_position())
+        } else {
+            self.get_position()
+        };
+        self.chapter_number = position.get_chapter();
         self.chapter_text_split = self.split_chapter_in_pages(true);
-        if self.current_page > self.chapter_text_split.len() - 1 {
-            if let Ok((_, index, _)) = load_data(self.get_path(), true) {
-                self.current_page = index;
-                remove_edited_chapter(self.get_path(), self.chapter_number);
-                let result = calculate_number_of_pages(
-                    self.path.as_str(),
-                    NUMBER_OF_LINES,
-                    MYENV.lock().unwrap().font.size,
-                );
-                self.number_of_pages = result.unwrap().0;
-                self.cumulative_current_page = get_cumulative_current_page_number(
-                    self.get_path().as_str(),
-                    self.chapter_number,
-                    self.current_page,
-                    None,
+        let page = self.page_of_offset(position.get_offset());
+        self.set_chapter_current_page_number(page);
+    }
+
+    fn repaginate(&mut self, sink: ExtEventSink) {
+        if self.chapter_text_split.is_empty() {
+            return;
+        }
+
+        let offset = self.current_page_range().start;
+        self.chapter_text_split = self.split_chapter_in_pages(true);
+        self.current_page = self.page_of_offset(offset);
+        self.notes
+            .update_current(self.chapter_number, self.current_page_range());
+
+        // counting the pages of the whole book takes long,
+        // until they are counted the ones of the old layout are used
+        match epub_utils::get_cached_pages_per_chapter(self.path.as_str()) {
+            Some(pages_per_chapter) => self.set_pages_per_chapter(pages_per_chapter),
+            None => {
+                self.cumulative_current_page =
+                    self.cumulative_page(self.chapter_number, self.current_page);
+                epub_utils::calculate_number_of_pages_in_background(
+                    sink,
+                    self.path.to_string(),
+                    PageLayout::current(),
                 );
             }
         }
     }
 
+    fn set_pages_per_chapter(&mut self, pages_per_chapter: Vec<(usize, usize)>) {
+        self.number_of_pages = pages_per_chapter.last().map_or(0, |(_, end)| end + 1);
+        self.pages_per_chapter = Rc::new(pages_per_chapter);
+        self.cumulative_current_page = self.cumulative_page(self.chapter_number, self.current_page);
+    }
+
+    fn load_toc(&mut self) {
+        self.toc = match epub_utils::get_toc(self.path.as_str()) {
+            Ok(toc) => toc,
+            Err(e) => {
+                println!("ERROR: failed to read toc of {}: {}", self.path, e);
+                Vector::new()
+            }
+        };
+        self.toc_flat = flatten_toc(&self.toc);
+    }
+
+    fn get_toc(&self) -> &Vector<TocEntry> {
+        &self.toc
+    }
+
+    fn get_toc_entries(&self) -> &Vector<TocEntry> {
+        &self.toc_flat
+    }
+
     fn load_notes(&mut self) {
         self.notes = BookNotes::with_loading(
             self.path.to_string(),
             self.chapter_number,
-            self.current_page,
+            self.current_page_range(),
         );
     }
 
+    fn load_bookmarks(&mut self) {
+        self.bookmarks = BookBookmarks::with_loading(self.path.to_string());
+    }
+
+    fn load_highlights(&mut self) {
+        self.highlights = BookHighlights::with_loading(self.path.to_string());
+    }
+
     fn set_favorite(&mut self, favorite: bool) {
         if self.is_favorite == favorite {
             println!("DEBUG: already set");
@@ -406,6 +613,24 @@ impl BookManagement for Book {
         }
     }
 
+    fn set_tags(&mut self, tags: Vector<String>) {
+        let tags = epub_utils::clean_tags(tags);
+        match save_tags(self.path.to_string(), tags.clone()) {
+            Ok(()) => println!("DEBUG: saved tags: {:?}", tags),
+            Err(e) => println!("ERROR: failed to save tags: {}", e),
+        }
+        self.tags = tags.into();
+    }
+
+    fn set_shelf(&mut self, shelf: impl Into<String>) {
+        let shelf = shelf.into().trim().to_string();
+        match save_shelf(self.path.to_string(), &shelf) {
+            Ok(()) => println!("DEBUG: saved shelf: {}", shelf),
+            Err(e) => println!("ERROR: failed to save shelf: {}", e),
+        }
+        self.shelf = shelf.into();
+    }
+
     fn get_notes(&self) -> &BookNotes {
         &self.notes
     }
@@ -413,6 +638,30 @@ impl BookManagement for Book {
     fn get_notes_mut(&mut self) -> &mut BookNotes {
         &mut self.notes
     }
+
+    fn get_bookmarks(&self) -> &BookBookmarks {
+        &self.bookmarks
+    }
+
+    fn get_bookmarks_mut(&mut self) -> &mut BookBookmarks {
+        &mut self.bookmarks
+    }
+
+    fn get_highlights(&self) -> &BookHighlights {
+        &self.highlights
+    }
+
+    fn get_highlights_mut(&mut self) -> &mut BookHighlights {
+        &mut self.highlights
+    }
+
+    fn get_search(&self) -> &BookSearch {
+        &self.search
+    }
+
+    fn get_search_mut(&mut self) -> &mut BookSearch {
+        &mut self.search
+    }
 }
 
 impl GUIBook for Book {
@@ -543,6 +792,31 @@ impl GUIBook for Book {
         self.is_favorite
     }
 
+    fn get_tags(&self) -> Vector<String> {
+        self.tags.clone()
+    }
+
+    fn get_shelf(&self) -> Rc<String> {
+        self.shelf.clone()
+    }
+
+    fn get_series(&self) -> Rc<String> {
+        self.series.clone()
+    }
+
+    fn get_series_index(&self) -> Option<f64> {
+        self.series_index
+    }
+
+    fn get_rating(&self) -> Option<u8> {
+        self.rating
+    }
+
+    fn set_series(&mut self, series: impl Into<String>, index: Option<f64>) {
+        self.series = Rc::new(series.into());
+        self.series_index = index;
+    }
+
     fn set_cover_image(&self, ctx: &mut PaintCtx) -> Result<(), Error> {
         let mut inner = self.cover_image.borrow_mut();
         let buf = self.get_cover_buffer();
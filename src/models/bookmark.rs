@@ -0,0 +1,170 @@
+use druid::{im::Vector, widget::ListIter, Data};
+
+use crate::{
+    traits::{
+        bookmark::BookmarkManagement,
+        reader::{BookManagement, BookReading},
+    },
+    utils::saveload::{load_bookmarks, save_bookmarks},
+};
+
+use super::{book::Book, position::Position};
+
+#[derive(Data, Clone, Debug, PartialEq)]
+pub struct Bookmark {
+    name: String,
+    /// where the bookmark points in the book
+    position: Position,
+}
+
+impl Bookmark {
+    pub fn new(name: String, position: Position) -> Bookmark {
+        Bookmark { name, position }
+    }
+
+    pub fn get_name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
+    pub fn get_position(&self) -> Position {
+        self.position
+    }
+}
+
+#[derive(Data, Clone, Debug, PartialEq)]
+/// A struct that contains the bookmarks of a book, sorted by position
+pub struct BookBookmarks {
+    bookmarks: Vector<Bookmark>,
+}
+
+impl BookBookmarks {
+    pub fn new() -> BookBookmarks {
+        BookBookmarks {
+            bookmarks: Vector::new(),
+        }
+    }
+
+    pub fn with_loading(path: String) -> BookBookmarks {
+        let Ok(bookmarks) = load_bookmarks(path) else {
+            return BookBookmarks::default();
+        };
+
+        BookBookmarks { bookmarks }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bookmarks.len()
+    }
+}
+
+impl Default for BookBookmarks {
+    fn default() -> Self {
+        BookBookmarks::new()
+    }
+}
+
+impl BookmarkManagement for BookBookmarks {
+    fn get(&self) -> &Vector<Bookmark> {
+        &self.bookmarks
+    }
+
+    fn get_bookmark(&self, position: &Position) -> Option<&Bookmark> {
+        self.bookmarks.iter().find(|b| b.get_position() == *position)
+    }
+
+    fn add_bookmark(&mut self, book: &Book, name: String) -> Option<Position> {
+        let position = book.get_position();
+        if self.get_bookmark(&position).is_some() {
+            return None;
+        }
+
+        let name = if name.trim().is_empty() {
+            default_name(book)
+        } else {
+            name.trim().to_string()
+        };
+
+        let mut bookmarks = self.bookmarks.clone();
+        let index = bookmarks
+            .iter()
+            .position(|b| b.get_position() > position)
+            .unwrap_or(bookmarks.len());
+        bookmarks.insert(index, Bookmark::new(name, position));
+
+        let Ok(_) = save_bookmarks(book.get_path(), &bookmarks) else {
+            return None;
+        };
+
+        self.bookmarks = bookmarks;
+        Some(position)
+    }
+
+    fn rename_bookmark(&mut self, book_path: String, position: &Position, name: String) {
+        let name = name.trim().to_string();
+        if name.is_empty() {
+            return;
+        }
+
+        let mut bookmarks = self.bookmarks.clone();
+        let Some(bookmark) = bookmarks.iter_mut().find(|b| b.get_position() == *position) else {
+            return;
+        };
+        bookmark.set_name(name);
+
+        let Ok(_) = save_bookmarks(book_path, &bookmarks) else {
+            return;
+        };
+
+        self.bookmarks = bookmarks;
+    }
+
+    fn delete_bookmark(&mut self, book_path: String, position: &Position) {
+        let mut bookmarks = self.bookmarks.clone();
+        bookmarks.retain(|b| b.get_position() != *position);
+
+        let Ok(_) = save_bookmarks(book_path, &bookmarks) else {
+            return;
+        };
+
+        self.bookmarks = bookmarks;
+    }
+}
+
+/// name of a bookmark added without a name:
+/// the entry of the table of contents or the chapter of the current page
+fn default_name(book: &Book) -> String {
+    let entry = book
+        .get_current_toc_index()
+        .and_then(|idx| book.get_toc_entries().get(idx));
+
+    match entry {
+        Some(entry) => format!("{}, pagina {}", entry.get_label(), book.get_current_page_number() + 1),
+        None => format!(
+            "Capitolo {}, pagina {}",
+            book.get_chapter_number() + 1,
+            book.get_current_page_number() + 1
+        ),
+    }
+}
+
+impl ListIter<Bookmark> for BookBookmarks {
+    fn data_len(&self) -> usize {
+        self.bookmarks.len()
+    }
+
+    fn for_each(&self, mut cb: impl FnMut(&Bookmark, usize)) {
+        for (i, bookmark) in self.bookmarks.iter().enumerate() {
+            cb(bookmark, i);
+        }
+    }
+
+    fn for_each_mut(&mut self, mut cb: impl FnMut(&mut Bookmark, usize)) {
+        for (i, bookmark) in self.bookmarks.iter_mut().enumerate() {
+            cb(bookmark, i);
+        }
+    }
+}
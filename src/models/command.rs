@@ -3,7 +3,10 @@ pub enum Trigger {
     NONE,
     OCR,
     OCRINVERSE,
-    ADDBOOK
+    ADDBOOK,
+    WATCHFOLDER,
+    ANNOTATIONS,
+    CALIBRE
 }
 
 impl Trigger {
@@ -12,6 +15,9 @@ impl Trigger {
             "ocr" | "OCR" => Trigger::OCR,
             "ocrinverse" | "OCRINVERSE" => Trigger::OCRINVERSE,
             "addbook" | "ADDBOOK" => Trigger::ADDBOOK,
+            "watchfolder" | "WATCHFOLDER" => Trigger::WATCHFOLDER,
+            "annotations" | "ANNOTATIONS" => Trigger::ANNOTATIONS,
+            "calibre" | "CALIBRE" => Trigger::CALIBRE,
             _ => Trigger::NONE,
         }
     }
@@ -0,0 +1,255 @@
+//! The content of the documents of the storage, as it is written on disk.
+//! Every field has a default, so documents written by older versions
+//! (or edited by hand) with missing fields can still be read.
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// books_saved.json: book id -> reading position
+pub type SaveDataDocument = BTreeMap<String, SavedBook>;
+/// books_notes.json: book id -> notes grouped by chapter
+pub type NotesDocument = BTreeMap<String, Vec<SavedChapterNotes>>;
+/// books_bookmarks.json: book id -> bookmarks
+pub type BookmarksDocument = BTreeMap<String, Vec<SavedBookmark>>;
+/// books_highlights.json: book id -> highlights
+pub type HighlightsDocument = BTreeMap<String, Vec<SavedHighlight>>;
+/// reading_stats.json: the reading sessions, see `stats`
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct ReadingStatsDocument {
+    pub sessions: Vec<ReadingSession>,
+    /// reading speed learned from the page turns, None until a page has been read
+    pub seconds_per_page: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct ReadingSession {
+    /// id of the book
+    pub book: String,
+    /// title of the book, the book may not be in the library anymore
+    pub title: String,
+    /// seconds from the epoch of the first and the last page turned
+    pub start: u64,
+    pub end: u64,
+    pub pages: usize,
+    pub chapters_finished: usize,
+}
+
+/// kosync.json: the account on a KOReader sync server, see `kosync`
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct KosyncDocument {
+    /// url of the server, empty if the sync is off
+    pub server: String,
+    pub username: String,
+    /// md5 of the password, as the protocol wants it
+    pub userkey: String,
+    /// random id of this installation, to tell its progress from the one of the devices
+    pub device_id: String,
+}
+
+/// opds_catalogs.json: the OPDS catalogs the books are downloaded from, see `opds`
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct OpdsCatalogsDocument {
+    pub catalogs: Vec<SavedCatalog>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct SavedCatalog {
+    pub name: String,
+    /// url of the root feed, with the credentials if the catalog needs them
+    pub url: String,
+}
+
+/// books.json: book path -> id of the book (see `identity::book_id`)
+pub type BooksDocument = BTreeMap<String, RegisteredBook>;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct SavedBook {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chapter: Option<usize>,
+    /// characters from the start of the chapter
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<usize>,
+    /// page of the chapter, written by the old versions in place of the offset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page: Option<usize>,
+    /// a piece of the text of the page, written by the old versions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    pub edited_chapters: Vec<usize>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct SavedChapterNotes {
+    pub chapter: usize,
+    pub notes: Vec<SavedNote>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct SavedNote {
+    /// the start of the page of the note
+    pub start: String,
+    pub note: String,
+    /// characters from the start of the chapter, missing in the notes of the old versions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct SavedBookmark {
+    pub chapter: usize,
+    pub offset: usize,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct SavedHighlight {
+    pub chapter: usize,
+    pub start: usize,
+    pub end: usize,
+    pub color: String,
+    pub text: String,
+    pub note: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct RegisteredBook {
+    pub id: String,
+    /// seconds from the epoch of the last change of the file when the id was calculated
+    pub modified: u64,
+    pub len: u64,
+}
+
+/// watched_folders.json: the folders watched for new books
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct WatchedFoldersDocument {
+    pub folders: Vec<String>,
+    /// path of a file in a watched folder -> its copy in the library
+    pub imported: BTreeMap<String, ImportedBook>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct ImportedBook {
+    /// path of the copy in the library, none if a book with the same name was already there
+    pub book: Option<String>,
+    /// seconds from the epoch of the last change of the file when it was imported
+    pub modified: u64,
+    pub len: u64,
+}
+
+/// metadata.json of a book
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct BookMetadata {
+    pub title: String,
+    pub author: String,
+    pub lang: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub desc: Option<String>,
+    pub source: String,
+    pub date: String,
+    pub rights: String,
+    pub identifier: String,
+    /// the other identifiers of the epub, e.g. the ISBN
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub identifiers: Vec<String>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub publisher: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub series: String,
+    /// number of the book in its series
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub series_index: Option<f64>,
+    /// stars given to the book, from 1 to 5
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rating: Option<u8>,
+    /// number of spine items
+    pub chapters: usize,
+    pub favorite: bool,
+    /// when the book was added to the library, in seconds from the epoch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub added: Option<u64>,
+    /// when the book was opened the last time, in seconds from the epoch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_opened: Option<u64>,
+    /// tags given by the user, at first the subjects of the epub.
+    /// None in the metadata extracted before the tags existed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    /// shelf of the library the book is on, empty if none
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub shelf: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_pages: Option<usize>,
+    /// first and last page of every chapter, for each page layout (see `PageLayout::key`)
+    pub pages_per_chapter: BTreeMap<String, Vec<(usize, usize)>>,
+    /// the keys this version doesn't know about
+    #[serde(flatten)]
+    pub other: HashMap<String, Value>,
+}
+
+impl Default for BookMetadata {
+    fn default() -> Self {
+        Self {
+            title: "No title".to_string(),
+            author: "No author".to_string(),
+            lang: "No language".to_string(),
+            desc: None,
+            source: String::new(),
+            date: String::new(),
+            rights: String::new(),
+            identifier: String::new(),
+            identifiers: vec![],
+            publisher: String::new(),
+            series: String::new(),
+            series_index: None,
+            rating: None,
+            chapters: 1,
+            favorite: false,
+            added: None,
+            last_opened: None,
+            tags: None,
+            shelf: String::new(),
+            total_pages: None,
+            pages_per_chapter: BTreeMap::new(),
+            other: HashMap::new(),
+        }
+    }
+}
+
+/// env.json, the settings of the application
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct EnvSettings {
+    pub font_color: String,
+    pub font_family: String,
+    pub font_size: String,
+    pub theme: String,
+    pub shadows: bool,
+}
+
+impl Default for EnvSettings {
+    fn default() -> Self {
+        Self {
+            font_color: "WHITE".to_string(),
+            font_family: "SYSTEM_UI".to_string(),
+            font_size: "medium".to_string(),
+            theme: "light".to_string(),
+            shadows: false,
+        }
+    }
+}
@@ -0,0 +1,264 @@
+use std::ops::Range;
+
+use druid::{im::Vector, widget::ListIter, Color, Data};
+
+use crate::{traits::highlight::HighlightManagement, utils::saveload::{load_highlights, save_highlights}};
+
+/// Colours available to highlight the text
+#[derive(Clone, Copy, Data, Debug, PartialEq, Eq)]
+pub enum HighlightColor {
+    Yellow,
+    Green,
+    Blue,
+    Pink,
+}
+
+impl HighlightColor {
+    pub const ALL: [HighlightColor; 4] = [
+        HighlightColor::Yellow,
+        HighlightColor::Green,
+        HighlightColor::Blue,
+        HighlightColor::Pink,
+    ];
+
+    /// the background painted under the text, translucent so the text stays readable
+    pub fn to_color(&self) -> Color {
+        match self {
+            HighlightColor::Yellow => Color::rgba8(0xFF, 0xD6, 0x00, 0x70),
+            HighlightColor::Green => Color::rgba8(0x4C, 0xD9, 0x64, 0x70),
+            HighlightColor::Blue => Color::rgba8(0x3F, 0xA9, 0xF5, 0x70),
+            HighlightColor::Pink => Color::rgba8(0xFF, 0x5C, 0xA8, 0x70),
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            HighlightColor::Yellow => "Giallo",
+            HighlightColor::Green => "Verde",
+            HighlightColor::Blue => "Azzurro",
+            HighlightColor::Pink => "Rosa",
+        }
+    }
+
+    pub fn to_str(&self) -> &'static str {
+        match self {
+            HighlightColor::Yellow => "yellow",
+            HighlightColor::Green => "green",
+            HighlightColor::Blue => "blue",
+            HighlightColor::Pink => "pink",
+        }
+    }
+
+    pub fn from_str(s: &str) -> HighlightColor {
+        match s {
+            "green" => HighlightColor::Green,
+            "blue" => HighlightColor::Blue,
+            "pink" => HighlightColor::Pink,
+            _ => HighlightColor::Yellow,
+        }
+    }
+}
+
+/// A range of characters of the text of a chapter
+#[derive(Clone, Copy, Data, Debug, Default, PartialEq, Eq)]
+pub struct TextRange {
+    chapter: usize,
+    start: usize,
+    end: usize,
+}
+
+impl TextRange {
+    pub fn new(chapter: usize, start: usize, end: usize) -> Self {
+        Self {
+            chapter,
+            start: start.min(end),
+            end: start.max(end),
+        }
+    }
+
+    pub fn get_chapter(&self) -> usize {
+        self.chapter
+    }
+
+    pub fn to_range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// Returns true if the range has characters in common
+    /// with the given chapter and range of characters
+    pub fn overlaps(&self, chapter: usize, range: &Range<usize>) -> bool {
+        self.chapter == chapter && self.start < range.end && range.start < self.end
+    }
+}
+
+#[derive(Data, Clone, Debug, PartialEq)]
+pub struct Highlight {
+    range: TextRange,
+    color: HighlightColor,
+    /// the highlighted text, as it is in the chapter
+    text: String,
+    note: String,
+}
+
+impl Highlight {
+    pub fn new(range: TextRange, color: HighlightColor, text: String) -> Highlight {
+        Highlight {
+            range,
+            color,
+            text,
+            note: String::new(),
+        }
+    }
+
+    pub fn with_note(mut self, note: String) -> Highlight {
+        self.note = note;
+        self
+    }
+
+    pub fn get_range(&self) -> TextRange {
+        self.range
+    }
+
+    pub fn get_color(&self) -> HighlightColor {
+        self.color
+    }
+
+    pub fn get_text(&self) -> &String {
+        &self.text
+    }
+
+    pub fn get_note(&self) -> &String {
+        &self.note
+    }
+
+    pub fn set_note(&mut self, note: String) {
+        self.note = note;
+    }
+}
+
+#[derive(Data, Clone, Debug, PartialEq)]
+/// A struct that contains the highlights of a book, sorted by position
+pub struct BookHighlights {
+    highlights: Vector<Highlight>,
+}
+
+impl BookHighlights {
+    pub fn new() -> BookHighlights {
+        BookHighlights {
+            highlights: Vector::new(),
+        }
+    }
+
+    pub fn with_loading(path: String) -> BookHighlights {
+        let Ok(highlights) = load_highlights(path) else {
+            return BookHighlights::default();
+        };
+
+        BookHighlights { highlights }
+    }
+
+    /// returns the highlights that overlap the given chapter and range of characters
+    pub fn get_in(&self, chapter: usize, range: &Range<usize>) -> Vector<Highlight> {
+        self.highlights
+            .iter()
+            .filter(|h| h.get_range().overlaps(chapter, range))
+            .cloned()
+            .collect()
+    }
+
+    /// returns the highlights of the given chapter
+    pub fn get_in_chapter(&self, chapter: usize) -> Vector<Highlight> {
+        self.highlights
+            .iter()
+            .filter(|h| h.get_range().get_chapter() == chapter)
+            .cloned()
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.highlights.len()
+    }
+
+    fn save(&mut self, book_path: String, highlights: Vector<Highlight>) -> bool {
+        if let Err(e) = save_highlights(book_path, &highlights) {
+            println!("ERROR: failed to save highlights: {}", e);
+            return false;
+        }
+        self.highlights = highlights;
+        true
+    }
+}
+
+impl Default for BookHighlights {
+    fn default() -> Self {
+        BookHighlights::new()
+    }
+}
+
+impl HighlightManagement for BookHighlights {
+    fn get(&self) -> &Vector<Highlight> {
+        &self.highlights
+    }
+
+    fn get_highlight(&self, range: &TextRange) -> Option<&Highlight> {
+        self.highlights.iter().find(|h| h.get_range() == *range)
+    }
+
+    fn add_highlight(&mut self, book_path: String, highlight: Highlight) -> bool {
+        let range = highlight.get_range();
+        if range.is_empty() {
+            return false;
+        }
+
+        // a highlight of the same text replaces the old one
+        let mut highlights = self.highlights.clone();
+        highlights.retain(|h| h.get_range() != range);
+        let key = |h: &Highlight| (h.get_range().chapter, h.get_range().start);
+        let index = highlights
+            .iter()
+            .position(|h| key(h) > key(&highlight))
+            .unwrap_or(highlights.len());
+        highlights.insert(index, highlight);
+
+        self.save(book_path, highlights)
+    }
+
+    fn set_highlight_note(&mut self, book_path: String, range: &TextRange, note: String) {
+        let mut highlights = self.highlights.clone();
+        let Some(highlight) = highlights.iter_mut().find(|h| h.get_range() == *range) else {
+            return;
+        };
+        highlight.set_note(note);
+
+        self.save(book_path, highlights);
+    }
+
+    fn delete_highlight(&mut self, book_path: String, range: &TextRange) {
+        let mut highlights = self.highlights.clone();
+        highlights.retain(|h| h.get_range() != *range);
+
+        self.save(book_path, highlights);
+    }
+}
+
+impl ListIter<Highlight> for BookHighlights {
+    fn data_len(&self) -> usize {
+        self.highlights.len()
+    }
+
+    fn for_each(&self, mut cb: impl FnMut(&Highlight, usize)) {
+        for (i, highlight) in self.highlights.iter().enumerate() {
+            cb(highlight, i);
+        }
+    }
+
+    fn for_each_mut(&mut self, mut cb: impl FnMut(&mut Highlight, usize)) {
+        for (i, highlight) in self.highlights.iter_mut().enumerate() {
+            cb(highlight, i);
+        }
+    }
+}
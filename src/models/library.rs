@@ -3,21 +3,39 @@ use druid::Selector;
 use druid::{im::Vector, Data, Lens};
 use epub::doc::EpubDoc;
 use image::io::Reader as ImageReader;
-use std::{io::Cursor, path::PathBuf, rc::Rc, sync::Arc};
+use std::{cmp::Ordering, io::Cursor, path::PathBuf, rc::Rc, sync::Arc};
 
-use crate::traits::reader::BookManagement;
+use crate::traits::reader::{BookManagement, BookReading};
 use crate::utils::thread_loader::{ThreadLoader, ThreadResult};
 use crate::{
-    models::book::Book,
+    models::{
+        book::Book,
+        metadata::MetadataEdit,
+        position::Position,
+        search::{LibraryHit, LibrarySearch},
+    },
     traits::gui::{GUIBook, GUILibrary},
     utils::{
-        dir_manager::{get_epub_dir, get_saved_books_dir},
-        epub_utils,
+        dir_manager::{get_book_dir, get_cover_path, get_epub_dir},
+        epub_utils, fulltext, saveload,
+        search::get_context,
     },
 };
 
 pub const SELECTED_BOOK_SELECTOR: Selector<Option<usize>> = Selector::new("selected-book");
 
+pub struct LibraryContentSearchLens;
+
+impl Lens<Library<Book>, LibrarySearch> for LibraryContentSearchLens {
+    fn with<V, F: FnOnce(&LibrarySearch) -> V>(&self, data: &Library<Book>, f: F) -> V {
+        f(&data.content_search)
+    }
+
+    fn with_mut<V, F: FnOnce(&mut LibrarySearch) -> V>(&self, data: &mut Library<Book>, f: F) -> V {
+        f(&mut data.content_search)
+    }
+}
+
 pub struct LibraryFilterLens;
 
 impl Lens<Library<Book>, String> for LibraryFilterLens {
@@ -34,6 +52,22 @@ impl Lens<Library<Book>, String> for LibraryFilterLens {
     }
 }
 
+pub struct LibraryTagFilterLens;
+
+impl Lens<Library<Book>, String> for LibraryTagFilterLens {
+    fn with<V, F: FnOnce(&String) -> V>(&self, data: &Library<Book>, f: F) -> V {
+        f(&data.filter_tags)
+    }
+
+    fn with_mut<V, F: FnOnce(&mut String) -> V>(&self, data: &mut Library<Book>, f: F) -> V {
+        let mut filter = data.filter_tags.to_string();
+        let res = f(&mut filter);
+        data.filter_tags = filter.into();
+        data.filter_books();
+        res
+    }
+}
+
 #[derive(Clone, Derivative, Lens, Data)]
 #[derivative(PartialEq)]
 pub struct Library<B: GUIBook + Data> {
@@ -42,7 +76,15 @@ pub struct Library<B: GUIBook + Data> {
     sorted_by: SortBy,
     filter_by: Rc<String>,
     filter_fav: bool,
+    /// tags or shelves, separated by commas, that the visible books must all have
+    filter_tags: Rc<String>,
+    /// tag or shelf typed in the details of the selected book
+    pub tag_input: String,
+    /// the metadata of the selected book is being edited
+    pub editing_metadata: bool,
+    pub metadata_edit: MetadataEdit,
     visible_books: usize,
+    content_search: LibrarySearch,
     #[data(ignore)]
     #[derivative(PartialEq = "ignore")]
     cover_loader: Arc<ThreadLoader<Vec<u8>>>,
@@ -63,6 +105,11 @@ impl Library<Book> {
             cover_loader: ThreadLoader::default().into(),
             book_loader: ThreadLoader::default().into(),
             filter_fav: false,
+            filter_tags: String::default().into(),
+            tag_input: String::new(),
+            editing_metadata: false,
+            metadata_edit: MetadataEdit::default(),
+            content_search: LibrarySearch::default(),
             do_paint_shadows: false,
         };
 
@@ -126,15 +173,21 @@ impl GUILibrary for Library<Book> {
         let tx = self.book_loader.tx();
         self.book_loader.execute(move || {
             let file_name = path.split("/").last().unwrap();
-            let folder = file_name.split(".").next().unwrap();
-            if !get_saved_books_dir().join(folder).exists() {
+            if !get_book_dir(&path).exists() {
                 let _res = epub_utils::extract_all(&path)
                     .expect(format!("Failed to extract {}", file_name).as_str());
             }
             let book = Book::new(&path);
+            let chapters = book.get_number_of_chapters();
             let result = ThreadResult::new(book, 0);
             tx.send(result)
                 .expect(format!("Failed to send {}", file_name).as_str());
+            // the book is already in the library, the search waits for the index
+            if !fulltext::is_indexed(&path) {
+                if let Err(e) = fulltext::index_book(&path, chapters) {
+                    println!("ERROR: failed to index {}: {}", file_name, e);
+                }
+            }
         });
     }
 
@@ -179,6 +232,7 @@ impl GUILibrary for Library<Book> {
     fn set_selected_book_idx(&mut self, idx: usize) {
         if idx < self.number_of_books() {
             self.unselect_current_book();
+            self.editing_metadata = false;
             self.selected_book = Some(idx);
             self.books[idx].select();
         }
@@ -203,8 +257,14 @@ impl GUILibrary for Library<Book> {
         let path = path.into();
         let tx = self.cover_loader.tx();
         self.cover_loader.execute(move || {
-            let mut epub = EpubDoc::new(path).map_err(|e| e.to_string()).unwrap();
-            let cover = epub.get_cover().map_err(|e| e.to_string()).unwrap();
+            // the cover chosen for the book, e.g. in Calibre, comes before the one of the epub
+            let cover = match std::fs::read(get_cover_path(&path)) {
+                Ok(cover) => cover,
+                Err(_) => {
+                    let mut epub = EpubDoc::new(path).map_err(|e| e.to_string()).unwrap();
+                    epub.get_cover().map_err(|e| e.to_string()).unwrap()
+                }
+            };
             let reader = ImageReader::new(Cursor::new(cover))
                 .with_guessed_format()
                 .map_err(|e| e.to_string())
@@ -283,6 +343,21 @@ pub enum SortBy {
     AuthorRev,
     PercRead,
     PercReadRev,
+    /// the books of a series are in order, after them the books without a series
+    Series,
+    /// the last opened first, the books never opened are the last ones
+    LastOpened,
+    LastOpenedRev,
+    /// the last added first
+    DateAdded,
+    DateAddedRev,
+    Language,
+    LanguageRev,
+    /// the shortest first, by number of pages
+    Length,
+    LengthRev,
+    FileSize,
+    FileSizeRev,
 }
 
 impl Library<Book> {
@@ -297,20 +372,7 @@ impl Library<Book> {
             .unwrap_or_default();
         let mut new_idx = None;
 
-        self.books.sort_by(|one, other| match by {
-            SortBy::Title => one.get_title().cmp(&other.get_title()),
-            SortBy::TitleRev => other.get_title().cmp(&one.get_title()),
-            SortBy::Author => one.get_author().cmp(&other.get_author()),
-            SortBy::AuthorRev => other.get_author().cmp(&one.get_author()),
-            SortBy::PercRead => one
-                .get_perc_read()
-                .partial_cmp(&other.get_perc_read())
-                .unwrap(),
-            SortBy::PercReadRev => other
-                .get_perc_read()
-                .partial_cmp(&one.get_perc_read())
-                .unwrap(),
-        });
+        self.books.sort_by(|one, other| sort_order(&by, one, other));
         self.books.iter_mut().enumerate().for_each(|(i, book)| {
             book.set_index(i);
             if book.get_title() == old_title {
@@ -324,6 +386,12 @@ impl Library<Book> {
     pub fn filter_books(&mut self) {
         let filter = self.get_filter_by();
         let only_fav = self.filter_fav;
+        let tags: Vec<String> = self
+            .filter_tags
+            .split(',')
+            .map(|tag| tag.trim().to_lowercase())
+            .filter(|tag| !tag.is_empty())
+            .collect();
         let mut cnt = 0;
         self.books.iter_mut().for_each(|book| {
             let auth = book.get_author().to_lowercase();
@@ -343,6 +411,8 @@ impl Library<Book> {
                 book.set_filtered_out(true);
             } else if only_fav && !book.is_favorite() {
                 book.set_filtered_out(true);
+            } else if !has_tags(&tags, &book.get_tags(), &book.get_shelf()) {
+                book.set_filtered_out(true);
             } else {
                 book.set_filtered_out(false);
                 cnt += 1;
@@ -359,6 +429,60 @@ impl Library<Book> {
     }
 }
 
+/// Orders two books as the sort order of the library requires
+fn sort_order(by: &SortBy, one: &Book, other: &Book) -> Ordering {
+    match by {
+        SortBy::Title => one.get_title().cmp(&other.get_title()),
+        SortBy::TitleRev => other.get_title().cmp(&one.get_title()),
+        SortBy::Author => one.get_author().cmp(&other.get_author()),
+        SortBy::AuthorRev => other.get_author().cmp(&one.get_author()),
+        SortBy::PercRead => one
+            .get_perc_read()
+            .partial_cmp(&other.get_perc_read())
+            .unwrap(),
+        SortBy::PercReadRev => other
+            .get_perc_read()
+            .partial_cmp(&one.get_perc_read())
+            .unwrap(),
+        SortBy::Series => series_order(one, other),
+        SortBy::LastOpened => other.get_last_opened().cmp(&one.get_last_opened()),
+        // the books never opened are the last ones in both orders
+        SortBy::LastOpenedRev => (one.get_last_opened() == 0)
+            .cmp(&(other.get_last_opened() == 0))
+            .then_with(|| one.get_last_opened().cmp(&other.get_last_opened())),
+        SortBy::DateAdded => other.get_added().cmp(&one.get_added()),
+        SortBy::DateAddedRev => one.get_added().cmp(&other.get_added()),
+        SortBy::Language => one.get_lang().cmp(&other.get_lang()),
+        SortBy::LanguageRev => other.get_lang().cmp(&one.get_lang()),
+        SortBy::Length => one.get_number_of_pages().cmp(&other.get_number_of_pages()),
+        SortBy::LengthRev => other.get_number_of_pages().cmp(&one.get_number_of_pages()),
+        SortBy::FileSize => one.get_file_size().cmp(&other.get_file_size()),
+        SortBy::FileSizeRev => other.get_file_size().cmp(&one.get_file_size()),
+    }
+}
+
+/// Orders the books by series and number in the series, then by title.
+/// The books without a series are the last ones
+fn series_order<B: GUIBook>(one: &B, other: &B) -> Ordering {
+    let (one_series, other_series) = (one.get_series(), other.get_series());
+    one_series
+        .is_empty()
+        .cmp(&other_series.is_empty())
+        .then_with(|| one_series.to_lowercase().cmp(&other_series.to_lowercase()))
+        .then_with(|| {
+            let index = |book: &B| book.get_series_index().unwrap_or(f64::MAX);
+            index(one).total_cmp(&index(other))
+        })
+        .then_with(|| one.get_title().cmp(&other.get_title()))
+}
+
+/// Returns true if every one of the (lowercase) tags is a tag of the book or its shelf
+fn has_tags(tags: &[String], book_tags: &Vector<String>, shelf: &str) -> bool {
+    tags.iter().all(|tag| {
+        shelf.to_lowercase() == *tag || book_tags.iter().any(|t| t.to_lowercase() == *tag)
+    })
+}
+
 pub struct LibrarySelectedBookLens;
 
 impl<L: GUILibrary<B = Book>> Lens<L, Book> for LibrarySelectedBookLens {
@@ -376,3 +500,225 @@ impl<L: GUILibrary<B = Book>> Lens<L, Book> for LibrarySelectedBookLens {
         f(book)
     }
 }
+
+impl Library<Book> {
+    pub fn get_content_search(&self) -> &LibrarySearch {
+        &self.content_search
+    }
+
+    /// Searches the typed query in the text of all the books
+    pub fn search_contents(&mut self) {
+        let query = self.content_search.query.trim().to_string();
+        let hits: Vector<LibraryHit> = fulltext::search(&query)
+            .into_iter()
+            // books in the index but not in the library are still loading
+            .filter_map(|hit| {
+                let book = self.books.iter().find(|b| b.get_path() == *hit.get_path())?;
+                Some((book.get_title(), hit))
+            })
+            .enumerate()
+            .map(|(index, (title, hit))| {
+                let text = epub_utils::get_chapter_text(hit.get_path(), hit.get_chapter());
+                let range = hit.get_offset()..hit.get_offset() + hit.get_term().chars().count();
+                LibraryHit::new(
+                    index,
+                    hit.get_path().clone(),
+                    title,
+                    Position::new(hit.get_chapter(), hit.get_offset()),
+                    hit.get_term().clone(),
+                    hit.get_score(),
+                    get_context(&text, &range),
+                )
+            })
+            .collect();
+        self.content_search.set_results(query, hits);
+    }
+
+    pub fn clear_content_search(&mut self) {
+        self.content_search.clear();
+    }
+
+    /// Adds the typed tag to the selected book
+    pub fn add_tag_to_selected(&mut self) {
+        let tag = std::mem::take(&mut self.tag_input);
+        if let Some(book) = self.get_selected_book_mut() {
+            let mut tags = book.get_tags();
+            tags.push_back(tag);
+            book.set_tags(tags);
+        }
+        self.filter_books();
+    }
+
+    /// Removes the typed tag from the selected book
+    pub fn remove_tag_from_selected(&mut self) {
+        let tag = std::mem::take(&mut self.tag_input).trim().to_lowercase();
+        if let Some(book) = self.get_selected_book_mut() {
+            let mut tags = book.get_tags();
+            tags.retain(|t| t.to_lowercase() != tag);
+            book.set_tags(tags);
+        }
+        self.filter_books();
+    }
+
+    /// Moves the selected book on the typed shelf, on none if nothing is typed
+    pub fn move_selected_to_shelf(&mut self) {
+        let shelf = std::mem::take(&mut self.tag_input);
+        if let Some(book) = self.get_selected_book_mut() {
+            book.set_shelf(shelf);
+        }
+        self.filter_books();
+    }
+
+    /// Starts editing the metadata of the selected book
+    pub fn start_metadata_edit(&mut self) {
+        let Some(book) = self.get_selected_book() else {
+            return;
+        };
+        let metadata = epub_utils::get_metadata_of_book(&book.get_path());
+        self.metadata_edit = MetadataEdit::from_metadata(&metadata);
+        self.editing_metadata = true;
+    }
+
+    /// Saves the edited metadata of the selected book and shows it
+    pub fn save_metadata_edit(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let edit = self.metadata_edit.clone();
+        let book = self.get_selected_book_mut().ok_or("no book selected")?;
+        let metadata = saveload::save_metadata_edit(book.get_path(), &edit)?;
+        book.set_title(metadata.title);
+        book.set_author(metadata.author);
+        book.set_lang(metadata.lang);
+        book.set_description(metadata.desc.unwrap_or("No description".to_string()));
+        book.set_series(metadata.series, metadata.series_index);
+        self.editing_metadata = false;
+        self.filter_books();
+        Ok(())
+    }
+
+    /// Returns the index of the book to read after the selected one, if it is finished
+    pub fn next_to_read_idx(&self) -> Option<usize> {
+        self.get_selected_book()
+            .filter(|book| book.is_finished())
+            .and_then(|_| self.next_in_series_idx())
+    }
+
+    /// Returns the index of the book that follows the selected one in its series
+    pub fn next_in_series_idx(&self) -> Option<usize> {
+        let book = self.get_selected_book()?;
+        let series = book.get_series().to_lowercase();
+        if series.is_empty() {
+            return None;
+        }
+        self.books
+            .iter()
+            .enumerate()
+            .filter(|(_, other)| {
+                other.get_series().to_lowercase() == series
+                    && series_order(*other, book) == Ordering::Greater
+            })
+            .min_by(|(_, one), (_, other)| series_order(*one, *other))
+            .map(|(idx, _)| idx)
+    }
+
+    /// Returns the paths of all the books of the library
+    pub fn book_paths(&self) -> Vec<String> {
+        self.books.iter().map(|b| b.get_path()).collect()
+    }
+
+    /// Returns the index of the book with the given path
+    pub fn find_book_idx(&self, path: &str) -> Option<usize> {
+        self.books.iter().position(|b| b.get_path() == path)
+    }
+
+    /// Removes the book with the given path, the books after it take the previous index
+    pub fn remove_book_with_path(&mut self, path: &str) -> bool {
+        let Some(idx) = self.find_book_idx(path) else {
+            return false;
+        };
+        match self.selected_book {
+            Some(selected) if selected == idx => self.unselect_current_book(),
+            Some(selected) if selected > idx => self.selected_book = Some(selected - 1),
+            _ => {}
+        }
+        if !self.books[idx].is_filtered_out() {
+            self.visible_books = self.visible_books.saturating_sub(1);
+        }
+        self.books.remove(idx);
+        self.books
+            .iter_mut()
+            .enumerate()
+            .skip(idx)
+            .for_each(|(i, book)| book.set_index(i));
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn books_need_every_tag_of_the_filter() {
+        let tags: Vector<String> = vec!["Romanzo".to_string(), "Classici".to_string()].into();
+        let filter = |f: &[&str]| f.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+
+        assert!(has_tags(&filter(&[]), &tags, ""));
+        assert!(has_tags(&filter(&["romanzo"]), &tags, ""));
+        assert!(has_tags(&filter(&["classici", "da leggere"]), &tags, "Da leggere"));
+        assert!(!has_tags(&filter(&["romanzo", "poesia"]), &tags, ""));
+    }
+
+    #[test]
+    fn series_are_in_order() {
+        let book = |title: &str, series: &str, index: Option<f64>| {
+            let mut book = Book::empty_book().with_title(title);
+            book.set_series(series, index);
+            book
+        };
+        let mut books = vec![
+            book("Alone", "", None),
+            book("Second", "Foundation", Some(2.0)),
+            book("Half", "Foundation", Some(1.5)),
+            book("First", "foundation", Some(1.0)),
+            book("Another", "Dune", None),
+        ];
+        books.sort_by(series_order);
+
+        let titles: Vec<String> = books.iter().map(|b| b.get_title()).collect();
+        assert_eq!(titles, vec!["Another", "First", "Half", "Second", "Alone"]);
+    }
+
+    #[test]
+    fn books_are_sorted_by_library_data() {
+        // title, added, last opened, language, pages, file size
+        let book = |title: &str, added, opened, lang: &str, pages, size| {
+            let mut book = Book::empty_book()
+                .with_title(title)
+                .with_number_of_pages(pages)
+                .with_library_data(added, opened, size);
+            book.set_lang(lang);
+            book
+        };
+        let books = vec![
+            book("A", 300, 0, "it", 120, 2_000),
+            book("B", 100, 500, "en", 300, 1_000),
+            book("C", 200, 900, "fr", 80, 3_000),
+        ];
+        let sorted = |by: SortBy| {
+            let mut books = books.clone();
+            books.sort_by(|one, other| sort_order(&by, one, other));
+            books.iter().map(|b| b.get_title()).collect::<Vec<String>>()
+        };
+
+        // the book never opened is the last one in both orders
+        assert_eq!(sorted(SortBy::LastOpened), vec!["C", "B", "A"]);
+        assert_eq!(sorted(SortBy::LastOpenedRev), vec!["B", "C", "A"]);
+        assert_eq!(sorted(SortBy::DateAdded), vec!["A", "C", "B"]);
+        assert_eq!(sorted(SortBy::DateAddedRev), vec!["B", "C", "A"]);
+        assert_eq!(sorted(SortBy::Language), vec!["B", "C", "A"]);
+        assert_eq!(sorted(SortBy::LanguageRev), vec!["A", "C", "B"]);
+        assert_eq!(sorted(SortBy::Length), vec!["C", "A", "B"]);
+        assert_eq!(sorted(SortBy::LengthRev), vec!["B", "A", "C"]);
+        assert_eq!(sorted(SortBy::FileSize), vec!["B", "A", "C"]);
+        assert_eq!(sorted(SortBy::FileSizeRev), vec!["C", "A", "B"]);
+    }
+}
@@ -0,0 +1,93 @@
+use druid::{Data, Lens};
+
+use crate::utils::epub_utils::AUTHORS_SEPARATOR;
+
+use super::documents::BookMetadata;
+
+/// The metadata of the selected book being edited in the book details.
+/// The fields are the text of the text boxes, the lists are separated by commas
+#[derive(Clone, Data, Lens, Default, PartialEq, Debug)]
+pub struct MetadataEdit {
+    pub title: String,
+    pub authors: String,
+    pub series: String,
+    pub series_index: String,
+    pub lang: String,
+    pub publisher: String,
+    pub desc: String,
+    pub identifiers: String,
+}
+
+impl MetadataEdit {
+    pub fn from_metadata(metadata: &BookMetadata) -> Self {
+        Self {
+            title: metadata.title.clone(),
+            authors: metadata
+                .author
+                .split(AUTHORS_SEPARATOR)
+                .collect::<Vec<_>>()
+                .join(", "),
+            series: metadata.series.clone(),
+            series_index: metadata
+                .series_index
+                .map(|index| index.to_string())
+                .unwrap_or_default(),
+            lang: metadata.lang.clone(),
+            publisher: metadata.publisher.clone(),
+            desc: metadata.desc.clone().unwrap_or_default(),
+            identifiers: metadata.identifiers.join(", "),
+        }
+    }
+
+    /// Sets the edited fields in the metadata, the others are left as they are
+    pub fn apply(&self, metadata: &mut BookMetadata) {
+        metadata.title = self.title.trim().to_string();
+        metadata.author = split_list(&self.authors).join(AUTHORS_SEPARATOR);
+        metadata.series = self.series.trim().to_string();
+        metadata.series_index = self.series_index.trim().replace(',', ".").parse().ok();
+        metadata.lang = self.lang.trim().to_string();
+        metadata.publisher = self.publisher.trim().to_string();
+        let desc = self.desc.trim();
+        metadata.desc = (!desc.is_empty()).then(|| desc.to_string());
+        metadata.identifiers = split_list(&self.identifiers);
+    }
+}
+
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edits_are_applied_to_the_metadata() {
+        let mut metadata = BookMetadata {
+            author: "Italo Svevo".to_string(),
+            chapters: 12,
+            ..BookMetadata::default()
+        };
+        let mut edit = MetadataEdit::from_metadata(&metadata);
+        edit.title = " La coscienza di Zeno ".to_string();
+        edit.authors = "Italo Svevo, Ettore Schmitz,".to_string();
+        edit.series_index = "2,5".to_string();
+        edit.identifiers = "urn:isbn:9788807900211".to_string();
+        edit.apply(&mut metadata);
+
+        assert_eq!(metadata.title, "La coscienza di Zeno");
+        assert_eq!(metadata.author, "Italo Svevo & Ettore Schmitz");
+        assert_eq!(metadata.series_index, Some(2.5));
+        assert_eq!(metadata.desc, None);
+        assert_eq!(metadata.identifiers, vec!["urn:isbn:9788807900211"]);
+        // not edited
+        assert_eq!(metadata.chapters, 12);
+        assert_eq!(
+            MetadataEdit::from_metadata(&metadata).authors,
+            "Italo Svevo, Ettore Schmitz"
+        );
+    }
+}
@@ -1,5 +1,13 @@
 pub mod book;
+pub mod bookmark;
+pub mod documents;
+pub mod highlight;
 pub mod library;
+pub mod metadata;
 pub mod note;
+pub mod opds;
+pub mod position;
 pub mod rich;
+pub mod search;
+pub mod toc;
 pub mod command;
@@ -1,21 +1,32 @@
 
-use std::{rc::Rc, collections::HashMap};
+use std::ops::Range;
 
 use druid::{Data, widget::ListIter, im::{Vector}};
 
-use crate::{traits::{note::NoteManagement, reader::{BookReading, BookManagement}}, utils::saveload::{save_note, load_notes, delete_note, delete_all_notes, delete_notes}};
+use crate::{traits::{note::NoteManagement, reader::{BookReading, BookManagement}}, utils::saveload::{save_note, load_notes, delete_note, delete_notes}};
 
-use super::book::{Book, book_derived_lenses::chapter_number};
+use super::{book::Book, position::Position};
 
 #[derive(Data, Clone, Debug, PartialEq)]
 pub struct Note {
     start: String,
     text: String,
+    /// where the note is anchored in the book
+    position: Position,
 }
 
 impl Note {
     pub fn new(start: String, text: String) -> Note {
-        Note { start, text }
+        Note { start, text, position: Position::default() }
+    }
+
+    pub fn get_position(&self) -> Position {
+        self.position
+    }
+
+    pub fn with_position(mut self, position: Position) -> Note {
+        self.position = position;
+        self
     }
 
     pub fn set_text(&mut self, text: String) {
@@ -46,34 +57,48 @@ impl Note {
 }
 
 #[derive(Data, Clone, Debug, PartialEq)]
-/// A struct that contains all the notes for chapter and page of a book
+/// A struct that contains all the notes of a book
+/// and the ones anchored in the current page
 pub struct BookNotes {
     #[data(ignore)]
-    all_notes: HashMap<(usize, usize), Vector<Note>>,
+    all_notes: Vector<Note>,
     chapter_page_notes: Vector<Note>
 }
 
 impl BookNotes {
     pub fn new() -> BookNotes {
         BookNotes {
-            all_notes: HashMap::new(),
+            all_notes: Vector::new(),
             chapter_page_notes: Vector::new()
         }
     }
 
-    pub fn update_current(&mut self, chapter: usize, page: usize) {
-        self.chapter_page_notes = self.all_notes.get(&(chapter, page)).unwrap_or(&Vector::new()).clone();
+    /// select the notes anchored in the given chapter and range of characters (the current page)
+    pub fn update_current(&mut self, chapter: usize, page: Range<usize>) {
+        self.chapter_page_notes = self
+            .all_notes
+            .iter()
+            .filter(|note| note.get_position().is_in(chapter, &page))
+            .cloned()
+            .collect();
     }
 
-    pub fn with_loading(path: String, chapter: usize, page: usize) -> BookNotes {
+    pub fn with_loading(path: String, chapter: usize, page: Range<usize>) -> BookNotes {
         let Ok(all_notes) = load_notes(path) else {
             return BookNotes::default();
         };
 
-        BookNotes { 
-            all_notes: all_notes.clone(),
-            chapter_page_notes: all_notes.get(&(chapter, page)).unwrap_or(&Vector::new()).clone()
-        }
+        let mut notes = BookNotes { 
+            all_notes,
+            chapter_page_notes: Vector::new()
+        };
+        notes.update_current(chapter, page);
+        notes
+    }
+
+    /// returns all the notes of the book
+    pub fn get_all(&self) -> &Vector<Note> {
+        &self.all_notes
     }
 
     pub fn len(&self) -> usize {
@@ -103,20 +128,20 @@ impl NoteManagement for BookNotes {
     fn add_note(&mut self, book: &Book, note: String) -> Option<String> {
         let book_path = book.get_path();
 
-        let chapter = book.get_chapter_number();
-        let page = book.get_current_page_number();
+        // the note is anchored to the start of the current page
+        let position = book.get_position();
 
         let text = book.get_page_of_chapter();
 
-        let Ok(start) = save_note(book_path, chapter, text, note.clone()) else {
+        let Ok(start) = save_note(book_path, position, text, note.clone()) else {
             return None;
         };
 
-        let this_note = Note::new(start.clone(), note);
+        let this_note = Note::new(start.clone(), note).with_position(position);
         
         self.chapter_page_notes.push_back(this_note.clone());
 
-        self.all_notes.entry((chapter, page)).or_insert(Vector::new()).push_back(this_note);
+        self.all_notes.push_back(this_note);
 
         Some(start)
     }
@@ -124,25 +149,25 @@ impl NoteManagement for BookNotes {
     fn edit_note(&mut self, book: &Book, start: &String, note: String) {
         let book_path = book.get_path();
 
-        let chapter = book.get_chapter_number();
-        let page = book.get_current_page_number();
-
-
-        let Ok(_) = save_note(book_path, chapter, start.into(), note.clone()) else {
+        let Some(position) = self.get_note(start).map(|n| n.get_position()) else {
             return;
         };
 
-        self.chapter_page_notes.iter_mut().find(|n| n.get_start() == start).map(|n| n.set_text(note));
-
-        self.all_notes.insert((chapter, page), self.chapter_page_notes.clone());
+        let Ok(_) = save_note(book_path, position, start.into(), note.clone()) else {
+            return;
+        };
 
+        for notes in [&mut self.chapter_page_notes, &mut self.all_notes] {
+            if let Some(n) = notes.iter_mut().find(|n| n.get_start() == start) {
+                n.set_text(note.clone());
+            }
+        }
     }
 
     fn delete_note(&mut self, book: &Book, start: &String) {
         let book_path = book.get_path();
 
         let chapter = book.get_chapter_number();
-        let page = book.get_current_page_number();
 
         let Ok(_) = delete_note(book_path, chapter, start.into()) else {
             return;
@@ -150,27 +175,23 @@ impl NoteManagement for BookNotes {
 
         self.chapter_page_notes.retain(|n| n.get_start() != start);
 
-        self.all_notes.insert((chapter, page), self.chapter_page_notes.clone());
-
+        self.all_notes.retain(|n| n.get_position().get_chapter() != chapter || n.get_start() != start);
     }
 
-    fn delete_notes(&mut self, book_path: String, chapter: usize, page: usize) {
-
-        // get the notes to remove
-        let Some(vec) = self.all_notes.get(&(chapter, page)) else {
-            return;
-        };
+    fn delete_notes(&mut self, book_path: String, chapter: usize) {
         // get the start of the notes to remove
-        let to_remove = vec.iter().map(|n| n.get_start().clone()).collect::<Vec<String>>();
+        let to_remove = self.chapter_page_notes.iter().map(|n| n.get_start().clone()).collect::<Vec<String>>();
+        if to_remove.is_empty() {
+            return;
+        }
 
         // remove the notes from file
-        let Ok(_) = delete_notes(book_path, chapter, to_remove) else {
+        let Ok(_) = delete_notes(book_path, chapter, to_remove.clone()) else {
             return;
         };
 
-
         self.chapter_page_notes = Vector::new();
-        self.all_notes.remove(&(chapter, page));
+        self.all_notes.retain(|n| n.get_position().get_chapter() != chapter || !to_remove.contains(n.get_start()));
     }
 
 }
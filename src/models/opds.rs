@@ -0,0 +1,219 @@
+use std::sync::Arc;
+
+use druid::{im::Vector, Data, ExtEventSink, ImageBuf, Lens};
+
+use crate::utils::opds;
+
+/// A catalog configured by the user
+#[derive(Clone, Data, Debug, Default, PartialEq, Lens)]
+pub struct OpdsCatalog {
+    pub name: String,
+    pub url: String,
+}
+
+/// An entry of a feed: a feed to navigate to or a book to download
+#[derive(Clone, Data, Default, Lens)]
+pub struct OpdsEntry {
+    pub title: String,
+    pub author: String,
+    pub summary: String,
+    /// the feed the entry leads to
+    pub navigation: Option<String>,
+    /// the epub of the book
+    pub acquisition: Option<String>,
+    pub cover_url: Option<String>,
+    /// the thumbnail of the cover, loaded after the feed
+    pub cover: Option<Arc<ImageBuf>>,
+}
+
+/// A page of a catalog
+#[derive(Clone, Data, Default, Lens)]
+pub struct OpdsFeed {
+    /// url the feed has been loaded from
+    pub url: String,
+    pub title: String,
+    pub entries: Vector<OpdsEntry>,
+    /// the next page of the entries
+    pub next: Option<String>,
+    /// url of the search with `{searchTerms}` in place of the words searched
+    pub search: Option<String>,
+}
+
+pub struct OpdsCatalogsLens;
+
+impl Lens<OpdsState, Vector<OpdsCatalog>> for OpdsCatalogsLens {
+    fn with<V, F: FnOnce(&Vector<OpdsCatalog>) -> V>(&self, data: &OpdsState, f: F) -> V {
+        f(&data.catalogs)
+    }
+
+    fn with_mut<V, F: FnOnce(&mut Vector<OpdsCatalog>) -> V>(
+        &self,
+        data: &mut OpdsState,
+        f: F,
+    ) -> V {
+        f(&mut data.catalogs)
+    }
+}
+
+pub struct OpdsEntriesLens;
+
+impl Lens<OpdsState, Vector<OpdsEntry>> for OpdsEntriesLens {
+    fn with<V, F: FnOnce(&Vector<OpdsEntry>) -> V>(&self, data: &OpdsState, f: F) -> V {
+        f(&data.feed.entries)
+    }
+
+    fn with_mut<V, F: FnOnce(&mut Vector<OpdsEntry>) -> V>(&self, data: &mut OpdsState, f: F) -> V {
+        f(&mut data.feed.entries)
+    }
+}
+
+/// State of the browser of the OPDS catalogs
+#[derive(Clone, Data, Default, Lens)]
+pub struct OpdsState {
+    catalogs: Vector<OpdsCatalog>,
+    /// name and url of the catalog being added
+    pub new_name: String,
+    pub new_url: String,
+    /// the feed shown, the catalogs are shown when it has no url
+    feed: OpdsFeed,
+    /// the feeds shown before the current one
+    history: Vector<String>,
+    /// url of the feed being loaded
+    loading: Option<String>,
+    /// the words searched in the catalog
+    pub query: String,
+    message: String,
+}
+
+impl OpdsState {
+    pub fn new() -> Self {
+        Self {
+            catalogs: opds::catalogs().into(),
+            ..Self::default()
+        }
+    }
+
+    pub fn get_feed(&self) -> &OpdsFeed {
+        &self.feed
+    }
+
+    pub fn get_message(&self) -> &String {
+        &self.message
+    }
+
+    /// Whether a feed is shown instead of the catalogs
+    pub fn is_browsing(&self) -> bool {
+        !self.feed.url.is_empty()
+    }
+
+    pub fn is_loading(&self) -> bool {
+        self.loading.is_some()
+    }
+
+    /// Opens a feed, the one shown goes in the history
+    pub fn open(&mut self, sink: ExtEventSink, url: String) {
+        if self.is_browsing() {
+            self.history.push_back(self.feed.url.clone());
+        }
+        self.load(sink, url);
+    }
+
+    fn load(&mut self, sink: ExtEventSink, url: String) {
+        self.loading = Some(url.clone());
+        self.message = "Caricamento...".to_string();
+        opds::load_in_background(sink, url);
+    }
+
+    /// Goes back to the previous feed, or to the catalogs from the first one
+    pub fn back(&mut self, sink: ExtEventSink) {
+        match self.history.pop_back() {
+            Some(url) => self.load(sink, url),
+            None => {
+                self.feed = OpdsFeed::default();
+                self.loading = None;
+                self.query.clear();
+                self.message.clear();
+            }
+        }
+    }
+
+    pub fn next_page(&mut self, sink: ExtEventSink) {
+        if let Some(next) = self.feed.next.clone() {
+            self.open(sink, next);
+        }
+    }
+
+    pub fn search(&mut self, sink: ExtEventSink) {
+        let Some(template) = self.feed.search.clone() else {
+            self.message = "Il catalogo non permette di cercare".to_string();
+            return;
+        };
+        if self.query.trim().is_empty() {
+            return;
+        }
+        let url = opds::search_url(&template, &self.query);
+        self.open(sink, url);
+    }
+
+    /// Shows the feed loaded from the url, if it is still the one wanted.
+    /// A feed loaded again with its covers replaces the one shown
+    pub fn loaded(&mut self, url: &str, feed: Result<OpdsFeed, String>) {
+        if self.loading.as_deref() != Some(url) {
+            match feed {
+                Ok(feed) if self.feed.url == url && self.loading.is_none() => self.feed = feed,
+                _ => {}
+            }
+            return;
+        }
+
+        self.loading = None;
+        match feed {
+            Ok(feed) => {
+                self.feed = feed;
+                self.message.clear();
+            }
+            Err(e) => self.message = format!("Non è stato possibile aprire il catalogo: {}", e),
+        }
+    }
+
+    pub fn download(&mut self, sink: ExtEventSink, url: String, title: String) {
+        self.message = format!("Download di {}...", title);
+        opds::download_in_background(sink, url, title);
+    }
+
+    pub fn downloaded(&mut self, result: &Result<String, String>) {
+        self.message = match result {
+            Ok(_) => "Download completato".to_string(),
+            Err(e) => format!("Non è stato possibile scaricare il libro: {}", e),
+        };
+    }
+
+    /// Adds the catalog typed by the user
+    pub fn add_catalog(&mut self) {
+        let url = opds::catalog_url(&self.new_url);
+        if url.is_empty() {
+            self.message = "Scrivi l'indirizzo del catalogo".to_string();
+            return;
+        }
+        let name = match self.new_name.trim() {
+            "" => url.clone(),
+            name => name.to_string(),
+        };
+        if let Err(e) = opds::add_catalog(&name, &url) {
+            println!("ERROR: failed to add the catalog {}: {}", url, e);
+            self.message = "Non è stato possibile aggiungere il catalogo".to_string();
+            return;
+        }
+        self.catalogs = opds::catalogs().into();
+        self.new_name.clear();
+        self.new_url.clear();
+        self.message.clear();
+    }
+
+    pub fn remove_catalog(&mut self, url: &str) {
+        if let Err(e) = opds::remove_catalog(url) {
+            println!("ERROR: failed to remove the catalog {}: {}", url, e);
+        }
+        self.catalogs = opds::catalogs().into();
+    }
+}
@@ -0,0 +1,127 @@
+use std::ops::Range;
+
+use druid::Data;
+
+/// A position inside a book that doesn't depend on the pagination:
+/// the spine item (chapter) and the number of characters
+/// from the start of the chapter text.
+/// Positions survive changes of font, window size and pagination.
+#[derive(Clone, Copy, Data, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Position {
+    chapter: usize,
+    offset: usize,
+}
+
+impl Position {
+    pub fn new(chapter: usize, offset: usize) -> Self {
+        Self { chapter, offset }
+    }
+
+    pub fn get_chapter(&self) -> usize {
+        self.chapter
+    }
+
+    /// Returns the offset in characters from the start of the chapter
+    pub fn get_offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The position of a piece of text saved by the old versions in place of the offset,
+    /// the start of the chapter if the text isn't found in it anymore
+    pub fn of_snippet(chapter: usize, text: &str, snippet: &str) -> Self {
+        Self::new(chapter, find_snippet(text, snippet).unwrap_or(0))
+    }
+
+    /// Returns true if the position is inside the given chapter
+    /// and range of characters
+    pub fn is_in(&self, chapter: usize, range: &Range<usize>) -> bool {
+        self.chapter == chapter && range.contains(&self.offset)
+    }
+}
+
+/// Converts an offset in characters to the index of the byte of `text`
+/// where the character starts, the length of the text if it is out of bounds
+pub fn char_to_byte(text: &str, offset: usize) -> usize {
+    text.char_indices()
+        .nth(offset)
+        .map_or(text.len(), |(byte, _)| byte)
+}
+
+/// Converts the index of a byte of `text` to an offset in characters
+pub fn byte_to_char(text: &str, byte: usize) -> usize {
+    let mut byte = byte.min(text.len());
+    while !text.is_char_boundary(byte) {
+        byte -= 1;
+    }
+    text[..byte].chars().count()
+}
+
+/// Finds the snippet in the text of a chapter and returns its offset in characters.
+/// Used to convert the positions saved by the old versions,
+/// that stored a piece of the page text instead of an offset
+pub fn find_snippet(text: &str, snippet: &str) -> Option<usize> {
+    let snippet = snippet.trim();
+    if snippet.is_empty() {
+        return None;
+    }
+    // the end of a saved snippet may have been cut in the middle of a word
+    let prefix_end = char_to_byte(snippet, 100);
+    text.find(&snippet[..prefix_end])
+        .map(|byte| byte_to_char(text, byte))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "Perché seguito da un forte disgusto fisico, ricordo un soggiorno";
+
+    #[test]
+    fn offsets_are_converted_between_bytes_and_chars() {
+        // "é" takes two bytes
+        assert_eq!(char_to_byte(TEXT, 5), 5);
+        assert_eq!(char_to_byte(TEXT, 6), 7);
+        assert_eq!(byte_to_char(TEXT, 7), 6);
+        for offset in 0..TEXT.chars().count() {
+            assert_eq!(byte_to_char(TEXT, char_to_byte(TEXT, offset)), offset);
+        }
+    }
+
+    #[test]
+    fn offsets_out_of_bounds_are_clamped() {
+        assert_eq!(char_to_byte(TEXT, 1000), TEXT.len());
+        assert_eq!(byte_to_char(TEXT, 1000), TEXT.chars().count());
+        // the middle of "é" is the character itself
+        assert_eq!(byte_to_char(TEXT, 6), 5);
+        assert_eq!(char_to_byte("", 3), 0);
+    }
+
+    #[test]
+    fn old_positions_are_upgraded_to_offsets() {
+        let text = format!("Capitolo 1\n\n{}", TEXT.repeat(3));
+        // the page started in the middle of the chapter
+        let start = text.find("fisico").unwrap();
+        let snippet = &text[start..];
+        let position = Position::of_snippet(2, &text, snippet);
+        assert_eq!(position, Position::new(2, byte_to_char(&text, start)));
+
+        // a snippet cut in the middle of a word is found by its first characters
+        let cut = format!("{}anz", &snippet[..char_to_byte(snippet, 120)]);
+        assert_eq!(find_snippet(&text, &cut), Some(position.get_offset()));
+
+        // a page that isn't in the chapter anymore, or without a snippet, starts the chapter
+        assert_eq!(
+            Position::of_snippet(2, &text, "testo cambiato"),
+            Position::new(2, 0)
+        );
+        assert_eq!(Position::of_snippet(2, &text, "  \n"), Position::new(2, 0));
+    }
+
+    #[test]
+    fn position_is_in_chapter_and_range() {
+        let position = Position::new(3, 10);
+        assert!(position.is_in(3, &(0..11)));
+        assert!(!position.is_in(3, &(0..10)));
+        assert!(!position.is_in(2, &(0..11)));
+    }
+}
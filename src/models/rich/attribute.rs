@@ -41,6 +41,7 @@ pub struct AttributeSpans {
     underline: SpanSet<bool>,
     strikethrough: SpanSet<bool>,
     font_descriptor: SpanSet<KeyOrValue<FontDescriptor>>,
+    highlight: SpanSet<KeyOrValue<Color>>,
 }
 
 /// A set of spans for a given attribute.
@@ -105,6 +106,9 @@ pub enum Attribute {
     Strikethrough(bool),
     /// A [`FontDescriptor`](struct.FontDescriptor.html).
     Descriptor(KeyOrValue<FontDescriptor>),
+    /// The background color of the text. Piet can't draw it,
+    /// it is painted by the widget that shows the text.
+    Highlight(KeyOrValue<Color>),
 }
 
 impl Link {
@@ -136,9 +140,18 @@ impl AttributeSpans {
             Attribute::Underline(attr) => self.underline.add(Span::new(range, attr)),
             Attribute::Strikethrough(attr) => self.strikethrough.add(Span::new(range, attr)),
             Attribute::Descriptor(attr) => self.font_descriptor.add(Span::new(range, attr)),
+            Attribute::Highlight(attr) => self.highlight.add(Span::new(range, attr)),
         }
     }
 
+    /// Returns the ranges of text to paint with a background color
+    pub(crate) fn to_highlights(&self, env: &Env) -> Vec<(Range<usize>, Color)> {
+        self.highlight
+            .iter()
+            .map(|s| (s.range.clone(), s.attr.resolve(env)))
+            .collect()
+    }
+
     pub(crate) fn to_piet_attrs(&self, env: &Env) -> Vec<(Range<usize>, PietAttr)> {
         let mut items = Vec::new();
         for Span { range, attr } in self.font_descriptor.iter() {
@@ -191,6 +204,44 @@ impl AttributeSpans {
         items
     }
 
+    /// Like `to_piet_attrs`, but only with the attributes that change
+    /// the size of the text. Attributes defined by a `Key` are skipped,
+    /// as they can't be resolved without an `Env`.
+    pub(crate) fn to_layout_attrs(&self) -> Vec<(Range<usize>, PietAttr)> {
+        let mut items = Vec::new();
+        for Span { range, attr } in self.font_descriptor.iter() {
+            if let KeyOrValue::Concrete(font) = attr {
+                items.push((range.clone(), PietAttr::FontFamily(font.family.clone())));
+                items.push((range.clone(), PietAttr::FontSize(font.size)));
+                items.push((range.clone(), PietAttr::Weight(font.weight)));
+                items.push((range.clone(), PietAttr::Style(font.style)));
+            }
+        }
+
+        items.extend(
+            self.family
+                .iter()
+                .map(|s| (s.range.clone(), PietAttr::FontFamily(s.attr.clone()))),
+        );
+        items.extend(self.size.iter().filter_map(|s| match s.attr {
+            KeyOrValue::Concrete(size) => Some((s.range.clone(), PietAttr::FontSize(size))),
+            KeyOrValue::Key(_) => None,
+        }));
+        items.extend(
+            self.weight
+                .iter()
+                .map(|s| (s.range.clone(), PietAttr::Weight(s.attr))),
+        );
+        items.extend(
+            self.style
+                .iter()
+                .map(|s| (s.range.clone(), PietAttr::Style(s.attr))),
+        );
+
+        items.sort_by(|a, b| a.0.start.cmp(&b.0.start));
+        items
+    }
+
     pub(crate) fn env_update(&self, ctx: &UpdateCtx) -> bool {
         self.size
             .iter()
@@ -203,6 +254,10 @@ impl AttributeSpans {
                 .font_descriptor
                 .iter()
                 .any(|span_attr| ctx.env_key_changed(&span_attr.attr))
+            || self
+                .highlight
+                .iter()
+                .any(|span_attr| ctx.env_key_changed(&span_attr.attr))
     }
 }
 
@@ -365,6 +420,11 @@ impl Attribute {
     pub fn font_descriptor(font: impl Into<KeyOrValue<FontDescriptor>>) -> Self {
         Attribute::Descriptor(font.into())
     }
+
+    /// Create a new background color attribute.
+    pub fn highlight(color: impl Into<KeyOrValue<Color>>) -> Self {
+        Attribute::Highlight(color.into())
+    }
 }
 
 impl<T> Default for SpanSet<T> {
@@ -1,18 +1,71 @@
+use std::ops::Range;
+
 use druid::Lens;
 
-use crate::{traits::reader::BookReading, utils::rich_text_fn::rebuild_rendered_text};
+use crate::{
+    models::{
+        position::char_to_byte,
+        search::{CURRENT_HIT_COLOR, HIT_COLOR},
+    },
+    traits::reader::{BookManagement, BookReading},
+    utils::rich_text_fn::rebuild_rendered_text,
+};
 
 use super::{rich_text::RichText};
 
+/// Renders a page of the current chapter with its highlights and the hits of the search
+fn render_page<B: BookReading + BookManagement>(data: &B, page: usize, text: &str) -> RichText {
+    let mut rich_text = rebuild_rendered_text(text);
+
+    let range = data.get_page_range(page);
+    // converts a range of characters of the chapter to a range of bytes of the page
+    let to_page = |chars: Range<usize>| {
+        let start = chars.start.max(range.start) - range.start;
+        let end = chars.end.min(range.end) - range.start;
+        char_to_byte(text, start)..char_to_byte(text, end)
+    };
+
+    let highlights = data
+        .get_highlights()
+        .get_in(data.get_chapter_number(), &range);
+    for highlight in highlights.iter() {
+        // the highlight may start or end in another page
+        rich_text.add_highlight(
+            to_page(highlight.get_range().to_range()),
+            highlight.get_color().to_color(),
+        );
+    }
+
+    let search = data.get_search();
+    let current = search.get_current_index();
+    for hit in search.get_in(data.get_chapter_number(), &range) {
+        let color = if Some(hit.get_index()) == current {
+            CURRENT_HIT_COLOR
+        } else {
+            HIT_COLOR
+        };
+        rich_text.add_highlight(to_page(hit.get_range()), color);
+    }
+    rich_text
+}
+
+/// Returns the index of the left page in dual view
+pub fn dual_page_0<B: BookReading>(data: &B) -> usize {
+    let page = data.get_current_page_number();
+    page - page % 2
+}
+
 pub struct SelectedPageLens;
 
-impl<B: BookReading> Lens<B, RichText> for SelectedPageLens {
+impl<B: BookReading + BookManagement> Lens<B, RichText> for SelectedPageLens {
     fn with<V, F: FnOnce(&RichText) -> V>(&self, data: &B, f: F) -> V {
-        f(&rebuild_rendered_text(data.get_page_of_chapter().as_str()))
+        let page = data.get_current_page_number();
+        f(&render_page(data, page, data.get_page_of_chapter().as_str()))
     }
 
     fn with_mut<V, F: FnOnce(&mut RichText) -> V>(&self, data: &mut B, f: F) -> V {
-        f(&mut rebuild_rendered_text(data.get_page_of_chapter().as_str()))
+        let page = data.get_current_page_number();
+        f(&mut render_page(data, page, data.get_page_of_chapter().as_str()))
     }
 }
 
@@ -20,22 +73,26 @@ pub struct DualPage0Lens;
 pub struct DualPage1Lens;
 
 
-impl<B: BookReading> Lens<B, RichText> for DualPage0Lens {
+impl<B: BookReading + BookManagement> Lens<B, RichText> for DualPage0Lens {
     fn with<V, F: FnOnce(&RichText) -> V>(&self, data: &B, f: F) -> V {
-        f(&rebuild_rendered_text(data.get_dual_pages().0.as_str()))
+        let page = dual_page_0(data);
+        f(&render_page(data, page, data.get_dual_pages().0.as_str()))
     }
 
     fn with_mut<V, F: FnOnce(&mut RichText) -> V>(&self, data: &mut B, f: F) -> V {
-        f(&mut rebuild_rendered_text(data.get_dual_pages().0.as_str()))
+        let page = dual_page_0(data);
+        f(&mut render_page(data, page, data.get_dual_pages().0.as_str()))
     }
 }
 
-impl<B: BookReading> Lens<B, RichText> for DualPage1Lens {
+impl<B: BookReading + BookManagement> Lens<B, RichText> for DualPage1Lens {
     fn with<V, F: FnOnce(&RichText) -> V>(&self, data: &B, f: F) -> V {
-        f(&rebuild_rendered_text(data.get_dual_pages().1.as_str()))
+        let page = dual_page_0(data) + 1;
+        f(&render_page(data, page, data.get_dual_pages().1.as_str()))
     }
 
     fn with_mut<V, F: FnOnce(&mut RichText) -> V>(&self, data: &mut B, f: F) -> V {
-        f(&mut rebuild_rendered_text(data.get_dual_pages().1.as_str()))
+        let page = dual_page_0(data) + 1;
+        f(&mut render_page(data, page, data.get_dual_pages().1.as_str()))
     }
-}
\ No newline at end of file
+}
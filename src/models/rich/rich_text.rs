@@ -34,6 +34,29 @@ pub struct RichText {
     buffer: ArcStr,
     attrs: Arc<AttributeSpans>,
     links: Arc<[Link]>,
+    sources: Arc<[SourceRun]>,
+}
+
+/// A piece of the text and the range of the source (markdown) text it was rendered from.
+/// Markup is not rendered, so positions in the source and in the text differ.
+#[derive(Clone, Debug)]
+pub struct SourceRun {
+    pub text: Range<usize>,
+    pub source: Range<usize>,
+}
+
+impl SourceRun {
+    /// Maps a position between the two ranges of the run. If the source was
+    /// transformed (escapes, entities) the lengths differ and only the ends can be mapped
+    fn map(&self, pos: usize, from: &Range<usize>, to: &Range<usize>) -> usize {
+        if from.len() == to.len() {
+            to.start + pos - from.start
+        } else if pos == from.start {
+            to.start
+        } else {
+            to.end
+        }
+    }
 }
 
 impl RichText {
@@ -48,6 +71,7 @@ impl RichText {
             buffer,
             attrs: Arc::new(attributes),
             links: Arc::new([]),
+            sources: Arc::new([]),
         }
     }
 
@@ -76,6 +100,59 @@ impl RichText {
         let range = util::resolve_range(range, self.buffer.len());
         Arc::make_mut(&mut self.attrs).add(range, attr);
     }
+
+    /// Returns the ranges of text to paint with a background color.
+    pub fn highlights(&self, env: &Env) -> Vec<(Range<usize>, Color)> {
+        self.attrs.to_highlights(env)
+    }
+
+    /// Highlights the text rendered from the given range of the source text.
+    pub fn add_highlight(&mut self, source: Range<usize>, color: impl Into<KeyOrValue<Color>>) {
+        let range = self.source_to_text(source.start)..self.source_to_text(source.end);
+        if !range.is_empty() {
+            self.add_attribute(range, Attribute::highlight(color));
+        }
+    }
+
+    /// Converts a position in the source text to the position in the text.
+    /// Positions inside markup are moved to the next rendered text.
+    pub fn source_to_text(&self, pos: usize) -> usize {
+        for run in self.sources.iter() {
+            if pos < run.source.start {
+                return run.text.start;
+            }
+            if pos <= run.source.end {
+                return run.map(pos, &run.source, &run.text);
+            }
+        }
+        self.buffer.len()
+    }
+
+    /// Converts a position in the text to the position in the source text.
+    /// Text that has no source (added by the renderer) is moved to the next source.
+    pub fn text_to_source(&self, pos: usize) -> usize {
+        for run in self.sources.iter() {
+            if pos < run.text.start {
+                return run.source.start;
+            }
+            if pos <= run.text.end {
+                return run.map(pos, &run.text, &run.source);
+            }
+        }
+        self.sources.last().map_or(pos, |run| run.source.end)
+    }
+
+    /// Adds to the builder the attributes that affect the size of the text,
+    /// used to measure the text outside of a widget (no `Env` is available).
+    pub(crate) fn add_layout_attributes(
+        &self,
+        mut builder: PietTextLayoutBuilder,
+    ) -> PietTextLayoutBuilder {
+        for (range, attr) in self.attrs.to_layout_attrs() {
+            builder = builder.range_attribute(range, attr);
+        }
+        builder
+    }
 }
 
 impl DruidTextStorage for RichText {
@@ -139,6 +216,7 @@ pub struct RichTextBuilder {
     buffer: String,
     attrs: AttributeSpans,
     links: Vec<Link>,
+    sources: Vec<SourceRun>,
 }
 
 impl RichTextBuilder {
@@ -157,6 +235,13 @@ impl RichTextBuilder {
         self.add_attributes_for_range(range)
     }
 
+    /// Append a `&str` rendered from the given range of the source text.
+    pub fn push_from_source(&mut self, string: &str, source: Range<usize>) -> AttributesAdder {
+        let text = self.buffer.len()..(self.buffer.len() + string.len());
+        self.sources.push(SourceRun { text, source });
+        self.push(string)
+    }
+
     /// Glue for usage of the write! macro.
     ///
     /// This method should generally not be invoked manually, but rather through the write! macro itself.
@@ -187,6 +272,7 @@ impl RichTextBuilder {
             buffer: self.buffer.into(),
             attrs: self.attrs.into(),
             links: self.links.into(),
+            sources: self.sources.into(),
         }
     }
 }
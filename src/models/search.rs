@@ -0,0 +1,254 @@
+use std::ops::Range;
+
+use druid::{im::Vector, widget::ListIter, Color, Data, Lens};
+
+use super::position::Position;
+
+/// background of the hits of the search in the page
+pub const HIT_COLOR: Color = Color::rgba8(0xFF, 0x98, 0x00, 0x50);
+/// background of the selected hit
+pub const CURRENT_HIT_COLOR: Color = Color::rgba8(0xFF, 0x98, 0x00, 0xC0);
+
+/// An occurrence of the searched text in the book
+#[derive(Clone, Data, Debug, PartialEq)]
+pub struct SearchHit {
+    /// index of the hit in the results
+    index: usize,
+    position: Position,
+    /// length of the match, in characters
+    len: usize,
+    /// the text around the match
+    context: String,
+}
+
+impl SearchHit {
+    pub fn new(index: usize, position: Position, len: usize, context: String) -> Self {
+        Self {
+            index,
+            position,
+            len,
+            context,
+        }
+    }
+
+    pub fn get_index(&self) -> usize {
+        self.index
+    }
+
+    pub fn get_position(&self) -> Position {
+        self.position
+    }
+
+    pub fn get_chapter(&self) -> usize {
+        self.position.get_chapter()
+    }
+
+    pub fn get_context(&self) -> &String {
+        &self.context
+    }
+
+    /// Returns the range of characters of the chapter text that matched
+    pub fn get_range(&self) -> Range<usize> {
+        self.position.get_offset()..self.position.get_offset() + self.len
+    }
+}
+
+/// The results of the last search in a book
+#[derive(Clone, Data, Debug, Default, PartialEq)]
+pub struct BookSearch {
+    query: String,
+    hits: Vector<SearchHit>,
+    /// index of the selected hit
+    current: Option<usize>,
+}
+
+impl BookSearch {
+    pub fn new(query: String, hits: Vector<SearchHit>) -> Self {
+        Self {
+            query,
+            hits,
+            current: None,
+        }
+    }
+
+    pub fn get_query(&self) -> &String {
+        &self.query
+    }
+
+    pub fn get_hits(&self) -> &Vector<SearchHit> {
+        &self.hits
+    }
+
+    pub fn len(&self) -> usize {
+        self.hits.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hits.is_empty()
+    }
+
+    pub fn get_current_index(&self) -> Option<usize> {
+        self.current
+    }
+
+    pub fn get_current(&self) -> Option<&SearchHit> {
+        self.hits.get(self.current?)
+    }
+
+    /// Selects the hit with the given index and returns it
+    pub fn select(&mut self, index: usize) -> Option<SearchHit> {
+        let hit = self.hits.get(index)?.clone();
+        self.current = Some(index);
+        Some(hit)
+    }
+
+    /// Selects the hit after the current one, the first after the last
+    pub fn next(&mut self) -> Option<SearchHit> {
+        if self.hits.is_empty() {
+            return None;
+        }
+        let index = self.current.map_or(0, |i| (i + 1) % self.hits.len());
+        self.select(index)
+    }
+
+    /// Selects the hit before the current one, the last before the first
+    pub fn prev(&mut self) -> Option<SearchHit> {
+        if self.hits.is_empty() {
+            return None;
+        }
+        let len = self.hits.len();
+        let index = self.current.map_or(len - 1, |i| (i + len - 1) % len);
+        self.select(index)
+    }
+
+    /// Returns the hits that overlap the given chapter and range of characters
+    pub fn get_in(&self, chapter: usize, range: &Range<usize>) -> Vec<&SearchHit> {
+        self.hits
+            .iter()
+            .filter(|hit| {
+                let hit_range = hit.get_range();
+                hit.get_chapter() == chapter
+                    && hit_range.start < range.end
+                    && range.start < hit_range.end
+            })
+            .collect()
+    }
+}
+
+/// A chapter of a book of the library that contains the searched words
+#[derive(Clone, Data, Debug, PartialEq)]
+pub struct LibraryHit {
+    /// index of the hit in the results
+    index: usize,
+    path: String,
+    title: String,
+    /// first occurrence of the most relevant word
+    position: Position,
+    /// the word found at `position`
+    term: String,
+    score: f64,
+    /// the text around the word
+    context: String,
+}
+
+impl LibraryHit {
+    pub fn new(
+        index: usize,
+        path: String,
+        title: String,
+        position: Position,
+        term: String,
+        score: f64,
+        context: String,
+    ) -> Self {
+        Self {
+            index,
+            path,
+            title,
+            position,
+            term,
+            score,
+            context,
+        }
+    }
+
+    pub fn get_index(&self) -> usize {
+        self.index
+    }
+
+    pub fn get_path(&self) -> &String {
+        &self.path
+    }
+
+    pub fn get_title(&self) -> &String {
+        &self.title
+    }
+
+    pub fn get_position(&self) -> Position {
+        self.position
+    }
+
+    pub fn get_term(&self) -> &String {
+        &self.term
+    }
+
+    pub fn get_score(&self) -> f64 {
+        self.score
+    }
+
+    pub fn get_context(&self) -> &String {
+        &self.context
+    }
+}
+
+/// The results of the last search in the text of all the books, the most relevant first
+#[derive(Clone, Data, Debug, Default, PartialEq, Lens)]
+pub struct LibrarySearch {
+    /// the text typed by the user
+    pub query: String,
+    /// the query of the shown results
+    searched: String,
+    hits: Vector<LibraryHit>,
+}
+
+impl LibrarySearch {
+    pub fn get_searched(&self) -> &String {
+        &self.searched
+    }
+
+    pub fn get_hits(&self) -> &Vector<LibraryHit> {
+        &self.hits
+    }
+
+    pub fn get_hit(&self, index: usize) -> Option<&LibraryHit> {
+        self.hits.get(index)
+    }
+
+    pub fn set_results(&mut self, searched: String, hits: Vector<LibraryHit>) {
+        self.searched = searched;
+        self.hits = hits;
+    }
+
+    pub fn clear(&mut self) {
+        self.query.clear();
+        self.set_results(String::new(), Vector::new());
+    }
+}
+
+impl ListIter<LibraryHit> for LibrarySearch {
+    fn data_len(&self) -> usize {
+        self.hits.len()
+    }
+
+    fn for_each(&self, mut cb: impl FnMut(&LibraryHit, usize)) {
+        for (i, hit) in self.hits.iter().enumerate() {
+            cb(hit, i);
+        }
+    }
+
+    fn for_each_mut(&mut self, mut cb: impl FnMut(&mut LibraryHit, usize)) {
+        for (i, hit) in self.hits.iter_mut().enumerate() {
+            cb(hit, i);
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use std::rc::Rc;
+
+use druid::{im::Vector, Data, Lens};
+
+/// An entry of the table of contents of a book, as declared in the
+/// NCX file (EPUB2) or in the navigation document (EPUB3)
+#[derive(Clone, Data, Lens, Debug, PartialEq)]
+pub struct TocEntry {
+    label: Rc<String>,
+    /// index of the spine item (chapter) the entry points to
+    chapter: usize,
+    /// optional anchor inside the chapter
+    fragment: Option<Rc<String>>,
+    /// position of the anchor in the chapter text (in characters), if it has been found
+    offset: Option<usize>,
+    /// nesting level, 0 for top level entries
+    depth: usize,
+    children: Vector<TocEntry>,
+}
+
+impl TocEntry {
+    pub fn new(label: impl Into<String>, chapter: usize, fragment: Option<String>, depth: usize) -> Self {
+        Self {
+            label: Rc::new(label.into()),
+            chapter,
+            fragment: fragment.map(Rc::new),
+            offset: None,
+            depth,
+            children: Vector::new(),
+        }
+    }
+
+    pub fn with_children(mut self, children: Vector<TocEntry>) -> Self {
+        self.children = children;
+        self
+    }
+
+    pub fn get_label(&self) -> Rc<String> {
+        self.label.clone()
+    }
+
+    pub fn get_chapter(&self) -> usize {
+        self.chapter
+    }
+
+    pub fn get_fragment(&self) -> Option<Rc<String>> {
+        self.fragment.clone()
+    }
+
+    pub fn get_offset(&self) -> Option<usize> {
+        self.offset
+    }
+
+    pub fn set_offset(&mut self, offset: Option<usize>) {
+        self.offset = offset;
+    }
+
+    pub fn get_depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn get_children(&self) -> &Vector<TocEntry> {
+        &self.children
+    }
+
+    pub fn get_children_mut(&mut self) -> &mut Vector<TocEntry> {
+        &mut self.children
+    }
+
+    /// Returns the entry and all its descendants in reading order
+    pub fn flatten(&self) -> Vector<TocEntry> {
+        let mut flat = Vector::new();
+        flat.push_back(self.clone().with_children(Vector::new()));
+        for child in self.children.iter() {
+            flat.append(child.flatten());
+        }
+        flat
+    }
+}
+
+/// Returns all the entries of a toc tree in reading order
+pub fn flatten_toc(toc: &Vector<TocEntry>) -> Vector<TocEntry> {
+    toc.iter().fold(Vector::new(), |mut flat, entry| {
+        flat.append(entry.flatten());
+        flat
+    })
+}
@@ -0,0 +1,18 @@
+use druid::im::Vector;
+
+use crate::models::{book::Book, bookmark::Bookmark, position::Position};
+
+pub trait BookmarkManagement {
+    /// get all the bookmarks of the book, sorted by position
+    fn get(&self) -> &Vector<Bookmark>;
+    /// get the bookmark at the given position
+    fn get_bookmark(&self, position: &Position) -> Option<&Bookmark>;
+
+    /// add a bookmark at the current page of the book, return its position.
+    /// An empty name is replaced by the chapter and page of the bookmark
+    fn add_bookmark(&mut self, book: &Book, name: String) -> Option<Position>;
+    /// rename the bookmark at the given position
+    fn rename_bookmark(&mut self, book_path: String, position: &Position, name: String);
+    /// delete the bookmark at the given position
+    fn delete_bookmark(&mut self, book_path: String, position: &Position);
+}
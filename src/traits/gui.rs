@@ -1,5 +1,6 @@
 use crate::models::library::SortBy;
 use druid::{
+    im::Vector,
     piet::{Error, PietImage},
     Data, PaintCtx,
 };
@@ -93,6 +94,34 @@ pub trait GUIBook: PartialEq + Data {
 
     fn is_favorite(&self) -> bool;
 
+    /// Returns the tags given to the book
+    fn get_tags(&self) -> Vector<String>;
+
+    /// Returns the shelf of the library the book is on, empty if none
+    fn get_shelf(&self) -> Rc<String>;
+
+    /// Returns the series of the book, empty if none
+    fn get_series(&self) -> Rc<String>;
+
+    /// Returns the number of the book in its series
+    fn get_series_index(&self) -> Option<f64>;
+
+    /// Returns the stars given to the book, from 1 to 5
+    fn get_rating(&self) -> Option<u8>;
+
+    /// Sets the series of the book and its number in the series
+    fn set_series(&mut self, series: impl Into<String>, index: Option<f64>);
+
+    /// Returns the series and the number of the book, e.g. "Fondazione #2", empty if none
+    fn get_series_label(&self) -> String {
+        let series = self.get_series();
+        match self.get_series_index() {
+            _ if series.is_empty() => String::new(),
+            Some(index) => format!("{} #{}", series, index),
+            None => series.to_string(),
+        }
+    }
+
     fn set_cover_image(&self, ctx: &mut PaintCtx) -> Result<(), Error>;
 
     fn get_cover_image(&self) -> Ref<Option<PietImage>>;
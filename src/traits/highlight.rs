@@ -0,0 +1,18 @@
+use druid::im::Vector;
+
+use crate::models::highlight::{Highlight, TextRange};
+
+pub trait HighlightManagement {
+    /// get all the highlights of the book, sorted by position
+    fn get(&self) -> &Vector<Highlight>;
+    /// get the highlight of the given range of text
+    fn get_highlight(&self, range: &TextRange) -> Option<&Highlight>;
+
+    /// add a highlight, replacing the one of the same range if any.
+    /// Return false if the highlight couldn't be saved
+    fn add_highlight(&mut self, book_path: String, highlight: Highlight) -> bool;
+    /// attach a note to the highlight of the given range
+    fn set_highlight_note(&mut self, book_path: String, range: &TextRange, note: String);
+    /// delete the highlight of the given range
+    fn delete_highlight(&mut self, book_path: String, range: &TextRange);
+}
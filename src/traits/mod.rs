@@ -1,3 +1,5 @@
 pub mod gui;
 pub mod reader;
-pub mod note;
\ No newline at end of file
+pub mod note;
+pub mod bookmark;
+pub mod highlight;
\ No newline at end of file
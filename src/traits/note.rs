@@ -17,5 +17,5 @@ pub trait NoteManagement {
     /// delete a note for the current chapter, page and start
     fn delete_note(&mut self, book: &Book, start: &String);
     /// delete notes for the current chapter and page
-    fn delete_notes(&mut self, book_path: String, chapter: usize, page: usize);
+    fn delete_notes(&mut self, book_path: String, chapter: usize);
 }
\ No newline at end of file
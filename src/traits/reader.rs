@@ -1,6 +1,8 @@
-use druid::{text::RichText, im::Vector};
+use std::ops::Range;
 
-use crate::models::note::BookNotes;
+use druid::{text::RichText, im::Vector, ExtEventSink};
+
+use crate::models::{bookmark::BookBookmarks, highlight::BookHighlights, note::BookNotes, position::Position, search::BookSearch, toc::TocEntry};
 
 /// trait that describes the book reading functions
 pub trait BookReading {
@@ -36,6 +38,25 @@ pub trait BookReading {
     fn get_number_of_chapters(&self) -> usize;
 
     fn calculate_chars_until_current_page(&self, font_size: f64) -> usize;
+
+    /// Method that returns the range of characters of the chapter text
+    /// shown in the given page of the current chapter
+    fn get_page_range(&self, page: usize) -> Range<usize>;
+
+    /// Method that returns the position of the start of the current page,
+    /// independent from the pagination
+    fn get_position(&self) -> Position;
+
+    /// Method that moves to the page that contains the given position
+    fn go_to_position(&mut self, position: Position);
+
+    /// Method that moves the reading position to the chapter and page
+    /// pointed by an entry of the table of contents
+    fn go_to_toc_entry(&mut self, entry: &TocEntry);
+
+    /// Method that returns the index (in the flattened table of contents)
+    /// of the entry that contains the current page
+    fn get_current_toc_index(&self) -> Option<usize>;
 }
 
 /// Trait that describes book management functions
@@ -44,7 +65,7 @@ pub trait BookManagement {
     /// Method that returns the path of the book
     fn get_path(&self) -> String;
 
-    /// Method that splits the chapter in blocks of const NUMBER_OF_LINES
+    /// Method that splits the chapter in pages that fit the current page layout
     /// and returns a vector of strings. Each string is a page of the chapter
     fn split_chapter_in_pages(&self, is_single_view: bool) -> Vector<String>;
 
@@ -56,11 +77,54 @@ pub trait BookManagement {
 
     fn load_chapter(&mut self);
 
+    /// Method that splits again the current chapter after the page layout
+    /// (window size or font) changed, keeping the reading position.
+    /// The pages of the whole book are counted in background if they aren't known yet,
+    /// see `set_pages_per_chapter`
+    fn repaginate(&mut self, sink: ExtEventSink);
+
+    /// Sets the first and last page of every chapter with the current layout
+    fn set_pages_per_chapter(&mut self, pages_per_chapter: Vec<(usize, usize)>);
+
     fn set_favorite(&mut self, favorite: bool);
 
+    /// Sets the tags of the book and saves them
+    fn set_tags(&mut self, tags: Vector<String>);
+
+    /// Moves the book on a shelf and saves it, an empty name removes it from its shelf
+    fn set_shelf(&mut self, shelf: impl Into<String>);
+
     fn load_notes(&mut self);
 
+    /// Method that reads the bookmarks of the book from file
+    fn load_bookmarks(&mut self);
+
+    /// Method that reads the highlights of the book from file
+    fn load_highlights(&mut self);
+
+    /// Method that reads the table of contents from the epub
+    fn load_toc(&mut self);
+
+    /// Method that returns the table of contents as a tree
+    fn get_toc(&self) -> &Vector<TocEntry>;
+
+    /// Method that returns all the entries of the table of contents in reading order
+    fn get_toc_entries(&self) -> &Vector<TocEntry>;
+
     fn get_notes(&self) -> &BookNotes;
 
     fn get_notes_mut(&mut self) -> &mut BookNotes;
+
+    fn get_bookmarks(&self) -> &BookBookmarks;
+
+    fn get_bookmarks_mut(&mut self) -> &mut BookBookmarks;
+
+    fn get_highlights(&self) -> &BookHighlights;
+
+    fn get_highlights_mut(&mut self) -> &mut BookHighlights;
+
+    /// Method that returns the results of the last search in the book
+    fn get_search(&self) -> &BookSearch;
+
+    fn get_search_mut(&mut self) -> &mut BookSearch;
 }
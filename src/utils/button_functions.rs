@@ -1,15 +1,23 @@
 use crate::{
-    MYENV,
-    models::book::Book,
-    utils::{saveload::{save_data}, envmanager::FontSize}, 
+    models::{
+        book::Book,
+        highlight::{Highlight, HighlightColor, TextRange},
+        position::{byte_to_char, Position},
+        rich::custom_lens::dual_page_0,
+        search::BookSearch,
+        toc::TocEntry,
+    },
+    utils::{saveload::save_data, search::search_book, stats},
     ReadingState, 
     CrabReaderState, 
     traits::{
         gui::{GUIBook, GUILibrary}, 
-        reader::{BookReading, BookManagement}, note::NoteManagement
+        reader::{BookReading, BookManagement}, note::NoteManagement,
+        highlight::HighlightManagement,
     },
 };
 use druid::EventCtx;
+use std::ops::Range;
 
 /// Activate or deactivate editing mode
 /// return the new value of is_editing
@@ -48,6 +56,7 @@ fn change_page(
         }
 
         let new_page = book.get_current_page_number() as isize + increaser;
+        let chapter_finished = new_page > book.get_last_page_number() as isize;
         if new_page > book.get_last_page_number() as isize {
             let last_page = book.get_number_of_pages() - 1;
             println!("DEBUG: current page: {}, last page of book: {}", book.get_current_page_number(), last_page);
@@ -70,19 +79,23 @@ fn change_page(
             println!("DEBUG: Changing page of chapter");
         }
         // function to save the page that the user is reading
-        save_data(
-            book.get_path().to_string(),
-            book.get_chapter_number(),
-            book.get_current_page_number(),
-            book.get_page_of_chapter(),
-            FontSize::from(MYENV.lock().unwrap().font.size),
-            false,
-        )
-        .unwrap();
+        save_position(book);
+        // the pages read again going back are not counted
+        if next {
+            let pages = increaser.unsigned_abs();
+            stats::record_page_turn(&book.get_path(), &book.get_title(), pages, chapter_finished);
+        }
         println!("DEBUG: Chapter: {}", book.get_chapter_number());
     }
 }
 
+// a failed save keeps the position in memory, the reading goes on
+fn save_position(book: &Book) {
+    if let Err(e) = save_data(book.get_path(), book.get_position(), false) {
+        println!("ERROR: failed to save the reading position: {}", e);
+    }
+}
+
 // function for going to next page
 pub fn go_next(data: &mut CrabReaderState) {
     let book = data.library.get_selected_book_mut().unwrap();
@@ -109,14 +122,7 @@ pub fn save_btn_fn(
             book.edit_text(reading_state.text_0.clone(), Some(reading_state.text_1.clone()));
         }
     }
-    let _ = save_data(
-        book.get_path(), 
-        book.get_chapter_number(), 
-        book.get_current_page_number(), 
-        book.get_page_of_chapter(), 
-        FontSize::from(MYENV.lock().unwrap().font.size), 
-        true
-    );
+    let _ = save_data(book.get_path(), book.get_position(), true);
     println!("DEBUG: SAVED");
     reading_state.is_editing = false;
     reading_state.text_0 = String::default();
@@ -141,13 +147,150 @@ pub fn change_chapter(book: &mut Book, chapter_number: usize) {
     // change chapter number in book
     book.set_chapter_number(chapter_number, true);
     // save the new reading position
-    save_data(
-        book.get_path().to_string(),
+    save_position(book);
+}
+
+pub fn go_to_toc_entry(book: &mut Book, entry: &TocEntry) {
+    // move to the chapter and page of the toc entry
+    book.go_to_toc_entry(entry);
+    // save the new reading position
+    save_position(book);
+}
+
+pub fn go_to_bookmark(book: &mut Book, position: Position) {
+    // move to the page that contains the bookmark
+    go_to_position(book, position);
+}
+
+/// Stores the text selected in a page of the reader.
+/// `source` is the selected range of the text of the page
+pub fn select_text(data: &mut CrabReaderState, side: usize, source: Range<usize>) {
+    let book = data.library.get_selected_book().unwrap();
+    if source.is_empty() {
+        data.reading_state.clear_selection();
+        return;
+    }
+
+    let (page, text) = if data.reading_state.single_view {
+        (book.get_current_page_number(), book.get_page_of_chapter())
+    } else {
+        let (left, right) = book.get_dual_pages();
+        let text = if side == 0 { left } else { right };
+        (dual_page_0(book) + side, text)
+    };
+
+    let start = byte_to_char(&text, source.start);
+    let end = byte_to_char(&text, source.end);
+    let page_start = book.get_page_range(page).start;
+
+    data.reading_state.selection = Some(TextRange::new(
         book.get_chapter_number(),
-        book.get_current_page_number(),
-        book.get_page_of_chapter(),
-        FontSize::from(MYENV.lock().unwrap().font.size),
-        false,
-    )
-    .unwrap();
-}
\ No newline at end of file
+        page_start + start,
+        page_start + end,
+    ));
+    data.reading_state.selected_text = text.chars().skip(start).take(end - start).collect();
+}
+
+/// Highlights the selected text, the text written in the notes box
+/// becomes the note of the highlight
+pub fn highlight_selection(data: &mut CrabReaderState, color: HighlightColor) {
+    let Some(range) = data.reading_state.selection else {
+        return;
+    };
+    let book = data.library.get_selected_book_mut().unwrap();
+    // the selection belongs to a page that is not shown anymore
+    if range.get_chapter() != book.get_chapter_number() {
+        data.reading_state.clear_selection();
+        return;
+    }
+
+    let highlight = Highlight::new(range, color, data.reading_state.selected_text.clone())
+        .with_note(data.reading_state.notes.trim().to_string());
+    let book_path = book.get_path();
+    if book.get_highlights_mut().add_highlight(book_path, highlight) {
+        data.reading_state.notes = String::default();
+        data.reading_state.clear_selection();
+    }
+}
+
+/// Searches the text written in the search box in the selected book
+/// and moves to the first hit
+pub fn search_in_book(data: &mut CrabReaderState) {
+    let query = data.reading_state.search_query.trim().to_string();
+    let book = data.library.get_selected_book_mut().unwrap();
+
+    let hits = search_book(
+        book.get_path().as_str(),
+        &query,
+        book.get_number_of_chapters(),
+    );
+    *book.get_search_mut() = BookSearch::new(query, hits);
+
+    if let Some(hit) = book.get_search_mut().next() {
+        go_to_position(book, hit.get_position());
+    }
+}
+
+/// Moves to the hit of the search with the given index
+pub fn go_to_search_hit(book: &mut Book, index: usize) {
+    if let Some(hit) = book.get_search_mut().select(index) {
+        go_to_position(book, hit.get_position());
+    }
+}
+
+/// Moves to the next (or previous) hit of the search
+pub fn go_to_next_search_hit(book: &mut Book, next: bool) {
+    let search = book.get_search_mut();
+    let hit = if next { search.next() } else { search.prev() };
+    if let Some(hit) = hit {
+        go_to_position(book, hit.get_position());
+    }
+}
+
+/// Opens the book of a result of the search in the library at the hit,
+/// the word found is searched in the book too.
+/// Returns false if the book is not in the library anymore
+pub fn open_library_hit(data: &mut CrabReaderState, index: usize) -> bool {
+    let Some(hit) = data.library.get_content_search().get_hit(index).cloned() else {
+        return false;
+    };
+    let Some(idx) = data.library.find_book_idx(hit.get_path()) else {
+        return false;
+    };
+    data.library.set_selected_book_idx(idx);
+
+    let book = data.library.get_selected_book_mut().unwrap();
+    book.load_chapter();
+    book.load_notes();
+    book.load_bookmarks();
+    book.load_highlights();
+    book.load_toc();
+
+    let term = hit.get_term().clone();
+    let hits = search_book(
+        book.get_path().as_str(),
+        &term,
+        book.get_number_of_chapters(),
+    );
+    *book.get_search_mut() = BookSearch::new(term.clone(), hits);
+    let selected = book
+        .get_search()
+        .get_hits()
+        .iter()
+        .find(|h| h.get_position() == hit.get_position())
+        .map(|h| h.get_index());
+    if let Some(selected) = selected {
+        book.get_search_mut().select(selected);
+    }
+    go_to_position(book, hit.get_position());
+
+    data.reading_state.search_query = term;
+    true
+}
+
+/// Moves the book to the position, that becomes the saved reading position
+pub fn go_to_position(book: &mut Book, position: Position) {
+    book.go_to_position(position);
+    // save the new reading position
+    save_position(book);
+}
@@ -0,0 +1,404 @@
+//! Import of a Calibre library.
+//! The metadata of the books is read from `metadata.db`, the database of the library,
+//! and saved as the metadata of the copies in the library: title, authors, series,
+//! tags and rating as they have been edited in Calibre, not as they are in the epubs.
+//! The cover of a book, `cover.jpg` in its folder, is used instead of the one of the epub.
+
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+};
+
+use druid::{ExtEventSink, Selector, Target};
+use rusqlite::{Connection, OpenFlags};
+
+use crate::{
+    models::documents::BookMetadata,
+    utils::{
+        dir_manager::get_cover_path,
+        duplicates,
+        epub_utils::{self, AUTHORS_SEPARATOR},
+        identity,
+        saveload::copy_book_in_folder,
+    },
+};
+
+/// the books of a Calibre library have been imported
+pub const CALIBRE_IMPORTED: Selector<CalibreReport> = Selector::new("crabreader.calibre_imported");
+
+/// value of the publication date of the books without one
+const UNDEFINED_DATE: &str = "0101-01-01";
+
+/// the ISO 639-2 codes of the languages used by Calibre with the ISO 639-1 code
+/// the epubs use, both the bibliographic and the terminology codes
+#[rustfmt::skip]
+const LANGUAGE_CODES: &[(&str, &str)] = &[
+    ("afr", "af"), ("alb", "sq"), ("ara", "ar"), ("arm", "hy"), ("baq", "eu"), ("bel", "be"),
+    ("ben", "bn"), ("bos", "bs"), ("bre", "br"), ("bul", "bg"), ("cat", "ca"), ("ces", "cs"),
+    ("chi", "zh"), ("cym", "cy"), ("cze", "cs"), ("dan", "da"), ("deu", "de"), ("dut", "nl"),
+    ("ell", "el"), ("eng", "en"), ("epo", "eo"), ("est", "et"), ("eus", "eu"), ("fas", "fa"),
+    ("fin", "fi"), ("fra", "fr"), ("fre", "fr"), ("fry", "fy"), ("geo", "ka"), ("ger", "de"),
+    ("gla", "gd"), ("gle", "ga"), ("glg", "gl"), ("gre", "el"), ("heb", "he"), ("hin", "hi"),
+    ("hrv", "hr"), ("hun", "hu"), ("hye", "hy"), ("ice", "is"), ("ind", "id"), ("isl", "is"),
+    ("ita", "it"), ("jpn", "ja"), ("kat", "ka"), ("kor", "ko"), ("lat", "la"), ("lav", "lv"),
+    ("lit", "lt"), ("ltz", "lb"), ("mac", "mk"), ("may", "ms"), ("mkd", "mk"), ("msa", "ms"),
+    ("nld", "nl"), ("nno", "nn"), ("nob", "nb"), ("nor", "no"), ("per", "fa"), ("pol", "pl"),
+    ("por", "pt"), ("roh", "rm"), ("ron", "ro"), ("rum", "ro"), ("rus", "ru"), ("slk", "sk"),
+    ("slo", "sk"), ("slv", "sl"), ("spa", "es"), ("sqi", "sq"), ("srp", "sr"), ("swe", "sv"),
+    ("tha", "th"), ("tur", "tr"), ("ukr", "uk"), ("urd", "ur"), ("vie", "vi"), ("wel", "cy"),
+    ("zho", "zh"),
+];
+
+/// A book of a Calibre library
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CalibreBook {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub series: Option<String>,
+    pub series_index: f64,
+    pub tags: Vec<String>,
+    /// from 0 to 10, two for every star
+    pub rating: Option<u8>,
+    /// description of the book, in html
+    pub comments: Option<String>,
+    pub publisher: Option<String>,
+    pub language: Option<String>,
+    /// type and value, e.g. ("isbn", "9788807900211")
+    pub identifiers: Vec<(String, String)>,
+    pub pubdate: Option<String>,
+    pub uuid: Option<String>,
+    /// the epub of the book, None if it has only other formats
+    pub epub: Option<PathBuf>,
+    pub cover: Option<PathBuf>,
+}
+
+impl CalibreBook {
+    /// The metadata of the book in the library, the chapters are counted when it is extracted
+    pub fn metadata(&self) -> BookMetadata {
+        let identifiers = self
+            .identifiers
+            .iter()
+            .map(|(kind, value)| match kind.as_str() {
+                "isbn" => format!("urn:isbn:{}", value),
+                _ => format!("{}:{}", kind, value),
+            })
+            .collect();
+        let date = self
+            .pubdate
+            .as_deref()
+            .map(|date| date.chars().take(10).collect::<String>())
+            .filter(|date| date != UNDEFINED_DATE)
+            .unwrap_or_default();
+
+        let default = BookMetadata::default();
+        BookMetadata {
+            title: self.title.clone(),
+            author: match self.authors.as_slice() {
+                [] => default.author,
+                authors => authors.join(AUTHORS_SEPARATOR),
+            },
+            lang: self
+                .language
+                .as_deref()
+                .map_or(default.lang, two_letter_language),
+            desc: self
+                .comments
+                .as_deref()
+                .map(|comments| rhtml2md::parse_html(comments).trim().to_string()),
+            date,
+            identifier: self
+                .uuid
+                .as_ref()
+                .map(|uuid| format!("urn:uuid:{}", uuid))
+                .unwrap_or_default(),
+            identifiers,
+            publisher: self.publisher.clone().unwrap_or_default(),
+            series: self.series.clone().unwrap_or_default(),
+            series_index: self.series.as_ref().map(|_| self.series_index),
+            // half stars are rounded up
+            rating: self
+                .rating
+                .filter(|rating| *rating > 0)
+                .map(|rating| (rating + 1) / 2),
+            tags: Some(self.tags.clone()),
+            ..default
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CalibreReport {
+    /// paths of the copies of the books in the library
+    pub imported: Vec<String>,
+    /// books already in the library
+    pub already_present: usize,
+    /// titles of the books that can't be imported, with the reason
+    pub skipped: Vec<(String, String)>,
+}
+
+impl CalibreReport {
+    pub fn describe(&self) -> String {
+        let mut text = format!(
+            "{} libri importati, {} già presenti",
+            self.imported.len(),
+            self.already_present
+        );
+        for (title, reason) in self.skipped.iter() {
+            text.push_str(&format!("\n{}: {}", title, reason));
+        }
+        text
+    }
+}
+
+fn names(connection: &Connection, sql: &str, book: i64) -> rusqlite::Result<Vec<String>> {
+    let mut statement = connection.prepare_cached(sql)?;
+    let names = statement.query_map([book], |row| row.get(0))?;
+    names.collect()
+}
+
+/// Reads the books of the Calibre library in the folder
+pub fn read_library(dir: &Path) -> Result<Vec<CalibreBook>, Box<dyn Error>> {
+    let database = dir.join("metadata.db");
+    if !database.exists() {
+        return Err(format!("{} non è una libreria di Calibre", dir.display()).into());
+    }
+    // Calibre may be open on the same library
+    let connection = Connection::open_with_flags(
+        database,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )?;
+
+    let mut statement = connection.prepare(
+        "SELECT b.id, b.title, b.path, b.has_cover, b.series_index, b.pubdate, b.uuid,
+            (SELECT name FROM data WHERE book = b.id AND format = 'EPUB' LIMIT 1),
+            (SELECT s.name FROM books_series_link l JOIN series s ON s.id = l.series
+                WHERE l.book = b.id),
+            (SELECT r.rating FROM books_ratings_link l JOIN ratings r ON r.id = l.rating
+                WHERE l.book = b.id),
+            (SELECT text FROM comments WHERE book = b.id),
+            (SELECT p.name FROM books_publishers_link l JOIN publishers p ON p.id = l.publisher
+                WHERE l.book = b.id),
+            (SELECT g.lang_code FROM books_languages_link l JOIN languages g ON g.id = l.lang_code
+                WHERE l.book = b.id ORDER BY l.item_order LIMIT 1)
+        FROM books b ORDER BY b.id",
+    )?;
+    let rows = statement.query_map([], |row| {
+        let id: i64 = row.get(0)?;
+        // the files of the book are in its folder, relative to the library
+        let folder = dir.join(row.get::<_, String>(2)?);
+        let has_cover: bool = row.get(3)?;
+        let epub: Option<String> = row.get(7)?;
+        let book = CalibreBook {
+            title: row.get(1)?,
+            series_index: row.get::<_, Option<f64>>(4)?.unwrap_or(1.0),
+            pubdate: row.get(5)?,
+            uuid: row.get(6)?,
+            epub: epub.map(|name| folder.join(format!("{}.epub", name))),
+            cover: has_cover.then(|| folder.join("cover.jpg")),
+            series: row.get(8)?,
+            rating: row
+                .get::<_, Option<i64>>(9)?
+                .map(|rating| rating.clamp(0, 10) as u8),
+            comments: row.get(10)?,
+            publisher: row.get(11)?,
+            language: row.get(12)?,
+            ..CalibreBook::default()
+        };
+        Ok((id, book))
+    })?;
+
+    let mut books = vec![];
+    for row in rows {
+        let (id, mut book) = row?;
+        book.authors = names(
+            &connection,
+            "SELECT a.name FROM books_authors_link l JOIN authors a ON a.id = l.author
+                WHERE l.book = ?1 ORDER BY l.id",
+            id,
+        )?;
+        book.tags = names(
+            &connection,
+            "SELECT t.name FROM books_tags_link l JOIN tags t ON t.id = l.tag
+                WHERE l.book = ?1 ORDER BY t.name",
+            id,
+        )?;
+        let mut statement =
+            connection.prepare_cached("SELECT type, val FROM identifiers WHERE book = ?1")?;
+        book.identifiers = statement
+            .query_map([id], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        books.push(book);
+    }
+    Ok(books)
+}
+
+/// Copies the epub in the library with the metadata of Calibre, returns the path of the copy
+fn import_book(book: &CalibreBook, epub: &str) -> Result<String, Box<dyn Error>> {
+    let path = copy_book_in_folder(&epub.to_string())?;
+    epub_utils::extract_all_with_metadata(&path, book.metadata())?;
+    if let Some(cover) = book.cover.as_ref().filter(|cover| cover.exists()) {
+        std::fs::copy(cover, get_cover_path(&path))?;
+    }
+    Ok(path)
+}
+
+/// Imports the epubs of the Calibre library in the folder,
+/// the books already in the library (`library` are their paths) are not imported again
+pub fn import_library(dir: &Path, library: &[String]) -> Result<CalibreReport, Box<dyn Error>> {
+    let mut report = CalibreReport::default();
+    let mut known = library.to_vec();
+    for book in read_library(dir)? {
+        let Some(epub) = book.epub.as_ref().and_then(|epub| epub.to_str()) else {
+            report
+                .skipped
+                .push((book.title.clone(), "nessun file EPUB".to_string()));
+            continue;
+        };
+        let Some(id) = identity::compute_id(epub) else {
+            report
+                .skipped
+                .push((book.title.clone(), "EPUB non leggibile".to_string()));
+            continue;
+        };
+        // the copies of the books of the library are skipped, there is no one to ask
+        if duplicates::find_copy(&id, &known).is_some() {
+            report.already_present += 1;
+            continue;
+        }
+
+        match import_book(&book, epub) {
+            Ok(path) => {
+                known.push(path.clone());
+                report.imported.push(path);
+            }
+            Err(e) => {
+                println!("ERROR: failed to import {}: {}", epub, e);
+                report.skipped.push((book.title.clone(), e.to_string()));
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Returns the ISO 639-1 code of a language, as the epubs have it, if the code
+/// is one of ISO 639-2; the other codes are kept as they are
+fn two_letter_language(code: &str) -> String {
+    LANGUAGE_CODES
+        .iter()
+        .find(|(three, _)| three.eq_ignore_ascii_case(code))
+        .map_or_else(|| code.to_string(), |(_, two)| two.to_string())
+}
+
+/// Imports the library in a thread, the report is sent with `CALIBRE_IMPORTED`
+pub fn import_in_background(sink: ExtEventSink, dir: PathBuf, library: Vec<String>) {
+    std::thread::spawn(move || {
+        let report = import_library(&dir, &library).unwrap_or_else(|e| {
+            println!("ERROR: failed to import {}: {}", dir.display(), e);
+            CalibreReport {
+                skipped: vec![(dir.display().to_string(), e.to_string())],
+                ..CalibreReport::default()
+            }
+        });
+        let _ = sink.submit_command(CALIBRE_IMPORTED, report, Target::Auto);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The tables of `metadata.db` the import reads
+    const SCHEMA: &str = "
+        CREATE TABLE books (id INTEGER PRIMARY KEY, title TEXT, path TEXT, has_cover BOOL,
+            series_index REAL, pubdate TIMESTAMP, uuid TEXT);
+        CREATE TABLE data (id INTEGER PRIMARY KEY, book INTEGER, format TEXT, name TEXT);
+        CREATE TABLE authors (id INTEGER PRIMARY KEY, name TEXT);
+        CREATE TABLE books_authors_link (id INTEGER PRIMARY KEY, book INTEGER, author INTEGER);
+        CREATE TABLE tags (id INTEGER PRIMARY KEY, name TEXT);
+        CREATE TABLE books_tags_link (id INTEGER PRIMARY KEY, book INTEGER, tag INTEGER);
+        CREATE TABLE series (id INTEGER PRIMARY KEY, name TEXT);
+        CREATE TABLE books_series_link (id INTEGER PRIMARY KEY, book INTEGER, series INTEGER);
+        CREATE TABLE ratings (id INTEGER PRIMARY KEY, rating INTEGER);
+        CREATE TABLE books_ratings_link (id INTEGER PRIMARY KEY, book INTEGER, rating INTEGER);
+        CREATE TABLE comments (id INTEGER PRIMARY KEY, book INTEGER, text TEXT);
+        CREATE TABLE publishers (id INTEGER PRIMARY KEY, name TEXT);
+        CREATE TABLE books_publishers_link (id INTEGER PRIMARY KEY, book INTEGER,
+            publisher INTEGER);
+        CREATE TABLE languages (id INTEGER PRIMARY KEY, lang_code TEXT);
+        CREATE TABLE books_languages_link (id INTEGER PRIMARY KEY, book INTEGER,
+            lang_code INTEGER, item_order INTEGER);
+        CREATE TABLE identifiers (id INTEGER PRIMARY KEY, book INTEGER, type TEXT, val TEXT);
+
+        INSERT INTO books VALUES (1, 'La coscienza di Zeno', 'Italo Svevo/La coscienza di Zeno (1)',
+            1, 2.0, '1923-05-01T00:00:00+00:00', 'b5a1');
+        INSERT INTO books VALUES (2, 'Senilità', 'Italo Svevo/Senilita (2)', 0, 1.0,
+            '0101-01-01T00:00:00+00:00', NULL);
+        INSERT INTO data VALUES (1, 1, 'EPUB', 'La coscienza di Zeno - Italo Svevo');
+        INSERT INTO data VALUES (2, 2, 'PDF', 'Senilita - Italo Svevo');
+        INSERT INTO authors VALUES (1, 'Italo Svevo'), (2, 'Ettore Schmitz');
+        INSERT INTO books_authors_link VALUES (1, 1, 1), (2, 1, 2), (3, 2, 1);
+        INSERT INTO tags VALUES (1, 'Romanzo'), (2, 'Classici');
+        INSERT INTO books_tags_link VALUES (1, 1, 1), (2, 1, 2);
+        INSERT INTO series VALUES (1, 'Trieste');
+        INSERT INTO books_series_link VALUES (1, 1, 1);
+        INSERT INTO ratings VALUES (1, 7);
+        INSERT INTO books_ratings_link VALUES (1, 1, 1);
+        INSERT INTO comments VALUES (1, 1, '<p>Il fumo</p>');
+        INSERT INTO languages VALUES (1, 'ita');
+        INSERT INTO books_languages_link VALUES (1, 1, 1, 0);
+        INSERT INTO identifiers VALUES (1, 1, 'isbn', '9788807900211');
+    ";
+
+    #[test]
+    fn calibre_library_is_read() {
+        let dir = std::env::temp_dir().join("crab-reader-calibre-test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        Connection::open(dir.join("metadata.db"))
+            .unwrap()
+            .execute_batch(SCHEMA)
+            .unwrap();
+
+        let books = read_library(&dir).unwrap();
+        assert_eq!(books.len(), 2);
+        let zeno = &books[0];
+        assert_eq!(zeno.authors, vec!["Italo Svevo", "Ettore Schmitz"]);
+        assert_eq!(zeno.tags, vec!["Classici", "Romanzo"]);
+        assert_eq!(
+            zeno.epub,
+            Some(dir.join(
+                "Italo Svevo/La coscienza di Zeno (1)/La coscienza di Zeno - Italo Svevo.epub"
+            ))
+        );
+        assert_eq!(
+            zeno.cover,
+            Some(dir.join("Italo Svevo/La coscienza di Zeno (1)/cover.jpg"))
+        );
+        // only the pdf
+        assert_eq!(books[1].epub, None);
+
+        let metadata = zeno.metadata();
+        assert_eq!(metadata.author, "Italo Svevo & Ettore Schmitz");
+        assert_eq!(metadata.series, "Trieste");
+        assert_eq!(metadata.series_index, Some(2.0));
+        assert_eq!(metadata.rating, Some(4));
+        // the language has the code of the epubs
+        assert_eq!(metadata.lang, "it");
+        assert_eq!(metadata.date, "1923-05-01");
+        assert_eq!(metadata.identifiers, vec!["urn:isbn:9788807900211"]);
+        assert_eq!(
+            metadata.tags,
+            Some(vec!["Classici".to_string(), "Romanzo".to_string()])
+        );
+
+        let metadata = books[1].metadata();
+        assert_eq!(metadata.date, "");
+        assert_eq!(metadata.series_index, None);
+        assert_eq!(metadata.rating, None);
+
+        let report = import_library(&dir, &[]).unwrap();
+        assert!(report.imported.is_empty());
+        assert_eq!(report.skipped.len(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,724 @@
+//! Import of the annotations made on e-ink readers: the `My Clippings.txt` file of the Kindle
+//! and the `metadata.epub.lua` sidecar files of KOReader.
+//! The clippings are matched to the books of the library by title and author, then their
+//! passage is looked for in the chapters of the book: first as it is (ignoring case, spaces
+//! and typographic quotes), then by its first or last words, at last with a fuzzy comparison.
+//! A passage found becomes a highlight, with the note written on the device if there is one.
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    path::{Path, PathBuf},
+};
+
+use druid::{ExtEventSink, Selector, Target};
+use rust_fuzzy_search::fuzzy_compare;
+
+use crate::{
+    models::highlight::{BookHighlights, Highlight, HighlightColor, TextRange},
+    traits::highlight::HighlightManagement,
+    utils::epub_utils::{get_chapter_text, get_metadata_of_book},
+};
+
+/// the annotations of a file have been imported, None if the file couldn't be read
+pub const ANNOTATIONS_IMPORTED: Selector<Option<ImportReport>> =
+    Selector::new("crabreader.annotations_imported");
+
+/// minimum similarity of a fuzzy match, the same required by the OCR page matching
+const MIN_SIMILARITY: f32 = 0.85;
+
+/// words used to find a passage by its start or its end
+const ANCHOR_WORDS: usize = 5;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Clipping {
+    pub title: String,
+    pub author: String,
+    /// the text highlighted on the device, empty for a note on a position
+    pub passage: String,
+    /// the note written on the device
+    pub note: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImportReport {
+    /// highlights created
+    pub imported: usize,
+    /// clippings already in the library
+    pub already_present: usize,
+    /// titles of the clippings without a matching book in the library
+    pub unknown_books: Vec<String>,
+    /// clippings whose passage hasn't been found in their book
+    pub not_found: usize,
+}
+
+impl ImportReport {
+    pub fn describe(&self) -> String {
+        let mut text = format!(
+            "{} annotazioni importate, {} già presenti",
+            self.imported, self.already_present
+        );
+        if self.not_found > 0 {
+            text.push_str(&format!(
+                "\n{} passaggi non trovati nei libri",
+                self.not_found
+            ));
+        }
+        if !self.unknown_books.is_empty() {
+            text.push_str(&format!(
+                "\nLibri non presenti nella libreria: {}",
+                self.unknown_books.join(", ")
+            ));
+        }
+        text
+    }
+}
+
+/// The text of a chapter, with its normalized characters to look for the passages in
+struct ChapterText {
+    text: String,
+    /// see `normalize_text`
+    chars: Vec<char>,
+    offsets: Vec<usize>,
+    /// length of the text in characters
+    len: usize,
+}
+
+impl ChapterText {
+    fn new(text: String) -> Self {
+        let (chars, offsets) = normalize_text(&text);
+        let len = text.chars().count();
+        Self {
+            text,
+            chars,
+            offsets,
+            len,
+        }
+    }
+}
+
+/// Reads the clippings of a file, a Kindle `My Clippings.txt` or a KOReader `.lua` sidecar
+pub fn read_clippings(file: &Path) -> Result<Vec<Clipping>, Box<dyn Error>> {
+    let text = std::fs::read_to_string(file)?;
+    if file.extension().map_or(false, |ext| ext == "lua") {
+        parse_koreader(&text)
+    } else {
+        Ok(parse_kindle(&text))
+    }
+}
+
+/// Imports the clippings of the file in the books of the library with the given paths
+pub fn import_file(file: &Path, library: &[String]) -> Result<ImportReport, Box<dyn Error>> {
+    let clippings = read_clippings(file)?;
+    let books: Vec<(String, String, String)> = library
+        .iter()
+        .map(|path| {
+            let metadata = get_metadata_of_book(path);
+            (path.clone(), metadata.title, metadata.author)
+        })
+        .collect();
+
+    // the text of the chapters of the books, read and normalized once
+    let mut texts: HashMap<String, Vec<ChapterText>> = HashMap::new();
+    let mut report = ImportReport::default();
+    for clipping in clippings {
+        if clipping.passage.trim().is_empty() {
+            // a note on a position of the device can't be placed in the book
+            report.not_found += 1;
+            continue;
+        }
+        let Some(path) = find_book(&clipping, &books) else {
+            if !report.unknown_books.contains(&clipping.title) {
+                report.unknown_books.push(clipping.title.clone());
+            }
+            continue;
+        };
+        let chapters = texts.entry(path.clone()).or_insert_with(|| {
+            (0..get_metadata_of_book(path).chapters)
+                .map(|chapter| ChapterText::new(get_chapter_text(path, chapter).to_string()))
+                .collect()
+        });
+        let (passage, _) = normalize_text(&clipping.passage);
+        let locate = |fuzzy: bool| {
+            chapters.iter().enumerate().find_map(|(chapter, text)| {
+                locate_passage(text, &passage, fuzzy).map(|range| (chapter, range))
+            })
+        };
+        // the fuzzy search is slower, it is tried only if the passage is in no chapter
+        let found = locate(false).or_else(|| locate(true));
+        let Some((chapter, (start, end))) = found else {
+            report.not_found += 1;
+            continue;
+        };
+
+        let range = TextRange::new(chapter, start, end);
+        let mut highlights = BookHighlights::with_loading(path.clone());
+        if highlights.get_highlight(&range).is_some() {
+            report.already_present += 1;
+            continue;
+        }
+        let text: String = chapters[chapter]
+            .text
+            .chars()
+            .skip(start)
+            .take(end - start)
+            .collect();
+        let highlight = Highlight::new(range, HighlightColor::Yellow, text)
+            .with_note(clipping.note.trim().to_string());
+        if highlights.add_highlight(path.clone(), highlight) {
+            report.imported += 1;
+        }
+    }
+    Ok(report)
+}
+
+/// Imports the clippings of the file in a thread, the report is sent with `ANNOTATIONS_IMPORTED`
+pub fn import_in_background(sink: ExtEventSink, file: PathBuf, library: Vec<String>) {
+    std::thread::spawn(move || {
+        let report = match import_file(&file, &library) {
+            Ok(report) => Some(report),
+            Err(e) => {
+                println!("ERROR: failed to import {}: {}", file.display(), e);
+                None
+            }
+        };
+        let _ = sink.submit_command(ANNOTATIONS_IMPORTED, report, Target::Auto);
+    });
+}
+
+/// Returns the path of the book of the clipping, `books` are the paths with title and author
+fn find_book<'a>(clipping: &Clipping, books: &'a [(String, String, String)]) -> Option<&'a String> {
+    let title = normalize_name(&clipping.title);
+    let author = normalize_author(&clipping.author);
+    books
+        .iter()
+        .filter_map(|(path, book_title, book_author)| {
+            let book_title = normalize_name(book_title);
+            let book_author = normalize_author(book_author);
+            let mut similarity = fuzzy_compare(&title, &book_title);
+            // the devices may add a subtitle to the title
+            if !title.is_empty()
+                && (title.starts_with(&book_title) || book_title.starts_with(&title))
+            {
+                similarity = similarity.max(0.9);
+            }
+            if !author.is_empty() && !book_author.is_empty() {
+                similarity = (similarity + fuzzy_compare(&author, &book_author).max(0.5)) / 2.0;
+            }
+            (similarity >= MIN_SIMILARITY).then(|| (path, similarity))
+        })
+        .max_by(|(_, one), (_, other)| one.total_cmp(other))
+        .map(|(path, _)| path)
+}
+
+fn normalize_name(name: &str) -> String {
+    name.to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The names of the authors in any order, "Svevo, Italo" is "Italo Svevo"
+fn normalize_author(author: &str) -> String {
+    let name = normalize_name(author);
+    let mut words: Vec<&str> = name.split(' ').collect();
+    words.sort_unstable();
+    words.join(" ")
+}
+
+/// The text without case, repeated spaces and typographic characters, with the offset
+/// in characters in the original text of each of its characters
+fn normalize_text(text: &str) -> (Vec<char>, Vec<usize>) {
+    let mut chars = vec![];
+    let mut offsets = vec![];
+    for (offset, c) in text.chars().enumerate() {
+        let c = match c {
+            '\u{2018}' | '\u{2019}' | '`' => '\'',
+            '\u{201C}' | '\u{201D}' | '«' | '»' => '"',
+            '\u{2013}' | '\u{2014}' => '-',
+            '\u{00AD}' => continue,
+            c if c.is_whitespace() => ' ',
+            c => c,
+        };
+        if c == ' ' && chars.last().map_or(true, |last| *last == ' ') {
+            continue;
+        }
+        chars.extend(c.to_lowercase());
+        offsets.extend(std::iter::repeat(offset).take(chars.len() - offsets.len()));
+    }
+    if chars.last() == Some(&' ') {
+        chars.pop();
+        offsets.pop();
+    }
+    (chars, offsets)
+}
+
+fn find_chars(text: &[char], pattern: &[char]) -> Option<usize> {
+    if pattern.is_empty() || pattern.len() > text.len() {
+        return None;
+    }
+    text.windows(pattern.len())
+        .position(|window| window == pattern)
+}
+
+/// Finds the normalized passage in the text of a chapter,
+/// returns its start and end offsets in characters
+fn locate_passage(
+    chapter: &ChapterText,
+    passage_chars: &[char],
+    fuzzy: bool,
+) -> Option<(usize, usize)> {
+    let (text_chars, offsets) = (&chapter.chars, &chapter.offsets);
+    if passage_chars.is_empty() {
+        return None;
+    }
+    let len = passage_chars.len();
+    // the offset after the last character of the match
+    let end_of = |last: usize| offsets.get(last).map_or(chapter.len, |offset| offset + 1);
+
+    if !fuzzy {
+        if let Some(start) = find_chars(text_chars, passage_chars) {
+            return Some((offsets[start], end_of(start + len - 1)));
+        }
+
+        let words: Vec<&[char]> = passage_chars.split(|c| *c == ' ').collect();
+        if words.len() <= ANCHOR_WORDS {
+            return None;
+        }
+        let head = words[..ANCHOR_WORDS].join(&' ');
+        if let Some(start) = find_chars(text_chars, &head) {
+            let last = (start + len - 1).min(text_chars.len() - 1);
+            return Some((offsets[start], end_of(last)));
+        }
+        let tail = words[words.len() - ANCHOR_WORDS..].join(&' ');
+        return find_chars(text_chars, &tail).map(|start| {
+            let last = start + tail.len() - 1;
+            let first = (last + 1).saturating_sub(len);
+            (offsets[first], end_of(last))
+        });
+    }
+
+    // the window of the length of the passage, starting at a word, most similar to it
+    let passage: String = passage_chars.iter().collect();
+    let text_len = text_chars.len();
+    (0..text_len)
+        .filter(|start| *start == 0 || text_chars[start - 1] == ' ')
+        .filter(|start| start + len <= text_len)
+        .map(|start| {
+            let window: String = text_chars[start..start + len].iter().collect();
+            (start, fuzzy_compare(&passage, &window))
+        })
+        .filter(|(_, similarity)| *similarity >= MIN_SIMILARITY)
+        .max_by(|(_, one), (_, other)| one.total_cmp(other))
+        .map(|(start, _)| (offsets[start], end_of(start + len - 1)))
+}
+
+/// Parses a Kindle `My Clippings.txt`: entries separated by a line of `=`, each with the title
+/// and the author, a line describing the kind and the location, and the text.
+/// A note follows the highlight it is written on, and is joined to it
+pub fn parse_kindle(text: &str) -> Vec<Clipping> {
+    let mut clippings: Vec<(Clipping, String)> = vec![];
+    for entry in text.trim_start_matches('\u{feff}').split("==========") {
+        let mut lines = entry.trim().lines();
+        let (Some(header), Some(info)) = (lines.next(), lines.next()) else {
+            continue;
+        };
+        let content = lines.collect::<Vec<_>>().join("\n").trim().to_string();
+        let (title, author) = split_title_author(header.trim_start_matches('\u{feff}').trim());
+        let info_lower = info.to_lowercase();
+        let location = kindle_location(&info_lower);
+
+        if info_lower.contains("highlight") || info_lower.contains("evidenziazione") {
+            clippings.push((
+                Clipping {
+                    title,
+                    author,
+                    passage: content,
+                    note: String::new(),
+                },
+                location,
+            ));
+        } else if info_lower.contains("note") || info_lower.contains("nota") {
+            // the note is written at the end of the highlight it comments
+            let highlight = clippings
+                .iter_mut()
+                .rev()
+                .find(|(clipping, highlight_location)| {
+                    clipping.title == title
+                        && clipping.note.is_empty()
+                        && !location.is_empty()
+                        && highlight_location.rsplit('-').next() == Some(location.as_str())
+                });
+            match highlight {
+                Some((clipping, _)) => clipping.note = content,
+                None => clippings.push((
+                    Clipping {
+                        title,
+                        author,
+                        passage: String::new(),
+                        note: content,
+                    },
+                    location,
+                )),
+            }
+        }
+        // the bookmarks have no text
+    }
+    clippings
+        .into_iter()
+        .map(|(clipping, _)| clipping)
+        .collect()
+}
+
+/// "Title (Author)": the author is in the last parentheses
+fn split_title_author(header: &str) -> (String, String) {
+    match header
+        .strip_suffix(')')
+        .and_then(|rest| rest.rsplit_once('('))
+    {
+        Some((title, author)) => (title.trim().to_string(), author.trim().to_string()),
+        None => (header.to_string(), String::new()),
+    }
+}
+
+/// The location of a clipping, "150-152" in "- your highlight on page 12 | location 150-152 |"
+fn kindle_location(info: &str) -> String {
+    info.split('|')
+        .find(|part| {
+            part.contains("location") || part.contains("posizione") || part.contains("pos.")
+        })
+        .and_then(|part| part.split_whitespace().last())
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// A value of the Lua tables written by KOReader
+#[derive(Clone, Debug, PartialEq)]
+enum Lua {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    Str(String),
+    Table(Vec<(Lua, Lua)>),
+}
+
+impl Lua {
+    fn get(&self, key: &str) -> Option<&Lua> {
+        match self {
+            Lua::Table(entries) => entries
+                .iter()
+                .find(|(k, _)| matches!(k, Lua::Str(k) if k == key))
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    fn get_str(&self, key: &str) -> String {
+        match self.get(key) {
+            Some(Lua::Str(value)) => value.clone(),
+            _ => String::new(),
+        }
+    }
+
+    fn values(&self) -> Vec<&Lua> {
+        match self {
+            Lua::Table(entries) => entries.iter().map(|(_, value)| value).collect(),
+            _ => vec![],
+        }
+    }
+}
+
+struct LuaParser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl LuaParser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_blank(&mut self) {
+        loop {
+            match self.peek() {
+                Some(c) if c.is_whitespace() => self.pos += 1,
+                Some('-') if self.chars.get(self.pos + 1) == Some(&'-') => {
+                    while !matches!(self.peek(), None | Some('\n')) {
+                        self.pos += 1;
+                    }
+                }
+                _ => return,
+            }
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        self.skip_blank();
+        if self.peek() != Some(c) {
+            return Err(format!("expected '{}' at {}", c, self.pos));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn name(&mut self) -> String {
+        let start = self.pos;
+        while matches!(self.peek(), Some(c) if c.is_alphanumeric() || c == '_') {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    fn value(&mut self) -> Result<Lua, String> {
+        self.skip_blank();
+        match self.peek() {
+            Some('{') => self.table(),
+            Some(quote @ ('"' | '\'')) => self.string(quote).map(Lua::Str),
+            Some(c) if c.is_ascii_digit() || c == '-' || c == '.' => {
+                let start = self.pos;
+                self.pos += 1;
+                while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '+')
+                {
+                    self.pos += 1;
+                }
+                let number: String = self.chars[start..self.pos].iter().collect();
+                number
+                    .parse()
+                    .map(Lua::Number)
+                    .map_err(|_| format!("invalid number {}", number))
+            }
+            _ => match self.name().as_str() {
+                "true" => Ok(Lua::Bool(true)),
+                "false" => Ok(Lua::Bool(false)),
+                "nil" => Ok(Lua::Nil),
+                name => Err(format!("unexpected '{}' at {}", name, self.pos)),
+            },
+        }
+    }
+
+    fn string(&mut self, quote: char) -> Result<String, String> {
+        self.pos += 1;
+        let mut string = String::new();
+        loop {
+            let Some(c) = self.peek() else {
+                return Err("unterminated string".to_string());
+            };
+            self.pos += 1;
+            match c {
+                c if c == quote => return Ok(string),
+                '\\' => {
+                    let Some(escaped) = self.peek() else {
+                        return Err("unterminated string".to_string());
+                    };
+                    self.pos += 1;
+                    match escaped {
+                        'n' | '\n' => string.push('\n'),
+                        't' => string.push('\t'),
+                        'r' => string.push('\r'),
+                        c if c.is_ascii_digit() => {
+                            // a byte in decimal, up to three digits
+                            let mut code = c.to_digit(10).unwrap_or_default();
+                            for _ in 0..2 {
+                                match self.peek().and_then(|c| c.to_digit(10)) {
+                                    Some(digit) => {
+                                        code = code * 10 + digit;
+                                        self.pos += 1;
+                                    }
+                                    None => break,
+                                }
+                            }
+                            string.extend(char::from_u32(code));
+                        }
+                        c => string.push(c),
+                    }
+                }
+                c => string.push(c),
+            }
+        }
+    }
+
+    fn table(&mut self) -> Result<Lua, String> {
+        self.expect('{')?;
+        let mut entries = vec![];
+        let mut index = 1.0;
+        loop {
+            self.skip_blank();
+            match self.peek() {
+                Some('}') => {
+                    self.pos += 1;
+                    return Ok(Lua::Table(entries));
+                }
+                Some('[') => {
+                    self.pos += 1;
+                    let key = self.value()?;
+                    self.expect(']')?;
+                    self.expect('=')?;
+                    entries.push((key, self.value()?));
+                }
+                Some(c) if c.is_alphabetic() || c == '_' => {
+                    let start = self.pos;
+                    let name = self.name();
+                    self.skip_blank();
+                    if self.peek() == Some('=') {
+                        self.pos += 1;
+                        entries.push((Lua::Str(name), self.value()?));
+                    } else {
+                        // true, false or nil in a list
+                        self.pos = start;
+                        entries.push((Lua::Number(index), self.value()?));
+                        index += 1.0;
+                    }
+                }
+                Some(_) => {
+                    entries.push((Lua::Number(index), self.value()?));
+                    index += 1.0;
+                }
+                None => return Err("unterminated table".to_string()),
+            }
+            self.skip_blank();
+            if matches!(self.peek(), Some(',' | ';')) {
+                self.pos += 1;
+            }
+        }
+    }
+}
+
+/// Parses a KOReader sidecar: a Lua table with the properties of the document and its
+/// annotations, in `annotations` since 2024 and in `highlight` by page before
+pub fn parse_koreader(text: &str) -> Result<Vec<Clipping>, Box<dyn Error>> {
+    let mut parser = LuaParser {
+        chars: text.chars().collect(),
+        pos: 0,
+    };
+    parser.skip_blank();
+    if parser.name() != "return" {
+        parser.pos = 0;
+    }
+    let sidecar = parser.value()?;
+
+    let props = sidecar.get("doc_props");
+    let title = props
+        .map(|props| props.get_str("title"))
+        .unwrap_or_default();
+    let author = props
+        .map(|props| props.get_str("authors").replace('\n', ", "))
+        .unwrap_or_default();
+
+    let annotations: Vec<&Lua> = match sidecar.get("annotations") {
+        Some(annotations) => annotations.values(),
+        None => sidecar
+            .get("highlight")
+            .map(|pages| {
+                pages
+                    .values()
+                    .into_iter()
+                    .flat_map(|page| page.values())
+                    .collect()
+            })
+            .unwrap_or_default(),
+    };
+
+    Ok(annotations
+        .into_iter()
+        .map(|annotation| Clipping {
+            title: title.clone(),
+            author: author.clone(),
+            passage: annotation.get_str("text"),
+            note: annotation.get_str("note"),
+        })
+        .filter(|clipping| !clipping.passage.is_empty() || !clipping.note.is_empty())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kindle_clippings_are_parsed() {
+        let text = "\u{feff}La coscienza di Zeno (Svevo, Italo)
+- Your Highlight on page 3 | Location 40-42 | Added on Monday, 1 January 2024 10:00:00
+
+Il dottore mi ha pregato di scriverla.
+==========
+La coscienza di Zeno (Svevo, Italo)
+- Your Note on page 3 | Location 42 | Added on Monday, 1 January 2024 10:01:00
+
+Inizio del romanzo
+==========
+La coscienza di Zeno (Svevo, Italo)
+- Your Bookmark on page 5 | Location 60 | Added on Monday, 1 January 2024 10:02:00
+
+
+==========
+";
+        let clippings = parse_kindle(text);
+        assert_eq!(
+            clippings,
+            vec![Clipping {
+                title: "La coscienza di Zeno".to_string(),
+                author: "Svevo, Italo".to_string(),
+                passage: "Il dottore mi ha pregato di scriverla.".to_string(),
+                note: "Inizio del romanzo".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn koreader_sidecars_are_parsed() {
+        let text = r#"-- we can read Lua syntax here!
+return {
+    ["annotations"] = {
+        [1] = {
+            ["chapter"] = "Il fumo",
+            ["note"] = "Da rileggere",
+            ["pos0"] = "/body/DocFragment[3]/body/p[2]/text().0",
+            ["text"] = "l'ultima \"sigaretta\"\
+davvero",
+        },
+    },
+    ["doc_props"] = {
+        ["authors"] = "Italo Svevo",
+        ["title"] = "La coscienza di Zeno",
+    },
+    ["percent_finished"] = 0.25,
+    ["summary"] = { ["status"] = "reading", },
+}
+"#;
+        let clippings = parse_koreader(text).unwrap();
+        assert_eq!(clippings.len(), 1);
+        assert_eq!(clippings[0].title, "La coscienza di Zeno");
+        assert_eq!(clippings[0].passage, "l'ultima \"sigaretta\"\ndavvero");
+        assert_eq!(clippings[0].note, "Da rileggere");
+    }
+
+    #[test]
+    fn passages_are_located() {
+        let text =
+            "Il dottore mi ha pregato di scriverla. Decisi di fumare l\u{2019}ultima  sigaretta.";
+        let start = text.find("Decisi").unwrap();
+        let end = text.chars().count() - 1;
+        let chapter = ChapterText::new(text.to_string());
+        let locate_passage = |passage: &str, fuzzy: bool| {
+            locate_passage(&chapter, &normalize_text(passage).0, fuzzy)
+        };
+
+        assert_eq!(
+            locate_passage("decisi di fumare l'ultima sigaretta", false),
+            Some((start, end))
+        );
+        // the device cut the passage differently
+        assert_eq!(
+            locate_passage("Decisi di fumare l'ultima sigaretta e basta", false).map(|(s, _)| s),
+            Some(start)
+        );
+        assert_eq!(
+            locate_passage("Decisi di fumare l'ultma sigaretta", true).map(|(s, _)| s),
+            Some(start)
+        );
+        assert_eq!(locate_passage("Tutt'altro libro", true), None);
+    }
+}
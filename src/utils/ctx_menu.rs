@@ -1,16 +1,128 @@
-use crate::{CrabReaderState, utils::fonts::{FONT, self, SET_FONT_SMALL, SET_FONT_MEDIUM, SET_FONT_LARGE}, MYENV};
-use druid::{Menu, MenuItem, Command, Target, Env, FontFamily, FontDescriptor};
+use crate::{CrabReaderState, models::command::Trigger, traits::gui::{GUIBook, GUILibrary}, utils::fonts::{FONT, self, SET_FONT_SMALL, SET_FONT_MEDIUM, SET_FONT_LARGE}, MYENV};
+use druid::{commands::{SHOW_OPEN_PANEL, SHOW_SAVE_PANEL}, Data, FileDialogOptions, FileInfo, FileSpec, Menu, MenuItem, Command, Selector, Target, Env, FontFamily, FontDescriptor};
 
-use super::{colors::CrabTheme};
+use super::{
+    colors::CrabTheme,
+    duplicates::MERGE_DUPLICATES,
+    export::{EXPORT_BOOK_NOTES, EXPORT_LIBRARY_NOTES},
+    opds::SHOW_OPDS_BROWSER,
+    stats::SHOW_READING_STATS,
+    watcher,
+};
 
-fn file() -> Menu<CrabReaderState> {
+fn file(data: &CrabReaderState) -> Menu<CrabReaderState> {
     let add_file = MenuItem::new("Aggiungi un eBook");
     let rm_file = MenuItem::new("Rimuovi un eBook");
     let del_cache = MenuItem::new("Svuota cache");
+    let merge_duplicates = MenuItem::new("Unisci i libri doppi")
+        .command(Command::new(MERGE_DUPLICATES, (), Target::Auto));
+    let reading_stats = MenuItem::new("Statistiche di lettura")
+        .command(Command::new(SHOW_READING_STATS, (), Target::Auto));
+    let export_book_notes = MenuItem::new("Esporta le note del libro...")
+        .on_activate(|ctx, data: &mut CrabReaderState, _| {
+            let Some(book) = data.library.get_selected_book() else {
+                return;
+            };
+            ctx.submit_command(Command::new(
+                SHOW_SAVE_PANEL,
+                export_dialog(EXPORT_BOOK_NOTES, &format!("Note - {}", book.get_title())),
+                Target::Auto,
+            ));
+        })
+        .enabled_if(|data: &CrabReaderState, _| data.library.get_selected_book().is_some());
+    let export_library_notes = MenuItem::new("Esporta le note della libreria...")
+        .on_activate(|ctx, _, _| {
+            ctx.submit_command(Command::new(
+                SHOW_SAVE_PANEL,
+                export_dialog(EXPORT_LIBRARY_NOTES, "Note della libreria"),
+                Target::Auto,
+            ));
+        });
+    let opds_catalogs = MenuItem::new("Sfoglia i cataloghi OPDS...")
+        .command(Command::new(SHOW_OPDS_BROWSER, (), Target::Auto));
+    let import_annotations = MenuItem::new("Importa annotazioni da Kindle o KOReader...")
+        .on_activate(|ctx, data: &mut CrabReaderState, _| {
+            data.open_file_trigger = Trigger::ANNOTATIONS;
+            ctx.submit_command(Command::new(
+                SHOW_OPEN_PANEL,
+                FileDialogOptions::new()
+                    .allowed_types(vec![
+                        FileSpec::new("Kindle My Clippings", &["txt"]),
+                        FileSpec::new("KOReader", &["lua"]),
+                    ])
+                    .title("Importa annotazioni"),
+                Target::Auto,
+            ));
+        });
+    let import_calibre = MenuItem::new("Importa una libreria Calibre...")
+        .on_activate(|ctx, data: &mut CrabReaderState, _| {
+            data.open_file_trigger = Trigger::CALIBRE;
+            ctx.submit_command(Command::new(
+                SHOW_OPEN_PANEL,
+                FileDialogOptions::new()
+                    .select_directories()
+                    .title("Cartella della libreria Calibre"),
+                Target::Auto,
+            ));
+        });
     Menu::new("File")
         .entry(add_file)
+        .entry(opds_catalogs)
         .entry(rm_file)
         .entry(del_cache)
+        .entry(merge_duplicates)
+        .entry(reading_stats)
+        .entry(export_book_notes)
+        .entry(export_library_notes)
+        .entry(import_annotations)
+        .entry(import_calibre)
+        .entry(watched_folders(data))
+}
+
+/// Dialog to choose the file the notes are exported to, the format is given by its type
+fn export_dialog(command: Selector<FileInfo>, name: &str) -> FileDialogOptions {
+    let markdown = FileSpec::new("Markdown", &["md"]);
+    FileDialogOptions::new()
+        .allowed_types(vec![
+            markdown,
+            FileSpec::new("HTML", &["html", "htm"]),
+            FileSpec::new("JSON", &["json"]),
+        ])
+        .default_type(markdown)
+        .default_name(format!("{}.md", name))
+        .title("Esporta note ed evidenziazioni")
+        .accept_command(command)
+}
+
+/// Menu of the folders watched for new books, with one entry to stop watching each of them
+fn watched_folders(data: &CrabReaderState) -> Menu<CrabReaderState> {
+    let add_folder = MenuItem::new("Osserva una cartella...")
+        .on_activate(|ctx, data: &mut CrabReaderState, _| {
+            data.open_file_trigger = Trigger::WATCHFOLDER;
+            ctx.submit_command(Command::new(
+                SHOW_OPEN_PANEL,
+                FileDialogOptions::new().select_directories(),
+                Target::Auto,
+            ));
+        });
+
+    let mut menu = Menu::new("Cartelle osservate")
+        .rebuild_on(|old: &CrabReaderState, data: &CrabReaderState, _| {
+            !old.watched_folders.same(&data.watched_folders)
+        })
+        .entry(add_folder);
+
+    for folder in data.watched_folders.iter().cloned() {
+        let item = MenuItem::new(format!("Smetti di osservare {}", folder))
+            .on_activate(move |_, data: &mut CrabReaderState, _| {
+                if let Err(e) = watcher::unwatch_folder(&folder) {
+                    println!("ERROR: {}", e);
+                }
+                data.watched_folders = watcher::folders().into();
+            });
+        menu = menu.entry(item);
+    }
+    menu
 }
 
 fn options() -> Menu<CrabReaderState> {
@@ -28,8 +140,8 @@ fn text() -> Menu<CrabReaderState> {
 }
 
 /// Returns the context menu for the main window
-pub fn main_window() -> Menu<CrabReaderState> {
-    Menu::new("CrabMenù").entry(file()).entry(options())
+pub fn main_window(data: &CrabReaderState) -> Menu<CrabReaderState> {
+    Menu::new("CrabMenù").entry(file(data)).entry(options())
 }
 
 fn theme() -> Menu<CrabReaderState> {
@@ -1,7 +1,8 @@
 use druid::{
-    commands::OPEN_FILE,
+    commands::{OPEN_FILE, SAVE_FILE_AS},
     widget::{Align, Flex, Label, LineBreaking},
     AppDelegate, Code, Env, Event, Handled, KeyEvent, WindowDesc, FontDescriptor, FontFamily, KeyOrValue,
+    Target, WidgetExt,
 };
 use std::{path::Path, rc::Rc};
 
@@ -10,16 +11,47 @@ use super::{
     colors::SWITCH_THEME, fonts::{SET_FONT_SMALL, SET_FONT_MEDIUM, SET_FONT_LARGE},
 };
 use crate::{
+    components::{
+        bookmark_widget::{DELETE_BOOKMARK, GO_TO_BOOKMARK, RENAME_BOOKMARK},
+        buttons::rbtn::RoundedButton,
+        highlight_widget::DELETE_HIGHLIGHT,
+        library::content_search::OPEN_LIBRARY_HIT,
+        opds_browser::opds_browser,
+        page_label::TEXT_SELECTED,
+        reading_stats::reading_stats,
+        search_widget::GO_TO_SEARCH_HIT,
+    },
     models::{
         book::Book,
         command::Trigger,
         library::{Library, SortBy},
     },
     traits::{
+        bookmark::BookmarkManagement,
         gui::{GUIBook, GUILibrary},
+        highlight::HighlightManagement,
         reader::{BookManagement, BookReading},
     },
-    utils::{dir_manager::get_epub_dir, ocrmanager, saveload::copy_book_in_folder, fonts::FONT},
+    utils::{
+        calibre::{self, CALIBRE_IMPORTED},
+        clippings::{self, ANNOTATIONS_IMPORTED},
+        dir_manager::get_epub_dir,
+        duplicates::{self, DuplicateChoice, ResolveDuplicate, MERGE_DUPLICATES, RESOLVE_DUPLICATE},
+        epub_utils::{get_metadata_of_book, PAGES_CALCULATED},
+        export::{self, ExportScope, EXPORT_BOOK_NOTES, EXPORT_LIBRARY_NOTES},
+        fonts::{self, FONT},
+        kosync::{self, POSITION_PULLED},
+        ocrmanager,
+        opds::{
+            self, DOWNLOAD_OPDS_BOOK, OPDS_BOOK_DOWNLOADED, OPDS_FEED_LOADED, OPEN_OPDS_FEED,
+            REMOVE_OPDS_CATALOG, SHOW_OPDS_BROWSER,
+        },
+        opf,
+        paginator::PageLayout,
+        saveload::copy_book_in_folder,
+        stats::{self, SHOW_READING_STATS},
+        watcher::{self, WATCHED_FOLDERS_CHANGED},
+    },
     CrabReaderState, DisplayMode, ENTERING_READING_MODE, MYENV,
 };
 
@@ -37,6 +69,9 @@ impl AppDelegate<CrabReaderState> for ReadModeDelegate {
         match cmd {
             notif if notif.is(ENTERING_READING_MODE) => {
                 data.reading = true;
+                if let Some(book) = data.library.get_selected_book_mut() {
+                    book.mark_opened();
+                }
                 data.reading_state.enable(Rc::new(
                     data.library
                         .get_selected_book()
@@ -108,19 +143,10 @@ impl AppDelegate<CrabReaderState> for ReadModeDelegate {
                 // function to do if open file is triggered for ocr
                 fn ocr_fn(file_path: &Path, selected_book_mut: &mut Book, delegate_ctx: &mut druid::DelegateCtx, font_size: f64) {
                     let selected_book_path = selected_book_mut.get_path();
-                    
-                    //split by slash, get last element, split by dot, get first element
-                    let folder_name = selected_book_path
-                        .split("/")
-                        .last()
-                        .unwrap()
-                        .split(".")
-                        .next()
-                        .unwrap();
 
                     //call ocr on the img path
                     let ocr_result = ocrmanager::get_ebook_page(
-                        folder_name.to_string(),
+                        selected_book_path.to_string(),
                         file_path.to_str().unwrap().to_string(),
                         font_size
                     );
@@ -173,61 +199,6 @@ impl AppDelegate<CrabReaderState> for ReadModeDelegate {
                     );
                 }
 
-                // function to do if open file is triggered for add book
-                fn add_book_fn(
-                    file_path: &Path,
-                    library: &mut Library<Book>,
-                    delegate_ctx: &mut druid::DelegateCtx,
-                ) {
-                    let book_str = file_path.to_str().unwrap();
-                    let book_path = Path::new(book_str);
-
-                    let mut label_text =
-                        format!("Il libro {} è stato aggiunto alla libreria", book_str);
-                    let mut title = "Libro aggiunto".to_string();
-
-                    // check if book_path is a file from the epub folder
-                    let epub_dir = get_epub_dir();
-                    let file_name = book_path.file_name().unwrap();
-
-                    let mut exists = false;
-
-                    for path in epub_dir.read_dir().unwrap() {
-                        if path.unwrap().file_name() == file_name {
-                            exists = true;
-                            break;
-                        }
-                    }
-
-                    // if exists a book with the same name in the epub folder
-                    // or if the book is already in the library
-                    // then don't add it
-                    if exists || book_path == epub_dir.join(file_name) {
-                        //Book already in epub folder
-                        title = "Libro già presente".to_string();
-                        label_text = "Il libro è già presente nella libreria, non puoi aggiungerlo"
-                            .to_string();
-                    } else {
-                        if let Ok(_) = copy_book_in_folder(&book_str.to_string()) {
-
-                            let real_path = get_epub_dir().join(book_path.file_name().unwrap());
-                            if real_path.exists() {
-                                // library.add_book(real_path.to_str().unwrap());
-                                library.schedule_book_loading(real_path.to_str().unwrap());
-                            }
-                        } else {
-                            println!("Error copying book in folder");
-                        }
-                    }
-
-                    show_alert_dialog(
-                        delegate_ctx, 
-                        Label::<CrabReaderState>::new(label_text).with_line_break_mode(LineBreaking::WordWrap), 
-                        title.as_str(),
-                        (400.0, 100.0)
-                    );
-                }
-
                 match data.open_file_trigger {
                     Trigger::OCR => {
                         ocr_fn(file_path, data.library.get_selected_book_mut().unwrap(), delegate_ctx, data.font.size);
@@ -240,7 +211,25 @@ impl AppDelegate<CrabReaderState> for ReadModeDelegate {
                         data.font.size
                     ),
 
-                    Trigger::ADDBOOK => add_book_fn(file_path, &mut data.library, delegate_ctx),
+                    Trigger::ADDBOOK => {
+                        add_book(file_path, &mut data.library, delegate_ctx);
+                    }
+                    Trigger::WATCHFOLDER => {
+                        if let Err(e) = watcher::watch_folder(file_path) {
+                            println!("ERROR: {}", e);
+                        }
+                        data.watched_folders = watcher::folders().into();
+                    }
+                    Trigger::ANNOTATIONS => clippings::import_in_background(
+                        delegate_ctx.get_external_handle(),
+                        file_path.to_path_buf(),
+                        data.library.book_paths(),
+                    ),
+                    Trigger::CALIBRE => calibre::import_in_background(
+                        delegate_ctx.get_external_handle(),
+                        file_path.to_path_buf(),
+                        data.library.book_paths(),
+                    ),
                     _ => {}
                 } //end match
 
@@ -248,6 +237,254 @@ impl AppDelegate<CrabReaderState> for ReadModeDelegate {
 
                 Handled::Yes
             }
+            cmd if cmd.is(RESOLVE_DUPLICATE) => {
+                if let Some(resolve) = cmd.get(RESOLVE_DUPLICATE) {
+                    if let Err(e) = duplicates::resolve(resolve, &mut data.library) {
+                        println!("ERROR: failed to import {}: {}", resolve.source, e);
+                    }
+                    opds::remove_download(&resolve.source);
+                }
+                Handled::Yes
+            }
+            cmd if cmd.is(MERGE_DUPLICATES) => {
+                let text = if data.reading {
+                    "Chiudi il libro prima di unire i libri doppi".to_string()
+                } else {
+                    match duplicates::merge_duplicates(&mut data.library) {
+                        Ok(0) => "Nella libreria non ci sono libri doppi".to_string(),
+                        Ok(n) => format!(
+                            "{} copie eliminate, i progressi e le note sono stati uniti",
+                            n
+                        ),
+                        Err(e) => {
+                            println!("ERROR: failed to merge the duplicates: {}", e);
+                            "Non è stato possibile unire i libri doppi".to_string()
+                        }
+                    }
+                };
+                show_alert_dialog(
+                    delegate_ctx,
+                    Label::<CrabReaderState>::new(text).with_line_break_mode(LineBreaking::WordWrap),
+                    "Libri doppi",
+                    (400.0, 100.0)
+                );
+                Handled::Yes
+            }
+            cmd if cmd.is(EXPORT_BOOK_NOTES) => {
+                let target = cmd.get_unchecked(EXPORT_BOOK_NOTES).path();
+                let Some(book) = data.library.get_selected_book() else {
+                    return Handled::Yes;
+                };
+                export_notes(delegate_ctx, &[book.get_path()], ExportScope::Book, target);
+                Handled::Yes
+            }
+            cmd if cmd.is(EXPORT_LIBRARY_NOTES) => {
+                let target = cmd.get_unchecked(EXPORT_LIBRARY_NOTES).path();
+                let paths = data.library.book_paths();
+                export_notes(delegate_ctx, &paths, ExportScope::Library, target);
+                Handled::Yes
+            }
+            cmd if cmd.is(SAVE_FILE_AS) => {
+                // the only file saved is the copy of the selected book with its edited metadata
+                let target = cmd.get_unchecked(SAVE_FILE_AS).path();
+                let Some(book) = data.library.get_selected_book() else {
+                    return Handled::Yes;
+                };
+                let book_path = book.get_path();
+                let metadata = get_metadata_of_book(&book_path);
+                let text = match target
+                    .to_str()
+                    .ok_or_else(|| "invalid file name".into())
+                    .and_then(|target| opf::write_epub_with_metadata(&book_path, target, &metadata))
+                {
+                    Ok(()) => format!("Copia salvata in {}", target.display()),
+                    Err(e) => {
+                        println!("ERROR: failed to write the copy of {}: {}", book_path, e);
+                        "Non è stato possibile salvare la copia del libro".to_string()
+                    }
+                };
+                show_alert_dialog(
+                    delegate_ctx,
+                    Label::<CrabReaderState>::new(text).with_line_break_mode(LineBreaking::WordWrap),
+                    "Metadati",
+                    (400.0, 100.0)
+                );
+                Handled::Yes
+            }
+            cmd if cmd.is(SHOW_OPDS_BROWSER) => {
+                show_alert_dialog(
+                    delegate_ctx,
+                    opds_browser().lens(CrabReaderState::opds),
+                    "Cataloghi OPDS",
+                    (600.0, 700.0)
+                );
+                Handled::Yes
+            }
+            cmd if cmd.is(OPEN_OPDS_FEED) => {
+                if let Some(url) = cmd.get(OPEN_OPDS_FEED) {
+                    data.opds.open(delegate_ctx.get_external_handle(), url.clone());
+                }
+                Handled::Yes
+            }
+            cmd if cmd.is(OPDS_FEED_LOADED) => {
+                if let Some((url, feed)) = cmd.get(OPDS_FEED_LOADED) {
+                    data.opds.loaded(url, feed.clone());
+                }
+                Handled::Yes
+            }
+            cmd if cmd.is(REMOVE_OPDS_CATALOG) => {
+                if let Some(url) = cmd.get(REMOVE_OPDS_CATALOG) {
+                    data.opds.remove_catalog(url);
+                }
+                Handled::Yes
+            }
+            cmd if cmd.is(DOWNLOAD_OPDS_BOOK) => {
+                if let Some((url, title)) = cmd.get(DOWNLOAD_OPDS_BOOK) {
+                    data.opds.download(delegate_ctx.get_external_handle(), url.clone(), title.clone());
+                }
+                Handled::Yes
+            }
+            cmd if cmd.is(OPDS_BOOK_DOWNLOADED) => {
+                if let Some(result) = cmd.get(OPDS_BOOK_DOWNLOADED) {
+                    data.opds.downloaded(result);
+                    // the book is imported as the ones chosen from the disk,
+                    // the download is deleted once it is copied in the library
+                    if let Ok(path) = result {
+                        if add_book(Path::new(path), &mut data.library, delegate_ctx) {
+                            opds::remove_download(path);
+                        }
+                    }
+                }
+                Handled::Yes
+            }
+            cmd if cmd.is(POSITION_PULLED) => {
+                let (path, local, remote) = cmd.get_unchecked(POSITION_PULLED);
+                // the pulled position is used only if the book is still open where it was
+                if let Some(book) = data.library.get_selected_book_mut() {
+                    if data.reading && book.get_path() == *path && book.get_position() == *local {
+                        button_functions::go_to_position(book, *remote);
+                        println!("DEBUG: resumed {} at the position read on another device", path);
+                    }
+                }
+                Handled::Yes
+            }
+            cmd if cmd.is(ANNOTATIONS_IMPORTED) => {
+                let text = match cmd.get_unchecked(ANNOTATIONS_IMPORTED) {
+                    Some(report) => report.describe(),
+                    None => "Non è stato possibile leggere le annotazioni".to_string(),
+                };
+                show_alert_dialog(
+                    delegate_ctx,
+                    Label::<CrabReaderState>::new(text).with_line_break_mode(LineBreaking::WordWrap),
+                    "Importa annotazioni",
+                    (400.0, 150.0)
+                );
+                Handled::Yes
+            }
+            cmd if cmd.is(CALIBRE_IMPORTED) => {
+                if let Some(report) = cmd.get(CALIBRE_IMPORTED) {
+                    for path in report.imported.iter() {
+                        data.library.schedule_book_loading(path.clone());
+                    }
+                    show_alert_dialog(
+                        delegate_ctx,
+                        Label::<CrabReaderState>::new(report.describe()).with_line_break_mode(LineBreaking::WordWrap),
+                        "Importa una libreria Calibre",
+                        (400.0, 200.0)
+                    );
+                }
+                Handled::Yes
+            }
+            cmd if cmd.is(PAGES_CALCULATED) => {
+                if let Some((path, layout, pages_per_chapter)) = cmd.get(PAGES_CALCULATED) {
+                    // the layout may have changed again while the pages were counted
+                    let book = data.library.find_book_idx(path).and_then(|idx| data.library.get_book_mut(idx));
+                    if let Some(book) = book.filter(|_| *layout == PageLayout::current().key()) {
+                        book.set_pages_per_chapter(pages_per_chapter.clone());
+                    }
+                }
+                Handled::Yes
+            }
+            cmd if cmd.is(SHOW_READING_STATS) => {
+                show_alert_dialog(
+                    delegate_ctx,
+                    reading_stats::<CrabReaderState>(&stats::load_summary()),
+                    "Statistiche di lettura",
+                    (450.0, 500.0)
+                );
+                Handled::Yes
+            }
+            cmd if cmd.is(WATCHED_FOLDERS_CHANGED) => {
+                if let Some(changes) = cmd.get(WATCHED_FOLDERS_CHANGED) {
+                    let open_book = data
+                        .library
+                        .get_selected_book()
+                        .filter(|_| data.reading)
+                        .map(|book| book.get_path());
+                    watcher::apply_changes(changes, &mut data.library, open_book.as_deref());
+                }
+                Handled::Yes
+            }
+            cmd if cmd.is(GO_TO_BOOKMARK) => {
+                if let Some(position) = cmd.get(GO_TO_BOOKMARK) {
+                    if let Some(book) = data.library.get_selected_book_mut() {
+                        button_functions::go_to_bookmark(book, *position);
+                    }
+                }
+                Handled::Yes
+            }
+            cmd if cmd.is(RENAME_BOOKMARK) => {
+                if let Some(position) = cmd.get(RENAME_BOOKMARK) {
+                    let name = data.reading_state.bookmark_name.clone();
+                    if let Some(book) = data.library.get_selected_book_mut() {
+                        let book_path = book.get_path();
+                        book.get_bookmarks_mut()
+                            .rename_bookmark(book_path, position, name);
+                        data.reading_state.bookmark_name = String::default();
+                    }
+                }
+                Handled::Yes
+            }
+            cmd if cmd.is(DELETE_BOOKMARK) => {
+                if let Some(position) = cmd.get(DELETE_BOOKMARK) {
+                    if let Some(book) = data.library.get_selected_book_mut() {
+                        let book_path = book.get_path();
+                        book.get_bookmarks_mut().delete_bookmark(book_path, position);
+                    }
+                }
+                Handled::Yes
+            }
+            cmd if cmd.is(TEXT_SELECTED) => {
+                if let Some((side, source)) = cmd.get(TEXT_SELECTED) {
+                    button_functions::select_text(data, *side, source.clone());
+                }
+                Handled::Yes
+            }
+            cmd if cmd.is(DELETE_HIGHLIGHT) => {
+                if let Some(range) = cmd.get(DELETE_HIGHLIGHT) {
+                    if let Some(book) = data.library.get_selected_book_mut() {
+                        let book_path = book.get_path();
+                        book.get_highlights_mut().delete_highlight(book_path, range);
+                    }
+                }
+                Handled::Yes
+            }
+            cmd if cmd.is(GO_TO_SEARCH_HIT) => {
+                if let Some(index) = cmd.get(GO_TO_SEARCH_HIT) {
+                    if let Some(book) = data.library.get_selected_book_mut() {
+                        button_functions::go_to_search_hit(book, *index);
+                    }
+                }
+                Handled::Yes
+            }
+            cmd if cmd.is(OPEN_LIBRARY_HIT) => {
+                if let Some(index) = cmd.get(OPEN_LIBRARY_HIT) {
+                    if button_functions::open_library_hit(data, *index) {
+                        delegate_ctx.submit_command(ENTERING_READING_MODE);
+                    }
+                }
+                Handled::Yes
+            }
             cmd if cmd.is(SWITCH_THEME) => {
                 if let Some(theme) = cmd.get(SWITCH_THEME) {
                     data.theme = theme.clone();
@@ -399,6 +636,10 @@ fn handle_esc(
 
     if data.reading {
         data.reading = false;
+        stats::end_session();
+        if let Some(book) = data.library.get_selected_book() {
+            kosync::push_position(&book.get_path(), book.get_position(), true);
+        }
         return;
     }
 
@@ -443,6 +684,8 @@ fn handle_enter(
 ) {
     if let Some(book) = data.library.get_selected_book_mut() {
         book.load_chapter();
+        kosync::pull_in_background(ctx.get_external_handle(), book.get_path(), book.get_position());
+        book.load_toc();
         ctx.submit_command(ENTERING_READING_MODE);
     }
 }
@@ -558,6 +801,104 @@ fn handle_u(
     }
 }
 
+/// Asks what to do with a book being imported that is already in the library
+fn show_duplicate_dialog(ctx: &mut druid::DelegateCtx, source: &str, existing: &str) {
+    let existing_name = Path::new(existing)
+        .file_name()
+        .map_or(existing.to_string(), |name| name.to_string_lossy().to_string());
+    let text = format!(
+        "Il libro {} è già nella libreria come {}. Vuoi saltarlo, sostituire quello nella libreria o tenerli entrambi?",
+        source, existing_name
+    );
+
+    let button = |text: &str, choice: DuplicateChoice| {
+        let resolve = ResolveDuplicate {
+            source: source.to_string(),
+            existing: existing.to_string(),
+            choice,
+        };
+        RoundedButton::from_text(text)
+            .with_on_click(move |ctx, _: &mut CrabReaderState, _: &Env| {
+                ctx.submit_command(RESOLVE_DUPLICATE.with(resolve.clone()).to(Target::Global));
+                ctx.window().close();
+            })
+            .with_font(fonts::medium)
+    };
+
+    let buttons = Flex::row()
+        .with_flex_child(button("Salta", DuplicateChoice::Skip), 1.0)
+        .with_spacer(5.0)
+        .with_flex_child(button("Sostituisci", DuplicateChoice::Replace), 1.0)
+        .with_spacer(5.0)
+        .with_flex_child(button("Tieni entrambi", DuplicateChoice::KeepBoth), 1.0);
+
+    let dialog = Flex::column()
+        .with_child(Label::new(text).with_line_break_mode(LineBreaking::WordWrap))
+        .with_spacer(10.0)
+        .with_child(buttons)
+        .padding(10.0);
+
+    show_alert_dialog(ctx, dialog, "Libro già presente", (450.0, 180.0));
+}
+
+/// Exports the annotations of the books and tells the user how it went
+fn export_notes(ctx: &mut druid::DelegateCtx, paths: &[String], scope: ExportScope, target: &Path) {
+    let text = match export::export_to_file(paths, scope, target) {
+        Ok(count) => format!("{} note esportate in {}", count, target.display()),
+        Err(e) => {
+            println!("ERROR: failed to export the notes to {}: {}", target.display(), e);
+            "Non è stato possibile esportare le note".to_string()
+        }
+    };
+    show_alert_dialog(
+        ctx,
+        Label::<CrabReaderState>::new(text).with_line_break_mode(LineBreaking::WordWrap),
+        "Esporta note",
+        (400.0, 100.0)
+    );
+}
+
+/// Adds the book to the library, as a copy in the epubs folder:
+/// the books chosen by the user and the ones downloaded from a catalog.
+/// Returns false if the user is asked what to do with a copy of a book of the library,
+/// the file is needed until the choice is made
+fn add_book(
+    file_path: &Path,
+    library: &mut Library<Book>,
+    delegate_ctx: &mut druid::DelegateCtx,
+) -> bool {
+    let book_str = file_path.to_str().unwrap();
+    let book_path = Path::new(book_str);
+
+    let mut label_text = format!("Il libro {} è stato aggiunto alla libreria", book_str);
+    let mut title = "Libro aggiunto".to_string();
+
+    // if the book is already in the library then don't add it
+    if book_path.parent() == Some(get_epub_dir().as_path()) {
+        //Book already in epub folder
+        title = "Libro già presente".to_string();
+        label_text = "Il libro è già presente nella libreria, non puoi aggiungerlo".to_string();
+    } else if let Some(existing) = duplicates::find_duplicate(book_str, library) {
+        // the user chooses what to do with the copy
+        show_duplicate_dialog(delegate_ctx, book_str, &existing);
+        return false;
+    } else {
+        // a different book with the same name is copied with another name
+        match copy_book_in_folder(&book_str.to_string()) {
+            Ok(real_path) => library.schedule_book_loading(real_path),
+            Err(e) => println!("ERROR: copying book in folder: {}", e),
+        }
+    }
+
+    show_alert_dialog(
+        delegate_ctx, 
+        Label::<CrabReaderState>::new(label_text).with_line_break_mode(LineBreaking::WordWrap), 
+        title.as_str(),
+        (400.0, 100.0)
+    );
+    true
+}
+
 fn show_alert_dialog<T: druid::Data>(ctx: &mut druid::DelegateCtx, msg: impl druid::Widget<T> + 'static, title: &str, window_size: (f64, f64)) {
     //get coordinates of the center of the monitor
     let monitor = &druid::Screen::get_monitors()[0];
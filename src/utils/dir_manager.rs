@@ -1,7 +1,9 @@
-use std::path::{PathBuf, Path};
+use std::path::PathBuf;
 
 use dirs;
 
+use super::identity::book_id;
+
 const APP_NAME: &str = "crab-reader";
 
 fn get_app_dir() -> PathBuf {
@@ -58,6 +60,14 @@ pub fn get_saved_covers_dir() -> PathBuf {
     data_dir
 }
 
+/// Get path of the folder where the full-text index of the books is stored
+pub fn get_fulltext_index_dir() -> PathBuf {
+    let mut data_dir = get_app_dir();
+    data_dir.push("index");
+    let _ = std::fs::create_dir_all(&data_dir);
+    data_dir
+}
+
 /// Get path of env.json
 pub fn get_env_path() -> PathBuf {
     let mut config_file = get_config_dir();
@@ -78,15 +88,76 @@ pub fn get_books_notes_path() -> PathBuf {
     config_file
 }
 
+/// Get path of the bookmarks of the books
+pub fn get_books_bookmarks_path() -> PathBuf {
+    let mut config_file = get_config_dir();
+    config_file.push("books_bookmarks.json");
+    config_file
+}
+
+/// Get path of the highlights of the books
+pub fn get_books_highlights_path() -> PathBuf {
+    let mut config_file = get_config_dir();
+    config_file.push("books_highlights.json");
+    config_file
+}
+
+/// Get path of the folders watched for new books
+pub fn get_watched_folders_path() -> PathBuf {
+    let mut config_file = get_config_dir();
+    config_file.push("watched_folders.json");
+    config_file
+}
+
+/// Get path of the reading sessions
+pub fn get_reading_stats_path() -> PathBuf {
+    let mut config_file = get_config_dir();
+    config_file.push("reading_stats.json");
+    config_file
+}
+
+/// Get path of the settings of the progress sync
+pub fn get_kosync_path() -> PathBuf {
+    let mut config_file = get_config_dir();
+    config_file.push("kosync.json");
+    config_file
+}
+
+/// Get path of the OPDS catalogs
+pub fn get_opds_catalogs_path() -> PathBuf {
+    let mut config_file = get_config_dir();
+    config_file.push("opds_catalogs.json");
+    config_file
+}
+
+/// Get path of the ids of the books
+pub fn get_books_path() -> PathBuf {
+    let mut config_file = get_config_dir();
+    config_file.push("books.json");
+    config_file
+}
+
+/// Get path of the folder of the extracted chapters given a book path
+pub fn get_book_dir(book_path: &str) -> PathBuf {
+    get_saved_books_dir().join(book_id(book_path))
+}
+
+/// Get path of the folder of the edited chapters given a book path
+pub fn get_edited_book_dir(book_path: &str) -> PathBuf {
+    get_edited_books_dir().join(book_id(book_path))
+}
+
+/// Get path of the cover chosen for the book instead of the one of the epub
+pub fn get_cover_path(book_path: &str) -> PathBuf {
+    get_book_dir(book_path).join("cover.jpg")
+}
+
 /// Get path of the metadata file given a book path
 pub fn get_metadata_path(book_path: &String) -> PathBuf {
-    let book_name = Path::new(book_path).file_stem().unwrap().to_str().unwrap();
-
-    let mut book_dir = get_saved_books_dir()
-    .join(book_name);
+    let mut book_dir = get_book_dir(book_path);
 
     let _ = std::fs::create_dir_all(&book_dir);
     book_dir.push("metadata.json");
 
     book_dir
-}
\ No newline at end of file
+}
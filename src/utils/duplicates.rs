@@ -0,0 +1,169 @@
+//! Copies of the same book in the library: the same content, or the same OPF identifier
+//! (see `identity::same_book`). A book being imported is checked against the library,
+//! so that it can be skipped, replace the copy in the library or be kept as well;
+//! the copies already in the library can be merged into one, with their progress and notes.
+
+use druid::Selector;
+
+use crate::{
+    models::{book::Book, library::Library},
+    traits::gui::GUILibrary,
+    utils::{dir_manager::get_book_dir, fulltext, identity, saveload},
+};
+
+/// what to do with a book being imported that is already in the library
+pub const RESOLVE_DUPLICATE: Selector<ResolveDuplicate> =
+    Selector::new("crabreader.resolve_duplicate");
+
+/// merge the copies of the same book in the library
+pub const MERGE_DUPLICATES: Selector<()> = Selector::new("crabreader.merge_duplicates");
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DuplicateChoice {
+    /// the book is not imported
+    Skip,
+    /// the book replaces the copy in the library, which keeps its progress and notes
+    Replace,
+    /// the book is imported with another name
+    KeepBoth,
+}
+
+#[derive(Clone, Debug)]
+pub struct ResolveDuplicate {
+    /// the file being imported
+    pub source: String,
+    /// the copy in the library
+    pub existing: String,
+    pub choice: DuplicateChoice,
+}
+
+/// Returns the path of a book of the library that is the same book as the file
+pub fn find_duplicate(source: &str, library: &Library<Book>) -> Option<String> {
+    let id = identity::compute_id(source)?;
+    find_copy(&id, &library.book_paths())
+}
+
+/// Returns the path of one of the books that is the same book as the one with the id,
+/// for the imports that only have the paths of the library
+pub fn find_copy(id: &str, books: &[String]) -> Option<String> {
+    books
+        .iter()
+        .find(|path| identity::same_book(&identity::book_id(path), id))
+        .cloned()
+}
+
+/// Imports the book as chosen by the user
+pub fn resolve(
+    resolve: &ResolveDuplicate,
+    library: &mut Library<Book>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match resolve.choice {
+        DuplicateChoice::Skip => {}
+        DuplicateChoice::Replace => replace_book(&resolve.source, &resolve.existing, library)?,
+        DuplicateChoice::KeepBoth => {
+            let book = saveload::copy_book_in_folder(&resolve.source)?;
+            library.schedule_book_loading(&book);
+        }
+    }
+    Ok(())
+}
+
+/// Replaces the file of a book of the library with a new one: the chapters and the index
+/// are made again, the book keeps its data if the identifier of the epub is the same
+pub fn replace_book(
+    source: &str,
+    book: &str,
+    library: &mut Library<Book>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    library.remove_book_with_path(book);
+    let extracted = get_book_dir(book);
+    fulltext::remove_book(book)?;
+    std::fs::copy(source, book)?;
+    if extracted.exists() {
+        std::fs::remove_dir_all(extracted)?;
+    }
+    identity::forget(book);
+    library.schedule_book_loading(book);
+    Ok(())
+}
+
+/// Merges the copies of the same book in the library: the first one is kept,
+/// with the progress and the notes of the others, that are deleted.
+/// Returns the number of copies deleted
+pub fn merge_duplicates(library: &mut Library<Book>) -> Result<usize, Box<dyn std::error::Error>> {
+    let books: Vec<(String, String)> = library
+        .book_paths()
+        .into_iter()
+        .map(|path| {
+            let id = identity::book_id(&path);
+            (path, id)
+        })
+        .collect();
+
+    let mut merged = 0;
+    for group in duplicate_groups(&books) {
+        let Some((kept, copies)) = group.split_first() else {
+            continue;
+        };
+        for copy in copies {
+            saveload::merge_book_data(kept, copy)?;
+            library.remove_book_with_path(copy);
+            saveload::delete_book(copy)?;
+            merged += 1;
+        }
+        // loaded again with the merged progress
+        library.remove_book_with_path(kept);
+        library.schedule_book_loading(kept.as_str());
+    }
+    Ok(merged)
+}
+
+/// Groups the books, given with their ids, that are copies of the same book.
+/// Only the groups with more than one book are returned, in the order of the books
+fn duplicate_groups(books: &[(String, String)]) -> Vec<Vec<String>> {
+    let mut groups: Vec<(String, Vec<String>)> = vec![];
+    for (path, id) in books {
+        match groups
+            .iter_mut()
+            .find(|(first, _)| identity::same_book(first, id))
+        {
+            Some((_, paths)) => paths.push(path.clone()),
+            None => groups.push((id.clone(), vec![path.clone()])),
+        }
+    }
+    groups
+        .into_iter()
+        .map(|(_, paths)| paths)
+        .filter(|paths| paths.len() > 1)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book(path: &str, id: &str) -> (String, String) {
+        (path.to_string(), id.to_string())
+    }
+
+    #[test]
+    fn copies_are_grouped() {
+        let books = [
+            book("a.epub", "00000000000000aa-0000000000000001"),
+            book("b.epub", "00000000000000bb-0000000000000002"),
+            // same content
+            book("a (2).epub", "00000000000000cc-0000000000000001"),
+            // same identifier, another edition
+            book("b_new.epub", "00000000000000bb-0000000000000003"),
+            book("c.epub", "00000000000000dd-0000000000000004"),
+        ];
+
+        assert_eq!(
+            duplicate_groups(&books),
+            vec![
+                vec!["a.epub".to_string(), "a (2).epub".to_string()],
+                vec!["b.epub".to_string(), "b_new.epub".to_string()],
+            ]
+        );
+    }
+}
@@ -1,8 +1,9 @@
 
 use druid::{Color, FontDescriptor, FontFamily};
-use serde_json::{self, json};
 
-use super::{fonts, dir_manager::get_env_path};
+use crate::models::documents::EnvSettings;
+
+use super::{fonts, storage::{self, Document}};
 
 #[derive(Debug)]
 pub struct MyEnv {
@@ -21,78 +22,45 @@ impl MyEnv {
             shadows: false,
         };
 
-        let env_path = get_env_path();
-
-        //Take the JSON, turn it into a MAP
-        let Ok(json) = std::fs::read_to_string(&env_path) else {
+        if !Document::Env.exists() {
             //If the file doesn't exist, create it
-            let file = std::fs::File::create(env_path).unwrap();
-            let json = json!(
-                {
-                    "font_color": "WHITE",
-                    "font_family": "SISTEM_UI",
-                    "font_size": "medium",
-                    "theme": "light",
-                    "shadows": false
-                }
-            );
-            let _ = serde_json::to_writer_pretty(file, &json);
+            if let Err(e) = storage::save(&Document::Env, &EnvSettings::default()) {
+                println!("ERROR: {}", e);
+            }
             return new_env;
-        };
-        let json: serde_json::Value = serde_json::from_str(&json).unwrap();
-        let json = json.as_object().unwrap();
+        }
+        // an invalid file is reported by the storage, the defaults are used
+        let settings: EnvSettings = storage::load(&Document::Env).unwrap_or_default();
 
         //SET theme, font_color
-        new_env.theme = json.get("theme").unwrap().as_str().unwrap().to_string();
-        new_env.font_color = MyEnv::get_color(json.get("font_color").unwrap().to_string());
+        new_env.theme = settings.theme;
+        new_env.font_color = MyEnv::get_color(settings.font_color);
 
         //SET font_size, font_family
-        let font_size_string = json.get("font_size").unwrap().as_str().unwrap();
-        
-        let font_size_numeric: f64 = FontSize::from(font_size_string).to_f64();
+        let font_size_numeric: f64 = MyEnv::get_font_size(settings.font_size);
 
-        new_env.font = FontDescriptor::new(MyEnv::get_font_family(
-            json.get("font_family").unwrap().to_string(),
-        ))
-        .with_size(font_size_numeric);
+        new_env.font = FontDescriptor::new(MyEnv::get_font_family(settings.font_family))
+            .with_size(font_size_numeric);
 
-        new_env.shadows = json.get("shadows").unwrap().as_bool().unwrap();
+        new_env.shadows = settings.shadows;
 
         return new_env;
     }
 
     #[allow(dead_code)]
     pub fn save_to_env(&mut self) {
-        //open a new file, creating it if it doesn't exist
-        let file = std::fs::File::create(get_env_path()).unwrap();
-
-        //create a new json object
-        let mut json = serde_json::Map::new();
-
-        //add the values to the json object
-        json.insert(
-            "theme".to_string(),
-            serde_json::Value::String(self.theme.clone()),
-        );
-        json.insert(
-            "font_color".to_string(),
-            serde_json::Value::String(MyEnv::get_color_reverse(self.font_color.clone())),
-        );
-        json.insert(
-            "font_size".to_string(),
-            serde_json::Value::String(MyEnv::get_font_size_reverse(self.font.size)),
-        );
-        json.insert(
-            "font_family".to_string(),
-            serde_json::Value::String(MyEnv::get_font_family_reverse(self.font.family.clone())),
-        );
-        json.insert(
-            "shadows".to_string(),
-            serde_json::Value::Bool(self.shadows.clone()),
-        );
+        let settings = EnvSettings {
+            theme: self.theme.clone(),
+            font_color: MyEnv::get_color_reverse(self.font_color.clone()),
+            font_size: MyEnv::get_font_size_reverse(self.font.size),
+            font_family: MyEnv::get_font_family_reverse(self.font.family.clone()),
+            shadows: self.shadows,
+        };
 
-        //write the json object to the file
-        serde_json::to_writer_pretty(file, &json).unwrap();
+        //write the settings to the file
+        if let Err(e) = storage::save(&Document::Env, &settings) {
+            println!("ERROR: {}", e);
+        }
     }
 
     #[allow(dead_code)]
@@ -1,18 +1,27 @@
-use crate::{MYENV, utils::{envmanager::FontSize, dir_manager::get_edited_books_dir}, models::book::{PAGE_WIDTH, PAGE_HEIGHT}};
+use crate::{utils::{dir_manager::get_edited_book_dir, identity::book_id, paginator::{self, PageLayout}}, models::{documents::BookMetadata, position::byte_to_char, toc::TocEntry}};
 
-use super::{saveload::{get_chapter_bytes, FileExtension, remove_edited_chapter}, dir_manager::{get_saved_books_dir, get_saved_covers_dir, get_metadata_path}};
+use super::{saveload::{self, get_chapter_bytes, FileExtension, remove_edited_chapter}, dir_manager::{get_book_dir, get_saved_books_dir, get_saved_covers_dir, get_metadata_path}, storage::{self, Document}, xml_tree::{self, XmlNode}};
+use druid::{im::Vector, ExtEventSink, Selector, Target};
 use epub::doc::EpubDoc;
-use serde_json::json;
 use std::{
     collections::HashMap,
     error,
-    fs::{File, OpenOptions},
-    io::{BufReader, Write},
+    fs::File,
+    io::Write,
     path::{Path, PathBuf},
     rc::Rc,
     sync::{Arc, Mutex},
+    time::SystemTime,
 };
 
+/// the pages of a book have been counted in background:
+/// path of the book, key of the layout and first and last page of every chapter
+pub const PAGES_CALCULATED: Selector<(String, String, Vec<(usize, usize)>)> =
+    Selector::new("crabreader.pages_calculated");
+
+/// separates the authors of a book in its metadata
+pub const AUTHORS_SEPARATOR: &str = " & ";
+
 /// Method to extract metadata from epub file
 /// and returns explicit metadata.
 /// title: title of the book
@@ -26,60 +35,161 @@ use std::{
 
 
 fn get_metadata_from_epub(
-    book: &EpubDoc<File>,
-) -> Result<HashMap<String, String>, Box<dyn error::Error>> {
+    book: &mut EpubDoc<File>,
+) -> Result<BookMetadata, Box<dyn error::Error>> {
     for key in book.metadata.keys() {
         println!("DEBUG: {}: {}", key, book.mdata(key).unwrap());
     }
-    let mut metadata = HashMap::new();
-    metadata.insert(
-        "title".to_string(),
-        book.mdata("title").unwrap_or("no title".to_string()),
-    );
-
-    metadata.insert(
-        "author".to_string(),
-        book.mdata("creator").unwrap_or("no author".to_string()),
-    );
-
-    metadata.insert(
-        "lang".to_string(),
-        book.mdata("language").unwrap_or("no lang".to_string()),
-    );
-    /*
-    metadata.insert(
-        "cover".to_string(),
-        String::from_utf8(book.get_cover().unwrap_or(Vec::<u8>::default()))?,
-    );
-    */
-
-    metadata.insert(
-        "source".to_string(),
-        book.mdata("source").unwrap_or("no source".to_string()),
-    );
-
-    metadata.insert(
-        "date".to_string(),
-        book.mdata("date").unwrap_or("no date".to_string()),
-    );
-
-    metadata.insert(
-        "rights".to_string(),
-        book.mdata("rights").unwrap_or("no rights".to_string()),
-    );
-
-    metadata.insert(
-        "identifier".to_string(),
-        book.mdata("identifier")
+    let (series, series_index) = series_of(book);
+    let metadata = BookMetadata {
+        title: book.mdata("title").unwrap_or("no title".to_string()),
+        author: book
+            .metadata
+            .get("creator")
+            .map(|authors| authors.join(AUTHORS_SEPARATOR))
+            .unwrap_or("no author".to_string()),
+        lang: book.mdata("language").unwrap_or("no lang".to_string()),
+        source: book.mdata("source").unwrap_or("no source".to_string()),
+        date: book.mdata("date").unwrap_or("no date".to_string()),
+        rights: book.mdata("rights").unwrap_or("no rights".to_string()),
+        identifier: book
+            .mdata("identifier")
             .unwrap_or("no indetifier".to_string()),
-    );
-
-    metadata.insert("chapters".to_string(), book.get_num_pages().to_string());
-    metadata.insert("favorite".to_string(), "false".to_string());
+        identifiers: book
+            .metadata
+            .get("identifier")
+            .map(|ids| ids.iter().skip(1).cloned().collect())
+            .unwrap_or_default(),
+        desc: book.mdata("description"),
+        publisher: book.mdata("publisher").unwrap_or_default(),
+        chapters: book.get_num_pages(),
+        favorite: false,
+        tags: Some(subjects_of(book)),
+        series,
+        series_index,
+        ..BookMetadata::default()
+    };
 
     Ok(metadata)
 }
 
+/// Completes the metadata extracted before the tags, the series and the date
+/// the book was added existed, and saves it.
+/// The book was added when its file was copied in the library
+pub fn complete_metadata(path: &str, metadata: &mut BookMetadata) {
+    let mut json = serde_json::Map::new();
+    if metadata.tags.is_none() {
+        match EpubDoc::new(path) {
+            Ok(mut book) => {
+                metadata.tags = Some(subjects_of(&book));
+                (metadata.series, metadata.series_index) = series_of(&mut book);
+            }
+            Err(_) => metadata.tags = Some(vec![]),
+        }
+        json.insert("tags".into(), serde_json::json!(metadata.tags));
+        json.insert("series".into(), serde_json::json!(metadata.series));
+        json.insert("series_index".into(), serde_json::json!(metadata.series_index));
+    }
+    if metadata.added.is_none() {
+        let added = added_time(path);
+        metadata.added = Some(added);
+        json.insert("added".into(), serde_json::json!(added));
+    }
+    if json.is_empty() {
+        return;
+    }
+
+    let result = storage::update(&Document::Metadata(path.to_string()), |document| {
+        for (key, value) in json {
+            document[key] = value;
+        }
+    });
+    if let Err(e) = result {
+        println!("ERROR: failed to complete the metadata of {}: {}", path, e);
+    }
+}
+
+/// Returns when a book added before the date was saved entered the library:
+/// the time of its file, or now if the file can't be read
+fn added_time(path: &str) -> u64 {
+    std::fs::metadata(path)
+        .and_then(|file| file.created().or_else(|_| file.modified()))
+        .map(saveload::unix_time)
+        .unwrap_or_else(|_| saveload::unix_time(SystemTime::now()))
+}
+
+/// Returns the series of the book and its number in the series, from the OPF:
+/// the calibre metadata or the collection of an EPUB 3
+fn series_of(book: &mut EpubDoc<File>) -> (String, Option<f64>) {
+    let opf_path = book.root_file.clone();
+    book.get_resource_str_by_path(&opf_path)
+        .map_err(|e| e.to_string())
+        .and_then(|opf| xml_tree::parse(&opf))
+        .map(|opf| series_from_opf(&opf))
+        .unwrap_or_default()
+}
+
+fn series_from_opf(opf: &XmlNode) -> (String, Option<f64>) {
+    let metas: Vec<&XmlNode> = opf
+        .find("metadata")
+        .map(|metadata| metadata.find_all("meta"))
+        .unwrap_or_default();
+    let content = |name: &str| {
+        metas
+            .iter()
+            .find(|meta| meta.attr("name") == Some(name))
+            .and_then(|meta| meta.attr("content"))
+            .map(|content| content.trim().to_string())
+    };
+    let refining = |id: &str, property: &str| {
+        metas
+            .iter()
+            .find(|meta| {
+                meta.attr("refines") == Some(id) && meta.attr("property") == Some(property)
+            })
+            .map(|meta| meta.all_text().trim().to_string())
+    };
+    let parse_index = |index: String| index.replace(',', ".").parse::<f64>().ok();
+
+    if let Some(series) = content("calibre:series").filter(|series| !series.is_empty()) {
+        return (series, content("calibre:series_index").and_then(parse_index));
+    }
+
+    // a collection that isn't a set of books
+    let collection = metas.iter().find(|meta| {
+        meta.attr("property") == Some("belongs-to-collection")
+            && meta.attr("id").map_or(true, |id| {
+                refining(&format!("#{}", id), "collection-type").map_or(true, |t| t == "series")
+            })
+    });
+    match collection {
+        Some(collection) => {
+            let index = collection
+                .attr("id")
+                .and_then(|id| refining(&format!("#{}", id), "group-position"))
+                .and_then(parse_index);
+            (collection.all_text().trim().to_string(), index)
+        }
+        None => (String::new(), None),
+    }
+}
+
+fn subjects_of(book: &EpubDoc<File>) -> Vec<String> {
+    clean_tags(book.metadata.get("subject").into_iter().flatten().cloned())
+}
+
+/// Trims the tags and removes the empty and repeated ones, ignoring the case
+pub fn clean_tags(tags: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut cleaned: Vec<String> = vec![];
+    for tag in tags {
+        let tag = tag.trim();
+        if !tag.is_empty() && !cleaned.iter().any(|t| t.to_lowercase() == tag.to_lowercase()) {
+            cleaned.push(tag.to_string());
+        }
+    }
+    cleaned
+}
+
 /// Method to save the cover of the book as a png file
 /// in the path specified.
 /// image: String of vec[u8] (as u8) of the cover
@@ -104,19 +214,12 @@ pub fn edit_chapter(
     chapter_number: usize,
     text: impl Into<String>,
 ) -> Result<(), Box<dyn error::Error>> {
-    let folder_name = Path::new(path).file_stem().unwrap().to_str().unwrap();
-    let mut path_name: PathBuf = get_edited_books_dir().join(folder_name);
+    let mut path_name: PathBuf = get_edited_book_dir(path);
     println!("DEBUG: Folder path: {:?}", path_name);
     std::fs::create_dir_all(&path_name)?;
     path_name = path_name.join(format!("page_{}.txt", chapter_number));
 
-    let mut file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(&path_name)?;
-
-    file.write_all(text.into().as_bytes())?;
+    storage::write_atomic(&path_name, text.into().as_bytes())?;
 
     Ok(())
 }
@@ -124,16 +227,29 @@ pub fn edit_chapter(
 pub fn extract_all(path: &str) -> Result<(), Box<dyn error::Error>> {
 
     let mut book = EpubDoc::new(path)?;
-    let path_name = get_metadata_path(&path.to_string());
 
-    let mut metadata_file = File::create(&path_name).unwrap();
-    let metadata_map = get_metadata_from_epub(&book)?;
+    let metadata = get_metadata_from_epub(&mut book)?;
+    storage::save(&Document::Metadata(path.to_string()), &metadata)?;
+    extract_pages(path, book);
 
-    let json = json!(metadata_map);
-    //json["cover"] = json!(book.get_cover().unwrap_or_default());
-    metadata_file
-        .write_all(json.to_string().as_bytes())
-        .unwrap();
+    Ok(())
+}
+
+/// Extracts the book with metadata read somewhere else, e.g. from a Calibre library:
+/// only the number of chapters is taken from the epub
+pub fn extract_all_with_metadata(
+    path: &str,
+    mut metadata: BookMetadata,
+) -> Result<(), Box<dyn error::Error>> {
+    let book = EpubDoc::new(path)?;
+    metadata.chapters = book.get_num_pages();
+    storage::save(&Document::Metadata(path.to_string()), &metadata)?;
+    extract_pages(path, book);
+    Ok(())
+}
+
+fn extract_pages(path: &str, book: EpubDoc<File>) {
+    let path_name = get_metadata_path(&path.to_string());
     let len = book.get_num_pages();
 
     //extract all chapters
@@ -155,28 +271,19 @@ pub fn extract_all(path: &str) -> Result<(), Box<dyn error::Error>> {
             file.write_all(chapter.as_bytes()).unwrap();
         })
     }
-
-    Ok(())
+    // the chapters are read right after, to index the book
+    pool.join();
 }
 
-pub fn extract_metadata(path: &str) -> Result<HashMap<String, String>, Box<dyn error::Error>> {
-    let path_name = get_metadata_path(&path.to_string());
-    let mut metadata_file = File::create(&path_name).unwrap();
-    let book = EpubDoc::new(path)?;
-    let metadata_map = get_metadata_from_epub(&book)?;
-
-    let json = json!(metadata_map);
-    //json["cover"] = json!(book.get_cover().unwrap_or_default());
-    metadata_file
-        .write_all(json.to_string().as_bytes())
-        .unwrap();
-    Ok(metadata_map)
+pub fn extract_metadata(path: &str) -> Result<BookMetadata, Box<dyn error::Error>> {
+    let mut book = EpubDoc::new(path)?;
+    let metadata = get_metadata_from_epub(&mut book)?;
+    storage::save(&Document::Metadata(path.to_string()), &metadata)?;
+    Ok(metadata)
 }
 
 pub fn extract_chapters(path: &str) -> Result<(), Box<dyn error::Error>> {
-    let folder_name = Path::new(path).file_stem().unwrap().to_str().unwrap();
-    println!("DEBUG: Folder name: {:?}", folder_name);
-    let path_name: PathBuf = get_saved_books_dir().join(folder_name);
+    let path_name: PathBuf = get_book_dir(path);
     println!("DEBUG: Folder path: {:?}", path_name);
     std::fs::create_dir_all(&path_name)?;
 
@@ -214,7 +321,8 @@ pub fn get_chapter_text(path: &str, chapter_number: usize) -> Rc<String> {
 
 pub fn get_chapter_text_utf8(path: impl Into<String>, chapter_number: usize) -> Vec<u8> {
     let path = path.into();
-    let folder_name = Path::new(&path).file_stem().unwrap().to_str().unwrap();
+    let folder_name = book_id(&path);
+    let folder_name = folder_name.as_str();
 
     // try to read from txt files (where edited text is saved)
     if let Ok(text) = get_chapter_bytes(folder_name, chapter_number, FileExtension::TXT) {
@@ -272,27 +380,27 @@ pub fn get_chapter_text_utf8(path: impl Into<String>, chapter_number: usize) ->
     [0u8].into()
 }
 
-pub fn get_metadata_of_book(path: &str) -> HashMap<String, String> {
-    let metadata_path = get_metadata_path(&path.to_string());
-    if let Ok(metadata_file) = File::open(metadata_path) {
-        let reader = BufReader::new(metadata_file);
-        if let Ok(metadata) = serde_json::from_reader(reader) {
+pub fn get_metadata_of_book(path: &str) -> BookMetadata {
+    let document = Document::Metadata(path.to_string());
+    // a missing document is read as the default one
+    if document.exists() {
+        if let Ok(metadata) = storage::load(&document) {
             return metadata;
         }
     }
 
     // if it fails, read from epub, saves and return metadata
-    let metadata = extract_metadata(path).expect("Failed to extract metadata from epub");
-    metadata
+    extract_metadata(path).unwrap_or_else(|e| {
+        println!("ERROR: failed to extract metadata from {}: {}", path, e);
+        BookMetadata::default()
+    })
 }
 
 pub fn calculate_number_of_pages(
     path: &str,
-    number_of_lines: usize,
-    font_size: f64,
+    layout: &PageLayout,
 ) -> Result<(usize, Vec<(usize, usize)>), Box<dyn error::Error>> {
-    let mut metadata = get_metadata_of_book(path);
-    let number_of_chapters = metadata["chapters"].parse::<usize>().unwrap_or_default();
+    let number_of_chapters = get_metadata_of_book(path).chapters;
 
     let pool = threadpool::Builder::new().build();
 
@@ -301,16 +409,9 @@ pub fn calculate_number_of_pages(
     for i in 0..number_of_chapters {
         let tx = tx.clone();
         let path = path.to_string();
+        let layout = layout.clone();
         pool.execute(move || {
-            let pages = split_chapter_in_vec(
-                path.as_str(),
-                Option::None,
-                i,
-                number_of_lines,
-                font_size,
-                PAGE_WIDTH,
-                PAGE_HEIGHT,
-            );
+            let pages = split_chapter_in_vec(path.as_str(), Option::None, i, &layout);
             println!("DEBUG: chapter {} has {} pages", i, pages.len());
             // send tuple with index of chapter and number of pages
             tx.send((i, pages.len())).unwrap();
@@ -339,174 +440,481 @@ pub fn calculate_number_of_pages(
         }
     }
 
-    // save number of pages per chapter in metadata
-    metadata.insert(
-        format!("pages_per_chapter_{}", FontSize::from(font_size).to_string()),
-        format!(
-            "[{}]",
-            pages_per_chapter_start_end
-                .iter()
-                .map(|(a, b)| format!("({}-{})", a, b))
-                .collect::<Vec<String>>()
-                .join(", ")
-        ),
-    );
-    // save number of pages in metadata
-    metadata.insert("total_pages".into(), number_of_pages.to_string());
-
-    println!("DEBUG metadata: {:?}", metadata);
-
-    let json = json!(metadata);
-    let metadata_path = get_metadata_path(&path.to_string());
-
-    let metadata_file = OpenOptions::new()
-        .create(true)
-        .truncate(true)
-        .write(true)
-        .open(metadata_path)
-        .unwrap();
+    println!("DEBUG: pages per chapter with {}: {:?}", layout.key(), pages_per_chapter_start_end);
 
-    serde_json::to_writer_pretty(metadata_file, &json)?;
+    // save number of pages per chapter and number of pages in metadata,
+    // keeping what was changed meanwhile
+    storage::modify(&Document::Metadata(path.to_string()), |metadata: &mut BookMetadata| {
+        metadata
+            .pages_per_chapter
+            .insert(layout.key(), pages_per_chapter_start_end.clone());
+        metadata.total_pages = Some(number_of_pages);
+    })?;
 
     Ok((number_of_pages, pages_per_chapter_start_end))
 }
 
+/// Counts the pages of the book with the layout in a thread,
+/// the result is sent with `PAGES_CALCULATED`
+pub fn calculate_number_of_pages_in_background(sink: ExtEventSink, path: String, layout: PageLayout) {
+    std::thread::spawn(move || match calculate_number_of_pages(&path, &layout) {
+        Ok((_, pages_per_chapter)) => {
+            let _ = sink.submit_command(
+                PAGES_CALCULATED,
+                (path, layout.key(), pages_per_chapter),
+                Target::Auto,
+            );
+        }
+        Err(e) => println!("ERROR: failed to count the pages of {}: {}", path, e),
+    });
+}
+
 // get total number of pages in the book
 pub fn get_number_of_pages(path: &str) -> usize {
-    let metadata = get_metadata_of_book(path);
-
-    let result = metadata.get("total_pages");
-    if let Some(number_of_pages) = result {
-        number_of_pages.parse::<usize>().unwrap_or_default()
-    } else {
-        calculate_number_of_pages(path, 8, MYENV.lock().unwrap().font.size).unwrap_or_default().0
+    match get_metadata_of_book(path).total_pages {
+        Some(number_of_pages) => number_of_pages,
+        None => calculate_number_of_pages(path, &PageLayout::current()).unwrap_or_default().0,
     }
 }
 
 // get number of pages per chapter where the index of the vector is the chapter number
 // and the tuple is the start and end indexes page of the chapter (start, end)
-pub fn get_start_end_pages_per_chapter(path: &str, book_map: Option<HashMap<String,String>>) -> Vec<(usize, usize)> {
+pub fn get_start_end_pages_per_chapter(path: &str, book_map: Option<BookMetadata>) -> Vec<(usize, usize)> {
     let metadata = match book_map {
-        Some(map) => map,
+        Some(metadata) => metadata,
         None => get_metadata_of_book(path),
     };
     
     match get_indexes_from_local(metadata) {
         Some(indexes) => indexes,
-        None => calculate_number_of_pages(
-            path,
-            8,
-            MYENV.lock().unwrap().font.size,
-        )
-        .unwrap_or_default()
-        .1,
+        None => calculate_number_of_pages(path, &PageLayout::current())
+            .unwrap_or_default()
+            .1,
     }
 }
-/// internal method to get the start and end pages per chapter from the metadata 
-fn get_indexes_from_local(metadata: HashMap<String, String>) -> Option<Vec<(usize, usize)>> {
-    let result = metadata.get(format!("pages_per_chapter_{}", FontSize::from(MYENV.lock().unwrap().font.size).to_string()).as_str());
-    if let Some(pages_per_chapter) = result {
-        let vec_as_str = pages_per_chapter.to_string();
-        return Some(
-            vec_as_str
-                .trim_matches(|c| c == '[' || c == ']')
-                .split(',')
-                .map(|s| {
-                    let start_end = s
-                        .trim_matches(|c| c == '(' || c == ')' || c == ' ')
-                        .split('-')
-                        .map(|s| s.parse::<usize>().unwrap_or_default())
-                        .collect::<Vec<usize>>();
-                    (start_end[0], start_end[1])
-                })
-                .collect()
-        );
-    } 
-    None
+/// Returns the start and end pages per chapter with the current layout,
+/// if they have already been calculated
+pub fn get_cached_pages_per_chapter(path: &str) -> Option<Vec<(usize, usize)>> {
+    get_indexes_from_local(get_metadata_of_book(path))
 }
 
-// get the number of pages with respect to the total number of pages in the book
-pub fn get_cumulative_current_page_number(path: &str, chapter: usize, page: usize, book_map: Option<HashMap<String,String>>) -> usize {
-    if chapter > 0 {
-        let pages_per_chapter = get_start_end_pages_per_chapter(path, book_map);
-        return pages_per_chapter[chapter].0 + page;
-    }
-
-    page
+/// internal method to get the start and end pages per chapter from the metadata 
+fn get_indexes_from_local(mut metadata: BookMetadata) -> Option<Vec<(usize, usize)>> {
+    metadata.pages_per_chapter.remove(&PageLayout::current().key())
 }
 
 
 /// Function that split the text of the chapter
-/// into a vector of strings, each string is a paragraph
-/// calculated by the number of lines and (not now) the font size
-/// You  have to provide the path. Number of lines and font size
+/// into a vector of strings, one for each page.
+/// Pages are measured with the given layout (font and page size),
+/// see `paginator::paginate`, and joined together give back the chapter text.
 /// You can provide the text of the chapter as a RC String or
 /// you can provide the chapter number
 pub fn split_chapter_in_vec<S: Into<Option<Rc<String>>>, U: Into<Option<usize>>>(
     path: &str,
     opt_text: S,
     chapter_number: U,
-    number_of_lines: usize,
-    font_size: f64,
-    width: f32,
-    height: f32,
+    layout: &PageLayout,
 ) -> Vec<Rc<String>> {
-    // todo(): consider also the font size
-
     let text = match opt_text.into() {
         Some(book_chapter_text) => book_chapter_text,
         None => get_chapter_text(path, chapter_number.into().unwrap_or(0)),
     };
 
-    //through font-size, we can calculate the number N of lines that fit in the page
-    //split text in paragraphs long N lines
-    let wf = (width / (font_size as f32)) as usize;
-    let hf = (height / (font_size as f32)) as usize;
+    paginator::paginate(text.as_str(), layout)
+        .into_iter()
+        .map(|range| Rc::new(text[range].to_string()))
+        .collect()
+}
 
-    let wfhf = wf * hf;
+/// Method that reads the table of contents of the book.
+/// The EPUB3 navigation document is preferred, the NCX file is used otherwise.
+/// Every entry is mapped to the spine item (chapter) it points to,
+/// entries pointing outside the spine are dropped and their children promoted.
+/// If the book has no usable toc, one entry per chapter is returned.
+pub fn get_toc(path: &str) -> Result<Vector<TocEntry>, Box<dyn error::Error>> {
+    let mut book = EpubDoc::new(path)?;
+    let opf_path = book.root_file.clone();
+    let opf = xml_tree::parse(&book.get_resource_str_by_path(&opf_path)?)?;
+
+    // path of every spine item, the index is the chapter number
+    let spine_paths: Vec<PathBuf> = book
+        .spine
+        .iter()
+        .map(|id| book.resources.get(id).map(|(p, _)| p.clone()).unwrap_or_default())
+        .collect();
+
+    let manifest: Vec<&XmlNode> = opf
+        .find("manifest")
+        .map(|m| m.children_named("item").collect())
+        .unwrap_or_default();
+
+    let nav_href = manifest
+        .iter()
+        .find(|item| {
+            item.attr("properties")
+                .map_or(false, |p| p.split_whitespace().any(|p| p == "nav"))
+        })
+        .and_then(|item| item.attr("href"));
+
+    let ncx_id = opf.find("spine").and_then(|spine| spine.attr("toc"));
+    let ncx_href = manifest
+        .iter()
+        .find(|item| ncx_id.is_some() && item.attr("id") == ncx_id)
+        .or_else(|| {
+            manifest
+                .iter()
+                .find(|item| item.attr("media-type") == Some("application/x-dtbncx+xml"))
+        })
+        .and_then(|item| item.attr("href"));
+
+    let mut toc = Vector::new();
+
+    if let Some(href) = nav_href {
+        let (nav_path, _) = xml_tree::resolve_href(&opf_path, href);
+        if let Ok(nav) = book
+            .get_resource_str_by_path(&nav_path)
+            .map_err(|e| e.to_string())
+            .and_then(|content| xml_tree::parse(&content))
+        {
+            let toc_nav = nav
+                .find_all("nav")
+                .into_iter()
+                .find(|n| n.attr("type") == Some("toc"));
+            if let Some(list) = toc_nav.and_then(|n| n.find("ol")) {
+                toc = parse_nav_list(list, &nav_path, &spine_paths, 0);
+            }
+        }
+    }
 
-    //Split text by lines
-    let chapter_lines = text.split('\n').collect::<Vec<&str>>();
+    if toc.is_empty() {
+        if let Some(href) = ncx_href {
+            let (ncx_path, _) = xml_tree::resolve_href(&opf_path, href);
+            let ncx = xml_tree::parse(&book.get_resource_str_by_path(&ncx_path)?)?;
+            if let Some(nav_map) = ncx.find("navMap") {
+                toc = parse_nav_points(nav_map, &ncx_path, &spine_paths, 0);
+            }
+        }
+    }
 
-    //Add as many lines as possible to each component of a pages vector, until we reach wfhf characters
-    let mut pages = vec![];
-    let mut page = String::new();
-    let mut page_length = 0;
+    if toc.is_empty() {
+        toc = chapters_toc(spine_paths.len());
+    }
 
-    //remove all empty strings leading and trailing the vector
-    let chapter_lines = chapter_lines.into_iter().skip_while(|s| s.is_empty()).collect::<Vec<&str>>();
+    resolve_toc_offsets(path, &mut toc, &mut HashMap::new());
 
-    for i in 0..chapter_lines.len() {
+    Ok(toc)
+}
 
-        let line = chapter_lines[i];
+/// internal method that builds the toc of a book without one: an entry per chapter
+fn chapters_toc(chapters: usize) -> Vector<TocEntry> {
+    (0..chapters)
+        .map(|i| TocEntry::new(format!("Capitolo {}", i + 1), i, None, 0))
+        .collect()
+}
 
-        if page_length + line.len() < wfhf && i != chapter_lines.len() - 1 {
-            
-            //if line is equal to \n
-            if line.to_string() == "" {
-                page.push_str("\n\n");
-                page_length += wf;
-            }
-            else {
-                //add blank space at the end of line
-                let line = format!("{} ", line);
-                page.push_str(line.as_str());
-                page_length += line.len();
-            }
-        } else {
+/// internal method that converts an href of the toc into (chapter, fragment)
+fn toc_target(base: &Path, href: &str, spine_paths: &Vec<PathBuf>) -> Option<(usize, Option<String>)> {
+    let (target, fragment) = xml_tree::resolve_href(base, href);
+    let chapter = spine_paths.iter().position(|p| *p == target)?;
+    Some((chapter, fragment))
+}
 
-            if i == chapter_lines.len() - 1 {
-                let line = format!("{} ", line);
-                page.push_str(line.as_str());
-            }
+/// internal method to parse the <navMap> of a NCX file
+fn parse_nav_points(parent: &XmlNode, ncx_path: &Path, spine_paths: &Vec<PathBuf>, depth: usize) -> Vector<TocEntry> {
+    let mut entries = Vector::new();
+    for point in parent.children_named("navPoint") {
+        let label = point
+            .find("navLabel")
+            .map(|l| l.all_text())
+            .unwrap_or_default();
+        let target = point
+            .find("content")
+            .and_then(|c| c.attr("src"))
+            .and_then(|src| toc_target(ncx_path, src, spine_paths));
+        let children = parse_nav_points(point, ncx_path, spine_paths, depth + 1);
+
+        match target {
+            Some((chapter, fragment)) => entries.push_back(
+                TocEntry::new(label.trim(), chapter, fragment, depth).with_children(children),
+            ),
+            None => entries.append(children),
+        }
+    }
+    entries
+}
+
+/// internal method to parse an <ol> of the EPUB3 navigation document
+fn parse_nav_list(list: &XmlNode, nav_path: &Path, spine_paths: &Vec<PathBuf>, depth: usize) -> Vector<TocEntry> {
+    let mut entries = Vector::new();
+    for item in list.children_named("li") {
+        let link = item.children_named("a").next();
+        let label = link
+            .or_else(|| item.children_named("span").next())
+            .map(|l| l.all_text())
+            .unwrap_or_default();
+        let target = link
+            .and_then(|a| a.attr("href"))
+            .and_then(|href| toc_target(nav_path, href, spine_paths));
+        let children = item
+            .children_named("ol")
+            .next()
+            .map(|ol| parse_nav_list(ol, nav_path, spine_paths, depth + 1))
+            .unwrap_or_default();
+
+        match target {
+            Some((chapter, fragment)) => entries.push_back(
+                TocEntry::new(label.trim(), chapter, fragment, depth).with_children(children),
+            ),
+            None => entries.append(children),
+        }
+    }
+    entries
+}
+
+/// Method that returns the position (in characters) of the anchor `fragment`
+/// inside the text of the chapter, if it can be found
+pub fn get_fragment_offset(path: &str, chapter_number: usize, fragment: &str) -> Option<usize> {
+    let html = get_chapter_html(path, chapter_number)?;
+    let text = get_chapter_text(path, chapter_number);
+    find_fragment_offset(&html, &text, fragment)
+}
+
+/// Returns the original html of a chapter
+pub fn get_chapter_html(path: &str, chapter_number: usize) -> Option<String> {
+    match get_chapter_bytes(&book_id(path), chapter_number, FileExtension::HTML) {
+        Ok(bytes) => String::from_utf8(bytes).ok(),
+        Err(_) => {
+            let mut book = EpubDoc::new(path).ok()?;
+            book.set_current_page(chapter_number).ok()?;
+            book.get_current_str().ok()
+        }
+    }
+}
+
+/// internal method that looks for the anchor in the html of the chapter
+/// and returns the position of the text that follows it in the chapter text
+fn find_fragment_offset(html: &str, text: &str, fragment: &str) -> Option<usize> {
+    // start of the tag that declares the anchor
+    let anchor = [
+        format!("id=\"{}\"", fragment),
+        format!("id='{}'", fragment),
+        format!("name=\"{}\"", fragment),
+    ]
+    .iter()
+    .find_map(|attr| html.find(attr.as_str()))?;
+    let tag_start = html[..anchor].rfind('<')?;
+
+    // the first line of text after the anchor is looked up in the chapter
+    let following = rhtml2md::parse_html(&html[tag_start..]);
+    let snippet: String = following
+        .lines()
+        .map(|l| l.trim())
+        .find(|l| !l.is_empty())?
+        .chars()
+        .take(40)
+        .collect();
+
+    text.find(snippet.as_str())
+        .map(|byte| byte_to_char(text, byte))
+}
 
-            pages.push(Rc::from(page));
-            page = String::new();
-            page.push_str(line);
-            page_length = line.len();
+/// internal method that sets the offset of every entry with a fragment,
+/// chapters are read only once
+fn resolve_toc_offsets(
+    path: &str,
+    entries: &mut Vector<TocEntry>,
+    cache: &mut HashMap<usize, Option<(String, Rc<String>)>>,
+) {
+    for entry in entries.iter_mut() {
+        if let Some(fragment) = entry.get_fragment() {
+            let chapter = entry.get_chapter();
+            let chapter_data = cache.entry(chapter).or_insert_with(|| {
+                get_chapter_html(path, chapter).map(|html| (html, get_chapter_text(path, chapter)))
+            });
+            if let Some((html, text)) = chapter_data {
+                entry.set_offset(find_fragment_offset(html.as_str(), text.as_str(), &fragment));
+            }
         }
+        resolve_toc_offsets(path, entry.get_children_mut(), cache);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::toc::flatten_toc;
+
+    #[test]
+    fn series_is_read_from_calibre_or_epub3_metadata() {
+        let calibre = xml_tree::parse(
+            r#"<package><metadata>
+                <meta name="calibre:series" content="Il ciclo dei vinti"/>
+                <meta name="calibre:series_index" content="2.0"/>
+            </metadata></package>"#,
+        )
+        .unwrap();
+        assert_eq!(
+            series_from_opf(&calibre),
+            ("Il ciclo dei vinti".to_string(), Some(2.0))
+        );
+
+        let epub3 = xml_tree::parse(
+            r##"<package><metadata>
+                <meta property="belongs-to-collection" id="c01">Fondazione</meta>
+                <meta refines="#c01" property="collection-type">series</meta>
+                <meta refines="#c01" property="group-position">3</meta>
+            </metadata></package>"##,
+        )
+        .unwrap();
+        assert_eq!(series_from_opf(&epub3), ("Fondazione".to_string(), Some(3.0)));
+
+        let set = xml_tree::parse(
+            r##"<package><metadata>
+                <meta property="belongs-to-collection" id="c01">Classici</meta>
+                <meta refines="#c01" property="collection-type">set</meta>
+            </metadata></package>"##,
+        )
+        .unwrap();
+        assert_eq!(series_from_opf(&set), (String::new(), None));
     }
 
-    pages
+    fn spine() -> Vec<PathBuf> {
+        [
+            "OEBPS/text/cover.xhtml",
+            "OEBPS/text/ch1.xhtml",
+            "OEBPS/text/ch 2.xhtml",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect()
+    }
+
+    /// label, chapter, fragment and depth of every entry, in reading order
+    fn summary(toc: &Vector<TocEntry>) -> Vec<(String, usize, Option<String>, usize)> {
+        flatten_toc(toc)
+            .iter()
+            .map(|entry| {
+                (
+                    entry.get_label().to_string(),
+                    entry.get_chapter(),
+                    entry.get_fragment().map(|f| f.to_string()),
+                    entry.get_depth(),
+                )
+            })
+            .collect()
+    }
 
+    #[test]
+    fn nested_ncx_nav_points_are_read() {
+        let ncx = xml_tree::parse(
+            r#"<ncx><navMap>
+                <navPoint id="p1"><navLabel><text>Parte prima</text></navLabel>
+                    <content src="text/ch1.xhtml"/>
+                    <navPoint id="p2"><navLabel><text> Il fumo </text></navLabel>
+                        <content src="text/ch1.xhtml#fumo"/>
+                    </navPoint>
+                </navPoint>
+                <navPoint id="p3"><navLabel><text>Note</text></navLabel>
+                    <content src="../notes.xhtml"/>
+                    <navPoint id="p4"><navLabel><text>Secondo capitolo</text></navLabel>
+                        <content src="text/ch%202.xhtml"/>
+                    </navPoint>
+                </navPoint>
+            </navMap></ncx>"#,
+        )
+        .unwrap();
+        let nav_map = ncx.find("navMap").unwrap();
+        let toc = parse_nav_points(nav_map, Path::new("OEBPS/toc.ncx"), &spine(), 0);
+
+        assert_eq!(toc.len(), 2);
+        assert_eq!(
+            summary(&toc),
+            vec![
+                ("Parte prima".to_string(), 1, None, 0),
+                ("Il fumo".to_string(), 1, Some("fumo".to_string()), 1),
+                // the entry outside the spine is dropped, its child takes its place
+                ("Secondo capitolo".to_string(), 2, None, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_epub3_nav_lists_are_read() {
+        let nav = xml_tree::parse(
+            r##"<html xmlns:epub="http://www.idpf.org/2007/ops"><body>
+                <nav epub:type="landmarks"><ol><li><a href="cover.xhtml">Copertina</a></li></ol></nav>
+                <nav epub:type="toc"><ol>
+                    <li><a href="cover.xhtml">Copertina</a></li>
+                    <li><span>Parte prima</span>
+                        <ol>
+                            <li><a href="ch1.xhtml#s1">Il <em>fumo</em></a></li>
+                            <li><a href="ch%202.xhtml">La morte di mio padre</a></li>
+                        </ol>
+                    </li>
+                    <li><a href="https://esempio.it">Sito</a></li>
+                </ol></nav>
+            </body></html>"##,
+        )
+        .unwrap();
+        let list = nav
+            .find_all("nav")
+            .into_iter()
+            .find(|n| n.attr("type") == Some("toc"))
+            .and_then(|n| n.find("ol"))
+            .unwrap();
+        let toc = parse_nav_list(list, Path::new("OEBPS/text/nav.xhtml"), &spine(), 0);
+
+        assert_eq!(
+            summary(&toc),
+            vec![
+                ("Copertina".to_string(), 0, None, 0),
+                // the heading without a link is dropped, its entries are promoted
+                ("Il fumo".to_string(), 1, Some("s1".to_string()), 1),
+                ("La morte di mio padre".to_string(), 2, None, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn books_without_toc_have_an_entry_per_chapter() {
+        assert_eq!(
+            summary(&chapters_toc(2)),
+            vec![
+                ("Capitolo 1".to_string(), 0, None, 0),
+                ("Capitolo 2".to_string(), 1, None, 0),
+            ]
+        );
+        assert!(chapters_toc(0).is_empty());
+    }
+
+    #[test]
+    fn fragments_are_found_in_the_chapter_text() {
+        let html = r#"<html><body><p>Prefazione</p><p id="fumo">Il fumo</p>
+            <p>Il dottore al quale ne parlai</p></body></html>"#;
+        let text = "Prefazione\n\nIl fumo\n\nIl dottore al quale ne parlai";
+        assert_eq!(find_fragment_offset(html, text, "fumo"), Some(12));
+        assert_eq!(find_fragment_offset(html, text, "assente"), None);
+    }
+
+    #[test]
+    fn added_is_back_filled_from_the_file_time() {
+        let dir = std::env::temp_dir().join("crab-reader-epub-utils-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let epub = dir.join("book.epub");
+        std::fs::write(&epub, b"epub").unwrap();
+        let file = std::fs::metadata(&epub).unwrap();
+        let file_time = file.created().or_else(|_| file.modified()).unwrap();
+
+        let before = saveload::unix_time(SystemTime::now());
+        assert_eq!(
+            added_time(epub.to_str().unwrap()),
+            saveload::unix_time(file_time)
+        );
+        // a missing file was added now
+        let missing = dir.join("missing.epub");
+        assert!(added_time(missing.to_str().unwrap()) >= before);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,370 @@
+//! Export of the notes and the highlights of the books, for a report or a study document.
+//! The annotations of a book are grouped by chapter and sorted by their position;
+//! a library is exported as one document with a section for every annotated book.
+
+use std::{collections::BTreeMap, error::Error, path::Path};
+
+use druid::{FileInfo, Selector};
+use serde::Serialize;
+
+use crate::{
+    models::toc::flatten_toc,
+    utils::{
+        epub_utils::{get_metadata_of_book, get_toc},
+        saveload::{load_highlights, load_notes},
+        xml_tree::escape,
+    },
+};
+
+/// export the annotations of the selected book to the chosen file
+pub const EXPORT_BOOK_NOTES: Selector<FileInfo> = Selector::new("crabreader.export_book_notes");
+
+/// export the annotations of all the books to the chosen file
+pub const EXPORT_LIBRARY_NOTES: Selector<FileInfo> =
+    Selector::new("crabreader.export_library_notes");
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    Markdown,
+    Html,
+    Json,
+}
+
+impl ExportFormat {
+    /// Returns the format of a file from its extension, Markdown if it isn't known
+    pub fn from_path(path: &Path) -> Self {
+        match path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase())
+            .as_deref()
+        {
+            Some("html" | "htm") => ExportFormat::Html,
+            Some("json") => ExportFormat::Json,
+            _ => ExportFormat::Markdown,
+        }
+    }
+}
+
+/// What is exported: a book, also without annotations, or the annotated books of the library.
+/// A book is a single object in JSON, a library an array of them
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportScope {
+    Book,
+    Library,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct BookAnnotations {
+    pub title: String,
+    pub author: String,
+    pub path: String,
+    pub chapters: Vec<ChapterAnnotations>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ChapterAnnotations {
+    /// number of the chapter, from 1
+    pub chapter: usize,
+    /// title of the chapter in the table of contents
+    pub title: String,
+    pub annotations: Vec<Annotation>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AnnotationKind {
+    Note,
+    Highlight,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Annotation {
+    pub kind: AnnotationKind,
+    /// the passage of the book the annotation is anchored to
+    pub passage: String,
+    /// the text written by the user, empty for a highlight without a note
+    pub note: String,
+    /// offset in characters of the passage in the chapter text
+    pub offset: usize,
+    /// colour of the highlight
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+}
+
+impl BookAnnotations {
+    pub fn is_empty(&self) -> bool {
+        self.chapters.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.chapters
+            .iter()
+            .map(|chapter| chapter.annotations.len())
+            .sum()
+    }
+}
+
+/// Collects the notes and the highlights of the book
+pub fn collect(path: &str) -> Result<BookAnnotations, Box<dyn Error>> {
+    let metadata = get_metadata_of_book(path);
+    let mut chapters: BTreeMap<usize, Vec<Annotation>> = BTreeMap::new();
+
+    for note in load_notes(path)?.iter() {
+        let position = note.get_position();
+        chapters
+            .entry(position.get_chapter())
+            .or_default()
+            .push(Annotation {
+                kind: AnnotationKind::Note,
+                passage: note.get_start().trim().to_string(),
+                note: note.get_text().trim().to_string(),
+                offset: position.get_offset(),
+                color: None,
+            });
+    }
+    for highlight in load_highlights(path)?.iter() {
+        let range = highlight.get_range();
+        chapters
+            .entry(range.get_chapter())
+            .or_default()
+            .push(Annotation {
+                kind: AnnotationKind::Highlight,
+                passage: highlight.get_text().trim().to_string(),
+                note: highlight.get_note().trim().to_string(),
+                offset: range.to_range().start,
+                color: Some(highlight.get_color().to_str().to_string()),
+            });
+    }
+
+    // the first entry of the table of contents pointing to the chapter names it
+    let toc = get_toc(path)
+        .map(|toc| flatten_toc(&toc))
+        .unwrap_or_default();
+    let chapters = chapters
+        .into_iter()
+        .map(|(chapter, mut annotations)| {
+            annotations.sort_by_key(|annotation| annotation.offset);
+            let title = toc
+                .iter()
+                .find(|entry| entry.get_chapter() == chapter)
+                .map_or_else(
+                    || format!("Capitolo {}", chapter + 1),
+                    |entry| entry.get_label().to_string(),
+                );
+            ChapterAnnotations {
+                chapter: chapter + 1,
+                title,
+                annotations,
+            }
+        })
+        .collect();
+
+    Ok(BookAnnotations {
+        title: metadata.title,
+        author: metadata.author,
+        path: path.to_string(),
+        chapters,
+    })
+}
+
+/// Returns the annotations of the books in the given format
+pub fn render(
+    books: &[BookAnnotations],
+    format: ExportFormat,
+    scope: ExportScope,
+) -> Result<String, Box<dyn Error>> {
+    Ok(match (format, scope, books) {
+        (ExportFormat::Markdown, _, _) => to_markdown(books),
+        (ExportFormat::Html, _, _) => to_html(books, scope),
+        (ExportFormat::Json, ExportScope::Book, [book]) => serde_json::to_string_pretty(book)?,
+        (ExportFormat::Json, _, books) => serde_json::to_string_pretty(books)?,
+    })
+}
+
+/// Collects the annotations of the books, leaving out the ones without any
+/// when the library is exported
+pub fn collect_all(
+    paths: &[String],
+    scope: ExportScope,
+) -> Result<Vec<BookAnnotations>, Box<dyn Error>> {
+    let mut books = vec![];
+    for path in paths {
+        let book = collect(path)?;
+        if !book.is_empty() || scope == ExportScope::Book {
+            books.push(book);
+        }
+    }
+    Ok(books)
+}
+
+/// Writes the annotations of the books in the file, in the format of its extension,
+/// returns the number of annotations written
+pub fn export_to_file(
+    paths: &[String],
+    scope: ExportScope,
+    target: &Path,
+) -> Result<usize, Box<dyn Error>> {
+    let books = collect_all(paths, scope)?;
+    let text = render(&books, ExportFormat::from_path(target), scope)?;
+    std::fs::write(target, text)?;
+    Ok(books.iter().map(|book| book.len()).sum())
+}
+
+fn location(chapter: &ChapterAnnotations, annotation: &Annotation) -> String {
+    format!(
+        "Capitolo {}, carattere {}",
+        chapter.chapter, annotation.offset
+    )
+}
+
+fn to_markdown(books: &[BookAnnotations]) -> String {
+    let mut text = String::new();
+    for book in books {
+        text.push_str(&format!("# {}\n\n_{}_\n", book.title, book.author));
+        for chapter in book.chapters.iter() {
+            text.push_str(&format!("\n## {}\n", chapter.title));
+            for annotation in chapter.annotations.iter() {
+                text.push('\n');
+                for line in annotation.passage.lines() {
+                    text.push_str(&format!("> {}\n", line));
+                }
+                if !annotation.note.is_empty() {
+                    text.push_str(&format!("\n{}\n", annotation.note));
+                }
+                text.push_str(&format!("\n_{}_\n", location(chapter, annotation)));
+            }
+        }
+        text.push('\n');
+    }
+    text
+}
+
+fn to_html(books: &[BookAnnotations], scope: ExportScope) -> String {
+    let title = match (scope, books) {
+        (ExportScope::Book, [book]) => escape(&book.title),
+        _ => "Note della libreria".to_string(),
+    };
+    let mut body = String::new();
+    for book in books {
+        body.push_str(&format!(
+            "<section>\n<h1>{}</h1>\n<p class=\"author\">{}</p>\n",
+            escape(&book.title),
+            escape(&book.author)
+        ));
+        for chapter in book.chapters.iter() {
+            body.push_str(&format!("<h2>{}</h2>\n", escape(&chapter.title)));
+            for annotation in chapter.annotations.iter() {
+                let class = match (&annotation.kind, &annotation.color) {
+                    (AnnotationKind::Highlight, Some(color)) => format!("highlight {}", color),
+                    _ => "note".to_string(),
+                };
+                body.push_str(&format!(
+                    "<div class=\"{}\">\n<blockquote>{}</blockquote>\n",
+                    class,
+                    escape(&annotation.passage).replace('\n', "<br>")
+                ));
+                if !annotation.note.is_empty() {
+                    body.push_str(&format!(
+                        "<p>{}</p>\n",
+                        escape(&annotation.note).replace('\n', "<br>")
+                    ));
+                }
+                body.push_str(&format!(
+                    "<p class=\"location\">{}</p>\n</div>\n",
+                    location(chapter, annotation)
+                ));
+            }
+        }
+        body.push_str("</section>\n");
+    }
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{}</title>
+<style>
+body {{ font-family: serif; max-width: 45em; margin: 2em auto; padding: 0 1em; }}
+.author, .location {{ color: #666; font-style: italic; }}
+blockquote {{ margin: 0; padding-left: 1em; border-left: 4px solid #ccc; }}
+.highlight blockquote {{ border-left-color: #ffd600; }}
+.highlight.green blockquote {{ border-left-color: #4cd964; }}
+.highlight.blue blockquote {{ border-left-color: #3fa9f5; }}
+.highlight.pink blockquote {{ border-left-color: #ff5ca8; }}
+.note, .highlight {{ margin: 1.5em 0; }}
+</style>
+</head>
+<body>
+{}</body>
+</html>
+"#,
+        title, body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book() -> BookAnnotations {
+        BookAnnotations {
+            title: "Zeno & co".to_string(),
+            author: "Italo Svevo".to_string(),
+            path: "zeno.epub".to_string(),
+            chapters: vec![ChapterAnnotations {
+                chapter: 2,
+                title: "Il fumo".to_string(),
+                annotations: vec![
+                    Annotation {
+                        kind: AnnotationKind::Note,
+                        passage: "Il dottore".to_string(),
+                        note: "<importante>".to_string(),
+                        offset: 10,
+                        color: None,
+                    },
+                    Annotation {
+                        kind: AnnotationKind::Highlight,
+                        passage: "l'ultima sigaretta".to_string(),
+                        note: String::new(),
+                        offset: 42,
+                        color: Some("green".to_string()),
+                    },
+                ],
+            }],
+        }
+    }
+
+    #[test]
+    fn annotations_are_rendered() {
+        let markdown = render(&[book()], ExportFormat::Markdown, ExportScope::Book).unwrap();
+        assert!(markdown.starts_with("# Zeno & co\n"));
+        assert!(markdown.contains("## Il fumo\n\n> Il dottore\n\n<importante>\n"));
+        assert!(markdown.contains("_Capitolo 2, carattere 42_"));
+
+        let html = render(&[book()], ExportFormat::Html, ExportScope::Book).unwrap();
+        assert!(html.contains("<title>Zeno &amp; co</title>"));
+        assert!(html.contains("<p>&lt;importante&gt;</p>"));
+        assert!(html.contains("<div class=\"highlight green\">"));
+
+        let json = |scope| {
+            let text = render(&[book()], ExportFormat::Json, scope).unwrap();
+            serde_json::from_str::<serde_json::Value>(&text).unwrap()
+        };
+        let annotations = json(ExportScope::Book)["chapters"][0]["annotations"].clone();
+        assert_eq!(annotations[1]["kind"], "highlight");
+        assert_eq!(annotations[0].get("color"), None);
+        // a library is always an array, also with only one annotated book
+        let library_json = json(ExportScope::Library);
+        assert_eq!(library_json[0]["title"], "Zeno & co");
+        assert_eq!(library_json.as_array().map(|books| books.len()), Some(1));
+        let library_html = render(&[book()], ExportFormat::Html, ExportScope::Library).unwrap();
+        assert!(library_html.contains("<title>Note della libreria</title>"));
+
+        assert_eq!(
+            ExportFormat::from_path(Path::new("note.HTML")),
+            ExportFormat::Html
+        );
+    }
+}
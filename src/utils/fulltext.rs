@@ -0,0 +1,456 @@
+use std::{
+    collections::HashMap,
+    error,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use once_cell::sync::Lazy;
+use serde_json::{json, Value};
+
+use super::{
+    dir_manager::get_fulltext_index_dir, epub_utils::get_chapter_text, identity::book_id,
+    search::fold, storage,
+};
+
+/// maximum number of results of a search in the library
+const MAX_RESULTS: usize = 50;
+
+/// The index of all the books of the library, loaded from disk on first use
+static INDEX: Lazy<Mutex<FullTextIndex>> = Lazy::new(|| Mutex::new(FullTextIndex::load()));
+
+/// The occurrences of a term in a chapter of a book
+#[derive(Clone, Debug, PartialEq)]
+struct Posting {
+    /// the path of the book, shared with the key of `books`
+    book: Arc<str>,
+    chapter: usize,
+    /// number of occurrences of the term in the chapter
+    count: usize,
+    /// offset in characters of the first occurrence
+    first: usize,
+}
+
+/// A chapter of a book that contains all the searched terms
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexHit {
+    path: String,
+    chapter: usize,
+    /// offset in characters of the first occurrence of the most relevant term
+    offset: usize,
+    /// the term found at `offset`
+    term: String,
+    score: f64,
+}
+
+impl IndexHit {
+    pub fn get_path(&self) -> &String {
+        &self.path
+    }
+
+    pub fn get_chapter(&self) -> usize {
+        self.chapter
+    }
+
+    pub fn get_offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn get_term(&self) -> &String {
+        &self.term
+    }
+
+    pub fn get_score(&self) -> f64 {
+        self.score
+    }
+}
+
+/// Inverted index of the text of the books: every term points to the chapters that contain it.
+/// On disk every book has its own segment, a json file in the index folder
+#[derive(Default)]
+struct FullTextIndex {
+    terms: HashMap<String, Vec<Posting>>,
+    /// number of terms of every chapter of every book
+    books: HashMap<Arc<str>, Vec<usize>>,
+}
+
+impl FullTextIndex {
+    /// Reads all the segments of the index folder
+    fn load() -> Self {
+        let mut index = FullTextIndex::default();
+        let Ok(files) = std::fs::read_dir(get_fulltext_index_dir()) else {
+            return index;
+        };
+
+        for file in files.flatten() {
+            let Ok(opened_file) = File::open(file.path()) else {
+                continue;
+            };
+            let reader = BufReader::new(opened_file);
+            match serde_json::from_reader::<_, Value>(reader) {
+                Ok(segment) => {
+                    // a segment shared by copies of a book can name a deleted copy:
+                    // it is skipped and the copy left is indexed again
+                    let book = segment["path"].as_str().map(Path::new);
+                    if book.map_or(false, Path::exists) {
+                        index.add_segment(&segment);
+                    }
+                }
+                Err(e) => println!("ERROR: invalid index segment {:?}: {}", file.path(), e),
+            }
+        }
+        println!("DEBUG: full-text index of {} books loaded", index.books.len());
+        index
+    }
+
+    /// Returns the path of the book as stored in `books`, adding the book if missing
+    fn book(&mut self, path: &str) -> Arc<str> {
+        if let Some((book, _)) = self.books.get_key_value(path) {
+            return book.clone();
+        }
+        let book: Arc<str> = Arc::from(path);
+        self.books.insert(book.clone(), vec![]);
+        book
+    }
+
+    fn add_chapter(&mut self, path: &str, chapter: usize, text: &str) {
+        let tokens = tokenize(text);
+
+        let book = self.book(path);
+        let lengths = self.books.entry(book.clone()).or_default();
+        if lengths.len() <= chapter {
+            lengths.resize(chapter + 1, 0);
+        }
+        lengths[chapter] = tokens.len();
+
+        let mut postings: HashMap<String, Posting> = HashMap::new();
+        for (term, offset) in tokens {
+            postings
+                .entry(term)
+                .or_insert(Posting {
+                    book: book.clone(),
+                    chapter,
+                    count: 0,
+                    first: offset,
+                })
+                .count += 1;
+        }
+        for (term, posting) in postings {
+            self.terms.entry(term).or_default().push(posting);
+        }
+    }
+
+    fn remove_chapter(&mut self, path: &str, chapter: usize) {
+        self.terms.retain(|_, postings| {
+            postings.retain(|p| &*p.book != path || p.chapter != chapter);
+            !postings.is_empty()
+        });
+        if let Some(lengths) = self.books.get_mut(path) {
+            if let Some(length) = lengths.get_mut(chapter) {
+                *length = 0;
+            }
+        }
+    }
+
+    fn remove_book(&mut self, path: &str) {
+        self.terms.retain(|_, postings| {
+            postings.retain(|p| &*p.book != path);
+            !postings.is_empty()
+        });
+        self.books.remove(path);
+    }
+
+    /// Moves all the books of the other index into this one
+    fn merge(&mut self, other: FullTextIndex) {
+        for path in other.books.keys() {
+            self.remove_book(path);
+        }
+        for (term, postings) in other.terms {
+            self.terms.entry(term).or_default().extend(postings);
+        }
+        self.books.extend(other.books);
+    }
+
+    /// Returns the segment of a book: {path, lengths, terms: {term: [[chapter, count, first]]}}
+    fn segment(&self, path: &str) -> Value {
+        let mut terms = serde_json::Map::new();
+        for (term, postings) in self.terms.iter() {
+            let postings: Vec<Value> = postings
+                .iter()
+                .filter(|p| &*p.book == path)
+                .map(|p| json!([p.chapter, p.count, p.first]))
+                .collect();
+            if !postings.is_empty() {
+                terms.insert(term.clone(), Value::Array(postings));
+            }
+        }
+
+        json!({
+            "path": path,
+            "lengths": self.books.get(path).cloned().unwrap_or_default(),
+            "terms": terms,
+        })
+    }
+
+    fn add_segment(&mut self, segment: &Value) {
+        let Some(path) = segment["path"].as_str() else {
+            return;
+        };
+        let lengths = segment["lengths"]
+            .as_array()
+            .map(|lengths| {
+                lengths
+                    .iter()
+                    .map(|l| l.as_u64().unwrap_or(0) as usize)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let book = self.book(path);
+        self.books.insert(book.clone(), lengths);
+
+        let Some(terms) = segment["terms"].as_object() else {
+            return;
+        };
+        for (term, postings) in terms {
+            let postings = postings.as_array().into_iter().flatten().filter_map(|p| {
+                Some(Posting {
+                    book: book.clone(),
+                    chapter: p[0].as_u64()? as usize,
+                    count: p[1].as_u64()? as usize,
+                    first: p[2].as_u64()? as usize,
+                })
+            });
+            self.terms.entry(term.clone()).or_default().extend(postings);
+        }
+    }
+
+    fn save_segment(&self, path: &str) -> Result<(), Box<dyn error::Error>> {
+        let bytes = serde_json::to_vec(&self.segment(path))?;
+        storage::write_atomic(&segment_path(path), &bytes)?;
+        Ok(())
+    }
+
+    /// Ranks the chapters that contain all the terms of the query with tf-idf
+    fn search(&self, query: &str) -> Vec<IndexHit> {
+        let mut query_terms: Vec<String> = tokenize(query).into_iter().map(|(t, _)| t).collect();
+        query_terms.sort();
+        query_terms.dedup();
+        if query_terms.is_empty() {
+            return vec![];
+        }
+
+        let documents: usize = self.books.values().map(|lengths| lengths.len()).sum();
+        // (book, chapter) -> (score, matched terms, best term, its offset, its score)
+        let mut found: HashMap<(&str, usize), (f64, usize, &str, usize, f64)> = HashMap::new();
+
+        for term in query_terms.iter() {
+            let Some(postings) = self.terms.get(term) else {
+                return vec![];
+            };
+            let idf = (1.0 + documents as f64 / postings.len() as f64).ln();
+
+            for posting in postings {
+                let length = self
+                    .books
+                    .get(&*posting.book)
+                    .and_then(|lengths| lengths.get(posting.chapter))
+                    .copied()
+                    .unwrap_or(0)
+                    .max(1);
+                let score = posting.count as f64 / length as f64 * idf;
+
+                let entry = found
+                    .entry((&*posting.book, posting.chapter))
+                    .or_insert((0.0, 0, term.as_str(), posting.first, score));
+                entry.0 += score;
+                entry.1 += 1;
+                if score > entry.4 {
+                    (entry.2, entry.3, entry.4) = (term.as_str(), posting.first, score);
+                }
+            }
+        }
+
+        let mut hits: Vec<IndexHit> = found
+            .into_iter()
+            .filter(|(_, (_, matched, ..))| *matched == query_terms.len())
+            .map(|((path, chapter), (score, _, term, offset, _))| IndexHit {
+                path: path.to_string(),
+                chapter,
+                offset,
+                term: term.to_string(),
+                score,
+            })
+            .collect();
+
+        hits.sort_by(|one, other| {
+            other
+                .score
+                .partial_cmp(&one.score)
+                .unwrap()
+                .then_with(|| one.path.cmp(&other.path))
+                .then_with(|| one.chapter.cmp(&other.chapter))
+        });
+        hits.truncate(MAX_RESULTS);
+        hits
+    }
+}
+
+/// Returns the file of the segment of the book, named after its id:
+/// the copies of the same book share it
+pub fn segment_path(book_path: &str) -> PathBuf {
+    get_fulltext_index_dir().join(format!("{}.json", book_id(book_path)))
+}
+
+/// Splits the text in lower case words, each with its offset in characters
+pub fn tokenize(text: &str) -> Vec<(String, usize)> {
+    let mut tokens = vec![];
+    let mut word = String::new();
+    let mut start = 0;
+
+    for (i, c) in text.chars().enumerate() {
+        if c.is_alphanumeric() {
+            if word.is_empty() {
+                start = i;
+            }
+            word.push(fold(c));
+        } else if !word.is_empty() {
+            tokens.push((std::mem::take(&mut word), start));
+        }
+    }
+    if !word.is_empty() {
+        tokens.push((word, start));
+    }
+    tokens
+}
+
+/// Returns true if the book is already in the index
+pub fn is_indexed(path: &str) -> bool {
+    INDEX.lock().unwrap().books.contains_key(path)
+}
+
+/// Adds all the chapters of the book to the index and saves its segment
+pub fn index_book(path: &str, number_of_chapters: usize) -> Result<(), Box<dyn error::Error>> {
+    // the chapters are read without holding the lock of the index
+    let mut book_index = FullTextIndex::default();
+    for chapter in 0..number_of_chapters {
+        let text = get_chapter_text(path, chapter);
+        book_index.add_chapter(path, chapter, &text);
+    }
+    book_index.save_segment(path)?;
+
+    INDEX.lock().unwrap().merge(book_index);
+    println!("DEBUG: indexed {} chapters of {}", number_of_chapters, path);
+    Ok(())
+}
+
+/// Replaces the indexed text of an edited chapter
+pub fn reindex_chapter(path: &str, chapter: usize, text: &str) -> Result<(), Box<dyn error::Error>> {
+    let mut index = INDEX.lock().unwrap();
+    index.remove_chapter(path, chapter);
+    index.add_chapter(path, chapter, text);
+    index.save_segment(path)
+}
+
+/// Removes the book from the index in memory, its segment is left on disk
+pub fn forget_book(path: &str) {
+    INDEX.lock().unwrap().remove_book(path);
+}
+
+/// Removes the book from the index and deletes its segment
+pub fn remove_book(path: &str) -> Result<(), Box<dyn error::Error>> {
+    forget_book(path);
+    let segment = segment_path(path);
+    if segment.exists() {
+        std::fs::remove_file(segment)?;
+    }
+    Ok(())
+}
+
+/// Searches all the words of the query in the books of the library,
+/// the most relevant chapters first
+pub fn search(query: &str) -> Vec<IndexHit> {
+    let hits = INDEX.lock().unwrap().search(query);
+    println!("DEBUG: {} chapters found for '{}'", hits.len(), query);
+    hits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_index() -> FullTextIndex {
+        let mut index = FullTextIndex::default();
+        index.add_chapter("a.epub", 0, "Il gatto dorme sul divano.");
+        index.add_chapter("a.epub", 1, "Il cane abbaia al gatto, il gatto scappa.");
+        index.add_chapter("b.epub", 0, "Una lunga storia di mare e di navi, senza animali.");
+        index.add_chapter("b.epub", 1, "Perché il Gatto è sul tetto?");
+        index
+    }
+
+    #[test]
+    fn tokenize_folds_case_and_uses_char_offsets() {
+        let tokens = tokenize("Perché il Gatto?");
+        assert_eq!(
+            tokens,
+            vec![
+                ("perché".to_string(), 0),
+                ("il".to_string(), 7),
+                ("gatto".to_string(), 10)
+            ]
+        );
+    }
+
+    #[test]
+    fn search_ranks_chapters_by_relevance() {
+        let hits = test_index().search("GATTO");
+
+        assert_eq!(hits.len(), 3);
+        // two occurrences in eight words
+        assert_eq!((hits[0].get_path().as_str(), hits[0].get_chapter()), ("a.epub", 1));
+        assert_eq!(hits[0].get_offset(), 18);
+        assert!(hits.windows(2).all(|h| h[0].get_score() >= h[1].get_score()));
+    }
+
+    #[test]
+    fn search_needs_all_the_terms() {
+        let index = test_index();
+        let hits = index.search("gatto tetto");
+        assert_eq!(hits.len(), 1);
+        assert_eq!((hits[0].get_path().as_str(), hits[0].get_chapter()), ("b.epub", 1));
+
+        assert!(index.search("gatto balena").is_empty());
+        assert!(index.search("  ").is_empty());
+    }
+
+    #[test]
+    fn segments_and_edits_keep_the_index_consistent() {
+        let index = test_index();
+        let mut loaded = FullTextIndex::default();
+        loaded.add_segment(&index.segment("a.epub"));
+        loaded.add_segment(&index.segment("b.epub"));
+        assert_eq!(loaded.search("gatto"), index.search("gatto"));
+
+        loaded.remove_chapter("b.epub", 1);
+        loaded.add_chapter("b.epub", 1, "Il tetto è vuoto");
+        assert!(loaded.search("gatto tetto").is_empty());
+
+        loaded.remove_book("a.epub");
+        assert!(loaded.search("gatto").is_empty());
+        assert_eq!(loaded.search("tetto").len(), 1);
+    }
+
+    #[test]
+    fn postings_share_the_path_of_the_book() {
+        let index = test_index();
+        let (book, _) = index.books.get_key_value("a.epub").unwrap();
+        let postings = index
+            .terms
+            .values()
+            .flatten()
+            .filter(|p| &*p.book == "a.epub");
+        assert!(postings.clone().count() > 1);
+        assert!(postings.into_iter().all(|p| Arc::ptr_eq(&p.book, book)));
+    }
+}
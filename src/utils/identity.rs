@@ -0,0 +1,209 @@
+//! Identity of the books, independent of the name and the folder of their file.
+//! A book is identified by the unique identifier of its OPF and a hash of its content,
+//! so that the data saved for it survives a rename and two books with the same name
+//! don't share it. The id of every file is kept in `books.json`, so that a book is
+//! hashed again only when its file changes.
+
+use std::{collections::HashMap, path::Path, sync::Mutex, time::UNIX_EPOCH};
+
+use epub::doc::EpubDoc;
+use once_cell::sync::Lazy;
+
+use crate::{
+    models::documents::{BooksDocument, RegisteredBook},
+    utils::storage::{self, Document},
+};
+
+/// book path -> id, the books already identified since the start
+static IDS: Lazy<Mutex<HashMap<String, String>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Returns the id of the book with the given path.
+/// A changed file with the same OPF identifier keeps its id, as a new version of the same book
+pub fn book_id(path: &str) -> String {
+    if let Some(id) = IDS.lock().unwrap().get(path) {
+        return id.clone();
+    }
+
+    let registered = storage::load::<BooksDocument>(&Document::Books)
+        .ok()
+        .and_then(|mut books| books.remove(path));
+    let stamp = stamp_of(Path::new(path));
+    let id = match (registered, stamp) {
+        (Some(registered), Some((modified, len)))
+            if registered.modified == modified && registered.len == len =>
+        {
+            registered.id
+        }
+        (registered, Some((modified, len))) => {
+            let id = match (registered, compute_id(path)) {
+                (Some(registered), Some(id)) if same_identifier(&registered.id, &id) => {
+                    registered.id
+                }
+                (_, Some(id)) => id,
+                (registered, None) => registered.map_or_else(|| path_id(path), |r| r.id),
+            };
+            register(path, &id, modified, len);
+            id
+        }
+        // the file isn't there anymore: its data is still found by the old id
+        (Some(registered), None) => registered.id,
+        (None, None) => path_id(path),
+    };
+
+    IDS.lock().unwrap().insert(path.to_string(), id.clone());
+    id
+}
+
+/// Calculates the id of the book from its file, without using the registry:
+/// "<hash of the OPF identifier>-<hash of the file>"
+pub fn compute_id(path: &str) -> Option<String> {
+    let bytes = std::fs::read(path).ok()?;
+    let identifier = EpubDoc::new(path)
+        .ok()
+        .and_then(|book| book.mdata("identifier"))
+        .unwrap_or_default();
+    Some(format!(
+        "{:016x}-{:016x}",
+        fnv1a(identifier.trim().as_bytes()),
+        fnv1a(&bytes)
+    ))
+}
+
+/// The id of a file that can't be read
+pub fn path_id(path: &str) -> String {
+    format!("path-{:016x}", fnv1a(path.as_bytes()))
+}
+
+/// The cached id of the book is calculated again, e.g. after its file changed
+pub fn forget(path: &str) {
+    IDS.lock().unwrap().remove(path);
+}
+
+/// Removes the book from the registry,
+/// returns true if another file of the library has the same id
+pub fn unregister(path: &str) -> bool {
+    let id = book_id(path);
+    forget(path);
+    let result = storage::modify(&Document::Books, |books: &mut BooksDocument| {
+        books.remove(path);
+        books.values().any(|book| book.id == id)
+    });
+    match result {
+        Ok(shared) => shared,
+        Err(e) => {
+            println!("ERROR: failed to unregister {}: {}", path, e);
+            // keeping the data is safer
+            true
+        }
+    }
+}
+
+fn register(path: &str, id: &str, modified: u64, len: u64) {
+    let result = storage::modify(&Document::Books, |books: &mut BooksDocument| {
+        books.insert(
+            path.to_string(),
+            RegisteredBook {
+                id: id.to_string(),
+                modified,
+                len,
+            },
+        );
+    });
+    if let Err(e) = result {
+        println!("ERROR: failed to register the id of {}: {}", path, e);
+    }
+}
+
+/// Returns true if the ids are of the same book: the same OPF identifier or the same content
+pub fn same_book(a: &str, b: &str) -> bool {
+    let same_content = matches!((parts(a), parts(b)), (Some((_, a)), Some((_, b))) if a == b);
+    a == b || same_content || same_identifier(a, b)
+}
+
+/// Splits the id in the hash of the identifier and the hash of the content,
+/// none for the id of a file that couldn't be read
+fn parts(id: &str) -> Option<(&str, &str)> {
+    id.split_once('-')
+        .filter(|(identifier, _)| *identifier != "path")
+}
+
+/// The books without an identifier never have the same one
+fn same_identifier(a: &str, b: &str) -> bool {
+    match (parts(a), parts(b)) {
+        (Some((a, _)), Some((b, _))) => a == b && a != format!("{:016x}", fnv1a(b"")),
+        _ => false,
+    }
+}
+
+fn stamp_of(path: &Path) -> Option<(u64, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    let modified = metadata
+        .modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    Some((modified, metadata.len()))
+}
+
+/// 64 bit FNV-1a: unlike the hasher of the standard library,
+/// it gives the same value in every version, as needed for ids saved on disk
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_is_stable() {
+        // reference values of FNV-1a 64
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(path_id("book.epub"), path_id("book.epub"));
+        assert_ne!(path_id("a/book.epub"), path_id("b/book.epub"));
+    }
+
+    #[test]
+    fn new_content_with_the_same_identifier_is_the_same_book() {
+        assert!(same_identifier(
+            "00000000000000aa-0000000000000001",
+            "00000000000000aa-0000000000000002"
+        ));
+        assert!(!same_identifier(
+            "00000000000000aa-0000000000000001",
+            "00000000000000bb-0000000000000001"
+        ));
+        assert!(!same_identifier(
+            "path-00000000000000aa",
+            "00000000000000aa-0000000000000001"
+        ));
+        // no identifier
+        assert!(!same_identifier(
+            "cbf29ce484222325-0000000000000001",
+            "cbf29ce484222325-0000000000000002"
+        ));
+    }
+
+    #[test]
+    fn copies_are_the_same_book() {
+        assert!(same_book(
+            "00000000000000aa-0000000000000001",
+            "00000000000000bb-0000000000000001"
+        ));
+        assert!(same_book(
+            "00000000000000aa-0000000000000001",
+            "00000000000000aa-0000000000000002"
+        ));
+        assert!(!same_book(
+            "cbf29ce484222325-0000000000000001",
+            "cbf29ce484222325-0000000000000002"
+        ));
+        assert!(!same_book("path-0000000000000001", "path-0000000000000002"));
+    }
+}
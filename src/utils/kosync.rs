@@ -0,0 +1,558 @@
+//! Sync of the reading progress with a KOReader sync server (kosync protocol).
+//! A book is identified on the server by the partial md5 of its file, the same computed
+//! by KOReader, and its progress is an XPointer to the element being read
+//! ("/body/DocFragment[3]/body/div[1]/p[12]") with the percentage of the book read.
+//! The position is pushed while reading and when the book is closed, and pulled when it
+//! is opened: a position further on, read on another device, is where the reading resumes.
+//! The pull happens in background, the book opens at the saved position and moves
+//! to the pulled one when the server answers.
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use druid::{ExtEventSink, Selector, Target};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    models::{documents::KosyncDocument, position::Position},
+    utils::{
+        epub_utils::{get_chapter_html, get_chapter_text, get_metadata_of_book},
+        saveload::unix_time,
+        storage::{self, Document},
+        xml_tree::{self, XmlNode},
+    },
+};
+
+/// a position further on has been read on another device: the path of the book,
+/// the local position it has been compared with and the pulled one
+pub const POSITION_PULLED: Selector<(String, Position, Position)> =
+    Selector::new("crabreader.position_pulled");
+
+/// name of this application on the server
+const DEVICE: &str = "CrabReader";
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// seconds between two pushes of the progress while reading
+const PUSH_INTERVAL: u64 = 60;
+
+/// the account, None if the sync is off
+static SETTINGS: Lazy<Mutex<Option<KosyncDocument>>> = Lazy::new(|| {
+    let settings = storage::load::<KosyncDocument>(&Document::Kosync)
+        .ok()
+        .filter(|settings| !settings.server.is_empty());
+    Mutex::new(settings)
+});
+
+/// book path -> when its progress has been pushed the last time
+static LAST_PUSH: Lazy<Mutex<HashMap<String, u64>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// book path -> length in characters of the text of every chapter
+static CHAPTER_LENGTHS: Lazy<Mutex<HashMap<String, Vec<usize>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// The progress of a book, as the server stores it
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Progress {
+    pub document: String,
+    /// XPointer to the position in the book
+    pub progress: String,
+    /// part of the book read, from 0 to 1
+    pub percentage: f64,
+    pub device: String,
+    pub device_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+}
+
+/// Client of a kosync server
+pub struct Client {
+    server: String,
+    username: String,
+    userkey: String,
+}
+
+impl Client {
+    /// `userkey` is the md5 of the password
+    pub fn new(server: &str, username: &str, userkey: &str) -> Self {
+        Self {
+            server: server.trim_end_matches('/').to_string(),
+            username: username.to_string(),
+            userkey: userkey.to_string(),
+        }
+    }
+
+    fn request(&self, method: &str, path: &str) -> ureq::Request {
+        ureq::request(method, &format!("{}{}", self.server, path))
+            .timeout(TIMEOUT)
+            .set("Accept", "application/vnd.koreader.v1+json")
+            .set("Content-Type", "application/json")
+            .set("x-auth-user", &self.username)
+            .set("x-auth-key", &self.userkey)
+    }
+
+    /// Creates the account on the server
+    pub fn register(&self) -> Result<(), Box<dyn Error>> {
+        let body = serde_json::json!({ "username": self.username, "password": self.userkey });
+        self.request("POST", "/users/create")
+            .send_string(&body.to_string())?;
+        Ok(())
+    }
+
+    /// Checks the username and the password
+    pub fn authorize(&self) -> Result<(), Box<dyn Error>> {
+        self.request("GET", "/users/auth").call()?;
+        Ok(())
+    }
+
+    pub fn push(&self, progress: &Progress) -> Result<(), Box<dyn Error>> {
+        self.request("PUT", "/syncs/progress")
+            .send_string(&serde_json::to_string(progress)?)?;
+        Ok(())
+    }
+
+    /// Returns the progress saved for the document, None if there is none
+    pub fn pull(&self, document: &str) -> Result<Option<Progress>, Box<dyn Error>> {
+        let response = self
+            .request("GET", &format!("/syncs/progress/{}", document))
+            .call()?;
+        // an empty object if the document has never been pushed
+        let progress: Progress = serde_json::from_str(&response.into_string()?)?;
+        Ok((!progress.progress.is_empty()).then_some(progress))
+    }
+}
+
+/// Signs in to the server, creating the account if it doesn't exist, and turns the sync on
+pub fn login(server: &str, username: &str, password: &str) -> Result<(), Box<dyn Error>> {
+    let userkey = format!("{:x}", md5::compute(password));
+    let client = Client::new(server, username, &userkey);
+    match client.authorize() {
+        Ok(()) => {}
+        Err(e)
+            if matches!(
+                e.downcast_ref::<ureq::Error>(),
+                Some(ureq::Error::Status(401, _))
+            ) =>
+        {
+            client.register()?;
+        }
+        Err(e) => return Err(e),
+    }
+
+    let settings = storage::modify(&Document::Kosync, |settings: &mut KosyncDocument| {
+        settings.server = client.server.clone();
+        settings.username = client.username.clone();
+        settings.userkey = client.userkey.clone();
+        if settings.device_id.is_empty() {
+            settings.device_id = new_device_id();
+        }
+        settings.clone()
+    })?;
+    *SETTINGS.lock().unwrap() = Some(settings);
+    Ok(())
+}
+
+/// Turns the sync off, the id of the device is kept
+pub fn logout() -> Result<(), Box<dyn Error>> {
+    storage::modify(&Document::Kosync, |settings: &mut KosyncDocument| {
+        settings.server.clear();
+        settings.userkey.clear();
+    })?;
+    *SETTINGS.lock().unwrap() = None;
+    Ok(())
+}
+
+pub fn is_enabled() -> bool {
+    SETTINGS.lock().unwrap().is_some()
+}
+
+fn new_device_id() -> String {
+    let seed = format!("{:?}{}", SystemTime::now(), std::process::id());
+    format!("{:X}", md5::compute(seed))
+}
+
+fn client() -> Option<(Client, String)> {
+    let settings = SETTINGS.lock().unwrap().clone()?;
+    let client = Client::new(&settings.server, &settings.username, &settings.userkey);
+    Some((client, settings.device_id))
+}
+
+/// The partial md5 of KOReader: the md5 of 1 KiB read at 0, 1 KiB, 4 KiB, 16 KiB...
+/// up to 1 GiB or the end of the file
+pub fn document_hash(path: &str) -> Result<String, Box<dyn Error>> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut context = md5::Context::new();
+    let mut buffer = [0; 1024];
+    for i in -1..=10 {
+        let offset = if i < 0 { 0 } else { 1024u64 << (2 * i) };
+        if offset >= len {
+            break;
+        }
+        file.seek(SeekFrom::Start(offset))?;
+        let mut read = 0;
+        while read < buffer.len() {
+            match file.read(&mut buffer[read..])? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        context.consume(&buffer[..read]);
+    }
+    Ok(format!("{:x}", context.compute()))
+}
+
+/// Pushes the position of the book in the background,
+/// at most once every `PUSH_INTERVAL` seconds unless `force`d
+pub fn push_position(path: &str, position: Position, force: bool) {
+    if !is_enabled() {
+        return;
+    }
+    let now = unix_time(SystemTime::now());
+    {
+        let mut last_push = LAST_PUSH.lock().unwrap();
+        let last = last_push.get(path).copied().unwrap_or_default();
+        if !force && now.saturating_sub(last) < PUSH_INTERVAL {
+            return;
+        }
+        last_push.insert(path.to_string(), now);
+    }
+
+    let path = path.to_string();
+    std::thread::spawn(move || {
+        if let Err(e) = push_now(&path, position) {
+            println!("ERROR: failed to push the progress of {}: {}", path, e);
+        }
+    });
+}
+
+/// Pushes the position of the book and waits for the server
+pub fn push_now(path: &str, position: Position) -> Result<(), Box<dyn Error>> {
+    let (client, device_id) = client().ok_or("la sincronizzazione non è attiva")?;
+    let (progress, percentage) = to_progress(path, position);
+    client.push(&Progress {
+        document: document_hash(path)?,
+        progress,
+        percentage,
+        device: DEVICE.to_string(),
+        device_id,
+        timestamp: None,
+    })
+}
+
+/// Returns the position of the book read on another device, if it is further on than
+/// the local one
+pub fn pull_position(path: &str, local: Position) -> Option<Position> {
+    let (client, device_id) = client()?;
+    let pulled = document_hash(path).and_then(|document| client.pull(&document));
+    match pulled {
+        Ok(Some(progress)) if progress.device_id != device_id => {
+            let remote = to_position(path, &progress);
+            let lengths = chapter_lengths(path);
+            (percentage_of(&lengths, remote) > percentage_of(&lengths, local)).then_some(remote)
+        }
+        Ok(_) => None,
+        Err(e) => {
+            println!("ERROR: failed to pull the progress of {}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// Pulls the position of the book in the background,
+/// it is sent with `POSITION_PULLED` if it is further on than the local one
+pub fn pull_in_background(sink: ExtEventSink, path: String, local: Position) {
+    if !is_enabled() {
+        return;
+    }
+    std::thread::spawn(move || {
+        if let Some(remote) = pull_position(&path, local) {
+            let _ = sink.submit_command(POSITION_PULLED, (path, local, remote), Target::Auto);
+        }
+    });
+}
+
+fn chapter_lengths(path: &str) -> Vec<usize> {
+    if let Some(lengths) = CHAPTER_LENGTHS.lock().unwrap().get(path) {
+        return lengths.clone();
+    }
+    let lengths: Vec<usize> = (0..get_metadata_of_book(path).chapters)
+        .map(|chapter| get_chapter_text(path, chapter).chars().count())
+        .collect();
+    CHAPTER_LENGTHS
+        .lock()
+        .unwrap()
+        .insert(path.to_string(), lengths.clone());
+    lengths
+}
+
+/// The body of the html of the chapter, None if it isn't well-formed xml
+fn chapter_body(path: &str, chapter: usize) -> Option<XmlNode> {
+    let html = get_chapter_html(path, chapter)?;
+    let root = xml_tree::parse(&html).ok()?;
+    root.find("body").cloned()
+}
+
+/// Converts a position to the XPointer and the percentage of KOReader
+pub fn to_progress(path: &str, position: Position) -> (String, f64) {
+    let lengths = chapter_lengths(path);
+    let chapter = position.get_chapter();
+    let len = lengths.get(chapter).copied().unwrap_or_default();
+    let fraction = if len > 0 {
+        position.get_offset().min(len) as f64 / len as f64
+    } else {
+        0.0
+    };
+    let xpointer = match chapter_body(path, chapter) {
+        Some(body) => xpointer_of(&body, chapter, fraction),
+        None => format!("/body/DocFragment[{}]", chapter + 1),
+    };
+    (xpointer, percentage_of(&lengths, position))
+}
+
+/// Converts the progress of KOReader to a position: from the XPointer if it points in the book,
+/// from the percentage otherwise
+pub fn to_position(path: &str, progress: &Progress) -> Position {
+    let lengths = chapter_lengths(path);
+    let Some((chapter, segments, text_offset)) = parse_xpointer(&progress.progress) else {
+        return position_of_percentage(&lengths, progress.percentage);
+    };
+    let Some(len) = lengths.get(chapter).copied() else {
+        return position_of_percentage(&lengths, progress.percentage);
+    };
+    let fraction =
+        chapter_body(path, chapter).map_or(0.0, |body| fraction_of(&body, &segments, text_offset));
+    Position::new(chapter, (fraction * len as f64) as usize)
+}
+
+fn text_len(node: &XmlNode) -> usize {
+    node.all_text().chars().count()
+}
+
+/// The XPointer of the element of the body at the given fraction of its text.
+/// The text of an element is counted before the one of its children
+fn xpointer_of(body: &XmlNode, chapter: usize, fraction: f64) -> String {
+    let mut xpointer = format!("/body/DocFragment[{}]/body", chapter + 1);
+    let mut node = body;
+    let mut target = (fraction * text_len(body) as f64) as usize;
+    loop {
+        target = target.saturating_sub(node.text.chars().count());
+        let mut seen: HashMap<&str, usize> = HashMap::new();
+        let mut next = None;
+        for child in node.children.iter() {
+            let index = seen.entry(child.name.as_str()).or_default();
+            *index += 1;
+            let len = text_len(child);
+            if target < len {
+                next = Some((child, *index));
+                break;
+            }
+            target -= len;
+        }
+        let Some((child, index)) = next else {
+            return xpointer;
+        };
+        xpointer.push_str(&format!("/{}[{}]", child.name, index));
+        node = child;
+    }
+}
+
+/// Splits an XPointer in the chapter, the elements from the body with their index
+/// and the offset in the text
+fn parse_xpointer(xpointer: &str) -> Option<(usize, Vec<(String, usize)>, usize)> {
+    let rest = xpointer.strip_prefix("/body/DocFragment[")?;
+    let (index, rest) = rest.split_once(']')?;
+    let chapter = index.parse::<usize>().ok()?.checked_sub(1)?;
+
+    let mut segments = vec![];
+    let mut text_offset = 0;
+    for segment in rest.split('/').filter(|segment| !segment.is_empty()) {
+        if let Some(text) = segment.strip_prefix("text()") {
+            text_offset = text
+                .rsplit_once('.')
+                .and_then(|(_, offset)| offset.parse().ok())
+                .unwrap_or_default();
+            break;
+        }
+        let segment = segment.split('.').next().unwrap_or_default();
+        let (name, index) = match segment.split_once('[') {
+            Some((name, index)) => (name, index.trim_end_matches(']').parse().ok()?),
+            None => (segment, 1),
+        };
+        segments.push((name.to_string(), index));
+    }
+    // the body of the chapter, where the search starts
+    if segments.first().map(|(name, _)| name.as_str()) == Some("body") {
+        segments.remove(0);
+    }
+    Some((chapter, segments, text_offset))
+}
+
+/// The fraction of the text of the body before the element, as `xpointer_of` counts it
+fn fraction_of(body: &XmlNode, segments: &[(String, usize)], text_offset: usize) -> f64 {
+    let total = text_len(body);
+    if total == 0 {
+        return 0.0;
+    }
+    let mut before = 0;
+    let mut node = body;
+    for (name, index) in segments {
+        before += node.text.chars().count();
+        let mut seen = 0;
+        let mut found = None;
+        for child in node.children.iter() {
+            if child.name == *name {
+                seen += 1;
+                if seen == *index {
+                    found = Some(child);
+                    break;
+                }
+            }
+            before += text_len(child);
+        }
+        match found {
+            Some(child) => node = child,
+            None => break,
+        }
+    }
+    ((before + text_offset) as f64 / total as f64).min(1.0)
+}
+
+fn percentage_of(lengths: &[usize], position: Position) -> f64 {
+    let total: usize = lengths.iter().sum();
+    if total == 0 {
+        return 0.0;
+    }
+    let chapter = position.get_chapter().min(lengths.len());
+    let before: usize = lengths[..chapter].iter().sum();
+    let offset = lengths
+        .get(chapter)
+        .map_or(0, |len| position.get_offset().min(*len));
+    (before + offset) as f64 / total as f64
+}
+
+fn position_of_percentage(lengths: &[usize], percentage: f64) -> Position {
+    let total: usize = lengths.iter().sum();
+    let mut target = (percentage.clamp(0.0, 1.0) * total as f64) as usize;
+    for (chapter, len) in lengths.iter().enumerate() {
+        if target < *len {
+            return Position::new(chapter, target);
+        }
+        target -= len;
+    }
+    Position::new(lengths.len().saturating_sub(1), 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    #[test]
+    fn positions_are_converted() {
+        let body = xml_tree::parse(
+            "<body><h1>Uno</h1><div><p>Prima riga</p><p>Seconda riga</p></div><p>Fine</p></body>",
+        )
+        .unwrap();
+        let len = text_len(&body) as f64;
+
+        // in the second paragraph of the div
+        let xpointer = xpointer_of(&body, 2, 15.0 / len);
+        assert_eq!(xpointer, "/body/DocFragment[3]/body/div[1]/p[2]");
+        let (chapter, segments, offset) = parse_xpointer(&xpointer).unwrap();
+        assert_eq!(chapter, 2);
+        assert_eq!(fraction_of(&body, &segments, offset), 13.0 / len);
+
+        let (_, segments, offset) = parse_xpointer("/body/DocFragment[3]/body/p/text().2").unwrap();
+        assert_eq!(fraction_of(&body, &segments, offset), 27.0 / len);
+        assert_eq!(parse_xpointer("/body/DocFragment[0]/body"), None);
+
+        let lengths = [100, 300];
+        assert_eq!(percentage_of(&lengths, Position::new(1, 100)), 0.5);
+        assert_eq!(position_of_percentage(&lengths, 0.5), Position::new(1, 100));
+    }
+
+    /// A stand-in of the server that keeps the progress in memory
+    fn serve(listener: TcpListener, requests: usize) {
+        let mut saved = String::from("{}");
+        for stream in listener.incoming().take(requests) {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut length = 0;
+            let mut authorized = false;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                let header = header.trim().to_lowercase();
+                if header.is_empty() {
+                    break;
+                }
+                if let Some(value) = header.strip_prefix("content-length:") {
+                    length = value.trim().parse().unwrap();
+                }
+                authorized |= header == format!("x-auth-key: {:x}", md5::compute("segreta"));
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+
+            let (status, response) = match request_line.split_whitespace().next() {
+                _ if !authorized => ("401 Unauthorized", "{}".to_string()),
+                Some("PUT") => {
+                    saved = String::from_utf8(body).unwrap();
+                    ("200 OK", "{}".to_string())
+                }
+                _ => ("200 OK", saved.clone()),
+            };
+            write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                response.len(),
+                response
+            )
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn progress_is_pushed_and_pulled() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = format!("http://{}", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || serve(listener, 4));
+
+        let client = Client::new(
+            &server,
+            "lettore",
+            &format!("{:x}", md5::compute("segreta")),
+        );
+        assert_eq!(client.pull("abc").unwrap(), None);
+        let progress = Progress {
+            document: "abc".to_string(),
+            progress: "/body/DocFragment[3]/body/p[2]".to_string(),
+            percentage: 0.25,
+            device: DEVICE.to_string(),
+            device_id: "ID".to_string(),
+            timestamp: None,
+        };
+        client.push(&progress).unwrap();
+        assert_eq!(client.pull("abc").unwrap(), Some(progress));
+
+        let intruder = Client::new(&server, "lettore", "sbagliata");
+        assert!(intruder.authorize().is_err());
+        handle.join().unwrap();
+    }
+}
@@ -0,0 +1,223 @@
+//! Upgrades of the documents written by the older versions of the application.
+//! Every migration takes a document of the previous version to its own version,
+//! they are applied in order starting from the version written in the document.
+
+use std::path::PathBuf;
+
+use serde_json::{json, Map, Value};
+
+use super::{
+    dir_manager::{
+        get_edited_books_dir, get_epub_dir, get_fulltext_index_dir, get_saved_books_dir,
+    },
+    identity,
+    storage::Document,
+};
+
+type Migration = fn(&Document, &mut Value);
+
+/// The migrations with the version they upgrade to, in order.
+/// The last version must be `storage::SCHEMA_VERSION`
+const MIGRATIONS: &[(u64, Migration)] = &[(1, to_v1), (2, to_v2), (3, to_v3)];
+
+const PAGES_PREFIX: &str = "pages_per_chapter_";
+
+/// Upgrades the json of a document written with the given version to the current one
+pub fn upgrade(document: &Document, from: u64, json: &mut Value) {
+    for (version, migration) in MIGRATIONS {
+        if *version > from {
+            migration(document, json);
+        }
+    }
+}
+
+/// Version 1 started keeping the version of the storage, the documents didn't change
+fn to_v1(_document: &Document, _json: &mut Value) {}
+
+/// Version 2 writes the numbers and booleans of the metadata as such instead of strings,
+/// and the pages of the chapters in an object with an array for each page layout
+fn to_v2(document: &Document, json: &mut Value) {
+    let Document::Metadata(_) = document else {
+        return;
+    };
+    let Some(metadata) = json.as_object_mut() else {
+        return;
+    };
+
+    for key in ["chapters", "total_pages"] {
+        let number = metadata
+            .get(key)
+            .and_then(Value::as_str)
+            .and_then(|s| s.trim().parse::<u64>().ok());
+        if let Some(number) = number {
+            metadata.insert(key.to_string(), json!(number));
+        }
+    }
+    let favorite = metadata
+        .get("favorite")
+        .and_then(Value::as_str)
+        .and_then(|s| s.trim().parse::<bool>().ok());
+    if let Some(favorite) = favorite {
+        metadata.insert("favorite".to_string(), json!(favorite));
+    }
+
+    let mut pages = match metadata.remove("pages_per_chapter") {
+        Some(Value::Object(pages)) => pages,
+        _ => Map::new(),
+    };
+    let layouts: Vec<String> = metadata
+        .keys()
+        .filter(|key| key.starts_with(PAGES_PREFIX))
+        .cloned()
+        .collect();
+    for key in layouts {
+        let value = metadata.remove(&key);
+        // pages that can't be read are calculated again when needed
+        if let Some(ranges) = value.as_ref().and_then(Value::as_str).and_then(parse_page_ranges) {
+            pages.insert(key[PAGES_PREFIX.len()..].to_string(), json!(ranges));
+        }
+    }
+    if !pages.is_empty() {
+        metadata.insert("pages_per_chapter".to_string(), Value::Object(pages));
+    }
+}
+
+/// Version 3 keys the data of the books by their id instead of their path
+fn to_v3(document: &Document, json: &mut Value) {
+    let (Document::SaveData | Document::Notes | Document::Bookmarks | Document::Highlights) = document else {
+        return;
+    };
+    let Some(books) = json.as_object_mut() else {
+        return;
+    };
+
+    let old = std::mem::take(books);
+    for (key, value) in old {
+        if key == "version" {
+            books.insert(key, value);
+            continue;
+        }
+        let id = identity::compute_id(&key).unwrap_or_else(|| identity::path_id(&key));
+        match (books.get_mut(&id), value) {
+            // copies of the same book: their notes are kept together
+            (Some(Value::Array(items)), Value::Array(more)) => items.extend(more),
+            (Some(_), _) => println!("ERROR: the data of {} is already saved for the same book", key),
+            (None, value) => {
+                books.insert(id, value);
+            }
+        }
+    }
+}
+
+/// Renames the folders of the books named after the file to the id of the book, as read by version 3.
+/// The old folders are found from the files in the library, it does nothing once they are moved
+pub fn move_book_folders() {
+    let Ok(entries) = std::fs::read_dir(get_epub_dir()) else {
+        return;
+    };
+    let folders = [get_saved_books_dir(), get_edited_books_dir()];
+    for path in entries.flatten().map(|entry| entry.path()) {
+        let (Some(stem), Some(book)) = (path.file_stem().and_then(|s| s.to_str()), path.to_str()) else {
+            continue;
+        };
+        // the folders of the chapters and the segment of the full-text index
+        let old: Vec<(PathBuf, String)> = folders
+            .iter()
+            .map(|folder| (folder.join(stem), String::new()))
+            .chain([(get_fulltext_index_dir().join(format!("{}.json", stem)), ".json".to_string())])
+            .filter(|(old, _)| old.exists())
+            .collect();
+        if old.is_empty() {
+            continue;
+        }
+
+        // hashed only for the books that still have the old folders
+        let id = identity::compute_id(book).unwrap_or_else(|| identity::path_id(book));
+        for (old, extension) in old {
+            let new = old.with_file_name(format!("{}{}", id, extension));
+            if new.exists() {
+                continue;
+            }
+            match std::fs::rename(&old, &new) {
+                Ok(()) => println!("DEBUG: {:?} moved to {:?}", old, new),
+                Err(e) => println!("ERROR: failed to move {:?} to {:?}: {}", old, new, e),
+            }
+        }
+    }
+}
+
+/// Reads the pages of the chapters as written by version 1: "[(0-3), (4-9)]"
+fn parse_page_ranges(text: &str) -> Option<Vec<(usize, usize)>> {
+    text.trim()
+        .trim_matches(|c| c == '[' || c == ']')
+        .split(',')
+        .map(|range| {
+            let (start, end) = range
+                .trim_matches(|c| c == '(' || c == ')' || c == ' ')
+                .split_once('-')?;
+            Some((start.parse().ok()?, end.parse().ok()?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::storage::SCHEMA_VERSION;
+
+    #[test]
+    fn migrations_reach_the_current_version() {
+        assert_eq!(MIGRATIONS.last().map(|(version, _)| *version), Some(SCHEMA_VERSION));
+    }
+
+    #[test]
+    fn metadata_of_version_1_is_upgraded() {
+        let mut json = json!({
+            "title": "Title",
+            "chapters": "12",
+            "favorite": "true",
+            "total_pages": "10",
+            "pages_per_chapter_system-ui_16_800x600": "[(0-3), (4-9)]",
+            "pages_per_chapter_serif_20_800x600": "[(0-3), (4"
+        });
+        upgrade(&Document::Metadata("book.epub".to_string()), 1, &mut json);
+
+        assert_eq!(
+            json,
+            json!({
+                "title": "Title",
+                "chapters": 12,
+                "favorite": true,
+                "total_pages": 10,
+                "pages_per_chapter": { "system-ui_16_800x600": [[0, 3], [4, 9]] }
+            })
+        );
+    }
+
+    #[test]
+    fn other_documents_are_not_changed() {
+        let original = json!({ "folders": ["/books"], "imported": { "/books/book.epub": { "len": 4 } } });
+        let mut json = original.clone();
+        upgrade(&Document::WatchedFolders, 0, &mut json);
+
+        assert_eq!(json, original);
+    }
+
+    #[test]
+    fn books_are_keyed_by_id() {
+        let mut json = json!({
+            "/missing/book.epub": { "chapter": 12, "offset": 4 },
+            "version": 2
+        });
+        upgrade(&Document::SaveData, 2, &mut json);
+
+        // a book that can't be read is identified by its path
+        assert_eq!(
+            json,
+            json!({
+                (identity::path_id("/missing/book.epub")): { "chapter": 12, "offset": 4 },
+                "version": 2
+            })
+        );
+    }
+}
@@ -1,12 +1,28 @@
 pub mod button_functions;
+pub mod calibre;
+pub mod clippings;
 pub mod colors;
 pub mod ctx_menu;
 pub mod delegates;
 pub mod dir_manager;
+pub mod duplicates;
 pub mod envmanager;
 pub mod epub_utils;
+pub mod export;
 pub mod fonts;
+pub mod fulltext;
+pub mod identity;
+pub mod kosync;
+pub mod migrations;
 pub mod ocrmanager;
+pub mod opds;
+pub mod opf;
+pub mod paginator;
 pub mod rich_text_fn;
 pub mod saveload;
+pub mod search;
+pub mod stats;
+pub mod storage;
 pub mod thread_loader;
+pub mod watcher;
+pub mod xml_tree;
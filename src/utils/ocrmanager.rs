@@ -2,9 +2,7 @@ use rust_fuzzy_search::fuzzy_compare;
 
 use std::sync::{mpsc::channel, Arc, Mutex, Condvar};
 
-use crate::models::book::{PAGE_WIDTH, PAGE_HEIGHT};
-
-use super::epub_utils;
+use super::{epub_utils, paginator::PageLayout};
 
 #[derive(Debug)]
 struct Page {
@@ -14,7 +12,12 @@ struct Page {
 
 
 //function that, given a pic of a physical book page, gives the corresponding page in the ebook
-pub fn get_ebook_page(ebook_name: String, physical_page: String, font_size: f64) -> Option<(usize,usize)> {
+pub fn get_ebook_page(book_path: String, physical_page: String, font_size: f64) -> Option<(usize,usize)> {
+    find_ebook_page(book_path, physical_page, PageLayout::current().with_font_size(font_size))
+}
+
+//the pages of the ebook are the ones of the given layout
+fn find_ebook_page(book_path: String, physical_page: String, layout: PageLayout) -> Option<(usize,usize)> {
 
     //start timer
     let start = std::time::Instant::now();
@@ -27,10 +30,8 @@ pub fn get_ebook_page(ebook_name: String, physical_page: String, font_size: f64)
     //also remove all new lines, making the text a single big string
     let text = lt.get_utf8_text().unwrap().replace("-\n", "").replace("\n", " ");
 
-    //EBOOK PHASE: Setup book path and get chapter numbers through the metadata
-    let book_path = format!("saved_books/{}", ebook_name);
-    let book_metadata = epub_utils::get_metadata_of_book(book_path.as_str());
-    let chapters_number = book_metadata["chapters"].parse::<usize>().unwrap();
+    //EBOOK PHASE: Get chapter numbers through the metadata
+    let chapters_number = epub_utils::get_metadata_of_book(book_path.as_str()).chapters;
 
     //THREAD PHASE: Create a thread pool and a channel
     let pool = threadpool::Builder::new().build();
@@ -45,11 +46,12 @@ pub fn get_ebook_page(ebook_name: String, physical_page: String, font_size: f64)
 
         let book_path_clone = book_path.clone();
         let text_clone = text.clone();
+        let layout_clone = layout.clone();
 
         //..create a thread that will calculate the similarity between the physical page and the chapter pages
         //NOTE: the thread pool will aggregate these functions in 4 threads (see pool initialization)
         pool.execute(move || {
-            let result = compute_similarity(book_path_clone, text_clone, i, font_size);
+            let result = compute_similarity(book_path_clone, text_clone, i, &layout_clone);
             if result.is_some() {
                 tx.send(result.unwrap()).expect("Error in sending msg");
             }
@@ -98,9 +100,9 @@ pub fn get_ebook_page(ebook_name: String, physical_page: String, font_size: f64)
 
 //This function, given a chapter, gets its pages and iterates through them.
 //For each page, it computes the similarity with the given text: if it's higher than 0.85, the page is returned
-fn compute_similarity(book_path: String, text: String, chapter_to_examine: usize, font_size: f64) -> Option<Page> {
+fn compute_similarity(book_path: String, text: String, chapter_to_examine: usize, layout: &PageLayout) -> Option<Page> {
 
-    let chapter_pages = epub_utils::split_chapter_in_vec(book_path.as_str(), None, chapter_to_examine, 8, font_size, PAGE_WIDTH, PAGE_HEIGHT);
+    let chapter_pages = epub_utils::split_chapter_in_vec(book_path.as_str(), None, chapter_to_examine, layout);
 
     //Iterate through che chapter pages
     for i in 0..chapter_pages.len() {
@@ -175,8 +177,57 @@ pub fn get_physical_page(physical_page_path: String, chapter_number: usize, eboo
 mod tests {
 
     use super::*;
+    use crate::utils::dir_manager::get_epub_dir;
+    use druid::{FontDescriptor, FontFamily, Size};
     use serial_test::serial;
 
+    fn svevo_path() -> String {
+        get_epub_dir().join("svevo_la_coscienza_di_zeno.epub").to_str().unwrap().to_string()
+    }
+
+    //A layout that doesn't depend on the window: the page grows with the font,
+    //so that at every size it holds about the text of a physical page
+    fn layout(font_size: f64) -> PageLayout {
+        PageLayout {
+            font: FontDescriptor::new(FontFamily::SYSTEM_UI).with_size(font_size),
+            size: Size::new(28.0 * font_size, 22.0 * font_size),
+        }
+    }
+
+    //The page of the chapter, in the layout, that contains the passage of the photo
+    fn page_with(chapter: usize, passage: &str, layout: &PageLayout) -> (usize, usize) {
+        let pages = epub_utils::split_chapter_in_vec(svevo_path().as_str(), None, chapter, layout);
+        let page = pages.iter().position(|page| page.replace("\n", " ").contains(passage)).unwrap();
+        (chapter, page)
+    }
+
+    //Looks for the photos of the physical book in the pages of the layout
+    fn check_ebook_pages(font_size: f64) {
+        let layout = layout(font_size);
+        let find = |photo: &str| {
+            find_ebook_page(svevo_path(), format!("./test_ocr_images/OCR/{}", photo), layout.clone())
+        };
+
+        //CASE 1: First page of chapter
+        //Search for the page whose ebook version is the first page of the sixth chapter (index 5)
+        assert_eq!(find("svevo_ok1.png"), Some((5, 0)));
+        assert_eq!(page_with(5, "Il dottore al quale", &layout), (5, 0));
+
+        //CASE 2: Random page of chapter
+        //Search for the page of the eleventh chapter (index 10) with the passage of the photo
+        assert_eq!(find("svevo_ok2.png"), Some(page_with(10, "allargò il petto", &layout)));
+
+        //CASE 3: Last page of chapter
+        //Search for the last page of the eighth chapter (index 7)
+        let last = epub_utils::split_chapter_in_vec(svevo_path().as_str(), None, 7, &layout).len() - 1;
+        assert_eq!(page_with(7, "escluso dal bacio", &layout), (7, last));
+        assert_eq!(find("svevo_ok3.png"), Some((7, last)));
+
+        //CASE 4: Page non-existent in ebook
+        //Search for a page that is not in the ebook version
+        assert_eq!(find("err_screenshot.png"), None);
+    }
+
     #[test]
     //This method is used to test the fuzzy_compare() method
     fn test_fuzzy_compare() {
@@ -212,84 +263,21 @@ mod tests {
     #[test]
     #[serial]
     fn test_get_ebook_page_small_font() {
-
-        //CASE 1: First page of chapter
-        //Search for the page whose ebook version is the first page (index 0) of the sixth chapter (index 5)
-        let page = get_ebook_page("svevo_la_coscienza_di_zeno".to_string(), "./test_ocr_images/OCR/svevo_ok1.png".to_string(), 14.0);
-        assert_eq!(page, Some((5,0)));
-
-        //CASE 2: Random page of chapter
-        //Search for the page whose ebook version is the 21st page (index 20) of the eleventh chapter (index 10)
-        let page = get_ebook_page("svevo_la_coscienza_di_zeno".to_string(), "./test_ocr_images/OCR/svevo_ok2.png".to_string(), 14.0);
-        assert_eq!(page, Some((10,20)));
-
-        //CASE 3: Last page of chapter
-        //Search for the page whose ebook version is the last page (index 59) of the eight chapter (index 7)
-        let page = get_ebook_page("svevo_la_coscienza_di_zeno".to_string(), "./test_ocr_images/OCR/svevo_ok3.png".to_string(), 14.0);
-        assert_eq!(page, Some((7,59)));
-
-
-        //CASE 4: Page non-existent in ebook
-        //Search for a page that is not in the ebook version
-        let page = get_ebook_page("svevo_la_coscienza_di_zeno".to_string(), "./test_ocr_images/OCR/err_screenshot.png".to_string(), 14.0);
-        assert_eq!(page, None);
-
+        check_ebook_pages(14.0);
     }
 
     //This method is used to test get_ebook_page (medium font)
     #[test]
     #[serial]
     fn test_get_ebook_page_medium_font() {
-
-        //CASE 1: First page of chapter
-        //Search for the page whose ebook version is the first page (index 0) of the sixth chapter (index 5)
-        let page = get_ebook_page("svevo_la_coscienza_di_zeno".to_string(), "./test_ocr_images/OCR/svevo_ok1.png".to_string(), 18.0);
-        assert_eq!(page, Some((5,0)));
-
-        //CASE 2: Random page of chapter
-        //Search for the page whose ebook version is the 36th page (index 35) of the eleventh chapter (index 10)
-        let page = get_ebook_page("svevo_la_coscienza_di_zeno".to_string(), "./test_ocr_images/OCR/svevo_ok2.png".to_string(), 18.0);
-        assert_eq!(page, Some((10,35)));
-
-        //CASE 3: Last page of chapter
-        //Search for the page whose ebook version is the last page (index 100) of the eight chapter (index 7)
-        let page = get_ebook_page("svevo_la_coscienza_di_zeno".to_string(), "./test_ocr_images/OCR/svevo_ok3.png".to_string(), 18.0);
-        assert_eq!(page, Some((7,100)));
-
-
-        //CASE 4: Page non-existent in ebook
-        //Search for a page that is not in the ebook version
-        let page = get_ebook_page("svevo_la_coscienza_di_zeno".to_string(), "./test_ocr_images/OCR/err_screenshot.png".to_string(), 18.0);
-        assert_eq!(page, None);
-
+        check_ebook_pages(18.0);
     }
 
     //This method is used to test get_ebook_page (large font)
     #[test]
     #[serial]
     fn test_get_ebook_page_large_font() {
-
-        //CASE 1: First page of chapter
-        //Search for the page whose ebook version is the first page (index 0) of the sixth chapter (index 5)
-        let page = get_ebook_page("svevo_la_coscienza_di_zeno".to_string(), "./test_ocr_images/OCR/svevo_ok1.png".to_string(), 22.0);
-        assert_eq!(page, Some((5,0)));
-
-        //CASE 2: Random page of chapter
-        //Search for the page whose ebook version is the 59st page (index 58) of the eleventh chapter (index 10)
-        let page = get_ebook_page("svevo_la_coscienza_di_zeno".to_string(), "./test_ocr_images/OCR/svevo_ok2.png".to_string(), 22.0);
-        assert_eq!(page, Some((10,58)));
-
-        //CASE 3: Last page of chapter
-        //Search for the page whose ebook version is the last page (index 156) of the eight chapter (index 7)
-        let page = get_ebook_page("svevo_la_coscienza_di_zeno".to_string(), "./test_ocr_images/OCR/svevo_ok3.png".to_string(), 22.0);
-        assert_eq!(page, Some((7,156)));
-
-
-        //CASE 4: Page non-existent in ebook
-        //Search for a page that is not in the ebook version
-        let page = get_ebook_page("svevo_la_coscienza_di_zeno".to_string(), "./test_ocr_images/OCR/err_screenshot.png".to_string(), 22.0);
-        assert_eq!(page, None);
-
+        check_ebook_pages(22.0);
     }
 
     #[test]
@@ -0,0 +1,359 @@
+//! Writing the metadata edited by the user back into an epub.
+//! The book of the library is never changed: a copy of the epub is made with a new
+//! OPF package document, the other files are copied as they are, without compressing them again.
+//! Only the elements of the metadata that can be edited are replaced,
+//! the unique identifier of the book and the rest of the OPF are kept as they were.
+
+use std::{
+    error,
+    fs::File,
+    io::{Read, Write},
+    path::Path,
+};
+
+use zip::{write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::{models::documents::BookMetadata, utils::epub_utils::AUTHORS_SEPARATOR};
+
+use super::xml_tree::{self, escape};
+
+const CONTAINER_PATH: &str = "META-INF/container.xml";
+
+/// id of the series collection written in the OPF of an EPUB 3
+const SERIES_ID: &str = "crabreader-series";
+
+/// the dc elements replaced by the edited metadata
+const EDITED_ELEMENTS: [&str; 5] = ["title", "creator", "language", "description", "publisher"];
+
+/// Writes in `target` a copy of the epub with the given metadata in its OPF
+pub fn write_epub_with_metadata(
+    book_path: &str,
+    target: &str,
+    metadata: &BookMetadata,
+) -> Result<(), Box<dyn error::Error>> {
+    if Path::new(book_path) == Path::new(target) {
+        return Err("the copy can't replace the book of the library".into());
+    }
+    let mut archive = ZipArchive::new(File::open(book_path)?)?;
+    let opf_path = find_opf_path(&mut archive)?;
+    let opf = replace_metadata(&read_entry(&mut archive, &opf_path)?, metadata)?;
+
+    let mut writer = ZipWriter::new(File::create(target)?);
+    for i in 0..archive.len() {
+        let entry = archive.by_index(i)?;
+        if entry.name() == opf_path {
+            let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+            writer.start_file(opf_path.as_str(), options)?;
+            writer.write_all(opf.as_bytes())?;
+        } else {
+            // the mimetype stays first and stored, as it must be
+            writer.raw_copy_file(entry)?;
+        }
+    }
+    writer.finish()?;
+    println!(
+        "DEBUG: written {} with the edited metadata of {}",
+        target, book_path
+    );
+    Ok(())
+}
+
+fn read_entry(archive: &mut ZipArchive<File>, name: &str) -> Result<String, Box<dyn error::Error>> {
+    let mut content = String::new();
+    archive.by_name(name)?.read_to_string(&mut content)?;
+    Ok(content)
+}
+
+/// Returns the path in the epub of the OPF, as written in the container
+fn find_opf_path(archive: &mut ZipArchive<File>) -> Result<String, Box<dyn error::Error>> {
+    let container = xml_tree::parse(&read_entry(archive, CONTAINER_PATH)?)?;
+    let path = container
+        .find("rootfile")
+        .and_then(|rootfile| rootfile.attr("full-path"))
+        .ok_or("no OPF in the container of the epub")?;
+    Ok(path.to_string())
+}
+
+/// An element in the text of the OPF
+struct Element<'a> {
+    /// range of the whole element in the text
+    start: usize,
+    end: usize,
+    prefix: Option<&'a str>,
+    name: &'a str,
+    open_tag: &'a str,
+}
+
+impl Element<'_> {
+    /// Returns the value of the attribute with the given (qualified) name
+    fn attr(&self, name: &str) -> Option<&str> {
+        for quote in ['"', '\''] {
+            let pattern = format!("{}={}", name, quote);
+            let mut from = 0;
+            while let Some(idx) = self.open_tag[from..].find(&pattern) {
+                let idx = from + idx;
+                from = idx + pattern.len();
+                // not the end of another attribute name
+                if !self.open_tag[..idx].ends_with(char::is_whitespace) {
+                    continue;
+                }
+                let value = &self.open_tag[from..];
+                return value.find(quote).map(|end| &value[..end]);
+            }
+        }
+        None
+    }
+}
+
+/// Returns the elements that are direct children of the text, skipping comments and instructions
+fn elements(text: &str) -> Vec<Element> {
+    let mut elements = vec![];
+    let mut pos = 0;
+    while let Some(idx) = text[pos..].find('<') {
+        let start = pos + idx;
+        let rest = &text[start..];
+        let skip_to = |end: &str| rest.find(end).map_or(text.len(), |i| start + i + end.len());
+        if rest.starts_with("<!--") {
+            pos = skip_to("-->");
+            continue;
+        }
+        if rest.starts_with("<?") || rest.starts_with("<!") || rest.starts_with("</") {
+            pos = skip_to(">");
+            continue;
+        }
+        let Some(tag_end) = find_tag_end(rest) else {
+            break;
+        };
+        let open_tag = &rest[..tag_end + 1];
+        let qname = open_tag[1..]
+            .split(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .next()
+            .unwrap_or_default();
+        let end = if open_tag.ends_with("/>") {
+            start + open_tag.len()
+        } else {
+            let close = format!("</{}>", qname);
+            rest.find(&close)
+                .map_or(text.len(), |i| start + i + close.len())
+        };
+        let (prefix, name) = match qname.split_once(':') {
+            Some((prefix, name)) => (Some(prefix), name),
+            None => (None, qname),
+        };
+        elements.push(Element {
+            start,
+            end,
+            prefix,
+            name,
+            open_tag,
+        });
+        pos = end;
+    }
+    elements
+}
+
+/// Returns the index of the '>' closing the tag, ignoring the ones in the attributes
+fn find_tag_end(tag: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in tag.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            (None, '>') => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Returns the OPF with the editable metadata replaced by the given one
+fn replace_metadata(opf: &str, metadata: &BookMetadata) -> Result<String, String> {
+    let root = xml_tree::parse(opf)?;
+    let unique_id = root.attr("unique-identifier").unwrap_or_default();
+    let epub3 = root.attr("version").map_or(false, |v| v.starts_with('3'));
+
+    let package = elements(opf)
+        .into_iter()
+        .find(|e| e.name == "package")
+        .ok_or("no package in the OPF")?;
+    let package_text = &opf[package.start..package.end];
+    let meta = elements(&package_text[package.open_tag.len()..])
+        .into_iter()
+        .find(|e| e.name == "metadata")
+        .ok_or("no metadata in the OPF")?;
+    // position of the metadata content in the OPF
+    let offset = package.start + package.open_tag.len();
+    let inner_start = offset + meta.start + meta.open_tag.len();
+    let inner_end =
+        offset + meta.end - meta.prefix.map_or(0, |p| p.len() + 1) - "</metadata>".len();
+    let inner = &opf[inner_start..inner_end];
+
+    let children = elements(inner);
+    let is_edited = |e: &Element| {
+        (e.prefix.is_some() && EDITED_ELEMENTS.contains(&e.name))
+            || (e.name == "identifier" && e.attr("id") != Some(unique_id))
+            || (e.name == "meta"
+                && (matches!(
+                    e.attr("name"),
+                    Some("calibre:series" | "calibre:series_index")
+                ) || e.attr("property") == Some("belongs-to-collection")))
+    };
+    let removed_ids: Vec<String> = children
+        .iter()
+        .filter(|e| is_edited(e))
+        .filter_map(|e| e.attr("id"))
+        .map(|id| format!("#{}", id))
+        .collect();
+    // e.g. the roles of the authors in an EPUB 3
+    let refines_removed = |e: &Element| {
+        e.name == "meta"
+            && e.attr("refines")
+                .map_or(false, |r| removed_ids.iter().any(|id| id == r))
+    };
+    let dc = children
+        .iter()
+        .find(|e| e.name == "title" || e.name == "identifier")
+        .and_then(|e| e.prefix)
+        .unwrap_or("dc");
+
+    let mut content = String::new();
+    let mut pos = 0;
+    for e in children
+        .iter()
+        .filter(|e| is_edited(e) || refines_removed(e))
+    {
+        content.push_str(inner[pos..e.start].trim_end());
+        pos = e.end;
+    }
+    content.push_str(inner[pos..].trim_end());
+
+    let mut add = |element: String| {
+        content.push_str("\n    ");
+        content.push_str(&element);
+    };
+    add(format!(
+        "<{dc}:title>{}</{dc}:title>",
+        escape(&metadata.title)
+    ));
+    for author in metadata.author.split(AUTHORS_SEPARATOR) {
+        add(format!("<{dc}:creator>{}</{dc}:creator>", escape(author)));
+    }
+    add(format!(
+        "<{dc}:language>{}</{dc}:language>",
+        escape(&metadata.lang)
+    ));
+    if let Some(desc) = &metadata.desc {
+        add(format!(
+            "<{dc}:description>{}</{dc}:description>",
+            escape(desc)
+        ));
+    }
+    if !metadata.publisher.is_empty() {
+        add(format!(
+            "<{dc}:publisher>{}</{dc}:publisher>",
+            escape(&metadata.publisher)
+        ));
+    }
+    for identifier in &metadata.identifiers {
+        add(format!(
+            "<{dc}:identifier>{}</{dc}:identifier>",
+            escape(identifier)
+        ));
+    }
+    if !metadata.series.is_empty() {
+        let series = escape(&metadata.series);
+        add(format!(
+            r#"<meta name="calibre:series" content="{}"/>"#,
+            series
+        ));
+        if let Some(index) = metadata.series_index {
+            add(format!(
+                r#"<meta name="calibre:series_index" content="{}"/>"#,
+                index
+            ));
+        }
+        if epub3 {
+            add(format!(
+                r#"<meta property="belongs-to-collection" id="{SERIES_ID}">{}</meta>"#,
+                series
+            ));
+            add(format!(
+                r##"<meta refines="#{SERIES_ID}" property="collection-type">series</meta>"##
+            ));
+            if let Some(index) = metadata.series_index {
+                add(format!(
+                    r##"<meta refines="#{SERIES_ID}" property="group-position">{}</meta>"##,
+                    index
+                ));
+            }
+        }
+    }
+    content.push_str("\n  ");
+
+    Ok(format!(
+        "{}{}{}",
+        &opf[..inner_start],
+        content,
+        &opf[inner_end..]
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPF: &str = r##"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="uid">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="uid">urn:uuid:1234</dc:identifier>
+    <dc:identifier>urn:isbn:0000</dc:identifier>
+    <dc:title>titolo sbagliato</dc:title>
+    <dc:creator id="author">no author</dc:creator>
+    <meta refines="#author" property="role">aut</meta>
+    <dc:language>en</dc:language>
+    <!-- <dc:title>commented</dc:title> -->
+    <meta name="calibre:series" content="Vecchia"/>
+    <meta property="dcterms:modified">2020-01-01T00:00:00Z</meta>
+  </metadata>
+  <manifest><item id="c1" href="c1.xhtml" media-type="application/xhtml+xml"/></manifest>
+</package>"##;
+
+    #[test]
+    fn edited_metadata_replaces_the_old_one() {
+        let metadata = BookMetadata {
+            title: "Senilità".to_string(),
+            author: "Italo Svevo & Ettore Schmitz".to_string(),
+            lang: "it".to_string(),
+            identifiers: vec!["urn:isbn:9788807900211".to_string()],
+            series: "Romanzi <triestini>".to_string(),
+            series_index: Some(2.0),
+            ..BookMetadata::default()
+        };
+        let opf = replace_metadata(OPF, &metadata).unwrap();
+        let root = xml_tree::parse(&opf).unwrap();
+        let texts = |name: &str| {
+            root.find_all(name)
+                .iter()
+                .map(|node| node.all_text())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(texts("title"), vec!["Senilità"]);
+        assert_eq!(texts("creator"), vec!["Italo Svevo", "Ettore Schmitz"]);
+        assert_eq!(texts("language"), vec!["it"]);
+        assert_eq!(
+            texts("identifier"),
+            vec!["urn:uuid:1234", "urn:isbn:9788807900211"]
+        );
+        assert!(!opf.contains("refines=\"#author\""));
+        assert!(!opf.contains("Vecchia"));
+        assert!(
+            opf.contains(r#"<meta name="calibre:series" content="Romanzi &lt;triestini&gt;"/>"#)
+        );
+        assert!(opf.contains(r#"property="group-position">2</meta>"#));
+        // not editable
+        assert!(opf.contains("dcterms:modified"));
+        assert!(opf.contains("<!-- <dc:title>commented</dc:title> -->"));
+        assert!(opf.contains("<manifest>"));
+    }
+}
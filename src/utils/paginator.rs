@@ -0,0 +1,333 @@
+use std::{iter::once, ops::Range, sync::Mutex};
+
+use druid::{
+    piet::{Device, PietText, RenderContext, Text, TextLayout, TextLayoutBuilder},
+    FontDescriptor, Size,
+};
+use once_cell::sync::Lazy;
+
+use crate::{
+    models::book::{PAGE_HEIGHT, PAGE_WIDTH},
+    utils::rich_text_fn::rebuild_rendered_text,
+    MYENV,
+};
+
+/// horizontal padding added by druid's Label on both sides of the text
+pub const LABEL_X_PADDING: f64 = 2.0;
+/// in dual view the two pages take 1.0 of flex each, plus 0.1 of spacer
+const DUAL_VIEW_FLEX: f64 = 2.1;
+/// the page size is a multiple of this, so that resizing the window by a few pixels
+/// neither paginates the books again nor adds layouts to their metadata
+const PAGE_SIZE_STEP: f64 = 20.0;
+
+/// Size of a single page of the reader view, updated when the window is resized
+static PAGE_SIZE: Lazy<Mutex<Size>> =
+    Lazy::new(|| Mutex::new(Size::new(PAGE_WIDTH as f64, PAGE_HEIGHT as f64)));
+
+/// Everything the pagination depends on: the font used by the reader
+/// and the size of the area in which a page is drawn
+#[derive(Clone, Debug, PartialEq)]
+pub struct PageLayout {
+    pub font: FontDescriptor,
+    pub size: Size,
+}
+
+impl PageLayout {
+    /// Returns the layout currently used by the reader view
+    pub fn current() -> Self {
+        Self {
+            font: MYENV.lock().unwrap().font.clone(),
+            size: *PAGE_SIZE.lock().unwrap(),
+        }
+    }
+
+    pub fn with_font_size(mut self, size: f64) -> Self {
+        self.font = self.font.with_size(size);
+        self
+    }
+
+    /// Returns a string that identifies the layout,
+    /// used to cache the number of pages in the metadata of the books
+    pub fn key(&self) -> String {
+        format!(
+            "{}_{}_{}x{}",
+            self.font.family.name().to_lowercase().replace(' ', "-"),
+            self.font.size,
+            self.size.width.round(),
+            self.size.height.round()
+        )
+    }
+}
+
+/// Updates the page size given the size of the reader view,
+/// rounded down to a multiple of `PAGE_SIZE_STEP`.
+/// Returns true if the page size changed and the books have to be paginated again
+pub fn set_view_size(view: Size, single_view: bool) -> bool {
+    let page = page_size(view, single_view);
+    let mut current = PAGE_SIZE.lock().unwrap();
+    let changed = current.width.round() != page.width.round()
+        || current.height.round() != page.height.round();
+    *current = page;
+    changed
+}
+
+/// Returns the size of a page in a reader view of the given size
+fn page_size(view: Size, single_view: bool) -> Size {
+    let width = if single_view {
+        view.width
+    } else {
+        view.width / DUAL_VIEW_FLEX
+    };
+    Size::new(snap(width - 2.0 * LABEL_X_PADDING), snap(view.height))
+}
+
+fn snap(length: f64) -> f64 {
+    ((length / PAGE_SIZE_STEP).floor() * PAGE_SIZE_STEP).max(PAGE_SIZE_STEP)
+}
+
+/// Splits the text of a chapter in pages that fit the given layout.
+/// The text is measured with the same piet text layout used to render it,
+/// so the pages neither overflow nor come out half empty.
+/// The returned ranges are consecutive and cover the whole text.
+pub fn paginate(text: &str, layout: &PageLayout) -> Vec<Range<usize>> {
+    match text_factory() {
+        Some(mut factory) => split_in_pages(text, layout.size.height, |page| {
+            measure(&mut factory, layout, page)
+        }),
+        None => {
+            println!("ERROR: unable to create a text factory, the pages will be estimated");
+            split_in_pages(text, layout.size.height, |page| estimate(layout, page))
+        }
+    }
+}
+
+/// Creates a text factory not bound to any window,
+/// so that the pages can be calculated also in background threads
+fn text_factory() -> Option<PietText> {
+    let mut device = Device::new().ok()?;
+    let mut target = device.bitmap_target(1, 1, 1.0).ok()?;
+    let mut rc = target.render_context();
+    let text = rc.text().clone();
+    rc.finish().ok()?;
+    Some(text)
+}
+
+/// Returns the height of the text once rendered in the page
+fn measure(factory: &mut PietText, layout: &PageLayout, text: &str) -> f64 {
+    let rich_text = rebuild_rendered_text(text);
+    if rich_text.is_empty() {
+        return 0.0;
+    }
+
+    let builder = factory
+        .new_text_layout(rich_text.clone())
+        .max_width(layout.size.width)
+        .font(layout.font.family.clone(), layout.font.size)
+        .default_attribute(layout.font.weight)
+        .default_attribute(layout.font.style);
+
+    match rich_text.add_layout_attributes(builder).build() {
+        Ok(text_layout) => text_layout.size().height,
+        Err(_) => estimate(layout, text),
+    }
+}
+
+/// Rough height of the text, used only if piet is not available
+fn estimate(layout: &PageLayout, text: &str) -> f64 {
+    let chars_per_line = (layout.size.width / (layout.font.size * 0.5)).max(1.0);
+    let lines: f64 = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| (line.chars().count() as f64 / chars_per_line).ceil())
+        .sum();
+    lines * layout.font.size * 1.2
+}
+
+/// Splits the text in consecutive ranges whose height, according to `measure`,
+/// is not greater than `height`. Pages end at the end of a line when possible,
+/// lines taller than a page are split at word boundaries.
+fn split_in_pages<F: FnMut(&str) -> f64>(
+    text: &str,
+    height: f64,
+    mut measure: F,
+) -> Vec<Range<usize>> {
+    // end of every line, newline included
+    let line_ends: Vec<usize> = text
+        .split_inclusive('\n')
+        .scan(0, |end, line| {
+            *end += line.len();
+            Some(*end)
+        })
+        .collect();
+    let line_starts = once(0).chain(line_ends.iter().copied());
+    let mut heights: Vec<f64> = line_starts
+        .zip(line_ends.iter())
+        .map(|(start, &end)| measure(&text[start..end]))
+        .collect();
+
+    let mut pages = vec![];
+    let mut start = 0;
+    // index of the line that contains `start`
+    let mut first = 0;
+
+    while first < line_ends.len() {
+        // the sum of the lines is only an estimate of the height of the page,
+        // the margins between paragraphs are known only measuring them together
+        let mut end_idx = first;
+        let mut sum = 0.0;
+        while end_idx < line_ends.len() && sum + heights[end_idx] <= height {
+            sum += heights[end_idx];
+            end_idx += 1;
+        }
+
+        if end_idx == first {
+            // the line alone is taller than the page
+            let line_end = line_ends[first];
+            let cut = fit_words(text, start..line_end, height, &mut measure);
+            pages.push(start..cut);
+            start = cut;
+            if cut == line_end {
+                first += 1;
+            } else {
+                heights[first] = measure(&text[cut..line_end]);
+            }
+            continue;
+        }
+
+        while end_idx > first + 1 && measure(&text[start..line_ends[end_idx - 1]]) > height {
+            end_idx -= 1;
+        }
+
+        let end = line_ends[end_idx - 1];
+        pages.push(start..end);
+        start = end;
+        first = end_idx;
+    }
+
+    if pages.is_empty() {
+        pages.push(0..text.len());
+    }
+    pages
+}
+
+/// Returns where to cut the range so that the first part fits in the page,
+/// always after at least one word so that the pagination moves on
+fn fit_words<F: FnMut(&str) -> f64>(
+    text: &str,
+    range: Range<usize>,
+    height: f64,
+    measure: &mut F,
+) -> usize {
+    let cuts: Vec<usize> = text[range.clone()]
+        .char_indices()
+        .filter(|(_, c)| c.is_whitespace())
+        .map(|(i, c)| range.start + i + c.len_utf8())
+        .filter(|&cut| cut < range.end)
+        .collect();
+
+    if cuts.is_empty() {
+        return range.end;
+    }
+
+    // binary search of the last cut that fits
+    let (mut lo, mut hi) = (0, cuts.len());
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        if measure(&text[range.start..cuts[mid]]) <= height {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+
+    cuts[lo.saturating_sub(1)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHARS_PER_LINE: usize = 20;
+    const LINE_HEIGHT: f64 = 10.0;
+
+    /// fake measure: every line wraps after CHARS_PER_LINE characters
+    fn fake_measure(text: &str) -> f64 {
+        text.lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| ((line.len() + CHARS_PER_LINE - 1) / CHARS_PER_LINE) as f64 * LINE_HEIGHT)
+            .sum()
+    }
+
+    fn assert_covers(text: &str, pages: &[Range<usize>]) {
+        assert_eq!(pages.first().unwrap().start, 0);
+        assert_eq!(pages.last().unwrap().end, text.len());
+        for pair in pages.windows(2) {
+            assert_eq!(pair[0].end, pair[1].start);
+        }
+    }
+
+    #[test]
+    fn empty_text_has_one_page() {
+        let pages = split_in_pages("", 100.0, fake_measure);
+        assert_eq!(pages, vec![0..0]);
+    }
+
+    #[test]
+    fn pages_cover_the_text_and_fit() {
+        let text = (0..50)
+            .map(|i| format!("line number {}\n", i))
+            .collect::<String>();
+        let pages = split_in_pages(&text, 45.0, fake_measure);
+
+        assert_covers(&text, &pages);
+        assert_eq!(pages.len(), 13);
+        for page in pages.iter() {
+            assert!(fake_measure(&text[page.clone()]) <= 45.0);
+            assert!(text[page.clone()].ends_with('\n'));
+        }
+    }
+
+    #[test]
+    fn long_paragraph_is_split_at_words() {
+        let text = "word ".repeat(100);
+        let pages = split_in_pages(&text, 30.0, fake_measure);
+
+        assert_covers(&text, &pages);
+        assert!(pages.len() > 1);
+        for page in pages.iter() {
+            assert!(fake_measure(&text[page.clone()]) <= 30.0);
+            assert!(text[page.clone()].ends_with(' '));
+        }
+    }
+
+    #[test]
+    fn layout_key_depends_on_font_and_size() {
+        let layout = PageLayout {
+            font: FontDescriptor::new(druid::FontFamily::SERIF).with_size(18.0),
+            size: Size::new(600.4, 400.0),
+        };
+        let bigger = PageLayout {
+            size: Size::new(800.0, 400.0),
+            ..layout.clone()
+        };
+
+        assert_eq!(layout.key(), layout.clone().key());
+        assert_ne!(layout.key(), bigger.key());
+        assert_ne!(layout.key(), layout.clone().with_font_size(22.0).key());
+    }
+
+    #[test]
+    fn small_resizes_keep_the_page_size() {
+        let page = page_size(Size::new(1005.0, 702.0), true);
+        assert_eq!(page, Size::new(1000.0, 700.0));
+        // a few pixels more or less don't change the pages
+        assert_eq!(page_size(Size::new(1018.0, 710.0), true), page);
+        assert_eq!(
+            page_size(Size::new(1030.0, 710.0), true),
+            Size::new(1020.0, 700.0)
+        );
+        assert_eq!(page_size(Size::new(1030.0, 710.0), false).width, 480.0);
+        // the page never disappears
+        assert_eq!(page_size(Size::ZERO, true), Size::new(20.0, 20.0));
+    }
+}
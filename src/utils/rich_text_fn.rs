@@ -16,20 +16,22 @@ const OPEN_LINK: Selector<String> = Selector::new("druid-example.open-link");
 
 
 /// Parse a markdown string and generate a `RichText` object with
-/// the appropriate attributes.
+/// the appropriate attributes. The rendered text remembers where every
+/// piece of text comes from, so that highlights and selections
+/// can be converted between the markdown and the rendered text.
 pub fn rebuild_rendered_text(text: &str) -> RichText {
     let mut current_pos = 0;
     let mut builder = RichTextBuilder::new();
     let mut tag_stack = Vec::new();
 
     let parser = Parser::new_ext(text, Options::ENABLE_STRIKETHROUGH);
-    for event in parser {
+    for (event, source) in parser.into_offset_iter() {
         match event {
             ParseEvent::Start(tag) => {
                 tag_stack.push((current_pos, tag));
             }
             ParseEvent::Text(txt) => {
-                builder.push(&txt);
+                builder.push_from_source(&txt, source);
                 current_pos += txt.len();
             }
             ParseEvent::End(end_tag) => {
@@ -47,12 +49,14 @@ pub fn rebuild_rendered_text(text: &str) -> RichText {
                 }
             }
             ParseEvent::Code(txt) => {
-                builder.push(&txt).font_family(FontFamily::MONOSPACE);
+                builder
+                    .push_from_source(&txt, source)
+                    .font_family(FontFamily::MONOSPACE);
                 current_pos += txt.len();
             }
             ParseEvent::Html(txt) => {
                 builder
-                    .push(&txt)
+                    .push_from_source(&txt, source)
                     .font_family(FontFamily::MONOSPACE)
                     .text_color(BLOCKQUOTE_COLOR);
                 current_pos += txt.len();
@@ -1,176 +1,109 @@
 use std::{
-    collections::HashMap,
-    fs::{create_dir_all, File, OpenOptions},
-    io::BufReader,
+    collections::BTreeMap,
     path::Path,
     str::FromStr,
-    sync::mpsc::channel,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 use druid::im::Vector;
-use rust_fuzzy_search::fuzzy_compare;
-use serde_json::{json, Value};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::json;
 
 use crate::{
-    models::{note::Note, book::{PAGE_WIDTH, PAGE_HEIGHT}},
-    utils::{
-        dir_manager::{
-            get_books_notes_path, get_edited_books_dir, get_epub_dir, get_saved_books_dir,
-            get_savedata_path,
+    models::{
+        bookmark::Bookmark,
+        documents::{
+            BookMetadata, BookmarksDocument, HighlightsDocument, NotesDocument, SaveDataDocument, SavedBook,
+            SavedBookmark, SavedChapterNotes, SavedHighlight, SavedNote,
         },
-        epub_utils::{get_metadata_of_book, split_chapter_in_vec},
+        highlight::{Highlight, HighlightColor, TextRange},
+        metadata::MetadataEdit,
+        note::Note,
+        position::{byte_to_char, char_to_byte, find_snippet, Position},
+    },
+    utils::{
+        dir_manager::{get_book_dir, get_edited_book_dir, get_edited_books_dir, get_epub_dir, get_saved_books_dir},
+        epub_utils::{get_chapter_text, get_metadata_of_book},
+        fulltext,
+        identity::{self, book_id},
+        kosync,
+        storage::{self, Document},
     },
-    MYENV,
 };
 
-use super::{dir_manager::get_metadata_path, envmanager::FontSize};
-
 pub enum FileExtension {
     TXT,
     HTML,
     EPUB,
 }
 
-/// function to save page of chapter of currently opened book
+/// function to save the reading position of currently opened book
 pub fn save_data<T: Into<String> + Clone>(
     book_path: T,
-    chapter: usize,
-    page: usize,
-    content: T,
-    font_size: FontSize,
+    position: Position,
     edited: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
-
-    // check if exists a savedata file
-    let savedata_path = get_savedata_path();
-    let mut json = json!({});
-    if let Ok(opened_file) = File::open(savedata_path.clone()) {
-        let reader = BufReader::new(opened_file);
-        if let Ok(content) = serde_json::from_reader(reader) {
-            json = content
-        };
-    } else {
-        create_dir_all(savedata_path.parent().unwrap()).unwrap();
-    }
-    // create a vec of edited chapters
-    let mut set = json[book_path.clone().into()]["edited_chapters"]
-        .as_array()
-        .unwrap_or(&vec![])
-        .iter()
-        .map(|x| x.as_u64().unwrap() as usize)
-        .collect::<Vec<usize>>();
-    if edited {
-        if !set.contains(&chapter) {
-            set.push(chapter);
-        }
-    }
-    // json value for the book
-    let value = json!(
-        {
-            "chapter":chapter,
-            "page":page,
-            "font_size":font_size.to_string(),
-            "edited_chapters": set,
-            "content":content.into()
+    let chapter = position.get_chapter();
+    let path = book_path.into();
+    let id = book_id(&path);
+
+    storage::modify(&Document::SaveData, |saved: &mut SaveDataDocument| {
+        let book = saved.entry(id).or_default();
+        // the chapters edited before are kept
+        let mut edited_chapters = std::mem::take(&mut book.edited_chapters);
+        if edited && !edited_chapters.contains(&chapter) {
+            edited_chapters.push(chapter);
         }
-    );
-
-    json[book_path.into()] = value;
-
-    // open file to write
-    let file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(get_savedata_path())?;
-
-    serde_json::to_writer_pretty(file, &json)?;
-
+        *book = SavedBook {
+            chapter: Some(chapter),
+            offset: Some(position.get_offset()),
+            edited_chapters,
+            ..SavedBook::default()
+        };
+    })?;
+    // the position is sent to the sync server too, if there is one
+    kosync::push_position(&path, position, false);
     Ok(())
 }
 
 pub fn remove_savedata_of_book<T: Into<String> + Clone>(
     book_path: T,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let (tx, rx) = channel();
-
-    let thread = std::thread::spawn(move || {
-        let mut json = json!({});
-        if let Ok(opened_file) = File::open(get_savedata_path()) {
-            // file exists
-            let reader = BufReader::new(opened_file);
-            if let Ok(content) = serde_json::from_reader(reader) {
-                json = content
-            };
-        }
-        tx.send(json).unwrap();
-    });
-
-    if let Ok(()) = thread.join() {
-        let mut json = rx.recv().unwrap();
-        if json[book_path.clone().into()].is_object() {
-            let file = OpenOptions::new()
-                .write(true)
-                .truncate(true)
-                .open(get_savedata_path())?;
-
-            json.as_object_mut().unwrap().remove(&book_path.into());
-            serde_json::to_writer_pretty(file, &json)?;
-        }
-        drop(rx);
-        Ok(())
-    } else {
-        Err("Error while saving data".into())
-    }
+    let id = book_id(&book_path.into());
+    storage::modify(&Document::SaveData, |saved: &mut SaveDataDocument| {
+        saved.remove(&id);
+    })
 }
 
 pub fn remove_all_savedata() -> Result<(), Box<dyn std::error::Error>> {
-    let config_path = get_savedata_path();
-    if config_path.exists() {
-        std::fs::remove_file(config_path)?
-    }
-    Ok(())
+    storage::remove(&Document::SaveData)
 }
 
 pub fn remove_edited_chapter<T: Into<String> + Clone>(book_path: T, chapter_number: usize) {
-    let savedata_path = get_savedata_path();
-    let mut json = json!({});
-    if let Ok(opened_file) = File::open(savedata_path.clone()) {
-        // file exists
-        let reader = BufReader::new(opened_file);
-        if let Ok(content) = serde_json::from_reader(reader) {
-            json = content
-        };
+    let book: String = book_path.into();
+    let id = book_id(&book);
+    let Ok(saved) = storage::load::<SaveDataDocument>(&Document::SaveData) else {
+        return;
+    };
+    // most of the chapters are not edited: nothing to write
+    if !saved
+        .get(&id)
+        .map_or(false, |b| b.edited_chapters.contains(&chapter_number))
+    {
+        return;
     }
 
-    let mut set = json[book_path.clone().into()]["edited_chapters"]
-        .as_array()
-        .unwrap_or(&vec![])
-        .iter()
-        .map(|x| x.as_u64().unwrap() as usize)
-        .collect::<Vec<usize>>();
-
-    if let Ok(index) = set.binary_search(&chapter_number) {
-        set.remove(index);
-    } else {
+    let result = storage::modify(&Document::SaveData, |saved: &mut SaveDataDocument| {
+        if let Some(saved_book) = saved.get_mut(&id) {
+            saved_book.edited_chapters.retain(|c| *c != chapter_number);
+        }
+    });
+    if let Err(e) = result {
+        println!("ERROR: {}", e);
         return;
     }
 
-    json[book_path.clone().into()]["edited_chapters"] = json!(set);
-
-    let file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(get_savedata_path())
-        .unwrap();
-
-    serde_json::to_writer_pretty(file, &json).unwrap();
-    let book: String = book_path.into();
-    let folder_name = Path::new(&book).file_stem().unwrap().to_str().unwrap();
-    let path = get_edited_books_dir()
-        .join(folder_name)
-        .join(format!("page_{}.txt", chapter_number));
+    let path = get_edited_book_dir(&book).join(format!("page_{}.txt", chapter_number));
     let _ = std::fs::remove_file(path);
 }
 
@@ -179,105 +112,97 @@ fn evaluate_numeric_options(chapter: Option<u64>, page: Option<u64>) -> Option<(
     let page = page?;
     Some((chapter as usize, page as usize))
 }
-fn evaluate_str_options<'a>(
-    font_size: Option<&str>,
-    saved_content: Option<&'a str>,
-) -> Option<(FontSize, &'a str)> {
-    let font_size = font_size?;
-    let saved_content = saved_content?;
-    Some((FontSize::from(font_size.to_string()), saved_content))
-}
-
-/// function to get the most similar page of chapter to the last read one
-fn search_page<T: Into<String> + Clone>(book_path: T, chapter_number: usize, text: &str) -> usize {
-    let pages = split_chapter_in_vec(
-        book_path.clone().into().as_str(),
-        Option::None,
-        chapter_number,
-        8,
-        MYENV.lock().unwrap().font.size,
-        PAGE_WIDTH,
-        PAGE_HEIGHT,
-    );
-
-    let mut best_page = (0, 0.0);
-    for (i, page) in pages.iter().enumerate() {
-        let result = fuzzy_compare(text, page.as_str());
-        if result > best_page.1 {
-            best_page = (i, result);
-        }
-    }
-    best_page.0
-}
 
 pub fn save_favorite<T: Into<String> + Clone>(
     book_path: T,
     favorite: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut metadata = get_metadata_of_book(book_path.clone().into().as_str());
-    let old_string = &metadata["favorite"];
-    if (old_string == "true" && favorite) || (old_string == "false" && !favorite) {
+    let metadata = get_metadata_of_book(book_path.clone().into().as_str());
+    if metadata.favorite == favorite {
         return Ok(());
     }
 
-    metadata.insert(
-        "favorite".to_string(),
-        if favorite { "true" } else { "false" }.to_string(),
-    );
+    storage::update(&Document::Metadata(book_path.into()), |json| {
+        json["favorite"] = json!(favorite);
+    })
+}
 
-    let json = json!(metadata);
-    let metadata_path = get_metadata_path(&book_path.into());
+/// Returns the time in seconds from the epoch, the format of the times saved
+pub fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
 
-    let metadata_file = OpenOptions::new()
-        .create(true)
-        .truncate(true)
-        .write(true)
-        .open(metadata_path)
-        .unwrap();
+/// function to save when a book has been opened
+pub fn save_last_opened<T: Into<String> + Clone>(
+    book_path: T,
+    time: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    storage::update(&Document::Metadata(book_path.into()), |json| {
+        json["last_opened"] = json!(time);
+    })
+}
 
-    serde_json::to_writer_pretty(metadata_file, &json)?;
-    return Ok(());
+/// function to save the tags of a book in its metadata
+pub fn save_tags<T: Into<String> + Clone>(
+    book_path: T,
+    tags: Vec<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    storage::update(&Document::Metadata(book_path.into()), |json| {
+        json["tags"] = json!(tags);
+    })
 }
 
-/// function to load the last read page of a chapter given the path of the book
-pub fn load_data<T: Into<String> + Clone>(
+/// function to save the shelf of a book in its metadata, an empty one removes it
+pub fn save_shelf<T: Into<String> + Clone>(
     book_path: T,
-    force_fuzzy: bool,
-) -> Result<(usize, usize, f64), Box<dyn std::error::Error>> {
-    let mut chapter = 1;
-    let mut page = 0;
-    let mut font_size = FontSize::MEDIUM;
-    let mut content = "";
-
-    if let Ok(file) = File::open(get_savedata_path()) {
-        let reader = BufReader::new(file);
-        let json: Value = serde_json::from_reader(reader)?;
-
-        if let Some(value) = json.get(book_path.clone().into()) {
-            let saved_chapter_value = value.get("chapter").and_then(|v| v.as_u64());
-            let saved_page_value = value.get("page").and_then(|v| v.as_u64());
-            let saved_font_size = value.get("font_size").and_then(|v| v.as_str());
-            let saved_content = value.get("content").and_then(|v| v.as_str());
-
-            // assign values considering the same font size
-            if let Some((c, s)) = evaluate_numeric_options(saved_chapter_value, saved_page_value) {
-                chapter = c;
-                page = s;
-            }
-            if let Some((f, c)) = evaluate_str_options(saved_font_size, saved_content) {
-                font_size = f;
-                content = c;
+    shelf: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    storage::update(&Document::Metadata(book_path.into()), |json| {
+        match json.as_object_mut() {
+            Some(metadata) if shelf.is_empty() => {
+                metadata.remove("shelf");
             }
+            _ => json["shelf"] = json!(shelf),
+        }
+    })
+}
 
-            let app_font_size = FontSize::from(MYENV.lock().unwrap().font.size);
-            // check if the application font size (env) is different from the saved one
-            if (app_font_size != font_size) || force_fuzzy {
-                // need to "find" the last read page
-                page = search_page(book_path.clone(), chapter, content);
-            }
-        };
-    }
-    Ok((chapter, page, font_size.to_f64()))
+/// function to save the metadata edited by the user, returns the new metadata of the book
+pub fn save_metadata_edit<T: Into<String> + Clone>(
+    book_path: T,
+    edit: &MetadataEdit,
+) -> Result<BookMetadata, Box<dyn std::error::Error>> {
+    let book_path: String = book_path.into();
+    // extracted from the epub if it's missing, so that the fields not edited are kept
+    get_metadata_of_book(&book_path);
+    storage::modify(&Document::Metadata(book_path), |metadata: &mut BookMetadata| {
+        edit.apply(metadata);
+        metadata.clone()
+    })
+}
+
+/// function to load the last reading position given the path of the book
+pub fn load_data<T: Into<String> + Clone>(
+    book_path: T,
+) -> Result<Position, Box<dyn std::error::Error>> {
+    let mut position = Position::new(1, 0);
+    let saved: SaveDataDocument = storage::load(&Document::SaveData)?;
+
+    if let Some(book) = saved.get(&book_id(&book_path.clone().into())) {
+        if let (Some(chapter), Some(offset)) = (book.chapter, book.offset) {
+            position = Position::new(chapter, offset);
+        } else if let Some((chapter, _)) = evaluate_numeric_options(
+            book.chapter.map(|c| c as u64),
+            book.page.map(|p| p as u64),
+        ) {
+            // savedata written by the old versions: chapter, page and a piece
+            // of the page text, the piece is looked up in the chapter text
+            let text = get_chapter_text(book_path.clone().into().as_str(), chapter);
+            position =
+                Position::of_snippet(chapter, &text, book.content.as_deref().unwrap_or_default());
+        }
+    };
+    Ok(position)
 }
 
 pub fn get_chapter(
@@ -307,26 +232,16 @@ pub fn get_chapter_bytes(
     std::fs::read(filename).map_err(|e| e.to_string())
 }
 
-/// function to save note in a page of chapter of currently opened book
+/// function to save note in a page of chapter of currently opened book,
+/// the note is anchored to the given position
 pub fn save_note<T: Into<String> + Clone>(
     book_path: T,
-    chapter: usize,
+    position: Position,
     page_text: T,
     note: T,
 ) -> Result<String, Box<dyn std::error::Error>> {
-    // check if exists a file
-    let notes_path = get_books_notes_path();
-    let mut json = json!({});
-    if let Ok(opened_file) = File::open(notes_path.clone()) {
-        // file exists
-        let reader = BufReader::new(opened_file);
-        if let Ok(content) = serde_json::from_reader(reader) {
-            json = content
-        };
-    } else {
-        // file doesn't exist
-        create_dir_all(notes_path.parent().unwrap()).unwrap();
-    }
+    let chapter = position.get_chapter();
+    let id = book_id(&book_path.into());
 
     let text = page_text.into();
     let to_take = if text.len() > 200 {
@@ -334,79 +249,74 @@ pub fn save_note<T: Into<String> + Clone>(
     } else {
         text.len()
     };
-    // json value for the note to save
-    let value = json!(
-        {
-            "start": &text[..to_take],
-            "note":note.into()
-        }
-    );
-
-    // array of the book
-    if let Some(array) = json[book_path.clone().into()].as_array_mut() {
-        // other stuff
-        if let Some(obj) = array.iter_mut().find(|obj| obj["chapter"] == chapter) {
-            // chapter already exists
-            if let Some(array_notes) = obj["notes"].as_array_mut() {
-                // add the note to the array
-                array_notes.push(value);
-            } else {
-                // create the array
-                obj["notes"] = json!([value]);
-            }
+    let to_take = char_to_byte(&text, byte_to_char(&text, to_take));
+    let note = SavedNote {
+        start: text[..to_take].to_string(),
+        note: note.into(),
+        offset: Some(position.get_offset()),
+    };
+
+    storage::modify(&Document::Notes, |saved: &mut NotesDocument| {
+        let chapters = saved.entry(id).or_default();
+        if let Some(saved_chapter) = chapters.iter_mut().find(|c| c.chapter == chapter) {
+            saved_chapter.notes.push(note);
         } else {
-            // chapter doesn't exist
-            array.push(json!({"chapter":chapter, "notes":[value]}));
+            chapters.push(SavedChapterNotes {
+                chapter,
+                notes: vec![note],
+            });
         }
-    } else {
-        json[book_path.into()] = json!([
-            {"chapter":chapter,
-            "notes":[value]
-        }]);
-    }
-    // open file to write
-    let file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(get_books_notes_path())?;
-
-    serde_json::to_writer_pretty(file, &json)?;
+    })?;
 
     Ok(text[..to_take].to_string())
 }
 
-/// function to load notes of a book
+/// function to load notes of a book.
+/// Notes saved by the old versions have no offset: it is found looking
+/// for the start of the note in the chapter text, and saved in the file
 pub fn load_notes<T: Into<String> + Clone>(
     book_path: T,
-) -> Result<HashMap<(usize, usize), Vector<Note>>, Box<dyn std::error::Error>> {
-    let mut map: HashMap<(usize, usize), Vector<Note>> = HashMap::new();
+) -> Result<Vector<Note>, Box<dyn std::error::Error>> {
+    let book_path: String = book_path.into();
+    let id = book_id(&book_path);
+    let mut notes = Vector::new();
+    let mut offsets = vec![];
+
+    let saved: NotesDocument = storage::load(&Document::Notes)?;
+    for (i, chapter) in saved.get(&id).into_iter().flatten().enumerate() {
+        let mut chapter_text = None;
+        for (j, note) in chapter.notes.iter().enumerate() {
+            let offset = match note.offset {
+                Some(offset) => offset,
+                None => {
+                    let text = chapter_text
+                        .get_or_insert_with(|| get_chapter_text(&book_path, chapter.chapter));
+                    let offset = find_snippet(text, &note.start).unwrap_or(0);
+                    offsets.push((i, j, offset));
+                    offset
+                }
+            };
+            notes.push_back(
+                Note::new(note.start.clone(), note.note.clone())
+                    .with_position(Position::new(chapter.chapter, offset)),
+            );
+        }
+    }
 
-    if let Ok(file) = File::open(get_books_notes_path()) {
-        let reader = BufReader::new(file);
-        let json: Value = serde_json::from_reader(reader)?;
-
-        if let Some(book_array) = json[book_path.clone().into()].as_array() {
-            // exists a book with that name
-            for chapter in book_array {
-                let chapter_number = chapter["chapter"].as_u64().unwrap() as usize;
-                if let Some(notes_array) = chapter["notes"].as_array() {
-                    for note in notes_array {
-                        let start_page = note["start"].as_str().unwrap();
-                        let note_text = note["note"].as_str().unwrap().to_string();
-                        let page =
-                            search_page(book_path.clone().into(), chapter_number, start_page);
-                        map.entry((chapter_number, page))
-                            .and_modify(|v| {
-                                v.push_back(Note::new(start_page.into(), note_text.clone()))
-                            })
-                            .or_insert(Vector::from(vec![Note::new(start_page.into(), note_text)]));
-                    }
+    if !offsets.is_empty() {
+        storage::modify(&Document::Notes, |saved: &mut NotesDocument| {
+            for (i, j, offset) in offsets {
+                let note = saved
+                    .get_mut(&id)
+                    .and_then(|chapters| chapters.get_mut(i))
+                    .and_then(|chapter| chapter.notes.get_mut(j));
+                if let Some(note) = note {
+                    note.offset = Some(offset);
                 }
             }
-        }
+        })?;
     }
-    Ok(map)
+    Ok(notes)
 }
 
 /// function to delete a note of a book
@@ -415,53 +325,27 @@ pub fn delete_note<T: Into<String> + Clone>(
     chapter: usize,
     start_page: T,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    // open file to read
-    let Ok(file) = File::open(get_books_notes_path()) else {
+    if !Document::Notes.exists() {
         return Ok(());
-    };
-
-    let reader = BufReader::new(file);
-    let mut json: Value = serde_json::from_reader(reader)?;
-
-    // check if there is a book with that name and an array
-    let Some(book_array) = json[book_path.clone().into()].as_array_mut() else {
-        return Ok(());
-    };
-
-    let mut index_to_delete = None;
-
-    for chapter_value in book_array {
-        let chapter_number = chapter_value["chapter"].as_u64().unwrap() as usize;
-        if chapter_number == chapter {
-            // chapter found
-            let Some(notes_array) = chapter_value["notes"].as_array_mut() else {
-                return Ok(());
-            };
-
-            for (i, note) in notes_array.into_iter().enumerate() {
-                let saved_start_page = note["start"].as_str().unwrap();
-                if saved_start_page == start_page.clone().into() {
-                    // note found
-                    index_to_delete = Some(i);
-                }
-            }
+    }
+    let start_page: String = start_page.into();
+    let id = book_id(&book_path.into());
 
+    storage::modify(&Document::Notes, |saved: &mut NotesDocument| {
+        let Some(chapters) = saved.get_mut(&id) else {
+            return;
+        };
+        for saved_chapter in chapters.iter_mut().filter(|c| c.chapter == chapter) {
+            // the last note with that start
+            let index_to_delete = saved_chapter
+                .notes
+                .iter()
+                .rposition(|note| note.start == start_page);
             if let Some(index) = index_to_delete {
-                notes_array.remove(index);
+                saved_chapter.notes.remove(index);
             }
         }
-    }
-
-    // open file to write
-    let file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(get_books_notes_path())?;
-
-    serde_json::to_writer_pretty(file, &json)?;
-
-    Ok(())
+    })
 }
 
 /// delete notes of book_path given chapter, and vec of start_page (string)
@@ -470,96 +354,165 @@ pub fn delete_notes<T: Into<String> + Clone>(
     chapter: usize,
     start_pages: Vec<String>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let Ok(file) = File::open(get_books_notes_path()) else {
+    if !Document::Notes.exists() {
         return Ok(());
-    };
-
-    let reader = BufReader::new(file);
-    let mut json: Value = serde_json::from_reader(reader)?;
-
-    // check if there is a book with that name and an array
-    let Some(book_array) = json[book_path.clone().into()].as_array_mut() else {
-        return Ok(());
-    };
-
-    for item in book_array {
-        let chapter_number = item["chapter"].as_u64().unwrap() as usize;
-
-        if chapter_number != chapter {
-            continue;
-        }
-
-        let Some(notes_array) = item["notes"].as_array_mut() else {
-            return Ok(());
-        };
-
-        notes_array.retain(|note| {
-            !start_pages
-                .iter()
-                .any(|start| note["start"].as_str().unwrap() == start)
-        });
     }
+    let id = book_id(&book_path.into());
 
-    // open file to write
-    let file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(get_books_notes_path())?;
-
-    serde_json::to_writer_pretty(file, &json)?;
-
-    Ok(())
+    storage::modify(&Document::Notes, |saved: &mut NotesDocument| {
+        let Some(chapters) = saved.get_mut(&id) else {
+            return;
+        };
+        for saved_chapter in chapters.iter_mut().filter(|c| c.chapter == chapter) {
+            saved_chapter
+                .notes
+                .retain(|note| !start_pages.contains(&note.start));
+        }
+    })
 }
 
 /// function to delete all notes of a book
 pub fn delete_all_notes<T: Into<String> + Clone>(
     book_path: T,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    // open file to read
-    let Ok(file) = File::open(get_books_notes_path()) else {
+    if !Document::Notes.exists() {
         return Ok(());
-    };
+    }
+    let id = book_id(&book_path.into());
 
-    let reader = BufReader::new(file);
-    let mut json: Value = serde_json::from_reader(reader)?;
+    storage::modify(&Document::Notes, |saved: &mut NotesDocument| {
+        if let Some(chapters) = saved.get_mut(&id) {
+            chapters.clear();
+        }
+    })
+}
 
-    // check if there is a book with that name and an array
-    let Some(book_array) = json[book_path.clone().into()].as_array_mut() else {
-        return Ok(());
-    };
+/// Replaces the items of the book in the document, the book is removed when there are none
+fn save_book_items<I: Serialize + DeserializeOwned>(
+    document: &Document,
+    book_path: String,
+    items: Vec<I>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let id = book_id(&book_path);
+    storage::modify(document, |saved: &mut BTreeMap<String, Vec<I>>| {
+        if items.is_empty() {
+            saved.remove(&id);
+        } else {
+            saved.insert(id, items);
+        }
+    })
+}
 
-    book_array.clear();
+/// function to save the bookmarks of a book, replacing the saved ones
+pub fn save_bookmarks<T: Into<String> + Clone>(
+    book_path: T,
+    bookmarks: &Vector<Bookmark>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let items = bookmarks
+        .iter()
+        .map(|bookmark| SavedBookmark {
+            chapter: bookmark.get_position().get_chapter(),
+            offset: bookmark.get_position().get_offset(),
+            name: bookmark.get_name().clone(),
+        })
+        .collect();
+
+    save_book_items(&Document::Bookmarks, book_path.into(), items)
+}
 
-    // open file to write
-    let file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(get_books_notes_path())?;
+/// function to load the bookmarks of a book
+pub fn load_bookmarks<T: Into<String> + Clone>(
+    book_path: T,
+) -> Result<Vector<Bookmark>, Box<dyn std::error::Error>> {
+    let saved: BookmarksDocument = storage::load(&Document::Bookmarks)?;
+
+    let mut bookmarks: Vector<Bookmark> = saved
+        .get(&book_id(&book_path.into()))
+        .into_iter()
+        .flatten()
+        .map(|b| Bookmark::new(b.name.clone(), Position::new(b.chapter, b.offset)))
+        .collect();
+
+    bookmarks.sort_by_key(|b| b.get_position());
+    Ok(bookmarks)
+}
 
-    serde_json::to_writer_pretty(file, &json)?;
+/// function to save the highlights of a book, replacing the saved ones
+pub fn save_highlights<T: Into<String> + Clone>(
+    book_path: T,
+    highlights: &Vector<Highlight>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let items = highlights
+        .iter()
+        .map(|highlight| {
+            let range = highlight.get_range();
+            SavedHighlight {
+                chapter: range.get_chapter(),
+                start: range.to_range().start,
+                end: range.to_range().end,
+                color: highlight.get_color().to_str().to_string(),
+                text: highlight.get_text().clone(),
+                note: highlight.get_note().clone(),
+            }
+        })
+        .collect();
 
-    Ok(())
+    save_book_items(&Document::Highlights, book_path.into(), items)
 }
 
+/// function to load the highlights of a book
+pub fn load_highlights<T: Into<String> + Clone>(
+    book_path: T,
+) -> Result<Vector<Highlight>, Box<dyn std::error::Error>> {
+    let saved: HighlightsDocument = storage::load(&Document::Highlights)?;
+
+    let highlights = saved
+        .get(&book_id(&book_path.into()))
+        .into_iter()
+        .flatten()
+        .map(|h| {
+            let range = TextRange::new(h.chapter, h.start, h.end);
+            Highlight::new(range, HighlightColor::from_str(&h.color), h.text.clone())
+                .with_note(h.note.clone())
+        })
+        .collect();
+
+    Ok(highlights)
+}
 
 pub fn delete_book(book_path: &String) -> Result<(), Box<dyn std::error::Error>> {
     let epub = Path::new(book_path);
     // delete book from file
     if epub.exists() {
-        // remove from saved_books
-        let saved_book = get_saved_books_dir().join(epub.file_stem().unwrap());
-        std::fs::remove_dir_all(saved_book)?;
+        // both named after the id of the book, found before it is unregistered
+        let saved_book = get_book_dir(book_path);
+        let index_segment = fulltext::segment_path(book_path);
+
+        let shared = identity::unregister(book_path);
+
+        // remove from the full-text index
+        fulltext::forget_book(book_path);
 
         // remove from epubs dir
         std::fs::remove_file(book_path)?;
+
+        // remove from saved_books and the index, unless another copy of the book uses them
+        if !shared {
+            if saved_book.exists() {
+                std::fs::remove_dir_all(saved_book)?;
+            }
+            if index_segment.exists() {
+                std::fs::remove_file(index_segment)?;
+            }
+        }
     }
 
     Ok(())
 }
 
-pub fn copy_book_in_folder(from: &String) -> Result<(), Box<dyn std::error::Error>> {
+/// Copies the book in the epubs folder and returns the path of the copy.
+/// A different book with the same name is kept: the copy takes a name not used yet
+pub fn copy_book_in_folder(from: &String) -> Result<String, Box<dyn std::error::Error>> {
     let path = Path::new(from);
     if !path.exists() {
         return Err(Box::new(std::io::Error::new(
@@ -568,21 +521,102 @@ pub fn copy_book_in_folder(from: &String) -> Result<(), Box<dyn std::error::Erro
         )));
     }
 
-    let to = get_epub_dir().join(path.file_name().unwrap());
-    std::fs::copy(from, to)?;
-    Ok(())
+    let stem = path.file_stem().and_then(|s| s.to_str()).ok_or("invalid file name")?;
+    let mut to = get_epub_dir().join(path.file_name().unwrap());
+    let mut n = 2;
+    while to.exists() {
+        to = get_epub_dir().join(format!("{} ({}).epub", stem, n));
+        n += 1;
+    }
+    std::fs::copy(from, &to)?;
+    Ok(to.to_str().ok_or("invalid file name")?.to_string())
+}
+
+/// Moves the reading position, the notes, the bookmarks and the highlights of a copy of a book
+/// to another copy. The position furthest in the book is kept
+pub fn merge_book_data(into: &str, from: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let (into, from) = (book_id(into), book_id(from));
+    if into == from {
+        return Ok(());
+    }
+
+    storage::modify(&Document::SaveData, |saved: &mut SaveDataDocument| {
+        let Some(copy) = saved.remove(&from) else {
+            return;
+        };
+        let book = saved.entry(into.clone()).or_default();
+        if (copy.chapter, copy.offset) > (book.chapter, book.offset) {
+            // the chapters edited in the book are the ones in its folder
+            *book = SavedBook {
+                edited_chapters: std::mem::take(&mut book.edited_chapters),
+                ..copy
+            };
+        }
+    })?;
+
+    storage::modify(&Document::Notes, |saved: &mut NotesDocument| {
+        let Some(copy) = saved.remove(&from) else {
+            return;
+        };
+        let chapters = saved.entry(into.clone()).or_default();
+        for copy_chapter in copy {
+            match chapters.iter_mut().find(|c| c.chapter == copy_chapter.chapter) {
+                Some(chapter) => merge_items(&mut chapter.notes, copy_chapter.notes),
+                None => chapters.push(copy_chapter),
+            }
+        }
+    })?;
+
+    merge_book_items::<SavedBookmark>(&Document::Bookmarks, &into, &from)?;
+    merge_book_items::<SavedHighlight>(&Document::Highlights, &into, &from)
+}
+
+/// Adds the items that are not there yet
+fn merge_items<I: PartialEq>(items: &mut Vec<I>, more: Vec<I>) {
+    for item in more {
+        if !items.contains(&item) {
+            items.push(item);
+        }
+    }
+}
+
+fn merge_book_items<I: Serialize + DeserializeOwned + PartialEq>(
+    document: &Document,
+    into: &str,
+    from: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    storage::modify(document, |saved: &mut BTreeMap<String, Vec<I>>| {
+        if let Some(copy) = saved.remove(from) {
+            merge_items(saved.entry(into.to_string()).or_default(), copy);
+        }
+    })
 }
 
 
 // Tests are provided only for the functions that are really used
 #[cfg(test)]
 mod tests {
-    use std::{path::PathBuf, io::BufWriter};
+    use std::{
+        fs::{File, OpenOptions},
+        io::{BufReader, BufWriter},
+        path::PathBuf,
+    };
+
+    use crate::utils::dir_manager::{
+        get_books_bookmarks_path, get_books_highlights_path, get_books_notes_path, get_metadata_path,
+        get_savedata_path,
+    };
 
     use super::*;
     use druid::platform_menus::win::file::print;
     use serde_json::{json, Value};
 
+    /// the json as it is written, with the version of the storage
+    fn with_version(mut json: Value) -> Value {
+        json["version"] = json!(storage::SCHEMA_VERSION);
+        json
+    }
+
     fn delete_file(path: PathBuf) {
         // delete existing file
         let _ = std::fs::remove_file(&path);
@@ -633,7 +667,7 @@ mod tests {
     }
 
     fn create_note_file(path: PathBuf, chapter: usize, start: Vec<String>, notes: Vec<String>) -> Value {
-        let book = path.to_str().unwrap().to_string();
+        let book = book_id(path.to_str().unwrap());
         
         let json = json!({
             &book: [
@@ -663,7 +697,7 @@ mod tests {
 
         let file = File::create(get_books_notes_path()).unwrap();
         let writer = BufWriter::new(file);
-        serde_json::to_writer_pretty(writer, &json).unwrap();
+        serde_json::to_writer_pretty(writer, &with_version(json.clone())).unwrap();
 
         // assert that file exists
         assert_eq!(get_books_notes_path().exists(), true);
@@ -675,15 +709,14 @@ mod tests {
     fn save_create_file_and_write_correctly() {
         let book_path = "test_book";
         let chapter = 1;
-        let page = 1;
-        let content = "inventa gli ordigni fuori del suo corpo";
+        let offset = 1250;
 
         copy_existing_file(get_savedata_path());
 
         delete_file(get_savedata_path());
 
         // assert that function returns Ok
-        assert!(save_data(book_path, chapter, page, content, FontSize::MEDIUM, false).is_ok());
+        assert!(save_data(book_path, Position::new(chapter, offset), false).is_ok());
 
         // assert that file exists
         assert_eq!(get_savedata_path().exists(), true);
@@ -693,12 +726,11 @@ mod tests {
 
         // assert that file contains correct data
         let json: Value = serde_json::from_reader(reader).unwrap();
-        assert_eq!(json, json!({
-            book_path:{
-                "chapter":chapter, "page":page, "content":content,
-                "font_size": "medium", "edited_chapters": []
+        assert_eq!(json, with_version(json!({
+            book_id(book_path):{
+                "chapter":chapter, "offset":offset, "edited_chapters": []
             }
-        }));
+        })));
 
         delete_file(get_savedata_path());
         restore_existing_file(get_savedata_path());
@@ -709,32 +741,30 @@ mod tests {
     fn save_overwrite_existing_file() {
         let book_path = "test_book";
         let chapter = 1;
-        let page = 1;
-        let content = "inventa gli ordigni fuori del suo corpo";
+        let offset = 1250;
         
         copy_existing_file(get_savedata_path());
         delete_file(get_savedata_path());
 
         // assert that function returns Ok
-        assert!(save_data(book_path, chapter, page, content, FontSize::MEDIUM, false).is_ok());
+        assert!(save_data(book_path, Position::new(chapter, offset), false).is_ok());
 
         // assert that file exists
         assert_eq!(get_savedata_path().exists(), true);
 
         // save new data
-        let new_content = "li usa per distruggere il mondo";
-        assert!(save_data(book_path, chapter, page+1, new_content, FontSize::MEDIUM, false).is_ok());
+        let new_offset = 2400;
+        assert!(save_data(book_path, Position::new(chapter, new_offset), false).is_ok());
 
         // assert that file contains new data
         let file = File::open(get_savedata_path()).unwrap();
         let reader = BufReader::new(file);
         let json: Value = serde_json::from_reader(reader).unwrap();
-        assert_eq!(json, json!({
-            book_path:{
-                "chapter":chapter, "page":page+1, "content":new_content,
-                "font_size": "medium", "edited_chapters": []
+        assert_eq!(json, with_version(json!({
+            book_id(book_path):{
+                "chapter":chapter, "offset":new_offset, "edited_chapters": []
             }
-        }));
+        })));
 
         delete_file(get_savedata_path());
         restore_existing_file(get_savedata_path());
@@ -745,38 +775,34 @@ mod tests {
     fn save_another_book_existing_file() {
         let book_path = "test_book";
         let chapter = 1;
-        let page = 1;
-        let content = "inventa gli ordigni fuori del suo corpo";
+        let offset = 1250;
 
         copy_existing_file(get_savedata_path());
         delete_file(get_savedata_path());
 
         // assert that function returns Ok
-        assert!(save_data(book_path, chapter, page, content, FontSize::MEDIUM, false).is_ok());
+        assert!(save_data(book_path, Position::new(chapter, offset), false).is_ok());
 
         // assert that file exists
         assert_eq!(get_savedata_path().exists(), true);
 
         let new_book_path = "test_book_123";
-        let new_chapter = 1;
-        let new_page = 1;
-        let new_content = "inventa gli ordigni fuori del suo corpo";
-        assert!(save_data(new_book_path, new_chapter, new_page, new_content, FontSize::MEDIUM, false).is_ok());
+        let new_chapter = 3;
+        let new_offset = 42;
+        assert!(save_data(new_book_path, Position::new(new_chapter, new_offset), false).is_ok());
 
         // assert that file contains new data
         let file = File::open(get_savedata_path()).unwrap();
         let reader = BufReader::new(file);
         let json: Value = serde_json::from_reader(reader).unwrap();
-        assert_eq!(json, json!({
-            book_path:{
-                "chapter":chapter, "page":page, "content":new_content,
-                "font_size": "medium", "edited_chapters": []
+        assert_eq!(json, with_version(json!({
+            book_id(book_path):{
+                "chapter":chapter, "offset":offset, "edited_chapters": []
             },
-            new_book_path:{
-                "chapter":new_chapter, "page":new_page, "content":new_content,
-                "font_size": "medium", "edited_chapters": []
+            book_id(new_book_path):{
+                "chapter":new_chapter, "offset":new_offset, "edited_chapters": []
             }
-        }));
+        })));
 
         delete_file(get_savedata_path());
         restore_existing_file(get_savedata_path());
@@ -787,14 +813,13 @@ mod tests {
     fn save_book_with_edited_chapter() {
         let book_path = "test_book";
         let chapter = 1;
-        let page = 1;
-        let content = "inventa gli ordigni fuori del suo corpo";
+        let offset = 1250;
 
         copy_existing_file(get_savedata_path());
         delete_file(get_savedata_path());
 
         // assert that function returns Ok
-        assert!(save_data(book_path, chapter, page, content, FontSize::MEDIUM, true).is_ok());
+        assert!(save_data(book_path, Position::new(chapter, offset), true).is_ok());
 
         // assert that file exists
         assert_eq!(get_savedata_path().exists(), true);
@@ -804,12 +829,11 @@ mod tests {
         let file = File::open(get_savedata_path()).unwrap();
         let reader = BufReader::new(file);
         let json: Value = serde_json::from_reader(reader).unwrap();
-        assert_eq!(json, json!({
-            book_path:{
-                "chapter":chapter, "page":page, "content":content,
-                "font_size": "medium", "edited_chapters": [chapter]
+        assert_eq!(json, with_version(json!({
+            book_id(book_path):{
+                "chapter":chapter, "offset":offset, "edited_chapters": [chapter]
             }
-        }));
+        })));
 
         delete_file(get_savedata_path());
         restore_existing_file(get_savedata_path());
@@ -820,28 +844,22 @@ mod tests {
     #[ignore]
     fn load_data_from_existing_file() {
         let book_path = "test_book";
-        let chapter = 1;
-        let page = 1;
-        let content = "inventa gli ordigni fuori del suo corpo";
-        let font_size = FontSize::MEDIUM;
+        let position = Position::new(2, 1250);
 
         copy_existing_file(get_savedata_path());
         delete_file(get_savedata_path());
 
         // assert that function returns Ok
-        assert!(save_data(book_path, chapter, page, content, font_size, false).is_ok());
+        assert!(save_data(book_path, position, false).is_ok());
 
         // assert that file exists
         assert_eq!(get_savedata_path().exists(), true);
 
         // assert that function returns Ok
-        assert!(load_data(book_path, false).is_ok());
+        assert!(load_data(book_path).is_ok());
 
         // assert that function returns correct data
-        let (chapter, page, size) = load_data(book_path, false).unwrap();
-        assert_eq!(chapter, chapter);
-        assert_eq!(page, page);
-        assert_eq!(size, FontSize::MEDIUM.to_f64());
+        assert_eq!(load_data(book_path).unwrap(), position);
 
         delete_file(get_savedata_path());
         restore_existing_file(get_savedata_path());
@@ -856,13 +874,10 @@ mod tests {
         delete_file(get_savedata_path());
 
         // assert that function returns Ok
-        assert!(load_data(book_path, false).is_ok());
+        assert!(load_data(book_path).is_ok());
 
         // assert that function returns correct data
-        let (chapter, page, size) = load_data(book_path, false).unwrap();
-        assert_eq!(chapter, 1);
-        assert_eq!(page, 0);
-        assert_eq!(size, FontSize::MEDIUM.to_f64());
+        assert_eq!(load_data(book_path).unwrap(), Position::new(1, 0));
 
         delete_file(get_savedata_path());
         restore_existing_file(get_savedata_path());
@@ -870,7 +885,7 @@ mod tests {
 
     #[test]
     #[ignore]
-    fn load_data_with_diff_font_size() {
+    fn load_data_from_old_savedata() {
         let binding = get_epub_dir().join("svevo_la_coscienza_di_zeno.epub");
         // TO RUN THIS TEST YOU NEED TO HAVE THE EPUB IN THE EPUB DIRECTORY
         assert!(binding.exists());
@@ -899,29 +914,15 @@ mod tests {
 
         serde_json::to_writer_pretty(file, &json).unwrap();
 
-        // get font size from env
-        let mut my_env = MYENV.lock().unwrap();
-        let old_font = my_env.font.size;
-        // set different font size to test
-        let new_font = FontSize::SMALL;
-        my_env.font.size = new_font.to_f64();
-        my_env.save_to_env();
-
-        drop(my_env);
-
-        // assert that function returns Ok
-        assert!(load_data(book_path, false).is_ok());
-
-        // assert that function returns correct data
-        let (chapter, page, _) = load_data(book_path, false).unwrap();
-        assert_eq!(chapter, 5);
-        assert_eq!(page, 2);
+        // the saved piece of page is found in the chapter text,
+        // whatever font and page size are in use
+        let content = json[book_path]["content"].as_str().unwrap();
+        let position = load_data(book_path).unwrap();
+        assert_eq!(position.get_chapter(), 5);
 
-        // reset font size
-        let mut my_env = MYENV.lock().unwrap(); 
-        my_env.font.size = old_font;
-        my_env.save_to_env();
-        drop(my_env);
+        let text = get_chapter_text(book_path, 5);
+        let start = char_to_byte(&text, position.get_offset());
+        assert!(text[start..].starts_with(&content[..char_to_byte(content, 100)]));
 
         delete_file(get_savedata_path());
         restore_existing_file(get_savedata_path());
@@ -935,7 +936,7 @@ mod tests {
         let book = get_epub_dir().join("test_book.epub");
         let book_string = book.to_str().unwrap().to_string();
         // create dir for file
-        let _ = std::fs::create_dir_all(get_saved_books_dir().join("test_book"));
+        let _ = std::fs::create_dir_all(get_book_dir(&book_string));
         // create fake file
         let metadata_path = get_metadata_path(&book_string);
         let metadata_file = OpenOptions::new()
@@ -956,10 +957,10 @@ mod tests {
 
         // assert that file contains correct data
         let saved: Value = serde_json::from_reader(reader).unwrap();
-        assert_eq!(saved, json!({"favorite": "true",}));
+        assert_eq!(saved, with_version(json!({"favorite": true,})));
 
-        let _ = std::fs::remove_dir_all(get_saved_books_dir().join("test_book"));
-        assert_eq!(get_saved_books_dir().join("test_book").exists(), false);
+        let _ = std::fs::remove_dir_all(get_book_dir(&book_string));
+        assert_eq!(get_book_dir(&book_string).exists(), false);
     }
 
     #[test]
@@ -969,7 +970,7 @@ mod tests {
         let book = get_epub_dir().join("test_book.epub");
         let book_string = book.to_str().unwrap().to_string();
         // create dir for file
-        let _ = std::fs::create_dir_all(get_saved_books_dir().join("test_book"));
+        let _ = std::fs::create_dir_all(get_book_dir(&book_string));
         // create fake file
         let metadata_path = get_metadata_path(&book_string);
         let metadata_file = OpenOptions::new()
@@ -990,10 +991,10 @@ mod tests {
 
         // assert that file contains correct data
         let saved: Value = serde_json::from_reader(reader).unwrap();
-        assert_eq!(saved, json!({"favorite": "false",}));
+        assert_eq!(saved, with_version(json!({"favorite": false,})));
 
-        let _ = std::fs::remove_dir_all(get_saved_books_dir().join("test_book"));
-        assert_eq!(get_saved_books_dir().join("test_book").exists(), false);
+        let _ = std::fs::remove_dir_all(get_book_dir(&book_string));
+        assert_eq!(get_book_dir(&book_string).exists(), false);
     }
     
     #[test]
@@ -1003,7 +1004,7 @@ mod tests {
         let book = get_epub_dir().join("test_book.epub");
         let book_string = book.to_str().unwrap().to_string();
         // create dir for file
-        let _ = std::fs::create_dir_all(get_saved_books_dir().join("test_book"));
+        let _ = std::fs::create_dir_all(get_book_dir(&book_string));
         // create fake file
         let metadata_path = get_metadata_path(&book_string);
         let metadata_file = OpenOptions::new()
@@ -1026,8 +1027,8 @@ mod tests {
         let saved: Value = serde_json::from_reader(reader).unwrap();
         assert_eq!(saved, json!({"favorite": "true",}));
 
-        let _ = std::fs::remove_dir_all(get_saved_books_dir().join("test_book"));
-        assert_eq!(get_saved_books_dir().join("test_book").exists(), false);
+        let _ = std::fs::remove_dir_all(get_book_dir(&book_string));
+        assert_eq!(get_book_dir(&book_string).exists(), false);
     }
 
     // get_chapter_bytes
@@ -1104,7 +1105,7 @@ mod tests {
 
         assert!(save_note(
             &book,
-            chapter,
+            Position::new(chapter, 0),
             &page_text,
             &note_text,
         ).is_ok());
@@ -1117,18 +1118,19 @@ mod tests {
 
         // assert that file contains correct data
         let json: Value = serde_json::from_reader(reader).unwrap();
-        assert_eq!(json, json!({
-            book: [
+        assert_eq!(json, with_version(json!({
+            book_id(&book): [
                 {
                 "chapter": chapter,
                 "notes": [
                     {
                     "note": note_text,
+                    "offset": 0,
                     "start": page_text
                     }
                 ]}
             ]
-        }));
+        })));
 
         delete_file(get_books_notes_path());
         restore_existing_file(get_books_notes_path());
@@ -1147,18 +1149,19 @@ mod tests {
 
         let file = File::create(get_books_notes_path()).unwrap();
         let writer = BufWriter::new(file);
-        serde_json::to_writer(writer, &json!({
-            &book: [
+        serde_json::to_writer(writer, &with_version(json!({
+            book_id(&book): [
                 {
                 "chapter": chapter,
                 "notes": [
                     {
                     "note": note_text,
+                    "offset": 0,
                     "start": page_text
                     }
                 ]}
             ]
-        })).unwrap();
+        }))).unwrap();
 
         // assert that file exists
         assert_eq!(get_books_notes_path().exists(), true);
@@ -1166,7 +1169,7 @@ mod tests {
         // write a note for a different chapter
         assert!(save_note(
             &book,
-            chapter+1,
+            Position::new(chapter+1, 0),
             &page_text,
             &note_text,
         ).is_ok());
@@ -1174,7 +1177,7 @@ mod tests {
         let other_page = "other page".to_string();
         assert!(save_note(
             &book,
-            chapter,
+            Position::new(chapter, 500),
             &other_page,
             &note_text,
         ).is_ok());
@@ -1184,17 +1187,19 @@ mod tests {
 
         // assert that file contains correct data
         let json: Value = serde_json::from_reader(reader).unwrap();
-        assert_eq!(json, json!({
-            book: [
+        assert_eq!(json, with_version(json!({
+            book_id(&book): [
                 {
                 "chapter": chapter,
                 "notes": [
                     {
                     "note": note_text,
+                    "offset": 0,
                     "start": page_text
                     },
                     {
                     "note": note_text,
+                    "offset": 500,
                     "start": other_page
                     }
                 ]},
@@ -1203,11 +1208,12 @@ mod tests {
                 "notes": [
                     {
                     "note": note_text,
+                    "offset": 0,
                     "start": page_text
                     }
                 ]}
             ]
-        }));
+        })));
 
         delete_file(get_books_notes_path());
         restore_existing_file(get_books_notes_path());
@@ -1226,18 +1232,19 @@ mod tests {
 
         let file = File::create(get_books_notes_path()).unwrap();
         let writer = BufWriter::new(file);
-        serde_json::to_writer(writer, &json!({
-            &book: [
+        serde_json::to_writer(writer, &with_version(json!({
+            book_id(&book): [
                 {
                 "chapter": chapter,
                 "notes": [
                     {
                     "note": note_text,
+                    "offset": 0,
                     "start": page_text
                     }
                 ]}
             ]
-        })).unwrap();
+        }))).unwrap();
 
         // assert that file exists
         assert_eq!(get_books_notes_path().exists(), true);
@@ -1246,7 +1253,7 @@ mod tests {
 
         assert!(save_note(
             &book2,
-            chapter,
+            Position::new(chapter, 0),
             &page_text,
             &note_text,
         ).is_ok());
@@ -1256,28 +1263,30 @@ mod tests {
 
         // assert that file contains correct data
         let json: Value = serde_json::from_reader(reader).unwrap();
-        assert_eq!(json, json!({
-            book: [
+        assert_eq!(json, with_version(json!({
+            book_id(&book): [
                 {
                 "chapter": chapter,
                 "notes": [
                     {
                     "note": note_text,
+                    "offset": 0,
                     "start": page_text
                     }
                 ]}
             ],
-            book2: [
+            book_id(&book2): [
                 {
                 "chapter": chapter,
                 "notes": [
                     {
                     "note": note_text,
+                    "offset": 0,
                     "start": page_text
                     }
                 ]}
             ]
-        }));
+        })));
 
         delete_file(get_books_notes_path());
         restore_existing_file(get_books_notes_path());
@@ -1305,8 +1314,8 @@ mod tests {
         let file = File::open(get_books_notes_path()).unwrap();
         let reader = BufReader::new(file);
         let json_2: Value = serde_json::from_reader(reader).unwrap();
-        assert_eq!(json_2, json!({
-            &book: [
+        assert_eq!(json_2, with_version(json!({
+            book_id(&book): [
               {
                 "chapter": chapter,
                 "notes": [
@@ -1321,7 +1330,7 @@ mod tests {
                 ]
               }
             ]
-          }));
+          })));
 
         delete_file(get_books_notes_path());
         restore_existing_file(get_books_notes_path());
@@ -1351,7 +1360,7 @@ mod tests {
         let file = File::open(get_books_notes_path()).unwrap();
         let reader = BufReader::new(file);
         let json_2: Value = serde_json::from_reader(reader).unwrap();
-        assert_eq!(json_2, json);
+        assert_eq!(json_2, with_version(json.clone()));
 
         // delete notes of a not existing book
         let book2 = get_epub_dir().join("test_book2.epub").to_str().unwrap().to_string();
@@ -1360,7 +1369,7 @@ mod tests {
         let file = File::open(get_books_notes_path()).unwrap();
         let reader = BufReader::new(file);
         let json_3: Value = serde_json::from_reader(reader).unwrap();
-        assert_eq!(json_3, json);
+        assert_eq!(json_3, with_version(json));
 
         delete_file(get_books_notes_path());
         restore_existing_file(get_books_notes_path());
@@ -1383,22 +1392,32 @@ mod tests {
             ].map(|s| s.to_string());
         let notes = ["ciao come", "tutto bene", "testing", "ciao ancora"].map(|s| s.to_string());
 
-        let pages: Vec<usize> = start.iter().map(|s| 
-            search_page(book.clone(), chapter, s.as_str())
+        let text = get_chapter_text(&book, chapter);
+        let offsets: Vec<usize> = start.iter().map(|s|
+            byte_to_char(&text, text.find(s.as_str()).unwrap())
         ).collect();
 
+        // notes saved without offset, as the old versions did
         let _ = create_note_file(
             get_epub_dir().join("pg69058-images.epub"), 
             chapter, start.to_vec(), notes.to_vec()
         );
 
-        let notes_map = load_notes(&book).unwrap();
+        let saved_notes = load_notes(&book).unwrap();
         
-        for (i, page) in pages.iter().enumerate() {
-            let saved_notes = notes_map.get(&(chapter, *page)).unwrap();
-            assert!(
-                saved_notes.iter().any(|n| n.get_start() == &start[i] && n.get_text() == &notes[i])
-            );
+        for (i, offset) in offsets.iter().enumerate() {
+            assert!(saved_notes.iter().any(|n| {
+                n.get_start() == &start[i]
+                    && n.get_text() == &notes[i]
+                    && n.get_position() == Position::new(chapter, *offset)
+            }));
+        }
+
+        // the offsets have been saved in the file
+        let file = File::open(get_books_notes_path()).unwrap();
+        let json: Value = serde_json::from_reader(BufReader::new(file)).unwrap();
+        for (i, offset) in offsets.iter().enumerate() {
+            assert_eq!(json[book_id(&book)][0]["notes"][i]["offset"], json!(offset));
         }
 
         delete_file(get_books_notes_path());
@@ -1439,6 +1458,75 @@ mod tests {
         restore_existing_file(get_books_notes_path());
     }
 
+    // bookmarks
+    #[test]
+    #[ignore]
+    fn save_and_load_bookmarks() {
+        copy_existing_file(get_books_bookmarks_path());
+        delete_file(get_books_bookmarks_path());
+
+        let book = get_epub_dir().join("test_book.epub").to_str().unwrap().to_string();
+        let other_book = get_epub_dir().join("other_book.epub").to_str().unwrap().to_string();
+        let bookmarks: Vector<Bookmark> = vec![
+            Bookmark::new("inizio".to_string(), Position::new(1, 0)),
+            Bookmark::new("finale".to_string(), Position::new(12, 340)),
+        ]
+        .into();
+
+        assert!(save_bookmarks(&book, &bookmarks).is_ok());
+        assert!(save_bookmarks(&other_book, &bookmarks.take(1)).is_ok());
+
+        let file = File::open(get_books_bookmarks_path()).unwrap();
+        let json: Value = serde_json::from_reader(BufReader::new(file)).unwrap();
+        assert_eq!(json[book_id(&book)], json!([
+            { "chapter": 1, "offset": 0, "name": "inizio" },
+            { "chapter": 12, "offset": 340, "name": "finale" }
+        ]));
+
+        assert_eq!(load_bookmarks(&book).unwrap(), bookmarks);
+        assert_eq!(load_bookmarks(&other_book).unwrap().len(), 1);
+
+        // saving no bookmarks removes the book from the file
+        assert!(save_bookmarks(&other_book, &Vector::new()).is_ok());
+        assert!(load_bookmarks(&other_book).unwrap().is_empty());
+        assert_eq!(load_bookmarks(&book).unwrap(), bookmarks);
+
+        delete_file(get_books_bookmarks_path());
+        restore_existing_file(get_books_bookmarks_path());
+    }
+
+    // highlights
+    #[test]
+    #[ignore]
+    fn save_and_load_highlights() {
+        copy_existing_file(get_books_highlights_path());
+        delete_file(get_books_highlights_path());
+
+        let book = get_epub_dir().join("test_book.epub").to_str().unwrap().to_string();
+        let highlights: Vector<Highlight> = vec![
+            Highlight::new(TextRange::new(2, 10, 25), HighlightColor::Green, "testo evidenziato".to_string()),
+            Highlight::new(TextRange::new(3, 0, 4), HighlightColor::Pink, "ciao".to_string())
+                .with_note("una nota".to_string()),
+        ]
+        .into();
+
+        assert!(save_highlights(&book, &highlights).is_ok());
+
+        let file = File::open(get_books_highlights_path()).unwrap();
+        let json: Value = serde_json::from_reader(BufReader::new(file)).unwrap();
+        assert_eq!(json[book_id(&book)][1], json!({
+            "chapter": 3, "start": 0, "end": 4, "color": "pink", "text": "ciao", "note": "una nota"
+        }));
+
+        assert_eq!(load_highlights(&book).unwrap(), highlights);
+
+        assert!(save_highlights(&book, &Vector::new()).is_ok());
+        assert!(load_highlights(&book).unwrap().is_empty());
+
+        delete_file(get_books_highlights_path());
+        restore_existing_file(get_books_highlights_path());
+    }
+
     // delete_book
     #[test]
     #[ignore]
@@ -1446,9 +1534,9 @@ mod tests {
         let path = get_epub_dir().join("test.epub").to_str().unwrap().to_string();
         let epub = Path::new(&path);
         
-        let saved_book = get_saved_books_dir().join(epub.file_stem().unwrap());
-        assert!(std::fs::create_dir_all(&saved_book).is_ok());
         assert!(File::create(&path).is_ok());
+        let saved_book = get_book_dir(&path);
+        assert!(std::fs::create_dir_all(&saved_book).is_ok());
     
         assert!(delete_book(&path).is_ok());
 
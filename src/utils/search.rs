@@ -0,0 +1,121 @@
+use std::ops::Range;
+
+use druid::im::Vector;
+
+use crate::{
+    models::{
+        position::{byte_to_char, Position},
+        search::SearchHit,
+    },
+    utils::epub_utils::get_chapter_text,
+};
+
+/// characters of text shown before and after a match
+const CONTEXT_CHARS: usize = 40;
+
+/// Searches the text in all the chapters of the book, ignoring the case
+pub fn search_book(path: &str, query: &str, number_of_chapters: usize) -> Vector<SearchHit> {
+    let mut hits = Vector::new();
+    if query.trim().is_empty() {
+        return hits;
+    }
+
+    for chapter in 0..number_of_chapters {
+        let text = get_chapter_text(path, chapter);
+        for range in find_matches(&text, query) {
+            hits.push_back(SearchHit::new(
+                hits.len(),
+                Position::new(chapter, range.start),
+                range.len(),
+                get_context(&text, &range),
+            ));
+        }
+    }
+    println!("DEBUG: {} hits for '{}'", hits.len(), query);
+    hits
+}
+
+/// Lower case of a character, one character for one character
+/// so that the offsets of the text and of its lower case are the same
+pub fn fold(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+/// Returns the ranges of characters of the text where the query appears,
+/// ignoring the case. Matches don't overlap
+pub fn find_matches(text: &str, query: &str) -> Vec<Range<usize>> {
+    let query: String = query.trim().chars().map(fold).collect();
+    if query.is_empty() {
+        return vec![];
+    }
+    let folded: String = text.chars().map(fold).collect();
+    let query_len = query.chars().count();
+
+    let mut matches = vec![];
+    let mut from = 0;
+    while let Some(found) = folded[from..].find(&query) {
+        let byte = from + found;
+        let start = byte_to_char(&folded, byte);
+        matches.push(start..start + query_len);
+        from = byte + query.len();
+    }
+    matches
+}
+
+/// Returns the text around the range of characters, on a single line
+pub fn get_context(text: &str, range: &Range<usize>) -> String {
+    let start = range.start.saturating_sub(CONTEXT_CHARS);
+    let end = range.end + CONTEXT_CHARS;
+
+    let mut context: String = text
+        .chars()
+        .skip(start)
+        .take(end - start)
+        .map(|c| if c.is_whitespace() { ' ' } else { c })
+        .collect();
+    context = context.trim().to_string();
+
+    if start > 0 {
+        context.insert_str(0, "...");
+    }
+    if end < text.chars().count() {
+        context.push_str("...");
+    }
+    context
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_ignore_case_and_use_char_offsets() {
+        let text = "Perché la Rosa? la rosa è rosa, ROSA";
+        let matches = find_matches(text, "rosa");
+
+        assert_eq!(matches.len(), 4);
+        for range in matches.iter() {
+            let found: String = text.chars().skip(range.start).take(range.len()).collect();
+            assert_eq!(found.to_lowercase(), "rosa");
+        }
+        // "Perché " has an accented character, the offset is in characters
+        assert_eq!(matches[0], 10..14);
+    }
+
+    #[test]
+    fn no_matches_for_empty_query() {
+        assert!(find_matches("some text", "  ").is_empty());
+        assert!(find_matches("some text", "other").is_empty());
+    }
+
+    #[test]
+    fn context_is_a_single_line_around_the_match() {
+        let text = format!("{}\nmatch\n{}", "a".repeat(100), "b".repeat(100));
+        let context = get_context(&text, &(101..106));
+
+        assert!(context.starts_with("..."));
+        assert!(context.ends_with("..."));
+        assert!(context.contains(" match "));
+        assert!(!context.contains('\n'));
+    }
+}
@@ -0,0 +1,360 @@
+//! Reading statistics.
+//! A reading session starts with the first page turned in a book and goes on while its pages
+//! are turned: it ends when the book is closed, when another book is read or after a pause
+//! longer than `MAX_PAUSE`. The session is kept in memory and saved when it ends, when a chapter
+//! is finished and when the application is closed: the saves run in their own thread, so that
+//! turning a page doesn't wait for the disk.
+//! The time between two page turns of a session also teaches the reading speed of the user,
+//! used to estimate the time left to finish a chapter or a book.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use druid::Selector;
+use once_cell::sync::Lazy;
+use threadpool::ThreadPool;
+
+use crate::{
+    models::documents::{ReadingSession, ReadingStatsDocument},
+    utils::{
+        identity,
+        saveload::unix_time,
+        storage::{self, Document},
+    },
+};
+
+/// show the statistics of the reading sessions
+pub const SHOW_READING_STATS: Selector<()> = Selector::new("crabreader.show_reading_stats");
+
+/// seconds without turning a page after which a new session starts
+const MAX_PAUSE: u64 = 5 * 60;
+
+const DAY: u64 = 24 * 60 * 60;
+
+/// seconds under which a page has been skipped and not read
+const MIN_PAGE_TIME: u64 = 3;
+
+/// weight of the last page read in the reading speed
+const SPEED_WEIGHT: f64 = 0.05;
+
+/// seconds per page used until the reading speed of the user is learned
+const DEFAULT_SECONDS_PER_PAGE: f64 = 90.0;
+
+/// the session in progress, with the path of its book
+static ACTIVE: Lazy<Mutex<Option<(String, ReadingSession)>>> = Lazy::new(|| Mutex::new(None));
+
+/// a single thread, so that the saves of a session are written in order
+static SAVER: Lazy<Mutex<ThreadPool>> = Lazy::new(|| Mutex::new(ThreadPool::new(1)));
+
+/// the learned reading speed, loaded once and then kept in sync with the saved one
+static SECONDS_PER_PAGE: Lazy<Mutex<Option<f64>>> = Lazy::new(|| {
+    let speed = storage::load::<ReadingStatsDocument>(&Document::ReadingStats)
+        .map(|stats| stats.seconds_per_page)
+        .unwrap_or_else(|e| {
+            println!("ERROR: failed to load the reading speed: {}", e);
+            None
+        });
+    Mutex::new(speed)
+});
+
+/// Records a page turned in the book, that showed the given number of new pages (2 in dual view),
+/// `chapter_finished` if the page is the first of the next chapter
+pub fn record_page_turn(book_path: &str, title: &str, pages: usize, chapter_finished: bool) {
+    let now = unix_time(SystemTime::now());
+    // before locking the storage, see `identity`
+    let id = identity::book_id(book_path);
+
+    let (ended, finished, turn_time) = {
+        let mut active = ACTIVE.lock().unwrap();
+        // the time spent on the pages, unless the user went idle on them
+        let turn_time = match &*active {
+            Some((path, session)) if path == book_path => {
+                Some(now.saturating_sub(session.end)).filter(|time| *time <= MAX_PAUSE)
+            }
+            _ => None,
+        };
+        let continues = turn_time.is_some();
+        // the session replaced by a new one is over
+        let ended = if continues {
+            None
+        } else {
+            let started = ReadingSession {
+                book: id,
+                title: title.to_string(),
+                start: now,
+                end: now,
+                ..ReadingSession::default()
+            };
+            active
+                .replace((book_path.to_string(), started))
+                .map(|(_, session)| session)
+        };
+        let Some((_, session)) = active.as_mut() else {
+            return;
+        };
+        session.end = now;
+        session.pages += pages;
+        if chapter_finished {
+            session.chapters_finished += 1;
+        }
+        let finished = chapter_finished.then(|| session.clone());
+        (ended, finished, turn_time)
+    };
+
+    let speed = {
+        let mut speed = SECONDS_PER_PAGE.lock().unwrap();
+        if let Some(turn_time) = turn_time {
+            *speed = learn_speed(*speed, turn_time, pages);
+        }
+        *speed
+    };
+
+    for session in ended.into_iter().chain(finished) {
+        save_in_background(session, speed);
+    }
+}
+
+/// Ends the session in progress and saves it, the book has been closed
+pub fn end_session() {
+    let ended = ACTIVE.lock().unwrap().take();
+    if let Some((_, session)) = ended {
+        save_in_background(session, *SECONDS_PER_PAGE.lock().unwrap());
+    }
+}
+
+/// Ends the session in progress and waits for the saves, the application is closing
+pub fn save_on_exit() {
+    end_session();
+    SAVER.lock().unwrap().join();
+}
+
+fn save_in_background(session: ReadingSession, seconds_per_page: Option<f64>) {
+    SAVER.lock().unwrap().execute(move || {
+        if let Err(e) = save_session(session, seconds_per_page) {
+            println!("ERROR: failed to save the reading session: {}", e);
+        }
+    });
+}
+
+fn save_session(
+    session: ReadingSession,
+    seconds_per_page: Option<f64>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    storage::modify(
+        &Document::ReadingStats,
+        |stats: &mut ReadingStatsDocument| {
+            stats.seconds_per_page = seconds_per_page;
+            let saved = stats
+                .sessions
+                .iter_mut()
+                .rev()
+                .find(|saved| saved.book == session.book && saved.start == session.start);
+            match saved {
+                Some(saved) => *saved = session,
+                None => stats.sessions.push(session),
+            }
+        },
+    )
+}
+
+/// Updates the reading speed with the seconds spent on the pages of a turn,
+/// as a moving average so that it follows the user's changes of pace
+fn learn_speed(seconds_per_page: Option<f64>, turn_time: u64, pages: usize) -> Option<f64> {
+    let pages = pages.max(1) as u64;
+    if turn_time < MIN_PAGE_TIME * pages {
+        return seconds_per_page;
+    }
+    let page_time = turn_time as f64 / pages as f64;
+    Some(seconds_per_page.map_or(page_time, |speed| {
+        speed + (page_time - speed) * SPEED_WEIGHT
+    }))
+}
+
+/// Returns the estimated seconds to read the given number of pages
+pub fn time_to_read(pages: usize) -> u64 {
+    estimate(pages, *SECONDS_PER_PAGE.lock().unwrap())
+}
+
+fn estimate(pages: usize, seconds_per_page: Option<f64>) -> u64 {
+    (pages as f64 * seconds_per_page.unwrap_or(DEFAULT_SECONDS_PER_PAGE)).round() as u64
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReadingSummary {
+    /// title and seconds of reading of every book, the most read first
+    pub time_per_book: Vec<(String, u64)>,
+    /// pages read in each day (days from the epoch), the last day first
+    pub pages_per_day: Vec<(u64, usize)>,
+    /// days in a row with some reading, up to today
+    pub current_streak: usize,
+    pub longest_streak: usize,
+    pub total_time: u64,
+    pub total_pages: usize,
+    pub total_chapters: usize,
+}
+
+impl ReadingSummary {
+    pub fn pages_per_hour(&self) -> Option<f64> {
+        (self.total_time > 0).then(|| self.total_pages as f64 * 3600.0 / self.total_time as f64)
+    }
+}
+
+/// Returns the statistics of all the saved sessions
+pub fn load_summary() -> ReadingSummary {
+    // the session just ended may still be being saved
+    SAVER.lock().unwrap().join();
+    let stats =
+        storage::load::<ReadingStatsDocument>(&Document::ReadingStats).unwrap_or_else(|e| {
+            println!("ERROR: failed to load the reading sessions: {}", e);
+            ReadingStatsDocument::default()
+        });
+    summarize(&stats.sessions, unix_time(SystemTime::now()) / DAY)
+}
+
+/// Calculates the statistics of the sessions, `today` is in days from the epoch.
+/// The days are the ones of UTC
+fn summarize(sessions: &[ReadingSession], today: u64) -> ReadingSummary {
+    let mut books: HashMap<&str, (String, u64)> = HashMap::new();
+    let mut days: BTreeMap<u64, usize> = BTreeMap::new();
+    let mut summary = ReadingSummary::default();
+
+    for session in sessions {
+        let time = session.end.saturating_sub(session.start);
+        let book = books.entry(session.book.as_str()).or_default();
+        // the last title of the book
+        book.0 = session.title.clone();
+        book.1 += time;
+        *days.entry(session.start / DAY).or_default() += session.pages;
+        summary.total_time += time;
+        summary.total_pages += session.pages;
+        summary.total_chapters += session.chapters_finished;
+    }
+
+    let mut streak = 0;
+    let mut last_day = None;
+    for day in days.keys().copied() {
+        streak = if last_day == Some(day.wrapping_sub(1)) {
+            streak + 1
+        } else {
+            1
+        };
+        summary.longest_streak = summary.longest_streak.max(streak);
+        last_day = Some(day);
+    }
+    // today may have no reading yet, the streak isn't broken until tomorrow
+    if matches!(last_day, Some(day) if day + 1 >= today) {
+        summary.current_streak = streak;
+    }
+
+    summary.time_per_book = books.into_values().collect();
+    summary
+        .time_per_book
+        .sort_by(|one, other| other.1.cmp(&one.1).then_with(|| one.0.cmp(&other.0)));
+    summary.pages_per_day = days.into_iter().rev().collect();
+    summary
+}
+
+/// Formats a number of days from the epoch as dd/mm/yyyy
+pub fn format_day(day: u64) -> String {
+    // from the algorithms of Howard Hinnant for the civil calendar
+    let z = day as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    format!("{:02}/{:02}/{}", d, m, y)
+}
+
+/// Formats a number of seconds as hours and minutes
+pub fn format_duration(seconds: u64) -> String {
+    let minutes = seconds / 60;
+    if minutes < 60 {
+        format!("{} min", minutes)
+    } else {
+        format!("{} h {:02} min", minutes / 60, minutes % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(book: &str, day: u64, minutes: u64, pages: usize) -> ReadingSession {
+        let start = day * DAY + 3600;
+        ReadingSession {
+            book: book.to_string(),
+            title: book.to_uppercase(),
+            start,
+            end: start + minutes * 60,
+            pages,
+            chapters_finished: 0,
+        }
+    }
+
+    #[test]
+    fn sessions_are_summarized() {
+        let sessions = [
+            session("a", 10, 30, 20),
+            session("b", 11, 60, 40),
+            session("a", 11, 30, 30),
+            session("a", 12, 0, 1),
+            // a day without reading
+            session("b", 14, 30, 25),
+            session("b", 15, 30, 10),
+        ];
+        let summary = summarize(&sessions, 16);
+
+        assert_eq!(
+            summary.time_per_book,
+            vec![("B".to_string(), 120 * 60), ("A".to_string(), 60 * 60)]
+        );
+        assert_eq!(summary.pages_per_day[0], (15, 10));
+        assert_eq!(summary.pages_per_day[3], (11, 70));
+        assert_eq!(summary.longest_streak, 3);
+        assert_eq!(summary.current_streak, 2);
+        assert_eq!(summary.pages_per_hour(), Some(126.0 / 3.0));
+
+        // the streak is broken
+        assert_eq!(summarize(&sessions, 17).current_streak, 0);
+    }
+
+    #[test]
+    fn speed_is_learned_from_read_pages() {
+        assert_eq!(learn_speed(None, 60, 1), Some(60.0));
+        // skipped page
+        assert_eq!(learn_speed(Some(60.0), 1, 1), Some(60.0));
+        assert_eq!(learn_speed(Some(60.0), 80, 1), Some(61.0));
+        // two pages in dual view
+        assert_eq!(learn_speed(None, 120, 2), Some(60.0));
+        assert_eq!(learn_speed(Some(60.0), 5, 2), Some(60.0));
+    }
+
+    #[test]
+    fn estimates_dont_depend_on_the_view() {
+        assert_eq!(estimate(10, None), 900);
+
+        // a minute per page, turning one page or the two of the dual view
+        let turns = [(60, 1), (120, 2), (120, 2), (60, 1), (120, 2)];
+        let speed = turns.iter().fold(None, |speed, (time, pages)| {
+            learn_speed(speed, *time, *pages)
+        });
+        assert_eq!(speed, Some(60.0));
+        assert_eq!(estimate(10, speed), 600);
+    }
+
+    #[test]
+    fn days_are_formatted() {
+        assert_eq!(format_day(0), "01/01/1970");
+        assert_eq!(format_day(19723), "01/01/2024");
+        assert_eq!(format_day(19782), "29/02/2024");
+        assert_eq!(format_duration(59), "0 min");
+        assert_eq!(format_duration(3900), "1 h 05 min");
+    }
+}
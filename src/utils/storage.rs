@@ -0,0 +1,478 @@
+use std::{
+    error,
+    fs::{File, OpenOptions},
+    io::{self, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+};
+
+use once_cell::sync::Lazy;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::{
+    dir_manager::{
+        get_books_bookmarks_path, get_books_highlights_path, get_books_notes_path, get_books_path,
+        get_env_path, get_kosync_path, get_metadata_path, get_opds_catalogs_path,
+        get_reading_stats_path, get_saved_books_dir, get_savedata_path, get_watched_folders_path,
+    },
+    migrations,
+};
+
+/// Version of the format of the stored documents, written in every document.
+/// The documents written by an older version are upgraded by `init`
+pub const SCHEMA_VERSION: u64 = 3;
+
+/// Key of the version in the json object of every document
+const VERSION_KEY: &str = "version";
+
+/// Every read-modify-write of a document holds this lock,
+/// so that the threads of the loaders don't overwrite each other's changes.
+/// The path of the document is found before taking it: the path of the metadata
+/// needs the id of the book, that may be read from the storage
+static LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+/// A JSON document of the storage
+#[derive(Clone, Debug, PartialEq)]
+pub enum Document {
+    /// reading position and edited chapters of every book
+    SaveData,
+    Notes,
+    Bookmarks,
+    Highlights,
+    /// settings of the application
+    Env,
+    /// folders watched for new books, and the books imported from them
+    WatchedFolders,
+    /// id of every book file
+    Books,
+    /// reading sessions
+    ReadingStats,
+    /// account of the progress sync server
+    Kosync,
+    /// OPDS catalogs
+    OpdsCatalogs,
+    /// metadata of the book with the given path
+    Metadata(String),
+    /// a document in the given file, for the tests
+    #[cfg(test)]
+    Test(PathBuf),
+}
+
+impl Document {
+    pub fn path(&self) -> PathBuf {
+        match self {
+            Document::SaveData => get_savedata_path(),
+            Document::Notes => get_books_notes_path(),
+            Document::Bookmarks => get_books_bookmarks_path(),
+            Document::Highlights => get_books_highlights_path(),
+            Document::Env => get_env_path(),
+            Document::WatchedFolders => get_watched_folders_path(),
+            Document::Books => get_books_path(),
+            Document::ReadingStats => get_reading_stats_path(),
+            Document::Kosync => get_kosync_path(),
+            Document::OpdsCatalogs => get_opds_catalogs_path(),
+            Document::Metadata(book_path) => get_metadata_path(book_path),
+            #[cfg(test)]
+            Document::Test(path) => path.clone(),
+        }
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path().exists()
+    }
+
+    /// Returns the documents of the settings and the metadata of all the saved books, with their paths
+    fn all() -> Vec<(Document, PathBuf)> {
+        let mut documents: Vec<(Document, PathBuf)> = [
+            Document::SaveData,
+            Document::Notes,
+            Document::Bookmarks,
+            Document::Highlights,
+            Document::Env,
+            Document::WatchedFolders,
+            Document::Books,
+            Document::ReadingStats,
+            Document::Kosync,
+            Document::OpdsCatalogs,
+        ]
+        .into_iter()
+        .map(|document| {
+            let path = document.path();
+            (document, path)
+        })
+        .collect();
+        let Ok(entries) = std::fs::read_dir(get_saved_books_dir()) else {
+            return documents;
+        };
+        for entry in entries.flatten().filter(|entry| entry.path().is_dir()) {
+            // the book of the folder isn't needed to upgrade its metadata
+            let name = entry.file_name().to_string_lossy().to_string();
+            documents.push((Document::Metadata(name), entry.path().join("metadata.json")));
+        }
+        documents
+    }
+}
+
+/// A document as it is on disk: the version and the content
+#[derive(Deserialize)]
+struct Versioned<T> {
+    #[serde(rename = "version", default)]
+    _version: u64,
+    #[serde(flatten)]
+    content: T,
+}
+
+/// Only the version of a document, 0 if it was written before the versions
+#[derive(Deserialize)]
+struct Header {
+    #[serde(default)]
+    version: u64,
+}
+
+fn lock() -> MutexGuard<'static, ()> {
+    // a thread that panicked while holding the lock didn't write anything:
+    // the documents on disk are still whole
+    LOCK.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Reads the document, changes it with `f` and writes it back atomically.
+/// A document that can't be parsed is kept aside and replaced by an empty one
+pub fn update<R>(
+    document: &Document,
+    f: impl FnOnce(&mut Value) -> R,
+) -> Result<R, Box<dyn error::Error>> {
+    let path = document.path();
+    let _guard = lock();
+    update_file(document, &path, f)
+}
+
+/// Deletes the document, reading it again gives an empty object
+pub fn remove(document: &Document) -> Result<(), Box<dyn error::Error>> {
+    let path = document.path();
+    let _guard = lock();
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Reads the document as the given type, the default value if it doesn't exist yet.
+/// The error tells the document and where it doesn't match the type
+pub fn load<T: DeserializeOwned + Default>(document: &Document) -> Result<T, Box<dyn error::Error>> {
+    let path = document.path();
+    let _guard = lock();
+    load_file(document, &path)
+}
+
+/// Replaces the whole document with the given value
+pub fn save<T: Serialize>(document: &Document, value: &T) -> Result<(), Box<dyn error::Error>> {
+    let path = document.path();
+    let _guard = lock();
+    write_file(&path, serde_json::to_value(value)?)
+}
+
+/// Reads the document as the given type, changes it with `f` and writes it back atomically.
+/// A document that is valid json but doesn't match the type is not overwritten
+pub fn modify<T: Serialize + DeserializeOwned + Default, R>(
+    document: &Document,
+    f: impl FnOnce(&mut T) -> R,
+) -> Result<R, Box<dyn error::Error>> {
+    let path = document.path();
+    let _guard = lock();
+    let mut value: T = match parse_file(document, &path) {
+        Ok(value) => value,
+        // not even json: nothing can be kept
+        Err(e) if e.is_syntax() || e.is_eof() => {
+            set_aside(&path, &e)?;
+            T::default()
+        }
+        Err(e) => return Err(invalid_document(&path, e)),
+    };
+    let result = f(&mut value);
+    write_file(&path, serde_json::to_value(&value)?)?;
+    Ok(result)
+}
+
+/// Prepares the storage, to be called before anything is read:
+/// the documents written by an older version are upgraded, keeping a copy of them
+pub fn init() -> Result<(), Box<dyn error::Error>> {
+    let _guard = lock();
+    // before the documents, so that the metadata is found in the new folders
+    migrations::move_book_folders();
+    for (document, path) in Document::all() {
+        if !path.exists() {
+            continue;
+        }
+        if let Err(e) = upgrade_file(&document, &path) {
+            println!("ERROR: failed to upgrade {:?}: {}", path, e);
+        }
+    }
+    Ok(())
+}
+
+fn upgrade_file(document: &Document, path: &Path) -> Result<(), Box<dyn error::Error>> {
+    let mut json = read_file(path)?;
+    let version = version_of(&json);
+    if version > SCHEMA_VERSION {
+        println!(
+            "ERROR: {:?} was written by a newer version ({} > {})",
+            path, version, SCHEMA_VERSION
+        );
+        return Ok(());
+    }
+    if version == SCHEMA_VERSION {
+        return Ok(());
+    }
+
+    // the copy of the first upgrade is the one to keep
+    let backup = path.with_extension(format!("v{}.json", version));
+    if !backup.exists() {
+        std::fs::copy(path, &backup)?;
+    }
+    migrations::upgrade(document, version, &mut json);
+    write_file(path, json)?;
+    println!(
+        "DEBUG: {:?} upgraded from version {} to {}, the old one is in {:?}",
+        path, version, SCHEMA_VERSION, backup
+    );
+    Ok(())
+}
+
+fn version_of(json: &Value) -> u64 {
+    json.get(VERSION_KEY).and_then(Value::as_u64).unwrap_or(0)
+}
+
+/// Reads the json of the document as written by the current version:
+/// an older document is upgraded in memory, the version is removed
+fn read_current(document: &Document, path: &Path) -> Result<Value, Box<dyn error::Error>> {
+    let mut json = read_file(path)?;
+    let version = version_of(&json);
+    if version < SCHEMA_VERSION {
+        migrations::upgrade(document, version, &mut json);
+    }
+    if let Some(object) = json.as_object_mut() {
+        object.remove(VERSION_KEY);
+    }
+    Ok(json)
+}
+
+fn read_file(path: &Path) -> Result<Value, Box<dyn error::Error>> {
+    let Ok(file) = File::open(path) else {
+        return Ok(json!({}));
+    };
+    let reader = BufReader::new(file);
+    Ok(serde_json::from_reader(reader)?)
+}
+
+fn parse_file<T: DeserializeOwned + Default>(
+    document: &Document,
+    path: &Path,
+) -> Result<T, serde_json::Error> {
+    let Ok(text) = std::fs::read_to_string(path) else {
+        return Ok(T::default());
+    };
+    let header: Header = serde_json::from_str(&text)?;
+    if header.version < SCHEMA_VERSION {
+        let mut json: Value = serde_json::from_str(&text)?;
+        migrations::upgrade(document, header.version, &mut json);
+        if let Some(object) = json.as_object_mut() {
+            object.remove(VERSION_KEY);
+        }
+        return serde_json::from_value(json);
+    }
+    // parsed from the text, so that errors tell the line
+    Ok(serde_json::from_str::<Versioned<T>>(&text)?.content)
+}
+
+fn load_file<T: DeserializeOwned + Default>(
+    document: &Document,
+    path: &Path,
+) -> Result<T, Box<dyn error::Error>> {
+    parse_file(document, path).map_err(|e| invalid_document(path, e))
+}
+
+fn invalid_document(path: &Path, e: serde_json::Error) -> Box<dyn error::Error> {
+    let message = format!("{} is not valid: {}", path.display(), e);
+    println!("ERROR: {}", message);
+    message.into()
+}
+
+/// Moves a corrupted document next to the original, so it can be recovered by hand
+fn set_aside(path: &Path, e: &dyn error::Error) -> io::Result<()> {
+    let backup = path.with_extension("corrupted.json");
+    println!("ERROR: {:?} is corrupted ({}), moved to {:?}", path, e, backup);
+    std::fs::rename(path, backup)
+}
+
+fn update_file<R>(
+    document: &Document,
+    path: &Path,
+    f: impl FnOnce(&mut Value) -> R,
+) -> Result<R, Box<dyn error::Error>> {
+    let mut json = match read_current(document, path) {
+        Ok(json) => json,
+        Err(e) => {
+            set_aside(path, e.as_ref())?;
+            json!({})
+        }
+    };
+    let result = f(&mut json);
+    write_file(path, json)?;
+    Ok(result)
+}
+
+/// Writes the json of a document with the current version
+fn write_file(path: &Path, mut json: Value) -> Result<(), Box<dyn error::Error>> {
+    if let Some(object) = json.as_object_mut() {
+        object.insert(VERSION_KEY.to_string(), json!(SCHEMA_VERSION));
+    }
+    let bytes = serde_json::to_vec_pretty(&json)?;
+    write_atomic(path, &bytes)?;
+    Ok(())
+}
+
+/// Writes the bytes in a temporary file next to the destination and renames it:
+/// after a crash the file is either the old one or the new one, never a part of it
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&tmp_path)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join("crab-reader-storage-tests");
+        let _ = std::fs::create_dir_all(&dir);
+        let path = dir.join(name);
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn update_creates_and_changes_the_document() {
+        let path = test_path("update.json");
+
+        update_file(&Document::SaveData, &path, |json| json["book"] = json!({"chapter": 1})).unwrap();
+        let n = update_file(&Document::SaveData, &path, |json| {
+            json["book"]["offset"] = json!(10);
+            json.as_object().unwrap().len()
+        })
+        .unwrap();
+
+        assert_eq!(n, 1);
+        assert_eq!(
+            read_file(&path).unwrap(),
+            json!({"book": {"chapter": 1, "offset": 10}, "version": SCHEMA_VERSION})
+        );
+        assert!(!path.with_file_name("update.json.tmp").exists());
+    }
+
+    #[test]
+    fn corrupted_document_is_kept_aside() {
+        let path = test_path("corrupted.json");
+        let backup = path.with_extension("corrupted.json");
+        let _ = std::fs::remove_file(&backup);
+        std::fs::write(&path, "{\"book\": {\"chap").unwrap();
+
+        assert!(read_file(&path).is_err());
+        update_file(&Document::SaveData, &path, |json| json["other"] = json!(true)).unwrap();
+
+        assert_eq!(
+            read_file(&path).unwrap(),
+            json!({"other": true, "version": SCHEMA_VERSION})
+        );
+        assert_eq!(std::fs::read_to_string(&backup).unwrap(), "{\"book\": {\"chap");
+    }
+
+    #[test]
+    fn load_reports_the_invalid_field() {
+        use crate::models::documents::SaveDataDocument;
+
+        let path = test_path("typed.json");
+        let document = Document::SaveData;
+        let invalid = json!({"book": {"chapter": 2}, "other": {"offset": "ten"}, "version": SCHEMA_VERSION});
+        std::fs::write(&path, invalid.to_string()).unwrap();
+
+        let error = load_file::<SaveDataDocument>(&document, &path).unwrap_err().to_string();
+        assert!(error.contains("typed.json"));
+        assert!(error.contains("line 1"));
+
+        std::fs::write(&path, r#"{"book": {"chapter": 2}}"#).unwrap();
+        let saved: SaveDataDocument = load_file(&document, &path).unwrap();
+        assert_eq!(saved["book"].chapter, Some(2));
+        assert_eq!(saved["book"].offset, None);
+        assert!(saved["book"].edited_chapters.is_empty());
+    }
+
+    #[test]
+    fn concurrent_updates_are_not_lost() {
+        use std::collections::BTreeMap;
+
+        let document = Document::Test(test_path("concurrent.json"));
+
+        // every thread increments the counter many times, half of them through `modify`
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                let document = document.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..20 {
+                        if i % 2 == 0 {
+                            update(&document, |json| {
+                                json["count"] = json!(json["count"].as_u64().unwrap_or(0) + 1)
+                            })
+                            .unwrap();
+                        } else {
+                            modify(&document, |counts: &mut BTreeMap<String, u64>| {
+                                *counts.entry("count".to_string()).or_default() += 1
+                            })
+                            .unwrap();
+                        }
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let json = read_file(&document.path()).unwrap();
+        assert_eq!(json, json!({"count": 160, "version": SCHEMA_VERSION}));
+    }
+
+    #[test]
+    fn old_document_is_upgraded_keeping_a_copy() {
+        let path = test_path("metadata.json");
+        let backup = path.with_extension("v0.json");
+        let _ = std::fs::remove_file(&backup);
+        let old = r#"{"chapters": "3", "pages_per_chapter_serif_16_800x600": "[(0-1), (2-2), (3-5)]"}"#;
+        std::fs::write(&path, old).unwrap();
+        let document = Document::Metadata("metadata.epub".to_string());
+
+        upgrade_file(&document, &path).unwrap();
+
+        assert_eq!(std::fs::read_to_string(&backup).unwrap(), old);
+        let json = read_file(&path).unwrap();
+        assert_eq!(version_of(&json), SCHEMA_VERSION);
+        assert_eq!(json["chapters"], json!(3));
+        assert_eq!(
+            json["pages_per_chapter"]["serif_16_800x600"],
+            json!([[0, 1], [2, 2], [3, 5]])
+        );
+    }
+}
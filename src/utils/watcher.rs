@@ -0,0 +1,365 @@
+//! Folders watched for new books.
+//! A thread scans the folders every few seconds and tells the application which
+//! epubs were added, changed or removed; the changes are then applied to the library
+//! by `apply_changes`, copying the new books in the epubs folder as the ones added by hand.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use druid::{ExtEventSink, Selector, Target};
+
+use crate::{
+    models::{
+        book::Book,
+        documents::{ImportedBook, WatchedFoldersDocument},
+        library::Library,
+    },
+    traits::gui::GUILibrary,
+    utils::{
+        dir_manager::get_epub_dir,
+        duplicates, saveload,
+        storage::{self, Document},
+    },
+};
+
+/// the epubs of the watched folders changed since the last scan
+pub const WATCHED_FOLDERS_CHANGED: Selector<Vec<FolderChange>> =
+    Selector::new("crabreader.watched_folders_changed");
+
+const SCAN_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A change of an epub of a watched folder, with the path of the file in the folder
+#[derive(Clone, Debug, PartialEq)]
+pub enum FolderChange {
+    Added(String),
+    Changed(String),
+    Removed(String),
+}
+
+/// Last change and length of a file: the file is ready to be imported
+/// when they are the same in two scans, so that a copy in progress is not imported
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct FileStamp {
+    modified: u64,
+    len: u64,
+}
+
+impl FileStamp {
+    fn of(path: &Path) -> Option<Self> {
+        let metadata = std::fs::metadata(path).ok()?;
+        let modified = metadata
+            .modified()
+            .ok()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        Some(Self {
+            modified,
+            len: metadata.len(),
+        })
+    }
+
+    fn matches(&self, imported: &ImportedBook) -> bool {
+        self.modified == imported.modified && self.len == imported.len
+    }
+}
+
+/// The epubs found in the watched folders
+#[derive(Default)]
+struct Scan {
+    files: HashMap<String, FileStamp>,
+    /// watched folders that can't be read now, e.g. on a disk that isn't mounted:
+    /// their books are not removed from the library
+    unavailable: HashSet<String>,
+}
+
+/// Returns the watched folders
+pub fn folders() -> Vec<String> {
+    storage::load::<WatchedFoldersDocument>(&Document::WatchedFolders)
+        .map(|document| document.folders)
+        .unwrap_or_default()
+}
+
+/// Starts watching a folder, its epubs are imported at the next scans
+pub fn watch_folder(folder: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let folder = folder.to_str().ok_or("invalid folder name")?.to_string();
+    storage::modify(&Document::WatchedFolders, |document: &mut WatchedFoldersDocument| {
+        if !document.folders.contains(&folder) {
+            document.folders.push(folder);
+        }
+    })
+}
+
+/// Stops watching a folder, the books imported from it stay in the library
+pub fn unwatch_folder(folder: &str) -> Result<(), Box<dyn std::error::Error>> {
+    storage::modify(&Document::WatchedFolders, |document: &mut WatchedFoldersDocument| {
+        document.folders.retain(|f| f != folder);
+        document
+            .imported
+            .retain(|source, _| !Path::new(source).starts_with(folder));
+    })
+}
+
+/// Starts the thread that scans the watched folders,
+/// the changes are sent to the application with `WATCHED_FOLDERS_CHANGED`
+pub fn start(sink: ExtEventSink) {
+    std::thread::spawn(move || {
+        let mut last_scan = Scan::default();
+        loop {
+            let document = storage::load::<WatchedFoldersDocument>(&Document::WatchedFolders)
+                .unwrap_or_default();
+            let scan = scan_folders(&document.folders);
+            let changes = find_changes(&document, &last_scan, &scan);
+            last_scan = scan;
+
+            if !changes.is_empty() {
+                println!("DEBUG: watched folders changed: {:?}", changes);
+                // the application has been closed
+                if sink
+                    .submit_command(WATCHED_FOLDERS_CHANGED, changes, Target::Auto)
+                    .is_err()
+                {
+                    break;
+                }
+            }
+            std::thread::sleep(SCAN_INTERVAL);
+        }
+    });
+}
+
+fn scan_folders(folders: &[String]) -> Scan {
+    let mut scan = Scan::default();
+    for folder in folders {
+        if !scan_dir(Path::new(folder), &mut scan.files) {
+            scan.unavailable.insert(folder.clone());
+        }
+    }
+    scan
+}
+
+/// Adds the epubs of the folder and of its subfolders, false if it can't be read
+fn scan_dir(dir: &Path, files: &mut HashMap<String, FileStamp>) -> bool {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return false;
+    };
+    for path in entries.flatten().map(|entry| entry.path()) {
+        if path.is_dir() {
+            scan_dir(&path, files);
+        } else if path.extension().unwrap_or_default() == "epub" {
+            let (Some(name), Some(stamp)) = (path.to_str(), FileStamp::of(&path)) else {
+                continue;
+            };
+            files.insert(name.to_string(), stamp);
+        }
+    }
+    true
+}
+
+fn find_changes(document: &WatchedFoldersDocument, last_scan: &Scan, scan: &Scan) -> Vec<FolderChange> {
+    let mut changes = vec![];
+    for (source, stamp) in &scan.files {
+        // still being written
+        if last_scan.files.get(source) != Some(stamp) {
+            continue;
+        }
+        match document.imported.get(source) {
+            None => changes.push(FolderChange::Added(source.clone())),
+            Some(imported) if !stamp.matches(imported) => {
+                changes.push(FolderChange::Changed(source.clone()))
+            }
+            Some(_) => {}
+        }
+    }
+
+    for source in document.imported.keys() {
+        let folder = document
+            .folders
+            .iter()
+            .find(|folder| Path::new(source).starts_with(folder));
+        let Some(folder) = folder else {
+            continue;
+        };
+        if !scan.files.contains_key(source) && !scan.unavailable.contains(folder) {
+            changes.push(FolderChange::Removed(source.clone()));
+        }
+    }
+    changes
+}
+
+/// Applies the changes of the watched folders to the library.
+/// The open book is left as it is, its changes are applied after it is closed
+pub fn apply_changes(changes: &[FolderChange], library: &mut Library<Book>, open_book: Option<&str>) {
+    for change in changes {
+        let result = match change {
+            FolderChange::Added(source) => import_book(source, library),
+            FolderChange::Changed(source) => update_book(source, library, open_book),
+            FolderChange::Removed(source) => remove_book(source, library, open_book),
+        };
+        if let Err(e) = result {
+            println!("ERROR: failed to apply {:?}: {}", change, e);
+        }
+    }
+}
+
+fn imported_book(source: &str) -> Option<ImportedBook> {
+    storage::load::<WatchedFoldersDocument>(&Document::WatchedFolders)
+        .ok()?
+        .imported
+        .remove(source)
+}
+
+fn set_imported(source: &str, imported: Option<ImportedBook>) -> Result<(), Box<dyn std::error::Error>> {
+    storage::modify(&Document::WatchedFolders, |document: &mut WatchedFoldersDocument| {
+        match imported {
+            Some(imported) => document.imported.insert(source.to_string(), imported),
+            None => document.imported.remove(source),
+        };
+    })
+}
+
+fn import_book(source: &str, library: &mut Library<Book>) -> Result<(), Box<dyn std::error::Error>> {
+    // a scan may send the same book again before the first change has been applied
+    if imported_book(source).and_then(|imported| imported.book).is_some() {
+        return Ok(());
+    }
+    let stamp = FileStamp::of(Path::new(source)).ok_or("file not found")?;
+    let file_name = Path::new(source).file_name().ok_or("invalid file name")?;
+    let target = get_epub_dir().join(file_name);
+
+    let book = if target.exists() {
+        println!(
+            "ERROR: a book named {:?} is already in the library, {} is not imported",
+            file_name, source
+        );
+        None
+    } else if let Some(existing) = duplicates::find_duplicate(source, library) {
+        // nobody is asked what to do with a copy found in a folder: it is skipped
+        println!("DEBUG: {} is a copy of {}, it is not imported", source, existing);
+        None
+    } else {
+        std::fs::copy(source, &target)?;
+        let book = target.to_str().ok_or("invalid file name")?.to_string();
+        library.schedule_book_loading(&book);
+        Some(book)
+    };
+
+    set_imported(
+        source,
+        Some(ImportedBook {
+            book,
+            modified: stamp.modified,
+            len: stamp.len,
+        }),
+    )
+}
+
+fn update_book(
+    source: &str,
+    library: &mut Library<Book>,
+    open_book: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(book) = imported_book(source).and_then(|imported| imported.book) else {
+        // not imported before because of the name, it can be now
+        return import_book(source, library);
+    };
+    if open_book == Some(book.as_str()) {
+        return Ok(());
+    }
+    let stamp = FileStamp::of(Path::new(source)).ok_or("file not found")?;
+
+    // the chapters and the index of the old file are made again from the new one
+    duplicates::replace_book(source, &book, library)?;
+
+    set_imported(
+        source,
+        Some(ImportedBook {
+            book: Some(book),
+            modified: stamp.modified,
+            len: stamp.len,
+        }),
+    )
+}
+
+fn remove_book(
+    source: &str,
+    library: &mut Library<Book>,
+    open_book: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(book) = imported_book(source).and_then(|imported| imported.book) {
+        if open_book == Some(book.as_str()) {
+            return Ok(());
+        }
+        library.remove_book_with_path(&book);
+        saveload::delete_book(&book)?;
+    }
+    set_imported(source, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stamp(len: u64) -> FileStamp {
+        FileStamp {
+            modified: 1_700_000_000,
+            len,
+        }
+    }
+
+    fn imported(stamp: FileStamp) -> ImportedBook {
+        ImportedBook {
+            book: Some("/library/book.epub".to_string()),
+            modified: stamp.modified,
+            len: stamp.len,
+        }
+    }
+
+    #[test]
+    fn files_are_imported_when_they_stop_changing() {
+        let document = WatchedFoldersDocument {
+            folders: vec!["/books".to_string()],
+            ..Default::default()
+        };
+        let mut first = Scan::default();
+        first.files.insert("/books/new.epub".to_string(), stamp(100));
+        let mut second = Scan::default();
+        second.files.insert("/books/new.epub".to_string(), stamp(200));
+
+        // still being copied
+        assert!(find_changes(&document, &first, &second).is_empty());
+        assert_eq!(
+            find_changes(&document, &second, &second),
+            vec![FolderChange::Added("/books/new.epub".to_string())]
+        );
+    }
+
+    #[test]
+    fn changed_and_removed_files_are_found() {
+        let old = stamp(100);
+        let mut document = WatchedFoldersDocument {
+            folders: vec!["/books".to_string(), "/usb".to_string()],
+            ..Default::default()
+        };
+        document.imported.insert("/books/changed.epub".to_string(), imported(old));
+        document.imported.insert("/books/same.epub".to_string(), imported(old));
+        document.imported.insert("/books/removed.epub".to_string(), imported(old));
+        document.imported.insert("/usb/unmounted.epub".to_string(), imported(old));
+
+        let mut scan = Scan::default();
+        scan.files.insert("/books/changed.epub".to_string(), stamp(300));
+        scan.files.insert("/books/same.epub".to_string(), old);
+        scan.unavailable.insert("/usb".to_string());
+
+        let mut changes = find_changes(&document, &scan, &scan);
+        changes.sort_by_key(|change| format!("{:?}", change));
+        assert_eq!(
+            changes,
+            vec![
+                FolderChange::Changed("/books/changed.epub".to_string()),
+                FolderChange::Removed("/books/removed.epub".to_string()),
+            ]
+        );
+    }
+}
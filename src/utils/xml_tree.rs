@@ -0,0 +1,207 @@
+use std::path::{Component, Path, PathBuf};
+
+use xml::reader::{EventReader, XmlEvent};
+
+/// Minimal owned representation of an XML element.
+/// Names are stored without namespace prefix (e.g. `dc:title` -> `title`),
+/// the prefix is kept apart for the few cases in which it matters.
+#[derive(Debug, Clone, Default)]
+pub struct XmlNode {
+    pub name: String,
+    pub prefix: Option<String>,
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<XmlNode>,
+    pub text: String,
+}
+
+impl XmlNode {
+    /// Returns the value of the attribute with the given local name
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the direct children with the given local name
+    pub fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a XmlNode> {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+
+    /// Returns the first descendant (depth first) with the given local name
+    pub fn find(&self, name: &str) -> Option<&XmlNode> {
+        for child in self.children.iter() {
+            if child.name == name {
+                return Some(child);
+            }
+            if let Some(found) = child.find(name) {
+                return Some(found);
+            }
+        }
+        None
+    }
+
+    /// Returns all the descendants with the given local name, in document order
+    pub fn find_all<'a>(&'a self, name: &str) -> Vec<&'a XmlNode> {
+        let mut found = vec![];
+        for child in self.children.iter() {
+            if child.name == name {
+                found.push(child);
+            }
+            found.extend(child.find_all(name));
+        }
+        found
+    }
+
+    /// Returns the text of the node and of all its descendants
+    pub fn all_text(&self) -> String {
+        let mut text = self.text.clone();
+        for child in self.children.iter() {
+            text.push_str(&child.all_text());
+        }
+        text
+    }
+}
+
+/// Parses an XML document and returns its root element
+pub fn parse(content: &str) -> Result<XmlNode, String> {
+    let reader = EventReader::new(content.as_bytes());
+    let mut stack: Vec<XmlNode> = vec![];
+
+    for event in reader {
+        match event.map_err(|e| e.to_string())? {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => {
+                stack.push(XmlNode {
+                    name: name.local_name,
+                    prefix: name.prefix,
+                    attributes: attributes
+                        .into_iter()
+                        .map(|attr| (attr.name.local_name, attr.value))
+                        .collect(),
+                    children: vec![],
+                    text: String::new(),
+                });
+            }
+            XmlEvent::EndElement { .. } => {
+                let node = stack.pop().ok_or("unbalanced xml")?;
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(node),
+                    None => return Ok(node),
+                }
+            }
+            XmlEvent::Characters(text) | XmlEvent::CData(text) => {
+                if let Some(node) = stack.last_mut() {
+                    node.text.push_str(&text);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Err("xml document without root element".into())
+}
+
+/// Resolves an href found inside the file at `base_file` (paths are the ones used
+/// inside the epub archive) and splits the optional fragment.
+/// Example: ("OEBPS/toc.ncx", "text/ch1.xhtml#sec2") -> ("OEBPS/text/ch1.xhtml", Some("sec2"))
+pub fn resolve_href(base_file: &Path, href: &str) -> (PathBuf, Option<String>) {
+    let (file, fragment) = match href.split_once('#') {
+        Some((file, fragment)) => (file, Some(percent_decode(fragment))),
+        None => (href, None),
+    };
+
+    let joined = base_file
+        .parent()
+        .unwrap_or_else(|| Path::new(""))
+        .join(percent_decode(file));
+
+    // normalize "." and ".." without touching the filesystem
+    let mut normalized = PathBuf::new();
+    for component in joined.components() {
+        match component {
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::CurDir => {}
+            other => normalized.push(other.as_os_str()),
+        }
+    }
+
+    (normalized, fragment.filter(|f| !f.is_empty()))
+}
+
+/// Decodes the %XX sequences of an URI component
+pub fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or_default();
+            if let Ok(value) = u8::from_str_radix(hex, 16) {
+                decoded.push(value);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Escapes the characters that can't be in the text or in the attributes of XML and HTML
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hrefs_are_resolved_from_the_file_they_are_in() {
+        assert_eq!(
+            resolve_href(Path::new("OEBPS/toc.ncx"), "text/ch1.xhtml#sec2"),
+            (
+                PathBuf::from("OEBPS/text/ch1.xhtml"),
+                Some("sec2".to_string())
+            )
+        );
+        assert_eq!(
+            resolve_href(Path::new("OEBPS/nav/nav.xhtml"), "../text/./ch%201.xhtml"),
+            (PathBuf::from("OEBPS/text/ch 1.xhtml"), None)
+        );
+        // an empty fragment is no fragment
+        assert_eq!(
+            resolve_href(Path::new("toc.ncx"), "ch1.xhtml#"),
+            (PathBuf::from("ch1.xhtml"), None)
+        );
+        assert_eq!(
+            resolve_href(Path::new("OEBPS/toc.ncx"), "#note%201"),
+            (PathBuf::from("OEBPS"), Some("note 1".to_string()))
+        );
+    }
+
+    #[test]
+    fn percent_sequences_are_decoded() {
+        assert_eq!(percent_decode("ch%202.xhtml"), "ch 2.xhtml");
+        assert_eq!(percent_decode("perch%C3%A9"), "perché");
+        // invalid or truncated sequences are kept as they are
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%2"), "%zz%2");
+    }
+
+    #[test]
+    fn special_characters_are_escaped() {
+        assert_eq!(
+            escape("<a href=\"x\">Tom & Jerry</a>"),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&lt;/a&gt;"
+        );
+    }
+}